@@ -1,20 +1,117 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
+use restate_ffmpeg::{
+    CostRates, EnvConfig, FlagConfig, GuardrailConfig, HandlerConfig, HistoryRetention, LoadConfig,
+    RateLimitConfig, RoutingConfig, StagingConfig, StreamingConfig, Variant, WatchFolderConfig,
+    WatchdogConfig,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::config_restate::*;
 
-#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
+    /// Config files merged before this one (relative paths are resolved against this file)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+
     #[serde(default)]
     pub restate: RestateConfig,
 
     #[serde(default, alias = "profile")]
     pub profiles: HashMap<String, HashMap<String, String>>,
+
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+
+    #[serde(default)]
+    pub metering: MeteringConfig,
+
+    #[serde(default)]
+    pub history: HistoryConfig,
+
+    #[serde(default)]
+    pub staging: StagingConfig,
+
+    #[serde(default)]
+    pub guardrails: GuardrailConfig,
+
+    #[serde(default)]
+    pub load: LoadConfig,
+
+    #[serde(default)]
+    pub handlers: HandlerConfig,
+
+    /// Flags added to the arguments of ffmpeg requests
+    #[serde(default)]
+    pub flags: FlagConfig,
+
+    /// Upload of outputs ffmpeg writes to stdout
+    #[serde(default)]
+    pub streaming: StreamingConfig,
+
+    /// Environment variables requests may set for ffmpeg
+    #[serde(default)]
+    pub env: EnvConfig,
+
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+
+    /// Hot folders processed by the FFmpegWatchFolder object (keyed by folder name)
+    #[serde(default)]
+    pub watch_folders: HashMap<String, WatchFolderConfig>,
+
+    /// Variants of the FFmpeg service registered by this worker
+    #[serde(default = "default_variants")]
+    pub variants: Vec<Variant>,
+
+    #[serde(default)]
+    pub routing: RoutingConfig,
+}
+
+fn default_variants() -> Vec<Variant> {
+    vec![Variant::Default]
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct MeteringConfig {
+    /// Record the usage of jobs per tenant
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Hourly rates jobs are charged at (costs are left out when empty)
+    #[serde(default)]
+    pub rates: CostRates,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct RestateConfig {
     #[serde(default)]
     pub service: ServiceOptionsConfig,
+
+    #[serde(default)]
+    pub identity: IdentityConfig,
+}
+
+/// Request identity verification: only requests signed by the Restate runtime are accepted.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct IdentityConfig {
+    /// Public keys of the trusted Restate runtime (e.g. publickeyv1_...)
+    #[serde(default)]
+    pub keys: Vec<String>,
+
+    /// Refuse to start without identity keys
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct HistoryConfig {
+    /// Keep a history of finished jobs per caller
+    #[serde(default)]
+    pub enabled: bool,
+
+    #[serde(default, flatten)]
+    pub retention: HistoryRetention,
 }
@@ -1,7 +1,11 @@
 mod config;
 mod config_restate;
 
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::{Context, Result};
 use clap::Parser;
@@ -36,12 +40,74 @@ async fn main() -> Result<()> {
     DEFAULT_OPERATOR_REGISTRY.register::<services::Http>(services::HTTP_SCHEME);
     DEFAULT_OPERATOR_REGISTRY.register::<services::Http>("https");
 
-    let factory = create_factory(config.profiles.clone());
-
     let mut endpoint = Endpoint::builder();
 
-    let service = ServiceImpl::new(factory);
-    endpoint = endpoint.bind(service.serve());
+    let identity = &config.restate.identity;
+
+    if identity.required && identity.keys.is_empty() {
+        anyhow::bail!("Request identity verification is required, but no identity keys are set");
+    }
+
+    for key in &identity.keys {
+        endpoint = endpoint
+            .identity_key(key)
+            .with_context(|| format!("Invalid identity key: {key}"))?;
+    }
+
+    // Variants share the limits of callers
+    let limiter = Arc::new(RateLimiter::new(config.rate_limits.clone()));
+
+    let service = || {
+        ServiceImpl::new(create_factory(config.profiles.clone()))
+            .with_rate_limits(limiter.clone())
+            .with_metering(config.metering.enabled)
+            .with_cost_rates(config.metering.rates.clone())
+            .with_history(config.history.enabled)
+            .with_staging(config.staging.clone())
+            .with_guardrails(config.guardrails.clone())
+            .with_load(config.load.clone())
+            .with_handlers(config.handlers.clone())
+            .with_flags(config.flags.clone())
+            .with_streaming(config.streaming.clone())
+            .with_env(config.env.clone())
+            .with_watchdog(config.watchdog.clone())
+    };
+
+    for variant in &config.variants {
+        endpoint = match variant {
+            Variant::Default => endpoint.bind(service().serve()),
+            Variant::Gpu => endpoint.bind(VariantService::<_, GpuVariant>::new(service().serve())),
+            Variant::HighMem => {
+                endpoint.bind(VariantService::<_, HighMemVariant>::new(service().serve()))
+            }
+        };
+    }
+
+    if config.routing.enabled {
+        endpoint = endpoint.bind(
+            RouterImpl::new(
+                create_factory(config.profiles.clone()),
+                config.routing.clone(),
+                config.rate_limits.caller_header.clone(),
+            )
+            .serve(),
+        );
+    }
+
+    if config.metering.enabled {
+        endpoint = endpoint.bind(MeteringImpl.serve());
+    }
+
+    if config.history.enabled {
+        endpoint = endpoint.bind(HistoryImpl::new(config.history.retention.clone()).serve());
+    }
+
+    if !config.watch_folders.is_empty() {
+        let factory = create_factory(config.profiles.clone());
+
+        endpoint =
+            endpoint.bind(WatchFolderImpl::new(factory, config.watch_folders.clone()).serve());
+    }
 
     let bind_addr = format!("0.0.0.0:{}", cli.port);
 
@@ -53,6 +119,9 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Prefix of the environment variables overriding the configuration.
+const ENV_PREFIX: &str = "RESTATE_FFMPEG_";
+
 #[derive(Parser, Debug)]
 #[command(version)]
 struct Cli {
@@ -70,31 +139,75 @@ impl Cli {
         let mut figment = Figment::new();
 
         if let Some(path) = self.config.as_deref() {
-            if !path.exists() {
-                anyhow::bail!("Config file not found: {}", path.display());
-            }
-
-            figment = match path.extension().and_then(|s| s.to_str()) {
-                Some("toml") => figment.merge(Toml::file(path)),
-                Some("json") => figment.merge(Json::file(path)),
-                Some("yaml") | Some("yml") => figment.merge(Yaml::file(path)),
-                _ => anyhow::bail!(
-                    "Unsupported config file format. Use .toml, .json, .yaml, or .yml"
-                ),
-            };
+            figment = merge_file(figment, path, &mut Vec::new())?;
         }
 
-        figment = figment.merge(Env::raw().split("__")).merge(
-            Env::prefixed("OPENDAL_")
-                .filter(|k| k.starts_with("profile_"))
-                .map(move |key| key.as_str().replacen("_", ".", 2).into()),
-            // .split("_"),
-        );
+        figment = figment
+            .merge(Env::raw().split("__"))
+            .merge(
+                Env::prefixed("OPENDAL_")
+                    .filter(|k| k.starts_with("profile_"))
+                    .map(move |key| key.as_str().replacen("_", ".", 2).into()),
+                // .split("_"),
+            )
+            // Any field can be overridden, nested fields are separated by "__"
+            // (e.g. RESTATE_FFMPEG_RATE_LIMITS__CALLERS__ACME__CONCURRENT_JOBS=2)
+            .merge(Env::prefixed(ENV_PREFIX).split("__"));
 
         figment.extract().context("Failed to parse configuration")
     }
 }
 
+/// Merges a config file into the configuration, preceded by the files it includes.
+///
+/// Files listed in the `include` field are merged in order (later ones take precedence),
+/// then the including file itself, so it can override anything it includes.
+/// Relative includes are resolved against the directory of the including file.
+fn merge_file(figment: Figment, path: &Path, seen: &mut Vec<PathBuf>) -> Result<Figment> {
+    if !path.exists() {
+        anyhow::bail!("Config file not found: {}", path.display());
+    }
+
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve config file: {}", path.display()))?;
+
+    if seen.contains(&path) {
+        anyhow::bail!("Config file is included recursively: {}", path.display());
+    }
+
+    let file = match path.extension().and_then(|s| s.to_str()) {
+        Some("toml") => Figment::from(Toml::file_exact(&path)),
+        Some("json") => Figment::from(Json::file_exact(&path)),
+        Some("yaml") | Some("yml") => Figment::from(Yaml::file_exact(&path)),
+        _ => anyhow::bail!(
+            "Unsupported config file format ({}). Use .toml, .json, .yaml, or .yml",
+            path.display()
+        ),
+    };
+
+    let includes: Vec<PathBuf> = if file.contains("include") {
+        file.extract_inner("include")
+            .with_context(|| format!("Invalid includes in {}", path.display()))?
+    } else {
+        Vec::new()
+    };
+
+    seen.push(path.clone());
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut figment = figment;
+
+    for include in includes {
+        figment = merge_file(figment, &dir.join(include), seen)?;
+    }
+
+    seen.pop();
+
+    Ok(figment.merge(file))
+}
+
 fn create_factory(profiles: HashMap<String, HashMap<String, String>>) -> impl OperatorFactory {
     LambdaOperatorFactory::new(
         ChainOperatorFactory::builder()
@@ -0,0 +1,167 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::service::{Output, ServiceImpl, input_stem, run_ffmpeg_in, run_ffmpeg_stdout};
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_archive_request())]
+pub struct ArchiveRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Number of slices per frame (more slices encode faster and improve error resilience)
+    #[serde(default = "default_slices")]
+    pub slices: u32,
+
+    /// Verify that the decoded video of the archive is identical to the source
+    #[serde(default = "default_verify")]
+    pub verify: bool,
+}
+
+fn default_slices() -> u32 {
+    16
+}
+
+fn default_verify() -> bool {
+    true
+}
+
+fn example_archive_request() -> ArchiveRequest {
+    ArchiveRequest {
+        input: Url::parse("https://example.com/master.mov").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/archive/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        slices: default_slices(),
+        verify: default_verify(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveResponse {
+    /// Number of video frames compared during verification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verified_frames: Option<usize>,
+
+    /// Location of the archive file
+    pub output: Url,
+}
+
+/// Returns the per-frame hashes of the video streams of an input.
+///
+/// Only the stream index and the hash are kept: timestamps are expressed in the
+/// container time base, which legitimately differs between the source and the archive.
+pub(crate) async fn video_frame_hashes(input: &str) -> HandlerResult<Vec<(String, String)>> {
+    let framemd5 = run_ffmpeg_stdout(&[
+        "-i".to_string(),
+        input.to_string(),
+        "-map".to_string(),
+        "0:v".to_string(),
+        "-f".to_string(),
+        "framemd5".to_string(),
+        "-".to_string(),
+    ])
+    .await?;
+
+    Ok(framemd5
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+
+            Some((columns.first()?.to_string(), columns.last()?.to_string()))
+        })
+        .collect())
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _archive(&self, request: ArchiveRequest) -> HandlerResult<ArchiveResponse> {
+        // The input is read again for verification: storage inputs are downloaded once
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let work_dir = TempDir::new()?;
+
+        let filename = format!("{}.mkv", input_stem(&request.input));
+
+        run_ffmpeg_in(
+            work_dir.path(),
+            &[
+                "-i".to_string(),
+                input.clone(),
+                "-map".to_string(),
+                "0:v".to_string(),
+                "-map".to_string(),
+                "0:a?".to_string(),
+                "-map".to_string(),
+                "0:s?".to_string(),
+                "-c:v".to_string(),
+                "ffv1".to_string(),
+                "-level".to_string(),
+                "3".to_string(),
+                "-g".to_string(),
+                "1".to_string(),
+                "-slices".to_string(),
+                request.slices.to_string(),
+                "-slicecrc".to_string(),
+                "1".to_string(),
+                "-c:a".to_string(),
+                "flac".to_string(),
+                "-c:s".to_string(),
+                "copy".to_string(),
+                filename.clone(),
+            ],
+        )
+        .await?;
+
+        let verified_frames = if request.verify {
+            let archive = work_dir
+                .path()
+                .join(&filename)
+                .to_string_lossy()
+                .to_string();
+
+            let (source, archive) =
+                tokio::try_join!(video_frame_hashes(&input), video_frame_hashes(&archive),)?;
+
+            if source != archive {
+                let frame = source
+                    .iter()
+                    .zip(archive.iter())
+                    .position(|(a, b)| a != b)
+                    .unwrap_or(source.len().min(archive.len()));
+
+                return Err(TerminalError::new(format!(
+                    "archive is not lossless: video differs from the source at frame {frame} ({} source frames, {} archived frames)",
+                    source.len(),
+                    archive.len()
+                ))
+                .into());
+            }
+
+            Some(source.len())
+        } else {
+            None
+        };
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        Ok(ArchiveResponse {
+            verified_frames,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
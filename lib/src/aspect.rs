@@ -0,0 +1,340 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::encode::{VideoEncoding, default_container};
+use crate::inputs::{Input, input_arg};
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem};
+
+/// Display aspect ratio (e.g. 16:9).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AspectRatio {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AspectRatio {
+    fn value(&self) -> f64 {
+        self.width as f64 / self.height as f64
+    }
+}
+
+/// How the picture is fitted into the target aspect ratio.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AspectPolicy {
+    /// Fit the picture and fill the remaining area with a solid color
+    #[default]
+    Pad,
+    /// Fit the picture over a blurred, zoomed copy of itself
+    Blur,
+    /// Fill the frame and crop the overflow around the center
+    Crop,
+    /// Scale the picture to the target dimensions, distorting it
+    Stretch,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_convert_aspect_request())]
+pub struct ConvertAspectRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Target display aspect ratio
+    pub aspect_ratio: AspectRatio,
+
+    #[serde(default)]
+    pub policy: AspectPolicy,
+
+    /// Fill color used by the pad policy (a name, "#RRGGBB[AA]" or "0xRRGGBB[AA]", optionally followed by "@alpha")
+    #[serde(default = "default_pad_color")]
+    pub pad_color: String,
+
+    /// Output width (derived from the input when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output container extension
+    #[serde(default = "default_container")]
+    pub container: String,
+}
+
+fn default_pad_color() -> String {
+    "black".to_string()
+}
+
+fn example_convert_aspect_request() -> ConvertAspectRequest {
+    ConvertAspectRequest {
+        input: Url::parse("https://example.com/landscape.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/vertical/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        aspect_ratio: AspectRatio {
+            width: 9,
+            height: 16,
+        },
+        policy: AspectPolicy::Blur,
+        pad_color: default_pad_color(),
+        width: Some(1080),
+        video: VideoEncoding::default(),
+        container: default_container(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertAspectResponse {
+    /// Output width in pixels
+    pub width: u32,
+
+    /// Output height in pixels
+    pub height: u32,
+
+    /// Location of the converted file
+    pub output: Url,
+}
+
+/// Parses ratios reported by ffprobe (e.g. "16:9"), ignoring unknown values ("0:1", "N/A").
+pub(crate) fn parse_ratio(ratio: &str) -> Option<f64> {
+    let (num, den) = ratio.split_once([':', '/'])?;
+    let num: f64 = num.parse().ok()?;
+    let den: f64 = den.parse().ok()?;
+
+    (num > 0.0 && den > 0.0).then_some(num / den)
+}
+
+/// Checks a value against the color syntax of ffmpeg, so that it can't break out of a filtergraph.
+pub(crate) fn valid_color(value: &str) -> bool {
+    let (color, alpha) = match value.split_once('@') {
+        Some((color, alpha)) => (color, Some(alpha)),
+        None => (value, None),
+    };
+
+    let hex = |digits: &str| {
+        matches!(digits.len(), 6 | 8) && digits.chars().all(|c| c.is_ascii_hexdigit())
+    };
+
+    let valid_color = match color.strip_prefix('#').or_else(|| color.strip_prefix("0x")) {
+        Some(digits) => hex(digits),
+        None => !color.is_empty() && color.chars().all(|c| c.is_ascii_alphabetic()),
+    };
+
+    let valid_alpha = alpha.is_none_or(|alpha| match alpha.strip_prefix("0x") {
+        Some(digits) => digits.len() == 2 && digits.chars().all(|c| c.is_ascii_hexdigit()),
+        None => alpha
+            .parse::<f64>()
+            .is_ok_and(|alpha| (0.0..=1.0).contains(&alpha)),
+    });
+
+    valid_color && valid_alpha
+}
+
+/// Rounds a dimension to the nearest even number (required by most encoders).
+pub(crate) fn even(value: f64) -> u32 {
+    ((value / 2.0).round() as u32 * 2).max(2)
+}
+
+impl ConvertAspectRequest {
+    /// Computes the output dimensions from the square-pixel source dimensions.
+    fn dimensions(&self, source_width: f64, source_height: f64) -> (u32, u32) {
+        let target = self.aspect_ratio.value();
+
+        if let Some(width) = self.width {
+            return (even(width as f64), even(width as f64 / target));
+        }
+
+        let source = source_width / source_height;
+
+        // Padding keeps every source pixel, cropping never upscales
+        let keep_height = match self.policy {
+            AspectPolicy::Pad | AspectPolicy::Blur => source < target,
+            AspectPolicy::Crop => source > target,
+            AspectPolicy::Stretch => false,
+        };
+
+        if keep_height {
+            (even(source_height * target), even(source_height))
+        } else {
+            (even(source_width), even(source_width / target))
+        }
+    }
+
+    fn filter(&self, width: u32, height: u32, normalize: Option<(u32, u32)>) -> String {
+        // Anamorphic sources are converted to square pixels first so that fitting works on display dimensions
+        let input = match normalize {
+            Some((w, h)) => format!("[0:v]scale={w}:{h},setsar=1"),
+            None => "[0:v]null".to_string(),
+        };
+
+        match self.policy {
+            AspectPolicy::Pad => format!(
+                "{input},scale={width}:{height}:force_original_aspect_ratio=decrease:force_divisible_by=2,\
+                 pad={width}:{height}:(ow-iw)/2:(oh-ih)/2:color={},setsar=1[v]",
+                self.pad_color
+            ),
+            AspectPolicy::Blur => format!(
+                "{input},split[bg][fg];\
+                 [bg]scale={width}:{height}:force_original_aspect_ratio=increase,crop={width}:{height},boxblur=20:5[bg];\
+                 [fg]scale={width}:{height}:force_original_aspect_ratio=decrease:force_divisible_by=2[fg];\
+                 [bg][fg]overlay=(W-w)/2:(H-h)/2,setsar=1[v]"
+            ),
+            AspectPolicy::Crop => format!(
+                "{input},scale={width}:{height}:force_original_aspect_ratio=increase,crop={width}:{height},setsar=1[v]"
+            ),
+            AspectPolicy::Stretch => format!("{input},scale={width}:{height},setsar=1[v]"),
+        }
+    }
+
+    fn args(
+        &self,
+        (width, height): (u32, u32),
+        normalize: Option<(u32, u32)>,
+        filename: &str,
+        inputs: &mut Vec<Input>,
+    ) -> Vec<String> {
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&self.input, inputs),
+            "-filter_complex".to_string(),
+            self.filter(width, height, normalize),
+            "-map".to_string(),
+            "[v]".to_string(),
+            "-map".to_string(),
+            "0:a?".to_string(),
+            "-aspect".to_string(),
+            format!("{}:{}", self.aspect_ratio.width, self.aspect_ratio.height),
+        ];
+
+        args.extend(self.video.args());
+        args.extend(["-c:a".to_string(), "copy".to_string(), filename.to_string()]);
+
+        args
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _convert_aspect(
+        &self,
+        request: ConvertAspectRequest,
+    ) -> HandlerResult<ConvertAspectResponse> {
+        if request.aspect_ratio.width == 0 || request.aspect_ratio.height == 0 {
+            return Err(TerminalError::new_with_code(400, "aspect ratio must be positive").into());
+        }
+
+        if !valid_color(&request.pad_color) {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("invalid pad color {:?}", request.pad_color),
+            )
+            .into());
+        }
+
+        request.video.validate().await?;
+
+        let probe = self.probe(&request.input).await?;
+
+        let stream = probe
+            .stream("video")
+            .ok_or_else(|| TerminalError::new_with_code(400, "input has no video stream"))?;
+
+        let (Some(source_width), Some(source_height)) = (stream.width, stream.height) else {
+            return Err(TerminalError::new_with_code(400, "unknown video dimensions").into());
+        };
+
+        let sar = stream
+            .sample_aspect_ratio
+            .as_deref()
+            .and_then(parse_ratio)
+            .unwrap_or(1.0);
+
+        let display_width = source_width as f64 * sar;
+        let normalize = (sar != 1.0).then(|| (even(display_width), source_height as u32));
+
+        let (width, height) = request.dimensions(display_width, source_height as f64);
+
+        let filename = format!("{}.{}", input_stem(&request.input), request.container);
+
+        let mut inputs = Vec::new();
+
+        self._ffmpeg(FfmpegRequest {
+            args: request.args((width, height), normalize, &filename, &mut inputs),
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(ConvertAspectResponse {
+            width,
+            height,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_stage_storage_inputs() {
+        let mut request = example_convert_aspect_request();
+        request.input = Url::parse("s3://bucket/landscape.mp4").unwrap();
+        let mut inputs = Vec::new();
+
+        let args = request.args((1080, 1920), None, "vertical.mp4", &mut inputs);
+
+        assert_eq!(args[..2], ["-i", "{input0}"]);
+        assert_eq!(inputs[0].location, request.input);
+    }
+
+    #[test]
+    fn valid_colors() {
+        for color in [
+            "black",
+            "DarkSlateGray",
+            "#1a2b3c",
+            "#1A2B3C80",
+            "0x000000",
+            "white@0.5",
+            "red@0x80",
+        ] {
+            assert!(valid_color(color), "{color}");
+        }
+    }
+
+    #[test]
+    fn invalid_colors() {
+        for color in [
+            "",
+            "black[v];[0:a]anull",
+            "black:x=0",
+            "#12345",
+            "0xGGGGGG",
+            "white@2",
+            "white@",
+            "@0.5",
+        ] {
+            assert!(!valid_color(color), "{color}");
+        }
+    }
+}
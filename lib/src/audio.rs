@@ -0,0 +1,1166 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::capabilities::{require_encoder, require_muxer};
+use crate::inputs::input_arg;
+use crate::service::{
+    FfmpegRequest, Output, ServiceImpl, Stream, input_extension, input_stem, run_ffmpeg,
+    run_ffmpeg_stdout,
+};
+
+/// Content the Opus encoder is tuned for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OpusApplication {
+    /// Speech intelligibility
+    Voip,
+    /// Music and mixed content
+    #[default]
+    Audio,
+    /// Minimal coding delay
+    Lowdelay,
+}
+
+impl OpusApplication {
+    fn name(&self) -> &'static str {
+        match self {
+            OpusApplication::Voip => "voip",
+            OpusApplication::Audio => "audio",
+            OpusApplication::Lowdelay => "lowdelay",
+        }
+    }
+}
+
+/// Rate control of the Opus encoder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OpusVbr {
+    /// Constant bitrate
+    Off,
+    /// Variable bitrate
+    #[default]
+    On,
+    /// Variable bitrate capped at the target bitrate
+    Constrained,
+}
+
+impl OpusVbr {
+    fn name(&self) -> &'static str {
+        match self {
+            OpusVbr::Off => "off",
+            OpusVbr::On => "on",
+            OpusVbr::Constrained => "constrained",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_encode_opus_request())]
+pub struct EncodeOpusRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Target bitrate (e.g. "24k" for voice, "128k" for stereo music)
+    #[serde(default = "default_opus_bitrate")]
+    pub bitrate: String,
+
+    #[serde(default)]
+    pub vbr: OpusVbr,
+
+    #[serde(default)]
+    pub application: OpusApplication,
+
+    /// Discontinuous transmission: send almost nothing during silence
+    #[serde(default)]
+    pub dtx: bool,
+
+    /// Output channel count (defaults to the channel count of the source)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u32>,
+
+    /// Output container extension
+    #[serde(default = "default_opus_container")]
+    pub container: String,
+}
+
+fn default_opus_bitrate() -> String {
+    "64k".to_string()
+}
+
+fn default_opus_container() -> String {
+    "opus".to_string()
+}
+
+fn example_encode_opus_request() -> EncodeOpusRequest {
+    EncodeOpusRequest {
+        input: Url::parse("https://example.com/podcast.wav").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/voice/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        bitrate: "24k".to_string(),
+        vbr: OpusVbr::On,
+        application: OpusApplication::Voip,
+        dtx: true,
+        channels: Some(1),
+        container: default_opus_container(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeOpusResponse {
+    /// Channel count of the output
+    pub channels: u32,
+
+    /// Location of the encoded file
+    pub output: Url,
+}
+
+/// Channel layouts of Vorbis channel mapping (mapping family 1), which Opus uses above two channels.
+fn opus_layout(channels: u32) -> Option<&'static str> {
+    match channels {
+        1 => Some("mono"),
+        2 => Some("stereo"),
+        3 => Some("3.0"),
+        4 => Some("quad"),
+        5 => Some("5.0"),
+        6 => Some("5.1"),
+        7 => Some("6.1"),
+        8 => Some("7.1"),
+        _ => None,
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _encode_opus(
+        &self,
+        request: EncodeOpusRequest,
+    ) -> HandlerResult<EncodeOpusResponse> {
+        require_encoder("libopus").await?;
+
+        let channels = match request.channels {
+            Some(channels) => channels,
+            None => {
+                let probe = self.probe(&request.input).await?;
+
+                let stream = probe.stream("audio").ok_or_else(|| {
+                    TerminalError::new_with_code(400, "input has no audio stream")
+                })?;
+
+                stream.channels.unwrap_or(2) as u32
+            }
+        };
+
+        let Some(layout) = opus_layout(channels) else {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("Opus supports 1 to 8 channels, got {channels}"),
+            )
+            .into());
+        };
+
+        let filename = format!("{}.{}", input_stem(&request.input), request.container);
+
+        let mut inputs = Vec::new();
+
+        // Pinning the layout keeps mono sources mono and turns side-channel layouts
+        // (e.g. 5.1(side)) into the ones libopus accepts
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-map".to_string(),
+            "0:a:0".to_string(),
+            "-af".to_string(),
+            format!("aformat=channel_layouts={layout}"),
+            "-c:a".to_string(),
+            "libopus".to_string(),
+            "-b:a".to_string(),
+            request.bitrate.clone(),
+            "-vbr".to_string(),
+            request.vbr.name().to_string(),
+            "-application".to_string(),
+            request.application.name().to_string(),
+        ];
+
+        if channels > 2 {
+            args.extend(["-mapping_family".to_string(), "1".to_string()]);
+        }
+
+        if request.dtx {
+            args.extend(["-dtx".to_string(), "1".to_string()]);
+        }
+
+        args.push(filename.clone());
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(EncodeOpusResponse {
+            channels,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+/// Lossy codecs of the audio encode handler.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioCodec {
+    /// AAC-LC (native encoder)
+    #[default]
+    Aac,
+    /// MP3 (LAME)
+    Mp3,
+}
+
+impl AudioCodec {
+    fn encoder(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Mp3 => "libmp3lame",
+        }
+    }
+
+    fn default_container(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "m4a",
+            AudioCodec::Mp3 => "mp3",
+        }
+    }
+
+    /// Samples per frame.
+    fn frame_size(&self) -> u64 {
+        match self {
+            AudioCodec::Aac => 1024,
+            AudioCodec::Mp3 => 1152,
+        }
+    }
+
+    /// Samples the decoder outputs before the first sample of the input (priming).
+    ///
+    /// LAME delays the input by 576 samples, the decoder adds 528 + 1 (as ffmpeg reports it).
+    fn encoder_delay(&self) -> u64 {
+        match self {
+            AudioCodec::Aac => 1024,
+            AudioCodec::Mp3 => 576 + 528 + 1,
+        }
+    }
+
+    /// Containers able to carry the encoder delay and padding.
+    fn gapless_containers(&self) -> &'static [&'static str] {
+        match self {
+            AudioCodec::Aac => &["m4a", "mp4"],
+            AudioCodec::Mp3 => &["mp3"],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_encode_audio_request())]
+pub struct EncodeAudioRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    #[serde(default)]
+    pub codec: AudioCodec,
+
+    /// Target bitrate (e.g. "256k")
+    #[serde(default = "default_audio_bitrate")]
+    pub bitrate: String,
+
+    /// Output sample rate in Hz (defaults to the one of the source, at most 48000 for MP3)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+
+    /// Output channel count (defaults to the channel count of the source)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u32>,
+
+    /// Output container extension (defaults to "m4a" for AAC and "mp3" for MP3)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+
+    /// Record the encoder delay and padding so that players trim them (tracks of an album
+    /// play back without gaps)
+    #[serde(default = "default_gapless")]
+    pub gapless: bool,
+}
+
+fn default_audio_bitrate() -> String {
+    "256k".to_string()
+}
+
+fn default_gapless() -> bool {
+    true
+}
+
+fn example_encode_audio_request() -> EncodeAudioRequest {
+    EncodeAudioRequest {
+        input: Url::parse("s3://bucket/masters/track-02.flac").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/aac/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        codec: AudioCodec::Aac,
+        bitrate: default_audio_bitrate(),
+        sample_rate: None,
+        channels: None,
+        container: None,
+        gapless: true,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeAudioResponse {
+    /// Location of the encoded file
+    pub output: Url,
+
+    pub sample_rate: u32,
+
+    pub channels: u32,
+
+    /// Gapless playback information (when requested)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gapless: Option<GaplessInfo>,
+}
+
+/// Samples players trim from the start and the end of a track.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GaplessInfo {
+    /// Priming samples at the start
+    pub encoder_delay: u64,
+
+    /// Samples filling up the last frame
+    pub padding: u64,
+
+    /// Samples of the source
+    pub samples: u64,
+
+    /// Value of the iTunSMPB tag written to the file
+    pub itunsmpb: String,
+}
+
+impl GaplessInfo {
+    fn new(codec: AudioCodec, samples: u64) -> Self {
+        let encoder_delay = codec.encoder_delay();
+        let frames = (encoder_delay + samples).div_ceil(codec.frame_size());
+        let padding = frames * codec.frame_size() - encoder_delay - samples;
+
+        // Fields are hex: reserved, delay, padding, sample count, then reserved words
+        let itunsmpb = format!(
+            " 00000000 {encoder_delay:08X} {padding:08X} {samples:016X}{}",
+            " 00000000".repeat(8)
+        );
+
+        Self {
+            encoder_delay,
+            padding,
+            samples,
+            itunsmpb,
+        }
+    }
+}
+
+/// Sample rates the MP3 encoder takes above 32 kHz.
+fn mp3_sample_rate(sample_rate: u32) -> u32 {
+    match sample_rate {
+        rate if rate <= 48000 => rate,
+        rate if rate.is_multiple_of(44100) => 44100,
+        _ => 48000,
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Encodes the first audio stream of the input to AAC or MP3.
+    ///
+    /// For gapless playback the encoder delay is stored in an MP4 edit list (AAC) or in the LAME
+    /// header (MP3), and in an iTunSMPB tag for players only reading that one. The sample count
+    /// is taken from the source: the encoded file can't tell padding from silence.
+    pub(crate) async fn _encode_audio(
+        &self,
+        request: EncodeAudioRequest,
+    ) -> HandlerResult<EncodeAudioResponse> {
+        require_encoder(request.codec.encoder()).await?;
+
+        let container = request
+            .container
+            .clone()
+            .unwrap_or_else(|| request.codec.default_container().to_string());
+
+        if request.gapless
+            && !request
+                .codec
+                .gapless_containers()
+                .contains(&container.as_str())
+        {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!(
+                    "{container} can't carry gapless information, use one of: {}",
+                    request.codec.gapless_containers().join(", ")
+                ),
+            )
+            .into());
+        }
+
+        let probe = self.probe(&request.input).await?;
+
+        let stream = probe
+            .stream("audio")
+            .ok_or_else(|| TerminalError::new_with_code(400, "input has no audio stream"))?;
+
+        let source_rate = stream
+            .sample_rate
+            .as_deref()
+            .and_then(|rate| rate.parse().ok())
+            .unwrap_or(48000);
+
+        let sample_rate = match (request.sample_rate, request.codec) {
+            (Some(sample_rate), _) => sample_rate,
+            (None, AudioCodec::Mp3) => mp3_sample_rate(source_rate),
+            (None, AudioCodec::Aac) => source_rate,
+        };
+
+        let channels = request
+            .channels
+            .unwrap_or_else(|| stream.channels.unwrap_or(2) as u32);
+
+        let filename = format!("{}.{container}", input_stem(&request.input));
+
+        let mut inputs = Vec::new();
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-map".to_string(),
+            "0:a:0".to_string(),
+            "-c:a".to_string(),
+            request.codec.encoder().to_string(),
+            "-b:a".to_string(),
+            request.bitrate.clone(),
+            "-ar".to_string(),
+            sample_rate.to_string(),
+            "-ac".to_string(),
+            channels.to_string(),
+        ];
+
+        let gapless = if request.gapless {
+            let duration = stream
+                .duration
+                .as_deref()
+                .and_then(|duration| duration.parse::<f64>().ok())
+                .or_else(|| probe.duration())
+                .ok_or_else(|| {
+                    TerminalError::new_with_code(
+                        400,
+                        "input duration is unknown, gapless information can't be computed",
+                    )
+                })?;
+
+            let gapless = GaplessInfo::new(
+                request.codec,
+                (duration * sample_rate as f64).round() as u64,
+            );
+
+            match request.codec {
+                // The edit list skips the priming samples and cuts the padding
+                AudioCodec::Aac => args.extend([
+                    "-use_editlist".to_string(),
+                    "1".to_string(),
+                    "-movflags".to_string(),
+                    "+use_metadata_tags".to_string(),
+                ]),
+                // The LAME header in the Xing frame records the delay and the padding
+                AudioCodec::Mp3 => args.extend([
+                    "-write_xing".to_string(),
+                    "1".to_string(),
+                    "-id3v2_version".to_string(),
+                    "3".to_string(),
+                ]),
+            }
+
+            args.extend([
+                "-metadata".to_string(),
+                format!("iTunSMPB={}", gapless.itunsmpb),
+            ]);
+
+            Some(gapless)
+        } else {
+            None
+        };
+
+        args.push(filename.clone());
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(EncodeAudioResponse {
+            output: request.output.file_url(&filename),
+            sample_rate,
+            channels,
+            gapless,
+        })
+    }
+}
+
+/// Codecs delivered to home-theater receivers as-is.
+const PASSTHROUGH_CODECS: [&str; 4] = ["ac3", "eac3", "dts", "truehd"];
+
+fn passthrough_streams(streams: &[Stream]) -> Vec<&Stream> {
+    streams
+        .iter()
+        .filter(|stream| stream.codec_type == "audio")
+        .filter(|stream| {
+            stream
+                .codec_name
+                .as_deref()
+                .is_some_and(|codec| PASSTHROUGH_CODECS.contains(&codec))
+        })
+        .collect()
+}
+
+/// Hashes the packets of a stream without decoding them.
+async fn packet_hash(input: &str, index: i32) -> HandlerResult<String> {
+    let output = run_ffmpeg_stdout(&[
+        "-i".to_string(),
+        input.to_string(),
+        "-map".to_string(),
+        format!("0:{index}"),
+        "-c".to_string(),
+        "copy".to_string(),
+        "-f".to_string(),
+        "hash".to_string(),
+        "-hash".to_string(),
+        "sha256".to_string(),
+        "-".to_string(),
+    ])
+    .await?;
+
+    Ok(output
+        .trim()
+        .split_once('=')
+        .map_or(output.trim(), |(_, hash)| hash)
+        .to_string())
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_validate_passthrough_request())]
+pub struct ValidatePassthroughRequest {
+    /// Path or URL to the source file
+    pub source: Url,
+
+    /// Path or URL to the delivered file
+    pub encoded: Url,
+
+    /// Fail with a terminal error when a stream was altered (otherwise only report it)
+    #[serde(default = "default_fail_on_mismatch")]
+    pub fail_on_mismatch: bool,
+}
+
+fn default_fail_on_mismatch() -> bool {
+    true
+}
+
+fn example_validate_passthrough_request() -> ValidatePassthroughRequest {
+    ValidatePassthroughRequest {
+        source: Url::parse("s3://bucket/master.mkv").unwrap(),
+        encoded: Url::parse("s3://bucket/delivery.mp4").unwrap(),
+        fail_on_mismatch: true,
+    }
+}
+
+/// Result of comparing a passthrough stream of the source with its copy.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PassthroughStream {
+    /// Index of the stream in the source
+    pub source_index: i32,
+
+    /// Index of the matching stream in the delivered file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoded_index: Option<i32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+
+    /// Whether the packets of both streams are identical
+    pub bit_exact: bool,
+
+    /// Codec parameters that differ
+    pub issues: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidatePassthroughResponse {
+    pub streams: Vec<PassthroughStream>,
+}
+
+fn compare_passthrough(source: &Stream, encoded: &Stream) -> Vec<String> {
+    let fields = [
+        ("codec", &source.codec_name, &encoded.codec_name),
+        ("profile", &source.profile, &encoded.profile),
+        ("sample rate", &source.sample_rate, &encoded.sample_rate),
+        (
+            "channel layout",
+            &source.channel_layout,
+            &encoded.channel_layout,
+        ),
+        ("bitrate", &source.bit_rate, &encoded.bit_rate),
+    ];
+
+    let mut issues: Vec<String> = fields
+        .into_iter()
+        // Containers don't always report every parameter
+        .filter_map(|(name, source, encoded)| match (source, encoded) {
+            (Some(a), Some(b)) if a != b => Some(format!("{name} changed from {a} to {b}")),
+            _ => None,
+        })
+        .collect();
+
+    if source.channels != encoded.channels {
+        issues.push(format!(
+            "channel count changed from {:?} to {:?}",
+            source.channels, encoded.channels
+        ));
+    }
+
+    issues
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _validate_passthrough(
+        &self,
+        request: ValidatePassthroughRequest,
+    ) -> HandlerResult<ValidatePassthroughResponse> {
+        let (source_probe, encoded_probe) =
+            tokio::try_join!(self.probe(&request.source), self.probe(&request.encoded))?;
+
+        let source_streams = source_probe.streams.unwrap_or_default();
+        let encoded_streams = encoded_probe.streams.unwrap_or_default();
+
+        let source = passthrough_streams(&source_streams);
+        let encoded = passthrough_streams(&encoded_streams);
+
+        // Every paired stream is hashed: storage inputs are downloaded once (when there is any)
+        let (source_dir, encoded_dir) = (TempDir::new()?, TempDir::new()?);
+        let (source_input, encoded_input) = if source.is_empty() || encoded.is_empty() {
+            (request.source.to_string(), request.encoded.to_string())
+        } else {
+            tokio::try_join!(
+                self.local_input(&request.source, source_dir.path()),
+                self.local_input(&request.encoded, encoded_dir.path()),
+            )?
+        };
+
+        let mut streams = Vec::new();
+
+        // Streams are paired in order, other audio streams (e.g. stereo AAC) may be added freely
+        for (i, source) in source.iter().enumerate() {
+            let Some(encoded) = encoded.get(i) else {
+                streams.push(PassthroughStream {
+                    source_index: source.index,
+                    encoded_index: None,
+                    codec: source.codec_name.clone(),
+                    bit_exact: false,
+                    issues: vec!["stream is missing or was re-encoded".to_string()],
+                });
+                continue;
+            };
+
+            let issues = compare_passthrough(source, encoded);
+
+            let (source_hash, encoded_hash) = tokio::try_join!(
+                packet_hash(&source_input, source.index),
+                packet_hash(&encoded_input, encoded.index),
+            )?;
+
+            streams.push(PassthroughStream {
+                source_index: source.index,
+                encoded_index: Some(encoded.index),
+                codec: source.codec_name.clone(),
+                bit_exact: source_hash == encoded_hash,
+                issues,
+            });
+        }
+
+        let failures: Vec<String> = streams
+            .iter()
+            .filter(|stream| !stream.bit_exact || !stream.issues.is_empty())
+            .map(|stream| {
+                let mut issues = stream.issues.clone();
+                if stream.encoded_index.is_some() && !stream.bit_exact {
+                    issues.push("packets differ".to_string());
+                }
+                format!("stream {}: {}", stream.source_index, issues.join(", "))
+            })
+            .collect();
+
+        if request.fail_on_mismatch && !failures.is_empty() {
+            return Err(TerminalError::new_with_code(
+                422,
+                format!("audio was not passed through: {}", failures.join("; ")),
+            )
+            .into());
+        }
+
+        Ok(ValidatePassthroughResponse { streams })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_replaygain_request())]
+pub struct ReplaygainRequest {
+    /// Path or URL to the audio file
+    pub input: Url,
+
+    /// Write the values as tags to a copy of the input stored here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Output>,
+
+    /// Reference loudness in LUFS (ReplayGain 2.0 uses -18)
+    #[serde(default = "default_reference_loudness")]
+    pub reference_loudness: f64,
+}
+
+fn default_reference_loudness() -> f64 {
+    -18.0
+}
+
+fn example_replaygain_request() -> ReplaygainRequest {
+    ReplaygainRequest {
+        input: Url::parse("https://example.com/track.flac").unwrap(),
+        output: Some(Output {
+            location: Url::parse("s3://bucket/tagged/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        }),
+        reference_loudness: default_reference_loudness(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplaygainResponse {
+    /// Integrated loudness in LUFS
+    pub loudness: f64,
+
+    /// Track gain in dB
+    pub track_gain: f64,
+
+    /// Track true peak relative to full scale (1.0 = 0 dBFS)
+    pub track_peak: f64,
+
+    /// Location of the tagged file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Url>,
+}
+
+/// Parses the integrated loudness and the true peak (dBFS) from the ebur128 summary.
+fn parse_ebur128_summary(log: &str) -> Option<(f64, f64)> {
+    let (_, summary) = log.rsplit_once("Summary:")?;
+
+    let value = |label: &str| -> Option<f64> {
+        summary
+            .lines()
+            .map(str::trim)
+            .find_map(|line| line.strip_prefix(label))?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    };
+
+    Some((value("I:")?, value("Peak:")?))
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _replaygain(
+        &self,
+        request: ReplaygainRequest,
+    ) -> HandlerResult<ReplaygainResponse> {
+        // Both the measurement and the tagging read the input: storage inputs are downloaded once
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let log = run_ffmpeg(&[
+            "-i".to_string(),
+            input.clone(),
+            "-map".to_string(),
+            "0:a:0".to_string(),
+            "-af".to_string(),
+            "ebur128=peak=true".to_string(),
+            "-f".to_string(),
+            "null".to_string(),
+            "-".to_string(),
+        ])
+        .await?;
+
+        let (loudness, peak) = parse_ebur128_summary(&log)
+            .ok_or_else(|| HandlerError::from("failed to parse ebur128 summary"))?;
+
+        // Silence has no measurable loudness, leave such tracks untouched
+        let track_gain = if loudness.is_finite() {
+            request.reference_loudness - loudness
+        } else {
+            0.0
+        };
+        let track_peak = 10f64.powf(peak / 20.0);
+
+        let output = match &request.output {
+            Some(output) => {
+                let filename = format!(
+                    "{}.{}",
+                    input_stem(&request.input),
+                    input_extension(&request.input).unwrap_or_else(|| "flac".to_string())
+                );
+
+                self._ffmpeg(FfmpegRequest {
+                    args: vec![
+                        "-i".to_string(),
+                        input,
+                        "-map".to_string(),
+                        "0".to_string(),
+                        "-c".to_string(),
+                        "copy".to_string(),
+                        "-metadata".to_string(),
+                        format!("REPLAYGAIN_TRACK_GAIN={track_gain:.2} dB"),
+                        "-metadata".to_string(),
+                        format!("REPLAYGAIN_TRACK_PEAK={track_peak:.6}"),
+                        filename.clone(),
+                    ],
+                    output: output.clone(),
+                    dry_run: false,
+                    incremental_upload: false,
+                    env: Default::default(),
+                    inputs: Vec::new(),
+                    outputs: Vec::new(),
+                    sanitize_dimensions: false,
+                    logging: Default::default(),
+                })
+                .await?;
+
+                Some(output.file_url(&filename))
+            }
+            None => None,
+        };
+
+        Ok(ReplaygainResponse {
+            loudness,
+            track_gain,
+            track_peak,
+            output,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_analyze_loudness_request())]
+pub struct AnalyzeLoudnessRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Index of the stream among the audio streams of the input
+    #[serde(default)]
+    pub stream_index: u32,
+
+    /// Integrated loudness to normalize to in LUFS (EBU R128 uses -23)
+    #[serde(default = "default_target_loudness")]
+    pub target_loudness: f64,
+
+    /// Maximum true peak after normalization in dBTP
+    #[serde(default = "default_target_true_peak")]
+    pub target_true_peak: f64,
+
+    /// Loudness range to normalize to in LU
+    #[serde(default = "default_target_range")]
+    pub target_range: f64,
+}
+
+fn default_target_loudness() -> f64 {
+    -23.0
+}
+
+fn default_target_true_peak() -> f64 {
+    -1.0
+}
+
+fn default_target_range() -> f64 {
+    7.0
+}
+
+fn example_analyze_loudness_request() -> AnalyzeLoudnessRequest {
+    AnalyzeLoudnessRequest {
+        input: Url::parse("s3://bucket/masters/episode.wav").unwrap(),
+        stream_index: 0,
+        target_loudness: default_target_loudness(),
+        target_true_peak: default_target_true_peak(),
+        target_range: default_target_range(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzeLoudnessResponse {
+    /// Integrated loudness in LUFS (empty when the input is silent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrated: Option<f64>,
+
+    /// Loudness range (LRA) in LU
+    pub range: f64,
+
+    /// True peak in dBTP (empty when the input is silent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub true_peak: Option<f64>,
+
+    /// Gating threshold of the integrated loudness in LUFS (empty when the input is silent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+
+    /// Gain reaching the target loudness in dB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gain: Option<f64>,
+
+    /// Loudnorm filter normalizing the input to the targets with the measured values (second pass)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+}
+
+/// Measurement printed by the loudnorm filter (values are strings, "-inf" for silence).
+#[derive(Debug, Deserialize)]
+struct LoudnormMeasurement {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+/// Parses the JSON block printed by loudnorm at the end of the run.
+fn parse_loudnorm_measurement(log: &str) -> Option<LoudnormMeasurement> {
+    let start = log.rfind("[Parsed_loudnorm")?;
+    let log = &log[start..];
+    let json = &log[log.find('{')?..=log.rfind('}')?];
+
+    serde_json::from_str(json).ok()
+}
+
+/// Parses a measured value, silence is reported as an infinite value.
+fn finite(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+}
+
+impl AnalyzeLoudnessRequest {
+    fn validate(&self) -> HandlerResult<()> {
+        let invalid = |message: &str| -> HandlerResult<()> {
+            Err(TerminalError::new_with_code(400, message).into())
+        };
+
+        // Ranges accepted by the loudnorm filter
+        if !(-70.0..=-5.0).contains(&self.target_loudness) {
+            return invalid("target loudness must be between -70 and -5 LUFS");
+        }
+
+        if !(-9.0..=0.0).contains(&self.target_true_peak) {
+            return invalid("target true peak must be between -9 and 0 dBTP");
+        }
+
+        if !(1.0..=50.0).contains(&self.target_range) {
+            return invalid("target range must be between 1 and 50 LU");
+        }
+
+        Ok(())
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Runs loudnorm in measurement mode, the input is decoded into the null muxer.
+    pub(crate) async fn _analyze_loudness(
+        &self,
+        request: AnalyzeLoudnessRequest,
+    ) -> HandlerResult<AnalyzeLoudnessResponse> {
+        request.validate()?;
+
+        let targets = format!(
+            "I={}:TP={}:LRA={}",
+            request.target_loudness, request.target_true_peak, request.target_range
+        );
+
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let log = run_ffmpeg(&[
+            "-i".to_string(),
+            input,
+            "-map".to_string(),
+            format!("0:a:{}", request.stream_index),
+            "-af".to_string(),
+            format!("loudnorm={targets}:print_format=json"),
+            "-f".to_string(),
+            "null".to_string(),
+            "-".to_string(),
+        ])
+        .await?;
+
+        let measurement = parse_loudnorm_measurement(&log)
+            .ok_or_else(|| HandlerError::from("failed to parse loudnorm measurement"))?;
+
+        let integrated = finite(&measurement.input_i);
+        let true_peak = finite(&measurement.input_tp);
+        let threshold = finite(&measurement.input_thresh);
+
+        // Silence can't be normalized
+        let filter = match (integrated, true_peak, threshold) {
+            (Some(integrated), Some(true_peak), Some(threshold)) => Some(format!(
+                "loudnorm={targets}:measured_I={integrated}:measured_TP={true_peak}:\
+                 measured_LRA={}:measured_thresh={threshold}:offset={}:linear=true",
+                measurement.input_lra.trim(),
+                finite(&measurement.target_offset).unwrap_or_default()
+            )),
+            _ => None,
+        };
+
+        Ok(AnalyzeLoudnessResponse {
+            integrated,
+            range: finite(&measurement.input_lra).unwrap_or_default(),
+            true_peak,
+            threshold,
+            gain: integrated.map(|integrated| request.target_loudness - integrated),
+            filter,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_fingerprint_audio_request())]
+pub struct FingerprintAudioRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Seconds of audio fingerprinted from the start (AcoustID uses 120)
+    #[serde(default = "default_fingerprint_length")]
+    pub length: u32,
+}
+
+fn default_fingerprint_length() -> u32 {
+    120
+}
+
+fn example_fingerprint_audio_request() -> FingerprintAudioRequest {
+    FingerprintAudioRequest {
+        input: Url::parse("https://example.com/track.mp3").unwrap(),
+        length: default_fingerprint_length(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FingerprintAudioResponse {
+    /// Compressed, base64 encoded Chromaprint fingerprint (as produced by fpcalc)
+    pub fingerprint: String,
+
+    /// Duration of the input in seconds
+    pub duration: f64,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _fingerprint_audio(
+        &self,
+        request: FingerprintAudioRequest,
+    ) -> HandlerResult<FingerprintAudioResponse> {
+        if request.length == 0 {
+            return Err(TerminalError::new_with_code(400, "length must be positive").into());
+        }
+
+        require_muxer("chromaprint").await?;
+
+        let probe = self.probe(&request.input).await?;
+
+        if probe.stream("audio").is_none() {
+            return Err(TerminalError::new_with_code(400, "input has no audio stream").into());
+        }
+
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let fingerprint = run_ffmpeg_stdout(&[
+            "-i".to_string(),
+            input,
+            "-map".to_string(),
+            "0:a:0".to_string(),
+            "-t".to_string(),
+            request.length.to_string(),
+            "-f".to_string(),
+            "chromaprint".to_string(),
+            "-fp_format".to_string(),
+            "base64".to_string(),
+            "-".to_string(),
+        ])
+        .await?;
+
+        Ok(FingerprintAudioResponse {
+            fingerprint: fingerprint.trim().to_string(),
+            duration: probe.duration().unwrap_or_default(),
+        })
+    }
+}
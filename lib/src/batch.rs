@@ -0,0 +1,205 @@
+use futures::TryStreamExt;
+use globset::Glob;
+use opendal_util::OperatorFactory;
+use restate_sdk::context::RequestTarget;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::service::{ServiceImpl, parse_uri};
+use crate::watch::render;
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_process_prefix_request())]
+pub struct ProcessPrefixRequest {
+    /// Prefix listed for input files
+    pub location: Url,
+
+    /// Glob matched against the paths relative to the location
+    #[serde(default = "default_pattern")]
+    pub pattern: String,
+
+    /// FFmpeg handler processing the files (e.g. "convert_color")
+    pub handler: String,
+
+    /// Request sent to the handler, "{input}" and "{stem}" in strings are replaced
+    /// with the URL and the name (without extension) of the file
+    pub request: Value,
+
+    /// Number of files processed at the same time
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_pattern() -> String {
+    "**/*.{mp4,m4v,mov,mkv,mxf,ts,webm,avi,wav,mp3,m4a,aac,flac}".to_string()
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn example_process_prefix_request() -> ProcessPrefixRequest {
+    ProcessPrefixRequest {
+        location: Url::parse("s3://bucket/masters/").unwrap(),
+        pattern: "**/*.mov".to_string(),
+        handler: "mezzanine".to_string(),
+        request: serde_json::json!({
+            "input": "{input}",
+            "output": { "location": "s3://bucket/mezzanine/" },
+            "preset": "prores-hq",
+        }),
+        concurrency: default_concurrency(),
+    }
+}
+
+/// Outcome of processing a single file.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FileResult {
+    pub input: Url,
+
+    /// Response of the handler
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+
+    /// Error of the handler
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessPrefixResponse {
+    /// Number of files processed successfully
+    pub succeeded: usize,
+
+    /// Number of files that failed to process
+    pub failed: usize,
+
+    pub results: Vec<FileResult>,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Lists the files under a prefix matching a glob.
+    async fn list_prefix(&self, location: &Url, pattern: &str) -> HandlerResult<Vec<Url>> {
+        let matcher = Glob::new(pattern)
+            .map_err(|err| TerminalError::new_with_code(400, format!("invalid pattern: {err}")))?
+            .compile_matcher();
+
+        let (uri, mut path) = parse_uri(location.clone());
+
+        // Only directories can be listed
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+
+        let root = path.trim_start_matches('/');
+
+        let operator = self.factory.load(uri.as_str())?;
+
+        let entries: Vec<_> = operator
+            .lister_with(&path)
+            .recursive(true)
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut files: Vec<Url> = entries
+            .into_iter()
+            .filter(|entry| entry.metadata().is_file())
+            .filter(|entry| {
+                let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                matcher.is_match(relative.trim_start_matches('/'))
+            })
+            .map(|entry| {
+                let mut file = location.clone();
+                file.set_path(&format!("/{}", entry.path()));
+                file
+            })
+            .collect();
+
+        files.sort();
+
+        Ok(files)
+    }
+
+    /// Runs a request template against every matching file under a prefix.
+    ///
+    /// Files are processed as separate invocations of the handler (each of them admitted,
+    /// metered and retried on its own), in waves of `concurrency` files.
+    pub(crate) async fn _process_prefix(
+        &self,
+        ctx: &Context<'_>,
+        request: ProcessPrefixRequest,
+    ) -> HandlerResult<ProcessPrefixResponse> {
+        if request.concurrency == 0 {
+            return Err(TerminalError::new_with_code(400, "concurrency must be positive").into());
+        }
+
+        let files = ctx
+            .run(|| async {
+                Ok(Json(
+                    self.list_prefix(&request.location, &request.pattern)
+                        .await?,
+                ))
+            })
+            .await?
+            .into_inner();
+
+        let caller = self.limiter.caller(ctx.headers());
+
+        let mut results = Vec::with_capacity(files.len());
+
+        for wave in files.chunks(request.concurrency) {
+            let calls: Vec<_> = wave
+                .iter()
+                .map(|input| {
+                    let stem = input
+                        .path_segments()
+                        .and_then(|mut segments| segments.next_back())
+                        .map(|name| name.rsplit_once('.').map_or(name, |(stem, _)| stem))
+                        .unwrap_or_default()
+                        .to_string();
+
+                    ctx.request::<_, Json<Value>>(
+                        RequestTarget::service("FFmpeg", &request.handler),
+                        Json(render(&request.request, input.as_str(), &stem)),
+                    )
+                    .header(self.limiter.caller_header().to_string(), caller.clone())
+                    .call()
+                })
+                .collect();
+
+            for (input, call) in wave.iter().zip(calls) {
+                let (response, error) = match call.await {
+                    Ok(response) => (Some(response.into_inner()), None),
+                    Err(err) => (None, Some(err.to_string())),
+                };
+
+                results.push(FileResult {
+                    input: input.clone(),
+                    response,
+                    error,
+                });
+            }
+        }
+
+        let succeeded = results
+            .iter()
+            .filter(|result| result.error.is_none())
+            .count();
+
+        Ok(ProcessPrefixResponse {
+            succeeded,
+            failed: results.len() - succeeded,
+            results,
+        })
+    }
+}
@@ -0,0 +1,395 @@
+use std::sync::Mutex;
+
+use jiff::Timestamp;
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::capabilities::require_encoder;
+use crate::diagnose::last_progress;
+use crate::metering::BenchReport;
+use crate::service::{ServiceImpl, run_ffmpeg};
+
+/// Messages of ffmpeg falling back to software decoding.
+const HWACCEL_FAILURES: &[&str] = &[
+    "hwaccel initialisation returned error",
+    "Failed setup for format",
+    "No device available for decoder",
+    "Could not find hwaccel",
+];
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_benchmark_decode_request())]
+pub struct BenchmarkDecodeRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Index of the stream among the video streams of the input
+    #[serde(default)]
+    pub stream_index: u32,
+
+    /// Hardware decoding method (e.g. "cuda", "vaapi", "qsv", "videotoolbox" or "auto")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hwaccel: Option<String>,
+
+    /// Decoding threads (chosen by ffmpeg when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+
+    /// Seconds of the input decoded (the whole input when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+}
+
+fn example_benchmark_decode_request() -> BenchmarkDecodeRequest {
+    BenchmarkDecodeRequest {
+        input: Url::parse("s3://bucket/masters/feature.mov").unwrap(),
+        stream_index: 0,
+        hwaccel: Some("cuda".to_string()),
+        threads: None,
+        duration: Some(60.0),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchmarkDecodeResponse {
+    /// Decoder of the stream (e.g. "h264")
+    pub codec: String,
+
+    /// Frames decoded
+    pub frames: u64,
+
+    /// Wall-clock time of the run in seconds
+    pub seconds: f64,
+
+    /// Frames decoded per second
+    pub fps: f64,
+
+    /// CPU time (user and system) in seconds
+    pub cpu_seconds: f64,
+
+    /// Cores kept busy on average (CPU time over wall-clock time)
+    pub cpu_usage: f64,
+
+    /// Seconds of media decoded per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+
+    /// Hardware decoding was requested and ffmpeg did not fall back to software decoding
+    pub hardware: bool,
+}
+
+/// Pixels per second of 1080p30 video (the work of a score of 1).
+const REFERENCE_PIXEL_RATE: f64 = 1920.0 * 1080.0 * 30.0;
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_benchmark_encode_request())]
+pub struct BenchmarkEncodeRequest {
+    /// Video encoder (e.g. "libx264" or "h264_nvenc")
+    #[serde(default = "default_benchmark_codec")]
+    pub codec: String,
+
+    /// Encoder preset (the default of the encoder when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+
+    /// Width of the generated video in pixels
+    #[serde(default = "default_benchmark_width")]
+    pub width: u32,
+
+    /// Height of the generated video in pixels
+    #[serde(default = "default_benchmark_height")]
+    pub height: u32,
+
+    /// Frames per second of the generated video
+    #[serde(default = "default_benchmark_fps")]
+    pub fps: u32,
+
+    /// Seconds of video encoded
+    #[serde(default = "default_benchmark_duration")]
+    pub duration: f64,
+}
+
+fn default_benchmark_codec() -> String {
+    "libx264".to_string()
+}
+
+fn default_benchmark_width() -> u32 {
+    1920
+}
+
+fn default_benchmark_height() -> u32 {
+    1080
+}
+
+fn default_benchmark_fps() -> u32 {
+    30
+}
+
+fn default_benchmark_duration() -> f64 {
+    10.0
+}
+
+fn example_benchmark_encode_request() -> BenchmarkEncodeRequest {
+    BenchmarkEncodeRequest {
+        codec: default_benchmark_codec(),
+        preset: Some("medium".to_string()),
+        width: default_benchmark_width(),
+        height: default_benchmark_height(),
+        fps: default_benchmark_fps(),
+        duration: default_benchmark_duration(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchmarkEncodeResponse {
+    /// Frames encoded
+    pub frames: u64,
+
+    /// Wall-clock time of the run in seconds
+    pub seconds: f64,
+
+    /// Frames encoded per second
+    pub fps: f64,
+
+    /// CPU time (user and system) in seconds
+    pub cpu_seconds: f64,
+
+    /// Cores kept busy on average (CPU time over wall-clock time)
+    pub cpu_usage: f64,
+
+    /// Seconds of video encoded per second
+    pub speed: f64,
+
+    /// Score stored in the state of the worker
+    pub score: WorkerScore,
+}
+
+/// Encoding performance of a worker, used by the routing layer to weight job placement.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerScore {
+    /// Pixels encoded per second relative to real-time 1080p30 (1.0 keeps up with a live stream)
+    pub score: f64,
+
+    /// Encoder of the benchmark
+    pub codec: String,
+
+    pub measured_at: Timestamp,
+}
+
+/// Score of the last encode benchmark of the worker.
+#[derive(Debug, Default)]
+pub(crate) struct WorkerScores {
+    last: Mutex<Option<WorkerScore>>,
+}
+
+impl WorkerScores {
+    pub(crate) fn get(&self) -> Option<WorkerScore> {
+        self.last.lock().unwrap().clone()
+    }
+
+    fn set(&self, score: WorkerScore) {
+        *self.last.lock().unwrap() = Some(score);
+    }
+}
+
+impl BenchmarkEncodeRequest {
+    async fn validate(&self) -> HandlerResult<()> {
+        let invalid = |message: &str| -> HandlerResult<()> {
+            Err(TerminalError::new_with_code(400, message).into())
+        };
+
+        if self.width < 2
+            || self.height < 2
+            || !self.width.is_multiple_of(2)
+            || !self.height.is_multiple_of(2)
+            || self.width > 7680
+            || self.height > 4320
+        {
+            return invalid("width and height must be even and at most 7680x4320");
+        }
+
+        if !(1..=120).contains(&self.fps) {
+            return invalid("fps must be between 1 and 120");
+        }
+
+        if self.duration <= 0.0 || self.duration > 300.0 {
+            return invalid("duration must be positive and at most 300s");
+        }
+
+        require_encoder(&self.codec).await
+    }
+}
+
+/// Returns the frame count of a progress line ("frame=  120 fps= 24 ...").
+fn progress_frames(progress: &str) -> Option<u64> {
+    progress
+        .strip_prefix("frame=")?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Decodes a video stream into the null muxer, timing the run with -benchmark.
+    pub(crate) async fn _benchmark_decode(
+        &self,
+        request: BenchmarkDecodeRequest,
+    ) -> HandlerResult<BenchmarkDecodeResponse> {
+        if request.duration.is_some_and(|duration| duration <= 0.0) {
+            return Err(TerminalError::new_with_code(400, "duration must be positive").into());
+        }
+
+        let probe = self.probe(&request.input).await?;
+
+        let stream = probe
+            .streams
+            .iter()
+            .flatten()
+            .filter(|stream| stream.codec_type == "video")
+            .nth(request.stream_index as usize)
+            .ok_or_else(|| {
+                TerminalError::new_with_code(
+                    400,
+                    format!("input has no video stream {}", request.stream_index),
+                )
+            })?;
+
+        let mut args = Vec::new();
+
+        if let Some(hwaccel) = &request.hwaccel {
+            args.extend(["-hwaccel".to_string(), hwaccel.clone()]);
+        }
+
+        if let Some(threads) = request.threads {
+            args.extend(["-threads".to_string(), threads.to_string()]);
+        }
+
+        // Storage inputs are downloaded first: reading them isn't part of the measurement
+        let staging_dir = TempDir::new()?;
+
+        args.extend([
+            "-i".to_string(),
+            self.local_input(&request.input, staging_dir.path()).await?,
+        ]);
+
+        if let Some(duration) = request.duration {
+            args.extend(["-t".to_string(), format!("{duration:.3}")]);
+        }
+
+        args.extend([
+            "-map".to_string(),
+            format!("0:v:{}", request.stream_index),
+            "-f".to_string(),
+            "null".to_string(),
+            "-".to_string(),
+        ]);
+
+        let stderr = run_ffmpeg(&args).await?;
+
+        let bench = BenchReport::parse(&stderr)
+            .ok_or_else(|| TerminalError::new("ffmpeg reported no benchmark"))?;
+
+        let frames = last_progress(stderr.as_bytes())
+            .as_deref()
+            .and_then(progress_frames)
+            .unwrap_or_default();
+
+        let seconds = bench.real.max(f64::EPSILON);
+
+        let media_seconds = match (request.duration, probe.duration()) {
+            (Some(duration), Some(input)) => Some(duration.min(input)),
+            (duration, input) => duration.or(input),
+        };
+
+        Ok(BenchmarkDecodeResponse {
+            codec: stream.codec_name.clone().unwrap_or_default(),
+            frames,
+            seconds: bench.real,
+            fps: frames as f64 / seconds,
+            cpu_seconds: bench.cpu(),
+            cpu_usage: bench.cpu() / seconds,
+            speed: media_seconds.map(|media| media / seconds),
+            hardware: request.hwaccel.is_some()
+                && !HWACCEL_FAILURES
+                    .iter()
+                    .any(|failure| stderr.contains(failure)),
+        })
+    }
+
+    /// Encodes generated video into the null muxer, timing the run with -benchmark.
+    ///
+    /// The test pattern has moving parts and noise so encoders can't skip most of the work.
+    pub(crate) async fn _benchmark_encode(
+        &self,
+        request: BenchmarkEncodeRequest,
+    ) -> HandlerResult<BenchmarkEncodeResponse> {
+        request.validate().await?;
+
+        let mut args = vec![
+            "-f".to_string(),
+            "lavfi".to_string(),
+            "-i".to_string(),
+            format!(
+                "testsrc2=size={}x{}:rate={},noise=alls=20:allf=t",
+                request.width, request.height, request.fps
+            ),
+            "-t".to_string(),
+            format!("{:.3}", request.duration),
+            "-pix_fmt".to_string(),
+            "yuv420p".to_string(),
+            "-c:v".to_string(),
+            request.codec.clone(),
+        ];
+
+        if let Some(preset) = &request.preset {
+            args.extend(["-preset".to_string(), preset.clone()]);
+        }
+
+        args.extend(["-f".to_string(), "null".to_string(), "-".to_string()]);
+
+        let stderr = run_ffmpeg(&args).await?;
+
+        let bench = BenchReport::parse(&stderr)
+            .ok_or_else(|| TerminalError::new("ffmpeg reported no benchmark"))?;
+
+        let frames = last_progress(stderr.as_bytes())
+            .as_deref()
+            .and_then(progress_frames)
+            .unwrap_or_default();
+
+        let seconds = bench.real.max(f64::EPSILON);
+        let fps = frames as f64 / seconds;
+
+        let score = WorkerScore {
+            score: fps * f64::from(request.width) * f64::from(request.height)
+                / REFERENCE_PIXEL_RATE,
+            codec: request.codec,
+            measured_at: Timestamp::now(),
+        };
+
+        self.scores.set(score.clone());
+
+        Ok(BenchmarkEncodeResponse {
+            frames,
+            seconds: bench.real,
+            fps,
+            cpu_seconds: bench.cpu(),
+            cpu_usage: bench.cpu() / seconds,
+            speed: fps / f64::from(request.fps),
+            score,
+        })
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use restate_sdk::prelude::*;
+
+use crate::service::run_ffmpeg_stdout;
+
+static ENCODERS: OnceLock<HashSet<String>> = OnceLock::new();
+static MUXERS: OnceLock<HashSet<String>> = OnceLock::new();
+
+/// Parses the name column of the component lists printed by ffmpeg (e.g. `ffmpeg -encoders`).
+fn parse_component_list(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("--"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Lists the components of a kind (e.g. "-encoders") compiled into the ffmpeg binary.
+///
+/// The binary doesn't change while the service runs, so every list is only queried once.
+async fn components(
+    cache: &'static OnceLock<HashSet<String>>,
+    flag: &str,
+) -> HandlerResult<&'static HashSet<String>> {
+    if let Some(components) = cache.get() {
+        return Ok(components);
+    }
+
+    let output = run_ffmpeg_stdout(&[flag.to_string()]).await?;
+
+    Ok(cache.get_or_init(|| parse_component_list(&output)))
+}
+
+/// Returns the encoders compiled into the ffmpeg binary.
+pub(crate) async fn encoders() -> HandlerResult<&'static HashSet<String>> {
+    components(&ENCODERS, "-encoders").await
+}
+
+/// Returns the muxers compiled into the ffmpeg binary.
+pub(crate) async fn muxers() -> HandlerResult<&'static HashSet<String>> {
+    components(&MUXERS, "-muxers").await
+}
+
+/// Fails with a terminal error when the ffmpeg binary lacks the encoder.
+pub(crate) async fn require_encoder(encoder: &str) -> HandlerResult<()> {
+    if !encoders().await?.contains(encoder) {
+        return Err(TerminalError::new_with_code(
+            400,
+            format!("ffmpeg was built without the {encoder} encoder"),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Fails with a terminal error when the ffmpeg binary lacks the muxer.
+pub(crate) async fn require_muxer(muxer: &str) -> HandlerResult<()> {
+    if !muxers().await?.contains(muxer) {
+        return Err(TerminalError::new_with_code(
+            400,
+            format!("ffmpeg was built without the {muxer} muxer"),
+        )
+        .into());
+    }
+
+    Ok(())
+}
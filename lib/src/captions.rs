@@ -0,0 +1,153 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::inputs::input_arg;
+use crate::service::{FfmpegRequest, Output, ServiceImpl, escape_filter_value, input_stem};
+
+/// Text subtitle formats produced by the subtitle handlers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleFormat {
+    Webvtt,
+    Srt,
+    Ass,
+    Ttml,
+}
+
+impl SubtitleFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Webvtt => "vtt",
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Ass => "ass",
+            SubtitleFormat::Ttml => "ttml",
+        }
+    }
+
+    /// Returns the format matching a file extension.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "vtt" => Some(SubtitleFormat::Webvtt),
+            "srt" => Some(SubtitleFormat::Srt),
+            "ass" | "ssa" => Some(SubtitleFormat::Ass),
+            "ttml" | "dfxp" => Some(SubtitleFormat::Ttml),
+            _ => None,
+        }
+    }
+
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Webvtt => "webvtt",
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Ass => "ass",
+            SubtitleFormat::Ttml => "ttml",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_extract_captions_request())]
+pub struct ExtractCaptionsRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Formats the captions are converted to
+    #[serde(default = "default_formats")]
+    pub formats: Vec<SubtitleFormat>,
+}
+
+fn default_formats() -> Vec<SubtitleFormat> {
+    vec![SubtitleFormat::Webvtt]
+}
+
+fn example_extract_captions_request() -> ExtractCaptionsRequest {
+    ExtractCaptionsRequest {
+        input: Url::parse("https://example.com/broadcast.ts").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/captions/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        formats: vec![SubtitleFormat::Webvtt, SubtitleFormat::Srt],
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractCaptionsResponse {
+    /// Locations of the caption files
+    pub outputs: Vec<Url>,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _extract_captions(
+        &self,
+        request: ExtractCaptionsRequest,
+    ) -> HandlerResult<ExtractCaptionsResponse> {
+        if request.formats.is_empty() {
+            return Err(
+                TerminalError::new_with_code(400, "at least one format is required").into(),
+            );
+        }
+
+        let stem = input_stem(&request.input);
+        let mut inputs = Vec::new();
+
+        // Captions embedded in the video stream (A/53 side data) are only exposed
+        // as a subtitle stream by the movie source with the subcc output
+        let mut args = vec![
+            "-f".to_string(),
+            "lavfi".to_string(),
+            "-i".to_string(),
+            format!(
+                "movie={}[out0+subcc]",
+                escape_filter_value(&input_arg(&request.input, &mut inputs))
+            ),
+        ];
+
+        let mut filenames = Vec::new();
+
+        for format in &request.formats {
+            let filename = format!("{stem}.{}", format.extension());
+
+            args.extend([
+                "-map".to_string(),
+                "0:s".to_string(),
+                "-c:s".to_string(),
+                format.encoder().to_string(),
+                filename.clone(),
+            ]);
+
+            filenames.push(filename);
+        }
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(ExtractCaptionsResponse {
+            outputs: filenames
+                .iter()
+                .map(|filename| request.output.file_url(filename))
+                .collect(),
+        })
+    }
+}
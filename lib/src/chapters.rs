@@ -0,0 +1,204 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::highlights::{default_scene_threshold, scene_changes};
+use crate::service::{Output, ServiceImpl, input_extension, input_stem, run_ffmpeg_in};
+
+/// A chapter of the media file.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Chapter {
+    /// Start of the chapter in seconds
+    pub start: f64,
+
+    /// End of the chapter in seconds
+    pub end: f64,
+
+    pub title: String,
+}
+
+/// Escapes special characters of the FFMETADATA format.
+fn escape_metadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Renders chapters as an FFMETADATA file, as read by `-map_chapters`.
+pub(crate) fn ffmetadata_chapters(chapters: &[Chapter]) -> String {
+    let mut metadata = ";FFMETADATA1\n".to_string();
+
+    for chapter in chapters {
+        metadata.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (chapter.start * 1000.0).round() as u64,
+            (chapter.end * 1000.0).round() as u64,
+            escape_metadata(&chapter.title),
+        ));
+    }
+
+    metadata
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_generate_chapters_request())]
+pub struct GenerateChaptersRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Write the chapters into a copy of the input stored here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Output>,
+
+    /// Scene change threshold (0-1)
+    #[serde(default = "default_scene_threshold")]
+    pub scene_threshold: f64,
+
+    /// Minimum length of a chapter in seconds
+    #[serde(default = "default_min_length")]
+    pub min_length: f64,
+
+    /// Chapter titles are numbered after this prefix
+    #[serde(default = "default_title_prefix")]
+    pub title_prefix: String,
+}
+
+fn default_min_length() -> f64 {
+    60.0
+}
+
+fn default_title_prefix() -> String {
+    "Chapter".to_string()
+}
+
+fn example_generate_chapters_request() -> GenerateChaptersRequest {
+    GenerateChaptersRequest {
+        input: Url::parse("https://example.com/lecture.mp4").unwrap(),
+        output: Some(Output {
+            location: Url::parse("s3://bucket/chaptered/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        }),
+        scene_threshold: default_scene_threshold(),
+        min_length: default_min_length(),
+        title_prefix: default_title_prefix(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateChaptersResponse {
+    pub chapters: Vec<Chapter>,
+
+    /// Location of the file with chapters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Url>,
+}
+
+/// Turns scene cuts into chapter boundaries at least `min_length` apart.
+fn chapter_boundaries(cuts: &[f64], duration: f64, min_length: f64) -> Vec<f64> {
+    let mut boundaries = vec![0.0];
+
+    for &cut in cuts {
+        let last = *boundaries.last().unwrap_or(&0.0);
+
+        // The last chapter has to be long enough as well
+        if cut - last >= min_length && duration - cut >= min_length {
+            boundaries.push(cut);
+        }
+    }
+
+    boundaries.push(duration);
+    boundaries
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _generate_chapters(
+        &self,
+        request: GenerateChaptersRequest,
+    ) -> HandlerResult<GenerateChaptersResponse> {
+        let probe = self.probe(&request.input).await?;
+
+        let Some(duration) = probe.duration() else {
+            return Err(TerminalError::new_with_code(400, "unknown input duration").into());
+        };
+
+        // Both the scene detection and the remux read the input: storage inputs are downloaded once
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let cuts = scene_changes(&input, request.scene_threshold).await?;
+
+        let chapters: Vec<Chapter> = chapter_boundaries(&cuts, duration, request.min_length)
+            .windows(2)
+            .enumerate()
+            .map(|(i, bounds)| Chapter {
+                start: bounds[0],
+                end: bounds[1],
+                title: format!("{} {}", request.title_prefix, i + 1),
+            })
+            .collect();
+
+        let Some(output) = &request.output else {
+            return Ok(GenerateChaptersResponse {
+                chapters,
+                output: None,
+            });
+        };
+
+        let work_dir = TempDir::new()?;
+
+        let metadata = staging_dir.path().join("chapters.txt");
+        tokio::fs::write(&metadata, ffmetadata_chapters(&chapters)).await?;
+
+        let filename = format!(
+            "{}.{}",
+            input_stem(&request.input),
+            input_extension(&request.input).unwrap_or_else(|| "mp4".to_string())
+        );
+
+        run_ffmpeg_in(
+            work_dir.path(),
+            &[
+                "-i".to_string(),
+                input,
+                "-f".to_string(),
+                "ffmetadata".to_string(),
+                "-i".to_string(),
+                metadata.to_string_lossy().to_string(),
+                "-map".to_string(),
+                "0".to_string(),
+                "-map_metadata".to_string(),
+                "0".to_string(),
+                "-map_chapters".to_string(),
+                "1".to_string(),
+                "-c".to_string(),
+                "copy".to_string(),
+                filename.clone(),
+            ],
+        )
+        .await?;
+
+        self.upload(work_dir.path(), output).await?;
+
+        Ok(GenerateChaptersResponse {
+            chapters,
+            output: Some(output.file_url(&filename)),
+        })
+    }
+}
@@ -0,0 +1,182 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::encode::VideoEncoding;
+use crate::inputs::input_arg;
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_extension, input_stem};
+
+/// How the clip is cut out of the input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipMode {
+    /// Copy the streams from the keyframe at or before the start (no quality loss, the clip may
+    /// start a bit earlier)
+    #[default]
+    Fast,
+    /// Re-encode the clip so that it starts at the exact frame
+    Accurate,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_clip_request())]
+pub struct ClipRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Start of the clip in the input in seconds
+    #[serde(default)]
+    pub start: f64,
+
+    /// End of the clip in the input in seconds (the end of the input when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<f64>,
+
+    #[serde(default)]
+    pub mode: ClipMode,
+
+    /// Video encoding settings (accurate mode only)
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output container extension (the one of the input when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+}
+
+fn example_clip_request() -> ClipRequest {
+    ClipRequest {
+        input: Url::parse("s3://bucket/broadcasts/match.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/clips/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        start: 1834.2,
+        end: Some(1861.0),
+        mode: ClipMode::Accurate,
+        video: VideoEncoding::default(),
+        container: None,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipResponse {
+    /// Location of the clip
+    pub output: Url,
+
+    /// Length of the clip in seconds (shorter than requested at the end of the input)
+    pub duration: f64,
+}
+
+impl ClipRequest {
+    async fn validate(&self) -> HandlerResult<()> {
+        if self.start < 0.0 {
+            return Err(TerminalError::new_with_code(400, "start must not be negative").into());
+        }
+
+        if self.end.is_some_and(|end| end <= self.start) {
+            return Err(TerminalError::new_with_code(400, "end must be after start").into());
+        }
+
+        match self.mode {
+            ClipMode::Fast => Ok(()),
+            ClipMode::Accurate => self.video.validate().await,
+        }
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Cuts a clip out of the input.
+    ///
+    /// Seeking before the input (-ss before -i) jumps to the keyframe at or before the start:
+    /// copied streams start there, re-encoded ones drop the frames up to the start.
+    pub(crate) async fn _clip(&self, request: ClipRequest) -> HandlerResult<ClipResponse> {
+        request.validate().await?;
+
+        let probe = self.probe(&request.input).await?;
+
+        let input_duration = probe.duration();
+
+        if let Some(input_duration) = input_duration.filter(|duration| request.start >= *duration) {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("start is past the end of the input ({input_duration:.3}s)"),
+            )
+            .into());
+        }
+
+        let end = match (request.end, input_duration) {
+            (Some(end), Some(input_duration)) => Some(end.min(input_duration)),
+            (end, input_duration) => end.or(input_duration),
+        };
+
+        let container = request
+            .container
+            .clone()
+            .or_else(|| input_extension(&request.input))
+            .unwrap_or_else(|| "mp4".to_string());
+
+        let filename = format!("{}_clip.{container}", input_stem(&request.input));
+
+        let mut args = vec!["-ss".to_string(), format!("{:.6}", request.start)];
+
+        if let Some(end) = end {
+            args.extend(["-t".to_string(), format!("{:.6}", end - request.start)]);
+        }
+
+        let mut inputs = Vec::new();
+        args.extend(["-i".to_string(), input_arg(&request.input, &mut inputs)]);
+
+        match request.mode {
+            ClipMode::Fast => args.extend([
+                "-map".to_string(),
+                "0".to_string(),
+                "-c".to_string(),
+                "copy".to_string(),
+                // Copied packets keep timestamps from before the start otherwise
+                "-avoid_negative_ts".to_string(),
+                "make_zero".to_string(),
+            ]),
+            ClipMode::Accurate => {
+                args.extend([
+                    "-map".to_string(),
+                    "0:v:0?".to_string(),
+                    "-map".to_string(),
+                    "0:a?".to_string(),
+                ]);
+                args.extend(request.video.args());
+                args.extend(["-c:a".to_string(), "aac".to_string()]);
+            }
+        }
+
+        args.push(filename.clone());
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(ClipResponse {
+            output: request.output.file_url(&filename),
+            duration: end.map_or(0.0, |end| end - request.start),
+        })
+    }
+}
@@ -0,0 +1,409 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::encode::{VideoCodec, VideoEncoding, default_container};
+use crate::inputs::{Input, input_arg};
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorPrimaries {
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+impl ColorPrimaries {
+    fn zscale(&self) -> &'static str {
+        match self {
+            ColorPrimaries::Bt601 => "170m",
+            ColorPrimaries::Bt709 => "709",
+            ColorPrimaries::Bt2020 => "2020",
+        }
+    }
+
+    fn tag(&self) -> &'static str {
+        match self {
+            ColorPrimaries::Bt601 => "smpte170m",
+            ColorPrimaries::Bt709 => "bt709",
+            ColorPrimaries::Bt2020 => "bt2020",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorTransfer {
+    Bt601,
+    Bt709,
+    Bt2020,
+    /// SMPTE ST 2084 (HDR10)
+    Pq,
+    /// ARIB STD-B67 (hybrid log-gamma)
+    Hlg,
+}
+
+impl ColorTransfer {
+    fn zscale(&self) -> &'static str {
+        match self {
+            ColorTransfer::Bt601 => "601",
+            ColorTransfer::Bt709 => "709",
+            ColorTransfer::Bt2020 => "2020_10",
+            ColorTransfer::Pq => "smpte2084",
+            ColorTransfer::Hlg => "arib-std-b67",
+        }
+    }
+
+    fn tag(&self) -> &'static str {
+        match self {
+            ColorTransfer::Bt601 => "smpte170m",
+            ColorTransfer::Bt709 => "bt709",
+            ColorTransfer::Bt2020 => "bt2020-10",
+            ColorTransfer::Pq => "smpte2084",
+            ColorTransfer::Hlg => "arib-std-b67",
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, ColorTransfer::Pq | ColorTransfer::Hlg)
+    }
+
+    /// Parses the transfer characteristics reported by ffprobe.
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "smpte170m" => Some(ColorTransfer::Bt601),
+            "bt709" => Some(ColorTransfer::Bt709),
+            "bt2020-10" | "bt2020-12" => Some(ColorTransfer::Bt2020),
+            "smpte2084" => Some(ColorTransfer::Pq),
+            "arib-std-b67" => Some(ColorTransfer::Hlg),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMatrix {
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+impl ColorMatrix {
+    fn zscale(&self) -> &'static str {
+        match self {
+            ColorMatrix::Bt601 => "170m",
+            ColorMatrix::Bt709 => "709",
+            ColorMatrix::Bt2020 => "2020_ncl",
+        }
+    }
+
+    fn tag(&self) -> &'static str {
+        match self {
+            ColorMatrix::Bt601 => "smpte170m",
+            ColorMatrix::Bt709 => "bt709",
+            ColorMatrix::Bt2020 => "bt2020nc",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorRange {
+    /// Limited (TV) range
+    #[default]
+    Limited,
+    /// Full (PC) range
+    Full,
+}
+
+impl ColorRange {
+    fn zscale(&self) -> &'static str {
+        match self {
+            ColorRange::Limited => "limited",
+            ColorRange::Full => "full",
+        }
+    }
+
+    fn tag(&self) -> &'static str {
+        match self {
+            ColorRange::Limited => "tv",
+            ColorRange::Full => "pc",
+        }
+    }
+}
+
+/// Color properties of the source.
+///
+/// Fields left empty are taken from the tags of the input stream.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceColor {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primaries: Option<ColorPrimaries>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer: Option<ColorTransfer>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<ColorMatrix>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<ColorRange>,
+}
+
+/// Color properties of the output.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetColor {
+    pub primaries: ColorPrimaries,
+    pub transfer: ColorTransfer,
+    pub matrix: ColorMatrix,
+
+    #[serde(default)]
+    pub range: ColorRange,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_convert_color_request())]
+pub struct ConvertColorRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Color properties of the input (overrides input tags)
+    #[serde(default)]
+    pub source: SourceColor,
+
+    /// Color properties of the output
+    pub target: TargetColor,
+
+    /// Pixel format of the output (defaults to 10-bit for BT.2020 and HDR targets)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pix_fmt: Option<String>,
+
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output container extension
+    #[serde(default = "default_container")]
+    pub container: String,
+}
+
+fn example_convert_color_request() -> ConvertColorRequest {
+    ConvertColorRequest {
+        input: Url::parse("s3://bucket/hdr.mov").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/sdr/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        source: SourceColor {
+            primaries: Some(ColorPrimaries::Bt2020),
+            transfer: Some(ColorTransfer::Pq),
+            matrix: Some(ColorMatrix::Bt2020),
+            range: None,
+        },
+        target: TargetColor {
+            primaries: ColorPrimaries::Bt709,
+            transfer: ColorTransfer::Bt709,
+            matrix: ColorMatrix::Bt709,
+            range: ColorRange::Limited,
+        },
+        pix_fmt: None,
+        video: VideoEncoding::default(),
+        container: default_container(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertColorResponse {
+    /// Location of the converted file
+    pub output: Url,
+}
+
+impl ConvertColorRequest {
+    /// Builds the zscale filter chain converting the source to the target color space.
+    ///
+    /// Conversions from HDR to SDR go through linear light and get tone mapped,
+    /// since zscale alone would clip highlights.
+    fn filter(&self) -> String {
+        let mut input = Vec::new();
+
+        if let Some(primaries) = self.source.primaries {
+            input.push(format!("pin={}", primaries.zscale()));
+        }
+        if let Some(transfer) = self.source.transfer {
+            input.push(format!("tin={}", transfer.zscale()));
+        }
+        if let Some(matrix) = self.source.matrix {
+            input.push(format!("min={}", matrix.zscale()));
+        }
+        if let Some(range) = self.source.range {
+            input.push(format!("rin={}", range.zscale()));
+        }
+
+        let target = &self.target;
+        let output = format!(
+            "p={}:t={}:m={}:r={}",
+            target.primaries.zscale(),
+            target.transfer.zscale(),
+            target.matrix.zscale(),
+            target.range.zscale(),
+        );
+
+        let tonemap = self.source.transfer.is_some_and(|t| t.is_hdr()) && !target.transfer.is_hdr();
+
+        let mut chain = Vec::new();
+
+        if tonemap {
+            input.push("t=linear".to_string());
+            input.push("npl=100".to_string());
+            chain.push(format!("zscale={}", input.join(":")));
+            chain.push("format=gbrpf32le".to_string());
+            chain.push(format!("zscale=p={}", target.primaries.zscale()));
+            chain.push("tonemap=hable:desat=0".to_string());
+            chain.push(format!("zscale={output}"));
+        } else {
+            input.push(output);
+            chain.push(format!("zscale={}", input.join(":")));
+        }
+
+        chain.push(format!("format={}", self.pix_fmt()));
+
+        chain.join(",")
+    }
+
+    fn pix_fmt(&self) -> String {
+        self.pix_fmt.clone().unwrap_or_else(|| {
+            if self.target.transfer.is_hdr() || self.target.primaries == ColorPrimaries::Bt2020 {
+                "yuv420p10le".to_string()
+            } else {
+                "yuv420p".to_string()
+            }
+        })
+    }
+
+    fn args(&self, filename: &str, inputs: &mut Vec<Input>) -> Vec<String> {
+        let target = &self.target;
+
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&self.input, inputs),
+            "-vf".to_string(),
+            self.filter(),
+        ];
+
+        args.extend(self.video.args());
+
+        // Tag the output so players don't have to guess
+        args.extend([
+            "-color_primaries".to_string(),
+            target.primaries.tag().to_string(),
+            "-color_trc".to_string(),
+            target.transfer.tag().to_string(),
+            "-colorspace".to_string(),
+            target.matrix.tag().to_string(),
+            "-color_range".to_string(),
+            target.range.tag().to_string(),
+        ]);
+
+        // libx265 does not pick up the VUI from the codec context for HDR signaling
+        if self.video.codec == VideoCodec::H265 {
+            args.extend([
+                "-x265-params".to_string(),
+                format!(
+                    "colorprim={}:transfer={}:colormatrix={}:range={}",
+                    target.primaries.tag(),
+                    target.transfer.tag(),
+                    target.matrix.tag(),
+                    target.range.zscale(),
+                ),
+            ]);
+        }
+
+        args.extend(["-c:a".to_string(), "copy".to_string(), filename.to_string()]);
+
+        args
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _convert_color(
+        &self,
+        mut request: ConvertColorRequest,
+    ) -> HandlerResult<ConvertColorResponse> {
+        request.video.validate().await?;
+
+        // Tone mapping depends on the source transfer: untagged requests take it from the input
+        if request.source.transfer.is_none() {
+            let probe = self.probe(&request.input).await?;
+
+            request.source.transfer = probe
+                .stream("video")
+                .and_then(|stream| stream.color_transfer.as_deref())
+                .and_then(ColorTransfer::from_tag);
+        }
+
+        let filename = format!("{}.{}", input_stem(&request.input), request.container);
+        let mut inputs = Vec::new();
+
+        self._ffmpeg(FfmpegRequest {
+            args: request.args(&filename, &mut inputs),
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(ConvertColorResponse {
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_stage_storage_inputs() {
+        let request = example_convert_color_request();
+        let mut inputs = Vec::new();
+
+        let args = request.args("hdr.mp4", &mut inputs);
+
+        assert_eq!(args[..2], ["-i", "{input0}"]);
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].location, request.input);
+    }
+
+    #[test]
+    fn probed_hdr_transfer_tonemaps() {
+        let mut request = example_convert_color_request();
+        request.source = SourceColor {
+            transfer: ColorTransfer::from_tag("arib-std-b67"),
+            ..Default::default()
+        };
+
+        assert!(request.filter().contains("tonemap=hable"));
+
+        request.source.transfer = ColorTransfer::from_tag("bt709");
+
+        assert!(!request.filter().contains("tonemap"));
+    }
+}
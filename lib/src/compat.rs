@@ -0,0 +1,303 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::inputs::input_arg;
+use crate::service::{FfmpegRequest, Output, ServiceImpl, Stream, input_stem};
+use crate::streams::{StreamSelector, StreamType, typed_streams};
+
+/// Playback capabilities of a class of devices.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeviceProfile {
+    /// Every browser and mobile device: 8-bit 4:2:0 H.264 up to level 4.2 with AAC or MP3 audio
+    #[default]
+    WebBaseline,
+    /// Current browsers: H.264, VP9 or AV1 video with AAC, MP3 or Opus audio
+    WebModern,
+    /// Smart TVs and set-top boxes: H.264 or HEVC video with AAC, AC-3 or E-AC-3 audio
+    Tv,
+}
+
+impl DeviceProfile {
+    fn supports_video(&self, stream: &Stream) -> bool {
+        let codec = stream.codec_name.as_deref().unwrap_or_default();
+        let pix_fmt = stream.pix_fmt.as_deref().unwrap_or_default();
+
+        match self {
+            DeviceProfile::WebBaseline => {
+                codec == "h264"
+                    && pix_fmt == "yuv420p"
+                    && stream.level.is_some_and(|level| level <= 42)
+                    && matches!(
+                        stream.profile.as_deref(),
+                        Some("Constrained Baseline" | "Baseline" | "Main" | "High")
+                    )
+            }
+            DeviceProfile::WebModern => {
+                matches!(codec, "h264" | "vp9" | "av1")
+                    && matches!(pix_fmt, "yuv420p" | "yuv420p10le")
+            }
+            DeviceProfile::Tv => {
+                matches!(codec, "h264" | "hevc") && matches!(pix_fmt, "yuv420p" | "yuv420p10le")
+            }
+        }
+    }
+
+    fn supports_audio(&self, stream: &Stream) -> bool {
+        let codec = stream.codec_name.as_deref().unwrap_or_default();
+
+        match self {
+            DeviceProfile::WebBaseline => {
+                matches!(codec, "aac" | "mp3") && stream.channels.is_some_and(|c| c <= 2)
+            }
+            DeviceProfile::WebModern => matches!(codec, "aac" | "mp3" | "opus"),
+            DeviceProfile::Tv => matches!(codec, "aac" | "ac3" | "eac3"),
+        }
+    }
+
+    /// Encoder arguments for video streams the profile can't play.
+    fn video_args(&self, specifier: &str) -> Vec<String> {
+        let mut args = vec![
+            format!("-c:{specifier}"),
+            "libx264".to_string(),
+            format!("-pix_fmt:{specifier}"),
+            "yuv420p".to_string(),
+        ];
+
+        if *self == DeviceProfile::WebBaseline {
+            args.extend([
+                format!("-profile:{specifier}"),
+                "high".to_string(),
+                format!("-level:{specifier}"),
+                "4.1".to_string(),
+            ]);
+        }
+
+        args
+    }
+
+    /// Encoder arguments for audio streams the profile can't play.
+    fn audio_args(&self, specifier: &str, bitrate: &str) -> Vec<String> {
+        let mut args = vec![
+            format!("-c:{specifier}"),
+            "aac".to_string(),
+            format!("-b:{specifier}"),
+            bitrate.to_string(),
+        ];
+
+        if *self == DeviceProfile::WebBaseline {
+            args.extend([format!("-ac:{specifier}"), "2".to_string()]);
+        }
+
+        args
+    }
+}
+
+/// How a stream of the input ends up in the compatible output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamAction {
+    Copy,
+    Transcode,
+    Drop,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_make_compatible_request())]
+pub struct MakeCompatibleRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Devices the output must play on
+    #[serde(default)]
+    pub profile: DeviceProfile,
+
+    /// Constant rate factor of transcoded video streams
+    #[serde(default = "default_crf")]
+    pub crf: u8,
+
+    /// Encoder speed preset of transcoded video streams
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+
+    /// Bitrate of transcoded audio streams
+    #[serde(default = "default_audio_bitrate")]
+    pub audio_bitrate: String,
+}
+
+fn default_crf() -> u8 {
+    21
+}
+
+fn default_audio_bitrate() -> String {
+    "192k".to_string()
+}
+
+fn example_make_compatible_request() -> MakeCompatibleRequest {
+    MakeCompatibleRequest {
+        input: Url::parse("https://example.com/hevc.mkv").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/compatible/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        profile: DeviceProfile::WebBaseline,
+        crf: default_crf(),
+        preset: None,
+        audio_bitrate: default_audio_bitrate(),
+    }
+}
+
+/// Decision taken for a stream of the input.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamDecision {
+    #[serde(flatten)]
+    pub stream: StreamSelector,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+
+    pub action: StreamAction,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MakeCompatibleResponse {
+    /// What happened to each stream of the input
+    pub streams: Vec<StreamDecision>,
+
+    /// Location of the compatible file
+    pub output: Url,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _make_compatible(
+        &self,
+        request: MakeCompatibleRequest,
+    ) -> HandlerResult<MakeCompatibleResponse> {
+        let probe = self.probe(&request.input).await?;
+
+        let filename = format!("{}.mp4", input_stem(&request.input));
+
+        let mut inputs = Vec::new();
+        let mut args = vec!["-i".to_string(), input_arg(&request.input, &mut inputs)];
+        let mut codec_args = Vec::new();
+        let mut decisions = Vec::new();
+
+        // Output streams are counted separately since dropped streams shift the indexes
+        let (mut video, mut audio, mut subtitle) = (0, 0, 0);
+
+        for (selector, stream) in typed_streams(&probe) {
+            let attached_pic = stream
+                .disposition
+                .as_ref()
+                .is_some_and(|d| d.attached_pic == 1);
+
+            let action = match selector.kind {
+                StreamType::Video if attached_pic => StreamAction::Drop,
+                StreamType::Video => {
+                    let specifier = format!("v:{video}");
+                    video += 1;
+
+                    if request.profile.supports_video(stream) {
+                        codec_args.extend([format!("-c:{specifier}"), "copy".to_string()]);
+
+                        // Apple devices refuse HEVC tagged as hev1
+                        if stream.codec_name.as_deref() == Some("hevc") {
+                            codec_args.extend([format!("-tag:{specifier}"), "hvc1".to_string()]);
+                        }
+
+                        StreamAction::Copy
+                    } else {
+                        codec_args.extend(request.profile.video_args(&specifier));
+                        codec_args.extend([format!("-crf:{specifier}"), request.crf.to_string()]);
+                        if let Some(preset) = &request.preset {
+                            codec_args.extend([format!("-preset:{specifier}"), preset.clone()]);
+                        }
+                        StreamAction::Transcode
+                    }
+                }
+                StreamType::Audio => {
+                    let specifier = format!("a:{audio}");
+                    audio += 1;
+
+                    if request.profile.supports_audio(stream) {
+                        codec_args.extend([format!("-c:{specifier}"), "copy".to_string()]);
+                        StreamAction::Copy
+                    } else {
+                        codec_args.extend(
+                            request
+                                .profile
+                                .audio_args(&specifier, &request.audio_bitrate),
+                        );
+                        StreamAction::Transcode
+                    }
+                }
+                // MP4 only carries text subtitles as mov_text, bitmap subtitles can't be converted
+                StreamType::Subtitle => match stream.codec_name.as_deref() {
+                    Some("mov_text") => {
+                        codec_args.extend([format!("-c:s:{subtitle}"), "copy".to_string()]);
+                        subtitle += 1;
+                        StreamAction::Copy
+                    }
+                    Some("subrip" | "ass" | "ssa" | "webvtt" | "text") => {
+                        codec_args.extend([format!("-c:s:{subtitle}"), "mov_text".to_string()]);
+                        subtitle += 1;
+                        StreamAction::Transcode
+                    }
+                    _ => StreamAction::Drop,
+                },
+                StreamType::Data | StreamType::Attachment => StreamAction::Drop,
+            };
+
+            if action != StreamAction::Drop {
+                args.extend(["-map".to_string(), format!("0:{}", selector.specifier())]);
+            }
+
+            decisions.push(StreamDecision {
+                stream: selector,
+                codec: stream.codec_name.clone(),
+                action,
+            });
+        }
+
+        if video + audio == 0 {
+            return Err(TerminalError::new_with_code(400, "input has no audio or video").into());
+        }
+
+        args.extend(codec_args);
+        args.extend([
+            "-movflags".to_string(),
+            "+faststart".to_string(),
+            filename.clone(),
+        ]);
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(MakeCompatibleResponse {
+            streams: decisions,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
@@ -0,0 +1,698 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::encode::{VideoEncoding, default_container};
+use crate::inputs::input_arg;
+use crate::service::{
+    FfmpegRequest, FfprobeResponse, Output, ServiceImpl, input_extension, input_stem,
+};
+
+/// Visual transition between two clips (see the xfade filter).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TransitionKind {
+    #[default]
+    Fade,
+    Dissolve,
+    FadeBlack,
+    FadeWhite,
+    WipeLeft,
+    WipeRight,
+    WipeUp,
+    WipeDown,
+    SlideLeft,
+    SlideRight,
+    SlideUp,
+    SlideDown,
+    CircleOpen,
+    CircleClose,
+    Radial,
+    Pixelize,
+}
+
+impl TransitionKind {
+    fn xfade(&self) -> &'static str {
+        match self {
+            TransitionKind::Fade => "fade",
+            TransitionKind::Dissolve => "dissolve",
+            TransitionKind::FadeBlack => "fadeblack",
+            TransitionKind::FadeWhite => "fadewhite",
+            TransitionKind::WipeLeft => "wipeleft",
+            TransitionKind::WipeRight => "wiperight",
+            TransitionKind::WipeUp => "wipeup",
+            TransitionKind::WipeDown => "wipedown",
+            TransitionKind::SlideLeft => "slideleft",
+            TransitionKind::SlideRight => "slideright",
+            TransitionKind::SlideUp => "slideup",
+            TransitionKind::SlideDown => "slidedown",
+            TransitionKind::CircleOpen => "circleopen",
+            TransitionKind::CircleClose => "circleclose",
+            TransitionKind::Radial => "radial",
+            TransitionKind::Pixelize => "pixelize",
+        }
+    }
+}
+
+/// Shape of the audio crossfade (see the curves of the acrossfade filter).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Easing {
+    Linear,
+    /// Constant power: the loudness stays even through the crossfade
+    #[default]
+    Sine,
+    Quadratic,
+    Cubic,
+    Exponential,
+    Logarithmic,
+}
+
+impl Easing {
+    fn curve(&self) -> &'static str {
+        match self {
+            Easing::Linear => "tri",
+            Easing::Sine => "qsin",
+            Easing::Quadratic => "qua",
+            Easing::Cubic => "cub",
+            Easing::Exponential => "exp",
+            Easing::Logarithmic => "log",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Transition {
+    #[serde(default)]
+    pub kind: TransitionKind,
+
+    /// Duration of the transition in seconds (clips overlap for this long)
+    #[serde(default = "default_transition_duration")]
+    pub duration: f64,
+
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+fn default_transition_duration() -> f64 {
+    1.0
+}
+
+/// How the clips are joined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConcatMethod {
+    /// Concat demuxer: the streams are copied (the clips need the same codecs and properties)
+    Demuxer,
+    /// Concat filter: the clips are decoded, conformed and encoded again
+    Filter,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_concat_request())]
+pub struct ConcatRequest {
+    /// Clips joined in order
+    pub inputs: Vec<Url>,
+
+    pub output: Output,
+
+    /// Transition between consecutive clips (hard cuts when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transition: Option<Transition>,
+
+    /// Output width in pixels (the width of the first clip when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+
+    /// Output height in pixels (the height of the first clip when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+
+    /// Frame rate every clip is converted to
+    #[serde(default = "default_frame_rate")]
+    pub frame_rate: f64,
+
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output container extension
+    #[serde(default = "default_container")]
+    pub container: String,
+
+    /// How the clips are joined (the filter when empty)
+    ///
+    /// The demuxer copies the streams of compatible clips: the frame rate and the video encoding
+    /// are not applied.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<ConcatMethod>,
+}
+
+fn default_frame_rate() -> f64 {
+    30.0
+}
+
+fn example_concat_request() -> ConcatRequest {
+    ConcatRequest {
+        inputs: vec![
+            Url::parse("s3://bucket/clips/intro.mp4").unwrap(),
+            Url::parse("s3://bucket/clips/interview.mp4").unwrap(),
+            Url::parse("s3://bucket/clips/outro.mp4").unwrap(),
+        ],
+        output: Output {
+            location: Url::parse("s3://bucket/edits/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        transition: Some(Transition {
+            kind: TransitionKind::Dissolve,
+            duration: default_transition_duration(),
+            easing: Easing::Sine,
+        }),
+        width: None,
+        height: None,
+        frame_rate: default_frame_rate(),
+        video: VideoEncoding::default(),
+        container: default_container(),
+        method: None,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConcatResponse {
+    /// Location of the joined file
+    pub output: Url,
+
+    /// Duration of the joined file in seconds
+    pub duration: f64,
+
+    /// Times the transitions start at in seconds
+    pub offsets: Vec<f64>,
+
+    pub method: ConcatMethod,
+}
+
+/// Probed properties of a clip.
+pub(crate) struct Clip {
+    pub duration: f64,
+    pub audio: bool,
+}
+
+impl ConcatRequest {
+    fn validate(&self) -> Result<(), TerminalError> {
+        let invalid = |message: &str| Err(TerminalError::new_with_code(400, message));
+
+        if self.inputs.len() < 2 {
+            return invalid("at least two inputs are required");
+        }
+
+        if self.width.is_some_and(|width| width < 2 || width % 2 != 0)
+            || self
+                .height
+                .is_some_and(|height| height < 2 || height % 2 != 0)
+        {
+            return invalid("width and height must be even");
+        }
+
+        if self.frame_rate <= 0.0 {
+            return invalid("frameRate must be positive");
+        }
+
+        if self
+            .transition
+            .as_ref()
+            .is_some_and(|transition| transition.duration <= 0.0)
+        {
+            return invalid("transition duration must be positive");
+        }
+
+        Ok(())
+    }
+}
+
+/// Properties of the streams of a clip the concat demuxer needs to be the same in every clip.
+fn stream_signature(probe: &FfprobeResponse) -> Vec<String> {
+    probe
+        .streams
+        .iter()
+        .flatten()
+        .filter(|stream| stream.codec_type == "video" || stream.codec_type == "audio")
+        .map(|stream| {
+            format!(
+                "{} {:?} {:?}x{:?} {:?} {:?} {:?} {:?} {:?}",
+                stream.codec_type,
+                stream.codec_name,
+                stream.width,
+                stream.height,
+                stream.pix_fmt,
+                stream.r_frame_rate,
+                stream.sample_aspect_ratio,
+                stream.sample_rate,
+                stream.channels,
+            )
+        })
+        .collect()
+}
+
+/// Times the transitions start at: each one shortens the output by its duration.
+pub(crate) fn offsets(
+    transition: Option<&Transition>,
+    clips: &[Clip],
+) -> Result<Vec<f64>, TerminalError> {
+    let Some(transition) = transition else {
+        return Ok(Vec::new());
+    };
+
+    let last = clips.len() - 1;
+
+    for (i, clip) in clips.iter().enumerate() {
+        // Clips in the middle overlap with both neighbours
+        let overlap = if i == 0 || i == last { 1.0 } else { 2.0 } * transition.duration;
+
+        if clip.duration <= overlap {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!(
+                    "clip {i} ({:.3}s) is too short for {}s transitions",
+                    clip.duration, transition.duration
+                ),
+            ));
+        }
+    }
+
+    Ok(clips[..last]
+        .iter()
+        .scan(0.0, |end, clip| {
+            *end += clip.duration - transition.duration;
+            Some(*end)
+        })
+        .collect())
+}
+
+/// Builds the filter graph joining the clips into [v] and [a] (when any clip has audio).
+pub(crate) fn filter(
+    transition: Option<&Transition>,
+    frame_rate: f64,
+    clips: &[Clip],
+    (width, height): (u32, u32),
+    offsets: &[f64],
+) -> String {
+    let audio = clips.iter().any(|clip| clip.audio);
+
+    // xfade and concat take streams with the same properties
+    let mut graph: Vec<String> = Vec::new();
+
+    for (i, clip) in clips.iter().enumerate() {
+        graph.push(format!(
+            "[{i}:v]scale={width}:{height}:force_original_aspect_ratio=decrease,\
+             pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={},format=yuv420p,\
+             settb=AVTB,setpts=PTS-STARTPTS[v{i}]",
+            frame_rate
+        ));
+
+        if !audio {
+            continue;
+        }
+
+        // Silent clips get a silent track so that the audio stays in sync
+        if clip.audio {
+            graph.push(format!(
+                "[{i}:a]aformat=sample_rates=48000:channel_layouts=stereo,asetpts=PTS-STARTPTS[a{i}]"
+            ));
+        } else {
+            graph.push(format!(
+                "anullsrc=sample_rate=48000:channel_layout=stereo,atrim=duration={}[a{i}]",
+                clip.duration
+            ));
+        }
+    }
+
+    let Some(transition) = transition else {
+        let streams: String = (0..clips.len())
+            .map(|i| {
+                if audio {
+                    format!("[v{i}][a{i}]")
+                } else {
+                    format!("[v{i}]")
+                }
+            })
+            .collect();
+
+        graph.push(format!(
+            "{streams}concat=n={}:v=1:a={}[v]{}",
+            clips.len(),
+            audio as u8,
+            if audio { "[a]" } else { "" }
+        ));
+
+        return graph.join(";");
+    };
+
+    let last = clips.len() - 1;
+    let label = |kind: &str, i: usize| {
+        if i == last {
+            format!("[{kind}]")
+        } else {
+            format!("[{kind}x{i}]")
+        }
+    };
+
+    for (i, offset) in offsets.iter().enumerate() {
+        let previous = if i == 0 {
+            "[v0]".to_string()
+        } else {
+            label("v", i)
+        };
+
+        graph.push(format!(
+            "{previous}[v{}]xfade=transition={}:duration={}:offset={offset:.6}{}",
+            i + 1,
+            transition.kind.xfade(),
+            transition.duration,
+            label("v", i + 1)
+        ));
+
+        if audio {
+            let previous = if i == 0 {
+                "[a0]".to_string()
+            } else {
+                label("a", i)
+            };
+            let curve = transition.easing.curve();
+
+            graph.push(format!(
+                "{previous}[a{}]acrossfade=d={}:c1={curve}:c2={curve}{}",
+                i + 1,
+                transition.duration,
+                label("a", i + 1)
+            ));
+        }
+    }
+
+    graph.join(";")
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _concat(&self, request: ConcatRequest) -> HandlerResult<ConcatResponse> {
+        request.validate()?;
+        request.video.validate().await?;
+
+        let mut clips = Vec::new();
+        let mut size = None;
+        let mut incompatible = None;
+        let mut signature = None;
+
+        for (i, input) in request.inputs.iter().enumerate() {
+            let probe = self.probe(input).await?;
+
+            let current = stream_signature(&probe);
+
+            match &signature {
+                None => signature = Some(current),
+                Some(first) if incompatible.is_none() && *first != current => {
+                    incompatible = Some(i);
+                }
+                Some(_) => {}
+            }
+
+            let video = probe.stream("video").ok_or_else(|| {
+                TerminalError::new_with_code(400, format!("input {i} has no video stream"))
+            })?;
+
+            if size.is_none() {
+                size = video.width.zip(video.height);
+            }
+
+            clips.push(Clip {
+                duration: probe.duration().ok_or_else(|| {
+                    TerminalError::new_with_code(400, format!("duration of input {i} is unknown"))
+                })?,
+                audio: probe.stream("audio").is_some(),
+            });
+        }
+
+        let conformed =
+            request.transition.is_some() || request.width.is_some() || request.height.is_some();
+
+        let method = match request.method {
+            Some(ConcatMethod::Demuxer) if conformed => {
+                return Err(TerminalError::new_with_code(
+                    400,
+                    "the concat demuxer can't apply transitions or change the size",
+                )
+                .into());
+            }
+            Some(ConcatMethod::Demuxer) => match incompatible {
+                Some(i) => {
+                    return Err(TerminalError::new_with_code(
+                        400,
+                        format!(
+                            "input {i} has streams different from the first input's, \
+                             the concat demuxer can't join them"
+                        ),
+                    )
+                    .into());
+                }
+                None => ConcatMethod::Demuxer,
+            },
+            Some(method) => method,
+            None => ConcatMethod::Filter,
+        };
+
+        if method == ConcatMethod::Demuxer {
+            return self.concat_demuxer(&request, &clips).await;
+        }
+
+        let (width, height) = size.unwrap_or_default();
+        let width = request.width.unwrap_or(width as u32 / 2 * 2);
+        let height = request.height.unwrap_or(height as u32 / 2 * 2);
+
+        let offsets = offsets(request.transition.as_ref(), &clips)?;
+        let audio = clips.iter().any(|clip| clip.audio);
+
+        let filename = format!(
+            "{}_concat.{}",
+            input_stem(&request.inputs[0]),
+            request.container
+        );
+
+        let mut args = Vec::new();
+        let mut inputs = Vec::new();
+
+        for input in &request.inputs {
+            args.extend(["-i".to_string(), input_arg(input, &mut inputs)]);
+        }
+
+        args.extend([
+            "-filter_complex".to_string(),
+            filter(
+                request.transition.as_ref(),
+                request.frame_rate,
+                &clips,
+                (width, height),
+                &offsets,
+            ),
+            "-map".to_string(),
+            "[v]".to_string(),
+        ]);
+
+        if audio {
+            args.extend([
+                "-map".to_string(),
+                "[a]".to_string(),
+                "-c:a".to_string(),
+                "aac".to_string(),
+            ]);
+        }
+
+        args.extend(request.video.args());
+        args.push(filename.clone());
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        let overlap = request
+            .transition
+            .as_ref()
+            .map_or(0.0, |transition| transition.duration * offsets.len() as f64);
+
+        Ok(ConcatResponse {
+            output: request.output.file_url(&filename),
+            duration: clips.iter().map(|clip| clip.duration).sum::<f64>() - overlap,
+            offsets,
+            method,
+        })
+    }
+
+    /// Joins compatible clips with the concat demuxer, copying their streams.
+    ///
+    /// The demuxer reads the clips from a list file: they are staged locally first.
+    async fn concat_demuxer(
+        &self,
+        request: &ConcatRequest,
+        clips: &[Clip],
+    ) -> HandlerResult<ConcatResponse> {
+        let staging_dir = TempDir::new()?;
+
+        let mut list = String::new();
+
+        for (i, input) in request.inputs.iter().enumerate() {
+            let name = match input_extension(input) {
+                Some(extension) => format!("input-{i}.{extension}"),
+                None => format!("input-{i}"),
+            };
+            let path = staging_dir.path().join(name);
+
+            self.download(input, &path, None).await?;
+
+            list.push_str(&format!("file '{}'\n", path.display()));
+        }
+
+        let list_path = staging_dir.path().join("inputs.txt");
+        tokio::fs::write(&list_path, list).await?;
+
+        let filename = format!(
+            "{}_concat.{}",
+            input_stem(&request.inputs[0]),
+            request.container
+        );
+
+        self._ffmpeg(FfmpegRequest {
+            args: vec![
+                "-f".to_string(),
+                "concat".to_string(),
+                "-safe".to_string(),
+                "0".to_string(),
+                "-i".to_string(),
+                list_path.display().to_string(),
+                "-map".to_string(),
+                "0".to_string(),
+                "-c".to_string(),
+                "copy".to_string(),
+                filename.clone(),
+            ],
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(ConcatResponse {
+            output: request.output.file_url(&filename),
+            duration: clips.iter().map(|clip| clip.duration).sum(),
+            offsets: Vec::new(),
+            method: ConcatMethod::Demuxer,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clips(durations: &[f64]) -> Vec<Clip> {
+        durations
+            .iter()
+            .map(|&duration| Clip {
+                duration,
+                audio: true,
+            })
+            .collect()
+    }
+
+    fn transition(duration: f64) -> Transition {
+        Transition {
+            kind: TransitionKind::Dissolve,
+            duration,
+            easing: Easing::Linear,
+        }
+    }
+
+    #[test]
+    fn offsets_shorten_by_each_transition() {
+        let offsets = offsets(Some(&transition(1.5)), &clips(&[10.0, 8.0, 12.0, 6.0])).unwrap();
+
+        assert_eq!(offsets, [8.5, 15.0, 25.5]);
+    }
+
+    #[test]
+    fn offsets_without_transition() {
+        assert!(offsets(None, &clips(&[10.0, 8.0])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn offsets_reject_short_clips() {
+        // Clips in the middle overlap on both sides
+        assert!(offsets(Some(&transition(1.0)), &clips(&[10.0, 2.0, 10.0])).is_err());
+        assert!(offsets(Some(&transition(1.0)), &clips(&[10.0, 2.5, 10.0])).is_ok());
+        assert!(offsets(Some(&transition(1.0)), &clips(&[1.0, 10.0])).is_err());
+    }
+
+    #[test]
+    fn filter_chains_xfades() {
+        let mut clips = clips(&[10.0, 8.0, 12.0]);
+        clips[1].audio = false;
+
+        let transition = transition(1.0);
+        let offsets = offsets(Some(&transition), &clips).unwrap();
+
+        let graph = filter(Some(&transition), 25.0, &clips, (1280, 720), &offsets);
+        let graph: Vec<&str> = graph.split(';').collect();
+
+        assert_eq!(graph.len(), 10);
+        assert_eq!(
+            graph[3],
+            "anullsrc=sample_rate=48000:channel_layout=stereo,atrim=duration=8[a1]"
+        );
+        assert_eq!(
+            graph[6..],
+            [
+                "[v0][v1]xfade=transition=dissolve:duration=1:offset=9.000000[vx1]",
+                "[a0][a1]acrossfade=d=1:c1=tri:c2=tri[ax1]",
+                "[vx1][v2]xfade=transition=dissolve:duration=1:offset=16.000000[v]",
+                "[ax1][a2]acrossfade=d=1:c1=tri:c2=tri[a]",
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_concatenates_without_transition() {
+        let clips = vec![
+            Clip {
+                duration: 10.0,
+                audio: false,
+            },
+            Clip {
+                duration: 8.0,
+                audio: false,
+            },
+        ];
+
+        let graph = filter(None, 30.0, &clips, (1920, 1080), &[]);
+
+        assert!(graph.ends_with(";[v0][v1]concat=n=2:v=1:a=0[v]"));
+    }
+}
@@ -0,0 +1,249 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::highlights::log_value;
+use crate::service::{ServiceImpl, run_ffmpeg};
+
+/// Kind of a non-content segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentKind {
+    Black,
+    /// Static picture (e.g. a slate or a still credit card)
+    Freeze,
+    Silence,
+}
+
+impl SegmentKind {
+    fn prefix(&self) -> &'static str {
+        match self {
+            SegmentKind::Black => "black",
+            SegmentKind::Freeze => "freeze",
+            SegmentKind::Silence => "silence",
+        }
+    }
+}
+
+/// A detected non-content segment.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Segment {
+    pub kind: SegmentKind,
+
+    /// Start of the segment in seconds
+    pub start: f64,
+
+    /// End of the segment in seconds
+    pub end: f64,
+}
+
+/// Parses blackdetect, freezedetect and silencedetect logs, shifting timestamps by `offset`.
+fn parse_segments(log: &str, offset: f64, length: f64) -> Vec<Segment> {
+    let mut segments = Vec::new();
+
+    for kind in [
+        SegmentKind::Black,
+        SegmentKind::Freeze,
+        SegmentKind::Silence,
+    ] {
+        let start_key = format!("{}_start:", kind.prefix());
+        let end_key = format!("{}_end:", kind.prefix());
+
+        let mut open = None;
+
+        for line in log.lines() {
+            if let Some(start) = log_value(line, &start_key) {
+                open = Some(start);
+            }
+
+            if let Some(end) = log_value(line, &end_key)
+                && let Some(start) = open.take()
+            {
+                segments.push(Segment {
+                    kind,
+                    start: offset + start,
+                    end: offset + end,
+                });
+            }
+        }
+
+        // Segments running until the end of the analyzed region are never closed
+        if let Some(start) = open {
+            segments.push(Segment {
+                kind,
+                start: offset + start,
+                end: offset + length,
+            });
+        }
+    }
+
+    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+    segments
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_detect_content_bounds_request())]
+pub struct DetectContentBoundsRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Seconds analyzed at the start of the input for slates and leaders
+    #[serde(default = "default_head")]
+    pub head: f64,
+
+    /// Seconds analyzed at the end of the input for credits
+    #[serde(default = "default_tail")]
+    pub tail: f64,
+
+    /// Maximum gap between segments treated as one continuous non-content section in seconds
+    #[serde(default = "default_max_gap")]
+    pub max_gap: f64,
+}
+
+fn default_head() -> f64 {
+    120.0
+}
+
+fn default_tail() -> f64 {
+    600.0
+}
+
+fn default_max_gap() -> f64 {
+    1.0
+}
+
+fn example_detect_content_bounds_request() -> DetectContentBoundsRequest {
+    DetectContentBoundsRequest {
+        input: Url::parse("https://example.com/episode.mov").unwrap(),
+        head: default_head(),
+        tail: default_tail(),
+        max_gap: default_max_gap(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DetectContentBoundsResponse {
+    /// Suggested start of the content in seconds
+    pub content_start: f64,
+
+    /// Suggested end of the content in seconds
+    pub content_end: f64,
+
+    /// Segments detected at the start of the input
+    pub leading: Vec<Segment>,
+
+    /// Segments detected at the end of the input
+    pub trailing: Vec<Segment>,
+}
+
+/// Runs black, freeze and silence detection on a region of the input.
+async fn detect_segments(
+    input: &str,
+    start: f64,
+    length: f64,
+    has_audio: bool,
+) -> HandlerResult<Vec<Segment>> {
+    let mut args = vec![
+        "-ss".to_string(),
+        format!("{start:.3}"),
+        "-t".to_string(),
+        format!("{length:.3}"),
+        "-i".to_string(),
+        input.to_string(),
+        "-map".to_string(),
+        "0:v:0".to_string(),
+        "-vf".to_string(),
+        "blackdetect=d=0.5:pix_th=0.10,freezedetect=n=0.003:d=2".to_string(),
+    ];
+
+    if has_audio {
+        args.extend([
+            "-map".to_string(),
+            "0:a:0".to_string(),
+            "-af".to_string(),
+            "silencedetect=n=-50dB:d=1".to_string(),
+        ]);
+    }
+
+    args.extend(["-f".to_string(), "null".to_string(), "-".to_string()]);
+
+    let log = run_ffmpeg(&args).await?;
+
+    Ok(parse_segments(&log, start, length))
+}
+
+/// Returns where the chain of picture segments touching `from` ends, walking in the given direction.
+///
+/// Silence is reported but not chained: quiet scenes are too common to mark non-content on their own.
+fn chain(segments: &[Segment], from: f64, max_gap: f64, forward: bool) -> f64 {
+    let mut edge = from;
+
+    loop {
+        let next = segments.iter().find(|segment| {
+            let (near, far) = if forward {
+                (segment.start, segment.end)
+            } else {
+                (segment.end, segment.start)
+            };
+            let gap = if forward { near - edge } else { edge - near };
+            let extends = if forward { far > edge } else { far < edge };
+
+            segment.kind != SegmentKind::Silence && gap <= max_gap && extends
+        });
+
+        match next {
+            Some(segment) => edge = if forward { segment.end } else { segment.start },
+            None => return edge,
+        }
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _detect_content_bounds(
+        &self,
+        request: DetectContentBoundsRequest,
+    ) -> HandlerResult<DetectContentBoundsResponse> {
+        let probe = self.probe(&request.input).await?;
+
+        let Some(duration) = probe.duration() else {
+            return Err(TerminalError::new_with_code(400, "unknown input duration").into());
+        };
+
+        if probe.stream("video").is_none() {
+            return Err(TerminalError::new_with_code(400, "input has no video stream").into());
+        }
+
+        let has_audio = probe.stream("audio").is_some();
+
+        let head = request.head.clamp(0.0, duration);
+        let tail_start = (duration - request.tail).max(head);
+
+        // Both ends are scanned separately: storage inputs are downloaded once
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let (leading, trailing) = tokio::try_join!(
+            detect_segments(&input, 0.0, head, has_audio),
+            detect_segments(&input, tail_start, duration - tail_start, has_audio),
+        )?;
+
+        let content_start = chain(&leading, 0.0, request.max_gap, true);
+        let content_end = chain(&trailing, duration, request.max_gap, false).max(content_start);
+
+        Ok(DetectContentBoundsResponse {
+            content_start,
+            content_end,
+            leading,
+            trailing,
+        })
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+
+use futures::future::try_join_all;
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::encode::{VideoEncoding, default_container};
+use crate::inputs::{Input, input_arg};
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem, run_ffmpeg};
+
+/// A crop rectangle in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CropRect {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl CropRect {
+    /// Returns the crop filter applying this rectangle.
+    pub fn filter(&self) -> String {
+        format!("crop={}:{}:{}:{}", self.width, self.height, self.x, self.y)
+    }
+
+    /// Shrinks the rectangle by the given number of pixels on each side.
+    pub fn shrink(&self, margin: u32) -> Self {
+        let margin_x = margin.min(self.width.saturating_sub(2) / 2);
+        let margin_y = margin.min(self.height.saturating_sub(2) / 2);
+
+        CropRect {
+            width: self.width - 2 * margin_x,
+            height: self.height - 2 * margin_y,
+            x: self.x + margin_x,
+            y: self.y + margin_y,
+        }
+    }
+
+    /// Rounds the rectangle inwards so that every value is even, as required by 4:2:0 chroma subsampling.
+    pub fn even(&self) -> Self {
+        let x = self.x.next_multiple_of(2);
+        let y = self.y.next_multiple_of(2);
+
+        CropRect {
+            width: self.width.saturating_sub(x - self.x) & !1,
+            height: self.height.saturating_sub(y - self.y) & !1,
+            x,
+            y,
+        }
+    }
+
+    /// Parses the last `crop=w:h:x:y` value from a cropdetect log line.
+    fn parse(line: &str) -> Option<Self> {
+        let (_, value) = line.rsplit_once("crop=")?;
+        let mut parts = value.trim().splitn(4, ':').map(|part| part.parse().ok());
+
+        Some(CropRect {
+            width: parts.next()??,
+            height: parts.next()??,
+            x: parts.next()??,
+            y: parts.next()??,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_detect_crop_request())]
+pub struct DetectCropRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Number of intervals sampled across the input
+    #[serde(default = "default_samples")]
+    pub samples: u32,
+
+    /// Length of each sampled interval in seconds
+    #[serde(default = "default_sample_duration")]
+    pub sample_duration: f64,
+
+    /// Black level threshold passed to cropdetect (0-255)
+    #[serde(default = "default_limit")]
+    pub limit: u32,
+
+    /// Value the detected dimensions are divisible by
+    #[serde(default = "default_round")]
+    pub round: u32,
+}
+
+fn default_samples() -> u32 {
+    10
+}
+
+fn default_sample_duration() -> f64 {
+    2.0
+}
+
+fn default_limit() -> u32 {
+    24
+}
+
+fn default_round() -> u32 {
+    2
+}
+
+fn example_detect_crop_request() -> DetectCropRequest {
+    DetectCropRequest {
+        input: Url::parse("https://example.com/letterboxed.mp4").unwrap(),
+        samples: default_samples(),
+        sample_duration: default_sample_duration(),
+        limit: default_limit(),
+        round: default_round(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DetectCropResponse {
+    /// Most common crop rectangle across all sampled frames
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<CropRect>,
+
+    /// Share of sampled frames agreeing with the detected rectangle (0-1)
+    pub confidence: f64,
+
+    /// Number of frames analyzed
+    pub frames: u32,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _detect_crop(
+        &self,
+        request: DetectCropRequest,
+    ) -> HandlerResult<DetectCropResponse> {
+        if request.samples == 0 || request.sample_duration <= 0.0 {
+            return Err(TerminalError::new_with_code(
+                400,
+                "samples and sampleDuration must be positive",
+            )
+            .into());
+        }
+
+        let probe = self.probe(&request.input).await?;
+        let duration = probe.duration().unwrap_or_default();
+
+        // Skip the first and last 5% where logos, slates and credits usually live
+        let start = duration * 0.05;
+        let span = (duration * 0.9 - request.sample_duration).max(0.0);
+        let step = span / request.samples as f64;
+
+        let filter = format!(
+            "cropdetect=limit={}:round={}:reset=0",
+            request.limit, request.round
+        );
+
+        // Every sample reads the input: storage inputs are downloaded once
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let samples = (0..request.samples).map(|i| {
+            let args = vec![
+                "-ss".to_string(),
+                format!("{:.3}", start + step * i as f64),
+                "-i".to_string(),
+                input.clone(),
+                "-t".to_string(),
+                request.sample_duration.to_string(),
+                "-an".to_string(),
+                "-sn".to_string(),
+                "-vf".to_string(),
+                filter.clone(),
+                "-f".to_string(),
+                "null".to_string(),
+                "-".to_string(),
+            ];
+
+            async move { run_ffmpeg(&args).await }
+        });
+
+        let logs = try_join_all(samples).await?;
+
+        let mut counts: HashMap<CropRect, u32> = HashMap::new();
+        let mut frames = 0;
+
+        for line in logs.iter().flat_map(|log| log.lines()) {
+            if !line.contains("Parsed_cropdetect") {
+                continue;
+            }
+
+            if let Some(rect) = CropRect::parse(line) {
+                *counts.entry(rect).or_default() += 1;
+                frames += 1;
+            }
+        }
+
+        let best = counts.into_iter().max_by_key(|(_, count)| *count);
+
+        Ok(DetectCropResponse {
+            crop: best.map(|(rect, _)| rect),
+            confidence: best.map_or(0.0, |(_, count)| count as f64 / frames as f64),
+            frames,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_autocrop_request())]
+pub struct AutocropRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Number of intervals sampled for crop detection
+    #[serde(default = "default_samples")]
+    pub samples: u32,
+
+    /// Black level threshold passed to cropdetect (0-255)
+    #[serde(default = "default_limit")]
+    pub limit: u32,
+
+    /// Extra pixels cropped on each side to remove residual edges
+    #[serde(default = "default_margin")]
+    pub margin: u32,
+
+    /// Minimum detection confidence required to apply the crop
+    #[serde(default = "default_min_confidence")]
+    pub min_confidence: f64,
+
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output container extension
+    #[serde(default = "default_container")]
+    pub container: String,
+}
+
+fn default_margin() -> u32 {
+    2
+}
+
+fn default_min_confidence() -> f64 {
+    0.5
+}
+
+fn example_autocrop_request() -> AutocropRequest {
+    AutocropRequest {
+        input: Url::parse("https://example.com/letterboxed.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/cropped/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        samples: default_samples(),
+        limit: default_limit(),
+        margin: default_margin(),
+        min_confidence: default_min_confidence(),
+        video: VideoEncoding::default(),
+        container: default_container(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AutocropResponse {
+    /// Crop applied to the output (empty when no reliable crop was detected)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<CropRect>,
+
+    /// Crop detection result
+    pub detection: DetectCropResponse,
+
+    /// Location of the encoded file
+    pub output: Url,
+}
+
+impl AutocropRequest {
+    fn args(&self, crop: Option<CropRect>, filename: &str, inputs: &mut Vec<Input>) -> Vec<String> {
+        let mut args = vec!["-i".to_string(), input_arg(&self.input, inputs)];
+
+        if let Some(crop) = crop {
+            args.extend(["-vf".to_string(), crop.filter()]);
+        }
+
+        args.extend(self.video.args());
+        args.extend(["-c:a".to_string(), "copy".to_string(), filename.to_string()]);
+
+        args
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _autocrop(
+        &self,
+        request: AutocropRequest,
+    ) -> HandlerResult<AutocropResponse> {
+        request.video.validate().await?;
+
+        let detection = self
+            ._detect_crop(DetectCropRequest {
+                input: request.input.clone(),
+                samples: request.samples,
+                sample_duration: default_sample_duration(),
+                limit: request.limit,
+                round: default_round(),
+            })
+            .await?;
+
+        let crop = detection
+            .crop
+            .filter(|_| detection.confidence >= request.min_confidence)
+            .map(|rect| rect.shrink(request.margin).even());
+
+        let filename = format!("{}.{}", input_stem(&request.input), request.container);
+
+        let mut inputs = Vec::new();
+
+        self._ffmpeg(FfmpegRequest {
+            args: request.args(crop, &filename, &mut inputs),
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(AutocropResponse {
+            crop,
+            detection,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cropdetect_line() {
+        let line = "[Parsed_cropdetect_0 @ 0x55d5c8a3e2c0] x1:0 x2:1919 y1:140 y2:939 w:1920 h:800 \
+                    x:0 y:140 pts:1001 t:0.041708 limit:0.094118 crop=1920:800:0:140";
+
+        assert_eq!(
+            CropRect::parse(line),
+            Some(CropRect {
+                width: 1920,
+                height: 800,
+                x: 0,
+                y: 140,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_trailing_whitespace() {
+        let line = "[Parsed_cropdetect_0 @ 0x7f8e4c004a80] x1:240 x2:1679 y1:0 y2:1079 w:1440 h:1072 \
+                    x:240 y:4 pts:48048 t:2.002000 limit:0.094118 crop=1440:1072:240:4\r";
+
+        assert_eq!(
+            CropRect::parse(line),
+            Some(CropRect {
+                width: 1440,
+                height: 1072,
+                x: 240,
+                y: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn autocrop_args_stage_storage_inputs() {
+        let mut request = example_autocrop_request();
+        request.input = Url::parse("s3://bucket/letterboxed.mp4").unwrap();
+
+        let crop = CropRect {
+            width: 1920,
+            height: 800,
+            x: 0,
+            y: 140,
+        };
+        let mut inputs = Vec::new();
+
+        let args = request.args(Some(crop), "letterboxed.mp4", &mut inputs);
+
+        assert_eq!(args[..4], ["-i", "{input0}", "-vf", "crop=1920:800:0:140"]);
+        assert_eq!(inputs[0].location, request.input);
+    }
+
+    #[test]
+    fn parse_other_lines() {
+        assert_eq!(
+            CropRect::parse("frame=  250 fps=0.0 q=-0.0 Lsize=N/A time=00:00:10.00"),
+            None
+        );
+        assert_eq!(
+            CropRect::parse("[Parsed_cropdetect_0 @ 0x55d5c8a3e2c0] crop=1920:800"),
+            None
+        );
+    }
+}
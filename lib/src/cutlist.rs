@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::concat::{Clip, Easing, Transition, TransitionKind, filter, offsets};
+use crate::encode::{VideoEncoding, default_container};
+use crate::inputs::input_arg;
+use crate::service::{FfmpegRequest, FfprobeResponse, Output, ServiceImpl, input_stem};
+
+/// Largest number of entries in a cutlist.
+const MAX_ENTRIES: usize = 200;
+
+/// Part of a source placed on the timeline.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CutlistEntry {
+    /// Path or URL to the source media file
+    pub source: Url,
+
+    /// Start of the part in the source in seconds
+    #[serde(rename = "in")]
+    pub in_point: f64,
+
+    /// End of the part in the source in seconds
+    #[serde(rename = "out")]
+    pub out_point: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_render_cutlist_request())]
+pub struct RenderCutlistRequest {
+    /// Entries of the timeline in order (sources may be used more than once)
+    pub entries: Vec<CutlistEntry>,
+
+    pub output: Output,
+
+    /// Transition between consecutive entries (hard cuts when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transition: Option<Transition>,
+
+    /// Output width in pixels (the width of the first source when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+
+    /// Output height in pixels (the height of the first source when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+
+    /// Frame rate of the timeline
+    #[serde(default = "default_frame_rate")]
+    pub frame_rate: f64,
+
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output container extension
+    #[serde(default = "default_container")]
+    pub container: String,
+}
+
+fn default_frame_rate() -> f64 {
+    30.0
+}
+
+fn example_render_cutlist_request() -> RenderCutlistRequest {
+    let interview = Url::parse("s3://bucket/footage/interview.mov").unwrap();
+    let broll = Url::parse("s3://bucket/footage/broll.mov").unwrap();
+
+    RenderCutlistRequest {
+        entries: vec![
+            CutlistEntry {
+                source: interview.clone(),
+                in_point: 12.0,
+                out_point: 31.5,
+            },
+            CutlistEntry {
+                source: broll,
+                in_point: 4.0,
+                out_point: 9.0,
+            },
+            CutlistEntry {
+                source: interview,
+                in_point: 95.2,
+                out_point: 120.0,
+            },
+        ],
+        output: Output {
+            location: Url::parse("s3://bucket/edits/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        transition: Some(Transition {
+            kind: TransitionKind::Fade,
+            duration: 0.5,
+            easing: Easing::Sine,
+        }),
+        width: None,
+        height: None,
+        frame_rate: default_frame_rate(),
+        video: VideoEncoding::default(),
+        container: default_container(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderCutlistResponse {
+    /// Location of the rendered timeline
+    pub output: Url,
+
+    /// Duration of the rendered timeline in seconds
+    pub duration: f64,
+
+    /// Times the entries start at on the timeline in seconds
+    pub starts: Vec<f64>,
+}
+
+impl RenderCutlistRequest {
+    fn validate(&self) -> Result<(), TerminalError> {
+        let invalid = |message: String| Err(TerminalError::new_with_code(400, message));
+
+        if self.entries.is_empty() || self.entries.len() > MAX_ENTRIES {
+            return invalid(format!(
+                "a cutlist takes between 1 and {MAX_ENTRIES} entries"
+            ));
+        }
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            if entry.in_point < 0.0 || entry.out_point <= entry.in_point {
+                return invalid(format!(
+                    "entry {i} must have 0 <= in < out (got {} to {})",
+                    entry.in_point, entry.out_point
+                ));
+            }
+        }
+
+        if self.width.is_some_and(|width| width < 2 || width % 2 != 0)
+            || self
+                .height
+                .is_some_and(|height| height < 2 || height % 2 != 0)
+        {
+            return invalid("width and height must be even".to_string());
+        }
+
+        if self.frame_rate <= 0.0 {
+            return invalid("frameRate must be positive".to_string());
+        }
+
+        if self
+            .transition
+            .as_ref()
+            .is_some_and(|transition| transition.duration <= 0.0)
+        {
+            return invalid("transition duration must be positive".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _render_cutlist(
+        &self,
+        request: RenderCutlistRequest,
+    ) -> HandlerResult<RenderCutlistResponse> {
+        request.validate()?;
+        request.video.validate().await?;
+
+        // Sources used by several entries are probed once
+        let mut probes: HashMap<&Url, FfprobeResponse> = HashMap::new();
+
+        for entry in &request.entries {
+            if !probes.contains_key(&entry.source) {
+                probes.insert(&entry.source, self.probe(&entry.source).await?);
+            }
+        }
+
+        let mut clips = Vec::new();
+
+        for (i, entry) in request.entries.iter().enumerate() {
+            let probe = &probes[&entry.source];
+
+            if probe.stream("video").is_none() {
+                return Err(TerminalError::new_with_code(
+                    400,
+                    format!("source of entry {i} has no video stream"),
+                )
+                .into());
+            }
+
+            // Parts running past the end of their source are cut short
+            let out_point = probe
+                .duration()
+                .map_or(entry.out_point, |duration| entry.out_point.min(duration));
+
+            if out_point <= entry.in_point {
+                return Err(TerminalError::new_with_code(
+                    400,
+                    format!("entry {i} starts after the end of its source"),
+                )
+                .into());
+            }
+
+            clips.push(Clip {
+                duration: out_point - entry.in_point,
+                audio: probe.stream("audio").is_some(),
+            });
+        }
+
+        let (width, height) = probes[&request.entries[0].source]
+            .stream("video")
+            .and_then(|video| video.width.zip(video.height))
+            .unwrap_or_default();
+        let width = request.width.unwrap_or(width as u32 / 2 * 2);
+        let height = request.height.unwrap_or(height as u32 / 2 * 2);
+
+        let transition = request.transition.as_ref();
+        let offsets = offsets(transition, &clips)?;
+        let audio = clips.iter().any(|clip| clip.audio);
+
+        let filename = format!(
+            "{}_cut.{}",
+            input_stem(&request.entries[0].source),
+            request.container
+        );
+
+        let mut inputs = Vec::new();
+        let mut args = Vec::new();
+
+        // Every entry is an input of its own, seeking makes it start at its in point
+        // (entries cutting the same source share its staged copy)
+        for (entry, clip) in request.entries.iter().zip(&clips) {
+            args.extend([
+                "-ss".to_string(),
+                format!("{:.6}", entry.in_point),
+                "-t".to_string(),
+                format!("{:.6}", clip.duration),
+                "-i".to_string(),
+                input_arg(&entry.source, &mut inputs),
+            ]);
+        }
+
+        args.extend([
+            "-filter_complex".to_string(),
+            filter(
+                transition,
+                request.frame_rate,
+                &clips,
+                (width, height),
+                &offsets,
+            ),
+            "-map".to_string(),
+            "[v]".to_string(),
+        ]);
+
+        if audio {
+            args.extend([
+                "-map".to_string(),
+                "[a]".to_string(),
+                "-c:a".to_string(),
+                "aac".to_string(),
+            ]);
+        }
+
+        args.extend(request.video.args());
+        args.push(filename.clone());
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        let starts = if transition.is_some() {
+            std::iter::once(0.0)
+                .chain(offsets.iter().copied())
+                .collect()
+        } else {
+            clips
+                .iter()
+                .scan(0.0, |start, clip| {
+                    let current = *start;
+                    *start += clip.duration;
+                    Some(current)
+                })
+                .collect()
+        };
+
+        let overlap =
+            transition.map_or(0.0, |transition| transition.duration * offsets.len() as f64);
+
+        Ok(RenderCutlistResponse {
+            output: request.output.file_url(&filename),
+            duration: clips.iter().map(|clip| clip.duration).sum::<f64>() - overlap,
+            starts,
+        })
+    }
+}
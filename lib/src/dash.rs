@@ -0,0 +1,514 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::capabilities::require_encoder;
+use crate::captions::SubtitleFormat;
+use crate::encode::VideoCodec;
+use crate::hls::{
+    THUMBNAILS_DIR, VideoRendition, bandwidth, default_ladder, default_segment_duration,
+    fit_ladder, ladder_filter, parse_bitrate, validate_ladder,
+};
+use crate::inputs::input_arg;
+use crate::renditions::{PackagedSubtitles, SubtitleRendition, validate_subtitles};
+use crate::service::{FfmpegRequest, Output, ServiceImpl, copy_files};
+use crate::storyboard::{Storyboard, ThumbnailTiles};
+
+/// Name of the manifest.
+const MANIFEST: &str = "manifest.mpd";
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_dash_request())]
+pub struct DashRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Bitrate ladder (renditions taller than the input are left out)
+    ///
+    /// Audio is packaged in an adaptation set of its own, with a representation per distinct audio bitrate.
+    #[serde(default = "default_ladder")]
+    pub renditions: Vec<VideoRendition>,
+
+    /// Video codec
+    #[serde(default)]
+    pub codec: VideoCodec,
+
+    /// Encoder speed preset (e.g. "medium")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+
+    /// Target duration of the segments in seconds
+    #[serde(default = "default_segment_duration")]
+    pub segment_duration: f64,
+
+    /// Name template of the init segments ("$RepresentationID$" and "$ext$" are replaced by the packager)
+    #[serde(default = "default_init_segment_name")]
+    pub init_segment_name: String,
+
+    /// Name template of the media segments ("$Number%05d$" numbers them)
+    #[serde(default = "default_media_segment_name")]
+    pub media_segment_name: String,
+
+    /// Subtitles added to the manifest as side-loaded text adaptation sets
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtitles: Vec<SubtitleRendition>,
+
+    /// Format of the subtitle files (WebVTT or TTML)
+    #[serde(default = "default_subtitle_format")]
+    pub subtitle_format: SubtitleFormat,
+
+    /// Thumbnail tiles added to the manifest as an image adaptation set (for scrubbing)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnails: Option<ThumbnailTiles>,
+
+    /// Return the text of the generated manifest in the response (in addition to uploading it)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_manifest: bool,
+}
+
+fn default_subtitle_format() -> SubtitleFormat {
+    SubtitleFormat::Webvtt
+}
+
+fn default_init_segment_name() -> String {
+    "init-$RepresentationID$.$ext$".to_string()
+}
+
+fn default_media_segment_name() -> String {
+    "chunk-$RepresentationID$-$Number%05d$.$ext$".to_string()
+}
+
+fn example_dash_request() -> DashRequest {
+    DashRequest {
+        input: Url::parse("s3://bucket/masters/feature.mov").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/dash/feature/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        renditions: default_ladder(),
+        codec: VideoCodec::H264,
+        preset: None,
+        segment_duration: 4.0,
+        init_segment_name: default_init_segment_name(),
+        media_segment_name: default_media_segment_name(),
+        subtitles: Vec::new(),
+        subtitle_format: default_subtitle_format(),
+        thumbnails: None,
+        include_manifest: false,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DashResponse {
+    /// Location of the manifest
+    pub manifest: Url,
+
+    pub representations: Vec<DashRepresentation>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtitles: Vec<PackagedSubtitles>,
+
+    /// Location of the first thumbnail tile (the others are numbered after it)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnails: Option<Url>,
+
+    /// Text of the manifest (when includeManifest is set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_content: Option<String>,
+}
+
+/// Representation listed in the manifest.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DashRepresentation {
+    /// ID of the representation in the manifest
+    pub id: u32,
+
+    /// "video" or "audio"
+    pub kind: String,
+
+    /// Target bandwidth in bits per second
+    pub bandwidth: u64,
+
+    /// Height of the picture (video only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+impl DashRequest {
+    async fn validate(&self) -> HandlerResult<()> {
+        let invalid = |message: String| -> HandlerResult<()> {
+            Err(TerminalError::new_with_code(400, message).into())
+        };
+
+        validate_ladder(&self.renditions)?;
+        validate_subtitles(&self.subtitles)?;
+
+        if let Some(thumbnails) = &self.thumbnails {
+            thumbnails.validate()?;
+        }
+
+        if !matches!(
+            self.subtitle_format,
+            SubtitleFormat::Webvtt | SubtitleFormat::Ttml
+        ) {
+            return invalid("subtitleFormat must be webvtt or ttml".to_string());
+        }
+
+        if self.segment_duration <= 0.0 {
+            return invalid("segmentDuration must be positive".to_string());
+        }
+
+        // Segments of every representation are written next to each other
+        for (field, template) in [
+            ("initSegmentName", &self.init_segment_name),
+            ("mediaSegmentName", &self.media_segment_name),
+        ] {
+            if !template.contains("$RepresentationID$") {
+                return invalid(format!("{field} must contain $RepresentationID$"));
+            }
+
+            if template.contains(['/', '\\']) || template.starts_with('.') {
+                return invalid(format!("{field} must be a file name"));
+            }
+        }
+
+        if !self.media_segment_name.contains("$Number")
+            && !self.media_segment_name.contains("$Time")
+        {
+            return invalid("mediaSegmentName must contain $Number$ or $Time$".to_string());
+        }
+
+        require_encoder(self.codec.encoder()).await
+    }
+}
+
+/// Text adaptation set referencing a side-loaded subtitle file.
+fn subtitle_adaptation_set(
+    id: usize,
+    rendition: &SubtitleRendition,
+    format: SubtitleFormat,
+) -> String {
+    let mime_type = match format {
+        SubtitleFormat::Ttml => "application/ttml+xml",
+        _ => "text/vtt",
+    };
+
+    let mut set = vec![format!(
+        "\t\t<AdaptationSet id=\"{id}\" contentType=\"text\" mimeType=\"{mime_type}\" lang=\"{}\">",
+        rendition.language
+    )];
+
+    set.push(format!(
+        "\t\t\t<Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"{}\"/>",
+        rendition.role.dash_role()
+    ));
+
+    if rendition.default {
+        set.push(
+            "\t\t\t<Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"main\"/>".to_string(),
+        );
+    }
+
+    set.extend([
+        format!("\t\t\t<Label>{}</Label>", rendition.display_name()),
+        format!(
+            "\t\t\t<Representation id=\"{}\" bandwidth=\"256\">",
+            rendition.id()
+        ),
+        format!(
+            "\t\t\t\t<BaseURL>{}.{}</BaseURL>",
+            rendition.id(),
+            format.extension()
+        ),
+        "\t\t\t</Representation>".to_string(),
+        "\t\t</AdaptationSet>".to_string(),
+    ]);
+
+    set.join("\n")
+}
+
+/// Image adaptation set of thumbnail tiles (DASH-IF thumbnail tile extension).
+fn thumbnail_adaptation_set(id: usize, storyboard: &Storyboard, tiles: &ThumbnailTiles) -> String {
+    let (width, height) = storyboard.tile_size(tiles);
+
+    [
+        format!("\t\t<AdaptationSet id=\"{id}\" contentType=\"image\" mimeType=\"image/jpeg\">"),
+        format!(
+            "\t\t\t<SegmentTemplate media=\"{THUMBNAILS_DIR}/tile_$Number%05d$.jpg\" timescale=\"1000\" duration=\"{}\" startNumber=\"1\"/>",
+            (tiles.tile_duration() * 1000.0).round() as u64
+        ),
+        format!(
+            "\t\t\t<Representation id=\"thumbnails\" bandwidth=\"{}\" width=\"{width}\" height=\"{height}\">",
+            storyboard.bandwidth()
+        ),
+        format!(
+            "\t\t\t\t<EssentialProperty schemeIdUri=\"http://dashif.org/thumbnail_tile\" value=\"{}x{}\"/>",
+            tiles.columns, tiles.rows
+        ),
+        "\t\t\t</Representation>".to_string(),
+        "\t\t</AdaptationSet>".to_string(),
+    ]
+    .join("\n")
+}
+
+/// Adds adaptation sets to the period of a manifest written by ffmpeg.
+fn add_adaptation_sets(manifest: &str, sets: &[String]) -> String {
+    match manifest.rfind("</Period>") {
+        Some(end) => {
+            let (head, tail) = manifest.split_at(end);
+            let head = head.trim_end_matches(['\t', ' ']);
+
+            format!("{head}{}\n\t{tail}", sets.join("\n"))
+        }
+        None => manifest.to_string(),
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Encodes the ladder in one ffmpeg run, the dash muxer writing the manifest and the fMP4 segments.
+    pub(crate) async fn _dash(&self, request: DashRequest) -> HandlerResult<DashResponse> {
+        request.validate().await?;
+
+        let probe = self.probe(&request.input).await?;
+
+        let video = probe
+            .stream("video")
+            .ok_or_else(|| TerminalError::new_with_code(400, "input has no video stream"))?;
+
+        let ladder = fit_ladder(
+            &request.renditions,
+            video.height.and_then(|height| u32::try_from(height).ok()),
+        );
+
+        // Renditions sharing an audio bitrate share the audio representation
+        let mut audio_bitrates: Vec<&String> = Vec::new();
+
+        if probe.stream("audio").is_some() {
+            for rendition in &ladder {
+                if !audio_bitrates.contains(&&rendition.audio_bitrate) {
+                    audio_bitrates.push(&rendition.audio_bitrate);
+                }
+            }
+        }
+
+        let mut inputs = Vec::new();
+
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-filter_complex".to_string(),
+            ladder_filter(&ladder),
+        ];
+
+        for i in 0..ladder.len() {
+            args.extend(["-map".to_string(), format!("[v{i}]")]);
+        }
+
+        for _ in &audio_bitrates {
+            args.extend(["-map".to_string(), "0:a:0".to_string()]);
+        }
+
+        args.extend(["-c:v".to_string(), request.codec.encoder().to_string()]);
+
+        if let Some(preset) = &request.preset {
+            args.extend(["-preset".to_string(), preset.clone()]);
+        }
+
+        // Keyframes on segment boundaries, so that players can switch representations between segments
+        args.extend([
+            "-force_key_frames".to_string(),
+            format!("expr:gte(t,n_forced*{})", request.segment_duration),
+        ]);
+
+        for (i, rendition) in ladder.iter().enumerate() {
+            let bits = parse_bitrate(&rendition.bitrate).unwrap_or_default();
+
+            args.extend([
+                format!("-b:v:{i}"),
+                rendition.bitrate.clone(),
+                format!("-maxrate:v:{i}"),
+                bandwidth(bits).to_string(),
+                format!("-bufsize:v:{i}"),
+                (bits * 2).to_string(),
+            ]);
+        }
+
+        if !audio_bitrates.is_empty() {
+            args.extend(["-c:a".to_string(), "aac".to_string()]);
+        }
+
+        for (i, bitrate) in audio_bitrates.iter().enumerate() {
+            args.extend([format!("-b:a:{i}"), bitrate.to_string()]);
+        }
+
+        // Subtitle files are side-loaded next to the segments
+        let mut files = Vec::new();
+        let mut extra_sets = Vec::new();
+
+        for rendition in &request.subtitles {
+            let content = self
+                .convert_subtitle_rendition(&request.input, rendition, request.subtitle_format)
+                .await?;
+
+            files.push((
+                format!("{}.{}", rendition.id(), request.subtitle_format.extension()),
+                content,
+            ));
+
+            extra_sets.push(subtitle_adaptation_set(
+                extra_sets.len() + if audio_bitrates.is_empty() { 1 } else { 2 },
+                rendition,
+                request.subtitle_format,
+            ));
+        }
+
+        let tiles_dir = TempDir::new()?;
+
+        let storyboard = match &request.thumbnails {
+            Some(tiles) => {
+                let storyboard = self
+                    .render_storyboard(
+                        &request.input,
+                        &probe,
+                        tiles,
+                        tiles_dir.path(),
+                        THUMBNAILS_DIR,
+                    )
+                    .await?;
+
+                extra_sets.push(thumbnail_adaptation_set(
+                    extra_sets.len() + if audio_bitrates.is_empty() { 1 } else { 2 },
+                    &storyboard,
+                    tiles,
+                ));
+
+                Some(storyboard)
+            }
+            None => None,
+        };
+
+        let mut adaptation_sets = "id=0,streams=v".to_string();
+
+        if !audio_bitrates.is_empty() {
+            adaptation_sets.push_str(" id=1,streams=a");
+        }
+
+        args.extend([
+            "-f".to_string(),
+            "dash".to_string(),
+            "-seg_duration".to_string(),
+            request.segment_duration.to_string(),
+            "-use_template".to_string(),
+            "1".to_string(),
+            "-use_timeline".to_string(),
+            "1".to_string(),
+            "-init_seg_name".to_string(),
+            request.init_segment_name.clone(),
+            "-media_seg_name".to_string(),
+            request.media_segment_name.clone(),
+            "-adaptation_sets".to_string(),
+            adaptation_sets,
+            MANIFEST.to_string(),
+        ]);
+
+        let mut manifest_content = None;
+
+        self._ffmpeg_finishing(
+            FfmpegRequest {
+                args,
+                output: request.output.clone(),
+                dry_run: false,
+                incremental_upload: true,
+                env: Default::default(),
+                inputs,
+                outputs: Vec::new(),
+                sanitize_dimensions: false,
+                logging: Default::default(),
+            },
+            |work_dir| {
+                // The manifest is the only file of its kind: it's never uploaded before ffmpeg exits
+                let manifest = work_dir.join(MANIFEST);
+
+                if !extra_sets.is_empty() {
+                    if let Some(storyboard) = &storyboard {
+                        copy_files(&storyboard.tiles, tiles_dir.path(), work_dir)?;
+                    }
+
+                    for (name, content) in &files {
+                        std::fs::write(work_dir.join(name), content)?;
+                    }
+
+                    let content = std::fs::read_to_string(&manifest)?;
+                    std::fs::write(&manifest, add_adaptation_sets(&content, &extra_sets))?;
+                }
+
+                if request.include_manifest {
+                    manifest_content = Some(std::fs::read_to_string(&manifest)?);
+                }
+
+                Ok(())
+            },
+        )
+        .await?;
+
+        // Representation IDs follow the order of the output streams
+        let representations = ladder
+            .iter()
+            .map(|rendition| {
+                (
+                    "video",
+                    parse_bitrate(&rendition.bitrate).unwrap_or_default(),
+                    Some(rendition.height),
+                )
+            })
+            .chain(
+                audio_bitrates
+                    .iter()
+                    .map(|bitrate| ("audio", parse_bitrate(bitrate).unwrap_or_default(), None)),
+            )
+            .enumerate()
+            .map(|(id, (kind, bandwidth, height))| DashRepresentation {
+                id: id as u32,
+                kind: kind.to_string(),
+                bandwidth,
+                height,
+            })
+            .collect();
+
+        Ok(DashResponse {
+            manifest: request.output.file_url(MANIFEST),
+            representations,
+            subtitles: request
+                .subtitles
+                .iter()
+                .map(|rendition| PackagedSubtitles {
+                    language: rendition.language.clone(),
+                    role: rendition.role,
+                    location: request.output.file_url(&format!(
+                        "{}.{}",
+                        rendition.id(),
+                        request.subtitle_format.extension()
+                    )),
+                })
+                .collect(),
+            thumbnails: storyboard.map(|_| {
+                request
+                    .output
+                    .file_url(&format!("{THUMBNAILS_DIR}/tile_00001.jpg"))
+            }),
+            manifest_content,
+        })
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use opendal_util::OperatorFactory;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::progress::{ProcessProgress, Progress};
+use crate::service::ServiceImpl;
+use crate::templates::current_job_id;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnoseRequest {
+    /// Job to diagnose, as given in the x-job-id header of its invocation
+    /// (every ffmpeg process running on the worker when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnoseResponse {
+    pub processes: Vec<ProcessDiagnosis>,
+}
+
+/// State of a running ffmpeg process.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessDiagnosis {
+    /// Job the process runs for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+
+    pub pid: u32,
+
+    /// Time since the process was started
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub elapsed: Duration,
+
+    /// Last progress line ffmpeg wrote to stderr (e.g. "frame=  120 fps= 24 ... speed=0.98x")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_progress: Option<String>,
+
+    /// Time since ffmpeg last wrote to stderr
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub since_last_output: Duration,
+
+    /// CPU time used by the process in seconds (Linux only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_seconds: Option<f64>,
+
+    /// Average number of cores used since the process was started (Linux only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_usage: Option<f64>,
+
+    /// Number of open file descriptors (Linux only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_files: Option<usize>,
+}
+
+#[derive(Debug)]
+struct RunningProcess {
+    job_id: Option<String>,
+    started: Instant,
+    last_output: Instant,
+    last_progress: Option<String>,
+    progress: Option<Progress>,
+}
+
+/// Running ffmpeg processes of this worker, kept for diagnosing stuck jobs.
+#[derive(Debug, Default)]
+pub(crate) struct Processes {
+    processes: Mutex<HashMap<u32, RunningProcess>>,
+}
+
+/// Keeps a process registered until dropped.
+pub(crate) struct Registered<'a> {
+    processes: &'a Processes,
+    pid: Option<u32>,
+}
+
+impl Drop for Registered<'_> {
+    fn drop(&mut self) {
+        if let Some(pid) = self.pid {
+            self.processes.processes.lock().unwrap().remove(&pid);
+        }
+    }
+}
+
+impl Processes {
+    /// Registers a process for the job running in the current task (if any).
+    pub(crate) fn register(&self, pid: Option<u32>) -> Registered<'_> {
+        if let Some(pid) = pid {
+            let now = Instant::now();
+
+            self.processes.lock().unwrap().insert(
+                pid,
+                RunningProcess {
+                    job_id: current_job_id(),
+                    started: now,
+                    last_output: now,
+                    last_progress: None,
+                    progress: None,
+                },
+            );
+        }
+
+        Registered {
+            processes: self,
+            pid,
+        }
+    }
+
+    fn diagnose(&self, job_id: Option<&str>) -> Vec<ProcessDiagnosis> {
+        let processes = self.processes.lock().unwrap();
+
+        let mut diagnoses: Vec<_> = processes
+            .iter()
+            .filter(|(_, process)| job_id.is_none() || process.job_id.as_deref() == job_id)
+            .map(|(pid, process)| {
+                let elapsed = process.started.elapsed();
+                let cpu_seconds = cpu_seconds(*pid);
+
+                ProcessDiagnosis {
+                    job_id: process.job_id.clone(),
+                    pid: *pid,
+                    elapsed,
+                    last_progress: process.last_progress.clone(),
+                    since_last_output: process.last_output.elapsed(),
+                    cpu_seconds,
+                    cpu_usage: cpu_seconds.map(|cpu| cpu / elapsed.as_secs_f64().max(f64::EPSILON)),
+                    open_files: open_files(*pid),
+                }
+            })
+            .collect();
+
+        diagnoses.sort_by_key(|diagnosis| std::cmp::Reverse(diagnosis.elapsed));
+
+        diagnoses
+    }
+
+    pub(crate) fn progress(&self, job_id: Option<&str>) -> Vec<ProcessProgress> {
+        let processes = self.processes.lock().unwrap();
+
+        let mut progress: Vec<_> = processes
+            .iter()
+            .filter(|(_, process)| job_id.is_none() || process.job_id.as_deref() == job_id)
+            .map(|(pid, process)| {
+                (
+                    process.started,
+                    ProcessProgress {
+                        job_id: process.job_id.clone(),
+                        pid: *pid,
+                        progress: process.progress.clone(),
+                    },
+                )
+            })
+            .collect();
+
+        progress.sort_by_key(|(started, _)| *started);
+
+        progress.into_iter().map(|(_, progress)| progress).collect()
+    }
+}
+
+impl Registered<'_> {
+    pub(crate) fn set_progress(&self, progress: Progress) {
+        if let Some(pid) = self.pid
+            && let Some(process) = self.processes.processes.lock().unwrap().get_mut(&pid)
+        {
+            process.progress = Some(progress);
+        }
+    }
+
+    /// Returns the latest progress reported by the process.
+    pub(crate) fn progress(&self) -> Option<Progress> {
+        let processes = self.processes.processes.lock().unwrap();
+
+        processes.get(&self.pid?)?.progress.clone()
+    }
+
+    /// Reads the stderr of the process, keeping track of the last progress line.
+    pub(crate) async fn read_stderr(
+        &self,
+        mut stderr: impl AsyncRead + Unpin,
+    ) -> std::io::Result<String> {
+        let mut output = Vec::new();
+        let mut buffer = [0; 8192];
+
+        loop {
+            let read = stderr.read(&mut buffer).await?;
+
+            if read == 0 {
+                break;
+            }
+
+            output.extend_from_slice(&buffer[..read]);
+
+            if let Some(pid) = self.pid {
+                let mut processes = self.processes.processes.lock().unwrap();
+
+                if let Some(process) = processes.get_mut(&pid) {
+                    process.last_output = Instant::now();
+                    process.last_progress = last_progress(&output).or(process.last_progress.take());
+                }
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+}
+
+/// Returns the last progress line in the output (ffmpeg rewrites it in place with carriage returns).
+pub(crate) fn last_progress(output: &[u8]) -> Option<String> {
+    // Only the tail matters: progress lines are short
+    let tail = &output[output.len().saturating_sub(1024)..];
+
+    String::from_utf8_lossy(tail)
+        .split(['\r', '\n'])
+        .rev()
+        .map(str::trim)
+        .find(|line| line.starts_with("frame=") || line.starts_with("size="))
+        .map(str::to_string)
+}
+
+/// CPU time of a process from the scheduler statistics (in nanoseconds).
+fn cpu_seconds(pid: u32) -> Option<f64> {
+    let schedstat = std::fs::read_to_string(format!("/proc/{pid}/schedstat")).ok()?;
+    let nanos: u64 = schedstat.split_whitespace().next()?.parse().ok()?;
+
+    Some(nanos as f64 / 1e9)
+}
+
+fn open_files(pid: u32) -> Option<usize> {
+    Some(std::fs::read_dir(format!("/proc/{pid}/fd")).ok()?.count())
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) fn _diagnose(&self, request: DiagnoseRequest) -> DiagnoseResponse {
+        DiagnoseResponse {
+            processes: self.processes.diagnose(request.job_id.as_deref()),
+        }
+    }
+}
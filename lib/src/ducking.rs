@@ -0,0 +1,230 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::inputs::input_arg;
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_extension, input_stem};
+
+/// What the ducked mix is written to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DuckOutput {
+    /// Only the mixed audio track
+    #[default]
+    Track,
+    /// The input with its audio replaced by the mix (other streams are copied)
+    Remux,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_duck_audio_request())]
+pub struct DuckAudioRequest {
+    /// Path or URL to the media file whose audio (e.g. background music) is ducked
+    pub input: Url,
+
+    /// Path or URL to the voiceover the background is lowered under
+    pub voiceover: Url,
+
+    pub output: Output,
+
+    /// Voiceover level above which the background is lowered in dBFS (-60-0)
+    #[serde(default = "default_threshold")]
+    pub threshold: f64,
+
+    /// Compression ratio applied to the background (1-20)
+    #[serde(default = "default_ratio")]
+    pub ratio: f64,
+
+    /// Time it takes to lower the background in milliseconds
+    #[serde(default = "default_attack")]
+    pub attack: f64,
+
+    /// Time it takes to restore the background in milliseconds
+    #[serde(default = "default_release")]
+    pub release: f64,
+
+    #[serde(default)]
+    pub mode: DuckOutput,
+
+    /// Bitrate of the mixed track
+    #[serde(default = "default_audio_bitrate")]
+    pub audio_bitrate: String,
+
+    /// Output file extension ("m4a" for tracks, the input extension for remuxes when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+}
+
+fn default_threshold() -> f64 {
+    -30.0
+}
+
+fn default_ratio() -> f64 {
+    8.0
+}
+
+fn default_attack() -> f64 {
+    20.0
+}
+
+fn default_release() -> f64 {
+    250.0
+}
+
+fn default_audio_bitrate() -> String {
+    "192k".to_string()
+}
+
+fn example_duck_audio_request() -> DuckAudioRequest {
+    DuckAudioRequest {
+        input: Url::parse("s3://bucket/edits/promo.mp4").unwrap(),
+        voiceover: Url::parse("s3://bucket/voiceovers/promo.wav").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/mixed/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        threshold: default_threshold(),
+        ratio: default_ratio(),
+        attack: default_attack(),
+        release: default_release(),
+        mode: DuckOutput::Remux,
+        audio_bitrate: default_audio_bitrate(),
+        container: None,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DuckAudioResponse {
+    /// Location of the mixed file
+    pub output: Url,
+}
+
+impl DuckAudioRequest {
+    fn validate(&self) -> Result<(), TerminalError> {
+        let check = |valid: bool, message: &str| {
+            if valid {
+                Ok(())
+            } else {
+                Err(TerminalError::new_with_code(400, message))
+            }
+        };
+
+        check(
+            (-60.0..=0.0).contains(&self.threshold),
+            "threshold must be between -60 and 0 dBFS",
+        )?;
+        check(
+            (1.0..=20.0).contains(&self.ratio),
+            "ratio must be between 1 and 20",
+        )?;
+        check(
+            (0.01..=2000.0).contains(&self.attack),
+            "attack must be between 0.01 and 2000 ms",
+        )?;
+        check(
+            (0.01..=9000.0).contains(&self.release),
+            "release must be between 0.01 and 9000 ms",
+        )
+    }
+
+    /// Lowers the background [0:a] while the voiceover [1:a] speaks and mixes them into [a].
+    fn filter(&self) -> String {
+        // sidechaincompress takes a linear threshold
+        let threshold = 10f64.powf(self.threshold / 20.0);
+
+        format!(
+            "[1:a]asplit=2[sidechain][voiceover];\
+             [0:a][sidechain]sidechaincompress=threshold={threshold:.6}:ratio={}:attack={}:release={}[ducked];\
+             [ducked][voiceover]amix=inputs=2:duration=first:normalize=0[a]",
+            self.ratio, self.attack, self.release
+        )
+    }
+
+    fn filename(&self) -> String {
+        let extension = self.container.clone().unwrap_or_else(|| match self.mode {
+            DuckOutput::Track => "m4a".to_string(),
+            DuckOutput::Remux => input_extension(&self.input).unwrap_or_else(|| "mp4".to_string()),
+        });
+
+        format!("{}_ducked.{extension}", input_stem(&self.input))
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _duck_audio(
+        &self,
+        request: DuckAudioRequest,
+    ) -> HandlerResult<DuckAudioResponse> {
+        request.validate()?;
+
+        for (input, name) in [(&request.input, "input"), (&request.voiceover, "voiceover")] {
+            if self.probe(input).await?.stream("audio").is_none() {
+                return Err(TerminalError::new_with_code(
+                    400,
+                    format!("{name} has no audio stream"),
+                )
+                .into());
+            }
+        }
+
+        let filename = request.filename();
+
+        let mut inputs = Vec::new();
+
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-i".to_string(),
+            input_arg(&request.voiceover, &mut inputs),
+            "-filter_complex".to_string(),
+            request.filter(),
+        ];
+
+        match request.mode {
+            DuckOutput::Track => args.extend(["-map".to_string(), "[a]".to_string()]),
+            DuckOutput::Remux => args.extend([
+                "-map".to_string(),
+                "0".to_string(),
+                "-map".to_string(),
+                "-0:a".to_string(),
+                "-map".to_string(),
+                "[a]".to_string(),
+                "-c".to_string(),
+                "copy".to_string(),
+            ]),
+        }
+
+        args.extend([
+            "-c:a".to_string(),
+            "aac".to_string(),
+            "-b:a".to_string(),
+            request.audio_bitrate.clone(),
+            filename.clone(),
+        ]);
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(DuckAudioResponse {
+            output: request.output.file_url(&filename),
+        })
+    }
+}
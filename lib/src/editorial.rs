@@ -0,0 +1,376 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::capabilities::require_encoder;
+use crate::service::{FfprobeResponse, Output, ServiceImpl, input_stem, run_ffmpeg_in};
+
+/// Largest number of frames in a snapshot.
+const MAX_FRAMES: usize = 500;
+
+/// Image format of the extracted frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StillFormat {
+    #[default]
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl StillFormat {
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            StillFormat::Jpeg => "jpg",
+            StillFormat::Png => "png",
+            StillFormat::Webp => "webp",
+        }
+    }
+
+    /// Fails when ffmpeg can't write the format.
+    pub(crate) async fn validate(&self) -> HandlerResult<()> {
+        match self {
+            StillFormat::Webp => require_encoder("libwebp").await,
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_editorial_snapshot_request())]
+pub struct EditorialSnapshotRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Positions of the frames: SMPTE timecodes of the source ("01:00:10:12", ";" for drop-frame)
+    /// or seconds from the start ("10.5")
+    pub timecodes: Vec<String>,
+
+    #[serde(default)]
+    pub format: StillFormat,
+}
+
+fn example_editorial_snapshot_request() -> EditorialSnapshotRequest {
+    EditorialSnapshotRequest {
+        input: Url::parse("s3://bucket/dailies/A001C003.mov").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/editorial/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        timecodes: vec!["01:00:10:12".to_string(), "01:02:00:00".to_string()],
+        format: StillFormat::Jpeg,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EditorialSnapshotResponse {
+    /// Location of the JSON index of the frames
+    pub index: Url,
+
+    pub frames: Vec<EditorialFrame>,
+}
+
+/// JSON index uploaded along with the frames.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EditorialIndex {
+    pub input: Url,
+
+    pub frame_rate: f64,
+
+    /// Timecode of the first frame of the source
+    pub start_timecode: String,
+
+    pub frames: Vec<EditorialFrame>,
+}
+
+/// Frame extracted for a requested position.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EditorialFrame {
+    /// Position as requested
+    pub requested: String,
+
+    /// Location of the image
+    pub location: Url,
+
+    /// Presentation timestamp of the frame (in stream time base units)
+    pub pts: i64,
+
+    /// Presentation time of the frame in seconds
+    pub pts_time: f64,
+
+    /// Picture type of the frame ("I", "P" or "B")
+    pub pict_type: String,
+
+    pub key_frame: bool,
+
+    /// SMPTE timecode of the frame
+    pub timecode: String,
+}
+
+/// SMPTE timecode arithmetic at a given frame rate.
+struct Timecode {
+    /// Frames per second the timecode counts (e.g. 30 for 29.97)
+    nominal: u64,
+    drop_frame: bool,
+}
+
+impl Timecode {
+    fn new(frame_rate: f64, drop_frame: bool) -> Self {
+        let nominal = frame_rate.round().max(1.0) as u64;
+
+        Self {
+            nominal,
+            // Only NTSC rates drop frame numbers
+            drop_frame: drop_frame && nominal.is_multiple_of(30),
+        }
+    }
+
+    /// Frame numbers skipped at the start of every minute (but every tenth).
+    fn dropped(&self) -> u64 {
+        if self.drop_frame {
+            self.nominal / 15
+        } else {
+            0
+        }
+    }
+
+    fn parse(&self, timecode: &str) -> Option<u64> {
+        let parts: Vec<u64> = timecode
+            .split([':', ';', '.'])
+            .map(|part| part.parse().ok())
+            .collect::<Option<_>>()?;
+
+        let [hours, minutes, seconds, frames] = parts[..] else {
+            return None;
+        };
+
+        if minutes >= 60 || seconds >= 60 || frames >= self.nominal {
+            return None;
+        }
+
+        let total_minutes = hours * 60 + minutes;
+
+        Some(
+            (total_minutes * 60 + seconds) * self.nominal + frames
+                - self.dropped() * (total_minutes - total_minutes / 10),
+        )
+    }
+
+    fn format(&self, mut frame: u64) -> String {
+        let dropped = self.dropped();
+
+        if dropped > 0 {
+            let per_ten_minutes = self.nominal * 600 - dropped * 9;
+            let per_minute = self.nominal * 60 - dropped;
+
+            let tens = frame / per_ten_minutes;
+            let rest = frame % per_ten_minutes;
+
+            frame += dropped * 9 * tens;
+
+            if rest > dropped {
+                frame += dropped * ((rest - dropped) / per_minute);
+            }
+        }
+
+        let separator = if self.drop_frame { ';' } else { ':' };
+
+        format!(
+            "{:02}:{:02}:{:02}{separator}{:02}",
+            frame / (self.nominal * 3600),
+            frame / (self.nominal * 60) % 60,
+            frame / self.nominal % 60,
+            frame % self.nominal
+        )
+    }
+}
+
+/// Start timecode of the source (in the format tags or the tags of a stream).
+fn start_timecode(probe: &FfprobeResponse) -> Option<String> {
+    probe
+        .format
+        .as_ref()
+        .and_then(|format| format.tags.get("timecode"))
+        .or_else(|| {
+            probe
+                .streams
+                .iter()
+                .flatten()
+                .find_map(|stream| stream.tags.get("timecode"))
+        })
+        .cloned()
+}
+
+/// Returns the value of a field of a showinfo line (e.g. "pts_time:1.5").
+fn showinfo_field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let (_, value) = line.split_once(&format!(" {key}:"))?;
+
+    value.split_whitespace().next()
+}
+
+/// Parses the frame reported by the showinfo filter into its pts, pts_time, picture type and key flag.
+fn parse_showinfo(log: &str) -> Option<(i64, f64, String, bool)> {
+    let line = log
+        .lines()
+        .find(|line| line.contains("Parsed_showinfo") && line.contains(" pts_time:"))?;
+
+    Some((
+        showinfo_field(line, "pts")?.parse().ok()?,
+        showinfo_field(line, "pts_time")?.parse().ok()?,
+        showinfo_field(line, "type")?.to_string(),
+        showinfo_field(line, "iskey")? == "1",
+    ))
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _editorial_snapshot(
+        &self,
+        request: EditorialSnapshotRequest,
+    ) -> HandlerResult<EditorialSnapshotResponse> {
+        if request.timecodes.is_empty() || request.timecodes.len() > MAX_FRAMES {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("a snapshot takes between 1 and {MAX_FRAMES} timecodes"),
+            )
+            .into());
+        }
+
+        request.format.validate().await?;
+
+        let probe = self.probe(&request.input).await?;
+
+        let frame_rate = probe
+            .stream("video")
+            .ok_or_else(|| TerminalError::new_with_code(400, "input has no video stream"))?
+            .frame_rate()
+            .ok_or_else(|| TerminalError::new_with_code(400, "unknown input frame rate"))?;
+
+        let start_time: f64 = probe
+            .format
+            .as_ref()
+            .and_then(|format| format.start_time.as_deref())
+            .and_then(|start_time| start_time.parse().ok())
+            .unwrap_or_default();
+
+        let start = start_timecode(&probe).unwrap_or_else(|| "00:00:00:00".to_string());
+        let timecode = Timecode::new(frame_rate, start.contains(';'));
+
+        let start_frame = timecode.parse(&start).ok_or_else(|| {
+            TerminalError::new_with_code(400, format!("invalid source timecode {start}"))
+        })?;
+
+        // Every frame reads the input: storage inputs are downloaded once
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let work_dir = TempDir::new()?;
+        let stem = input_stem(&request.input);
+        let mut frames = Vec::new();
+
+        for (i, requested) in request.timecodes.iter().enumerate() {
+            // Seconds or a timecode of the source
+            let position = match requested.parse::<f64>() {
+                Ok(seconds) => seconds,
+                Err(_) => {
+                    let frame = timecode.parse(requested).ok_or_else(|| {
+                        TerminalError::new_with_code(400, format!("invalid timecode {requested}"))
+                    })?;
+
+                    frame.checked_sub(start_frame).ok_or_else(|| {
+                        TerminalError::new_with_code(
+                            400,
+                            format!(
+                                "timecode {requested} is before the start of the source ({start})"
+                            ),
+                        )
+                    })? as f64
+                        / frame_rate
+                }
+            };
+
+            if position < 0.0 {
+                return Err(TerminalError::new_with_code(
+                    400,
+                    format!("position {requested} is negative"),
+                )
+                .into());
+            }
+
+            let filename = format!("{stem}_{i:04}.{}", request.format.extension());
+
+            // Seeking before the input decodes up to the exact frame,
+            // copyts keeps the timestamps of the source in the showinfo log
+            let log = run_ffmpeg_in(
+                work_dir.path(),
+                &[
+                    "-ss".to_string(),
+                    format!("{position:.6}"),
+                    "-copyts".to_string(),
+                    "-i".to_string(),
+                    input.clone(),
+                    "-map".to_string(),
+                    "0:v:0".to_string(),
+                    "-vf".to_string(),
+                    "showinfo".to_string(),
+                    "-frames:v".to_string(),
+                    "1".to_string(),
+                    "-update".to_string(),
+                    "1".to_string(),
+                    filename.clone(),
+                ],
+            )
+            .await?;
+
+            let (pts, pts_time, pict_type, key_frame) = parse_showinfo(&log).ok_or_else(|| {
+                TerminalError::new_with_code(400, format!("no frame at {requested}"))
+            })?;
+
+            let offset = ((pts_time - start_time).max(0.0) * frame_rate).round() as u64;
+
+            frames.push(EditorialFrame {
+                requested: requested.clone(),
+                location: request.output.file_url(&filename),
+                pts,
+                pts_time,
+                pict_type,
+                key_frame,
+                timecode: timecode.format(start_frame + offset),
+            });
+        }
+
+        let index_name = format!("{stem}_index.json");
+
+        let index = EditorialIndex {
+            input: request.input.clone(),
+            frame_rate,
+            start_timecode: start,
+            frames: frames.clone(),
+        };
+
+        tokio::fs::write(
+            work_dir.path().join(&index_name),
+            serde_json::to_vec_pretty(&index)?,
+        )
+        .await?;
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        Ok(EditorialSnapshotResponse {
+            index: request.output.file_url(&index_name),
+            frames,
+        })
+    }
+}
@@ -0,0 +1,199 @@
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::capabilities::require_encoder;
+
+/// Default container extension of the typed handlers producing a single file.
+pub(crate) fn default_container() -> String {
+    "mp4".to_string()
+}
+
+/// Video codecs supported by the typed handlers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    #[default]
+    H264,
+    H265,
+    Vp9,
+    Av1,
+}
+
+impl VideoCodec {
+    /// Name of the ffmpeg encoder used for this codec.
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::H265 => "libx265",
+            VideoCodec::Vp9 => "libvpx-vp9",
+            VideoCodec::Av1 => "libsvtav1",
+        }
+    }
+
+    /// Arguments running one pass of a two-pass encode, with the statistics kept in `logfile`.
+    ///
+    /// Returns nothing for codecs whose ffmpeg encoder does not support two-pass encoding.
+    pub(crate) fn pass_args(&self, pass: u8, logfile: &str) -> Option<Vec<String>> {
+        match self {
+            VideoCodec::H264 | VideoCodec::Vp9 => Some(vec![
+                "-pass".to_string(),
+                pass.to_string(),
+                "-passlogfile".to_string(),
+                logfile.to_string(),
+            ]),
+            // libx265 ignores -pass: the pass is set through its own parameters
+            VideoCodec::H265 => Some(vec![
+                "-x265-params".to_string(),
+                format!("pass={pass}:stats={logfile}"),
+            ]),
+            VideoCodec::Av1 => None,
+        }
+    }
+
+    fn max_crf(&self) -> u8 {
+        match self {
+            VideoCodec::H264 | VideoCodec::H265 => 51,
+            VideoCodec::Vp9 | VideoCodec::Av1 => 63,
+        }
+    }
+}
+
+/// SVT-AV1 specific settings.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Av1Options {
+    /// Film grain synthesis strength (0-50)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub film_grain: Option<u8>,
+
+    /// Denoise the picture before encoding when film grain synthesis is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub film_grain_denoise: Option<bool>,
+
+    /// Number of tile rows as a power of two (0-6)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tile_rows: Option<u8>,
+
+    /// Number of tile columns as a power of two (0-4)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tile_columns: Option<u8>,
+}
+
+impl Av1Options {
+    fn params(&self) -> Vec<String> {
+        let mut params = Vec::new();
+
+        if let Some(film_grain) = self.film_grain {
+            params.push(format!("film-grain={film_grain}"));
+        }
+        if let Some(denoise) = self.film_grain_denoise {
+            params.push(format!("film-grain-denoise={}", denoise as u8));
+        }
+        if let Some(tile_rows) = self.tile_rows {
+            params.push(format!("tile-rows={tile_rows}"));
+        }
+        if let Some(tile_columns) = self.tile_columns {
+            params.push(format!("tile-columns={tile_columns}"));
+        }
+
+        params
+    }
+}
+
+/// Video encoding settings shared by the typed handlers.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoEncoding {
+    /// Video codec
+    #[serde(default)]
+    pub codec: VideoCodec,
+
+    /// Constant rate factor (quality-based encoding)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crf: Option<u8>,
+
+    /// Target bitrate (e.g. "5M")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<String>,
+
+    /// Encoder speed preset (e.g. "medium", "slow", or 0-13 for AV1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+
+    /// AV1 specific settings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub av1: Option<Av1Options>,
+}
+
+impl VideoEncoding {
+    /// Validates the settings against the codec and checks that ffmpeg ships the encoder.
+    pub(crate) async fn validate(&self) -> HandlerResult<()> {
+        let invalid = |message: String| -> HandlerResult<()> {
+            Err(TerminalError::new_with_code(400, message).into())
+        };
+
+        if let Some(crf) = self.crf.filter(|crf| *crf > self.codec.max_crf()) {
+            return invalid(format!(
+                "crf {crf} is out of range (0-{})",
+                self.codec.max_crf()
+            ));
+        }
+
+        if self.codec == VideoCodec::Av1 {
+            if let Some(preset) = &self.preset
+                && !preset.parse::<u8>().is_ok_and(|preset| preset <= 13)
+            {
+                return invalid(format!("AV1 preset must be between 0 and 13, got {preset}"));
+            }
+
+            if let Some(av1) = &self.av1 {
+                if av1.film_grain.is_some_and(|v| v > 50) {
+                    return invalid("filmGrain must be between 0 and 50".to_string());
+                }
+                if av1.tile_rows.is_some_and(|v| v > 6) {
+                    return invalid("tileRows must be between 0 and 6".to_string());
+                }
+                if av1.tile_columns.is_some_and(|v| v > 4) {
+                    return invalid("tileColumns must be between 0 and 4".to_string());
+                }
+            }
+        } else if self.av1.is_some() {
+            return invalid("av1 settings require the av1 codec".to_string());
+        }
+
+        require_encoder(self.codec.encoder()).await
+    }
+
+    /// Returns the ffmpeg output arguments for these settings.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec!["-c:v".to_string(), self.codec.encoder().to_string()];
+
+        if let Some(crf) = self.crf {
+            args.extend(["-crf".to_string(), crf.to_string()]);
+        }
+
+        if let Some(bitrate) = &self.bitrate {
+            args.extend(["-b:v".to_string(), bitrate.clone()]);
+        } else if self.codec == VideoCodec::Vp9 {
+            // libvpx-vp9 only runs in constant quality mode with a zero bitrate
+            args.extend(["-b:v".to_string(), "0".to_string()]);
+        }
+
+        if let Some(preset) = &self.preset {
+            args.extend(["-preset".to_string(), preset.clone()]);
+        }
+
+        let params = self
+            .av1
+            .as_ref()
+            .map(Av1Options::params)
+            .unwrap_or_default();
+
+        if self.codec == VideoCodec::Av1 && !params.is_empty() {
+            args.extend(["-svtav1-params".to_string(), params.join(":")]);
+        }
+
+        args
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::BTreeMap;
+
+use globset::{Glob, GlobSetBuilder};
+use restate_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Environment variables requests may set for ffmpeg.
+///
+/// Nothing is allowed by default: the variables of the worker (credentials included)
+/// must not be overridden by callers.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EnvConfig {
+    /// Names (or glob patterns, e.g. "SRT_*") of the allowed variables
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<String>,
+}
+
+impl EnvConfig {
+    /// Fails with a terminal error when the request sets a variable that is not allowed.
+    pub(crate) fn check(&self, env: &BTreeMap<String, String>) -> Result<(), TerminalError> {
+        if env.is_empty() {
+            return Ok(());
+        }
+
+        let mut builder = GlobSetBuilder::new();
+
+        for pattern in &self.allowed {
+            let glob = Glob::new(pattern).map_err(|err| {
+                TerminalError::new(format!("invalid allowed variable {pattern}: {err}"))
+            })?;
+            builder.add(glob);
+        }
+
+        let allowed = builder
+            .build()
+            .map_err(|err| TerminalError::new(err.to_string()))?;
+
+        let denied: Vec<_> = env
+            .keys()
+            .filter(|name| !allowed.is_match(name.as_str()))
+            .map(String::as_str)
+            .collect();
+
+        if denied.is_empty() {
+            return Ok(());
+        }
+
+        Err(TerminalError::new_with_code(
+            400,
+            format!("environment variables not allowed: {}", denied.join(", ")),
+        ))
+    }
+}
@@ -0,0 +1,123 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::inputs::Input;
+use crate::service::FfmpegRequest;
+
+/// Common options of ffmpeg not taking a value.
+pub(crate) const FLAG_OPTIONS: &[&str] = &[
+    "-an",
+    "-vn",
+    "-sn",
+    "-dn",
+    "-y",
+    "-n",
+    "-re",
+    "-shortest",
+    "-copyts",
+    "-start_at_zero",
+    "-hide_banner",
+    "-nostats",
+    "-stats",
+    "-nostdin",
+    "-benchmark",
+];
+
+/// Resolved command of an ffmpeg request.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FfmpegPlan {
+    /// Command line ffmpeg is run with
+    pub command: Vec<String>,
+
+    /// Flags added to the arguments of the request
+    pub flags: Vec<String>,
+
+    /// Inputs read by ffmpeg (in the order they are referenced by -map)
+    pub inputs: Vec<String>,
+
+    /// Inputs staged from storage, along with the placeholders they replace in the command
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub staged: Vec<Input>,
+
+    /// Files written by ffmpeg and where they are uploaded to
+    pub uploads: Vec<PlannedUpload>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedUpload {
+    /// Output file as given to ffmpeg ("-" for stdout, patterns are expanded by ffmpeg)
+    pub file: String,
+
+    /// Location the file is uploaded to
+    pub location: Url,
+
+    /// Returned in the response as a data URL instead of being uploaded
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inline: bool,
+}
+
+impl FfmpegRequest {
+    /// Resolves the command of the request (run with the given flags) without running it.
+    pub(crate) fn plan(&self, flags: Vec<String>) -> FfmpegPlan {
+        let command = std::iter::once("ffmpeg".to_string())
+            .chain(flags.iter().cloned())
+            .chain(self.args.iter().cloned())
+            .collect();
+
+        let inputs = self
+            .args
+            .windows(2)
+            .filter(|pair| pair[0] == "-i")
+            .map(|pair| pair[1].clone())
+            .collect();
+
+        let uploads = self
+            .output_files()
+            .into_iter()
+            .map(|file| {
+                let output = self.destination(&file);
+
+                PlannedUpload {
+                    location: if file == "-" {
+                        self.output.location.clone()
+                    } else {
+                        output.file_url(&file)
+                    },
+                    inline: output.inline,
+                    file,
+                }
+            })
+            .collect();
+
+        FfmpegPlan {
+            command,
+            flags,
+            inputs,
+            staged: self.inputs.clone(),
+            uploads,
+        }
+    }
+
+    /// Returns the output files of the command.
+    ///
+    /// Outputs are the positional arguments: anything that is neither an option
+    /// nor the value of one. Options are expected to take a value, unless listed
+    /// in [`FLAG_OPTIONS`].
+    fn output_files(&self) -> Vec<String> {
+        let mut files = Vec::new();
+        let mut args = self.args.iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "-" || !arg.starts_with('-') {
+                files.push(arg.clone());
+            } else if !FLAG_OPTIONS.contains(&arg.as_str()) {
+                args.next();
+            }
+        }
+
+        files
+    }
+}
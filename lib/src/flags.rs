@@ -0,0 +1,378 @@
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Verbosity of the log of ffmpeg (-loglevel), from the quietest.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Quiet,
+    Panic,
+    Fatal,
+    Error,
+    Warning,
+    /// Default of ffmpeg (levels below hide the progress and the benchmark report)
+    Info,
+    Verbose,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 9] = [
+        LogLevel::Quiet,
+        LogLevel::Panic,
+        LogLevel::Fatal,
+        LogLevel::Error,
+        LogLevel::Warning,
+        LogLevel::Info,
+        LogLevel::Verbose,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Quiet => "quiet",
+            LogLevel::Panic => "panic",
+            LogLevel::Fatal => "fatal",
+            LogLevel::Error => "error",
+            LogLevel::Warning => "warning",
+            LogLevel::Info => "info",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+
+    /// Numeric value of the level as ffmpeg takes it.
+    fn value(&self) -> i32 {
+        match self {
+            LogLevel::Quiet => -8,
+            LogLevel::Panic => 0,
+            LogLevel::Fatal => 8,
+            LogLevel::Error => 16,
+            LogLevel::Warning => 24,
+            LogLevel::Info => 32,
+            LogLevel::Verbose => 40,
+            LogLevel::Debug => 48,
+            LogLevel::Trace => 56,
+        }
+    }
+
+    /// Parses the value of -loglevel ("debug", "48" or with flags like "repeat+level+debug").
+    fn parse(value: &str) -> Option<Self> {
+        let level = value.rsplit('+').next()?;
+
+        Self::ALL.into_iter().find(|known| {
+            known.name() == level
+                || level
+                    .parse::<i32>()
+                    .is_ok_and(|level| level == known.value())
+        })
+    }
+}
+
+/// Logging of an ffmpeg run.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LogOptions {
+    /// Log level of ffmpeg (the configured one when empty)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loglevel: Option<LogLevel>,
+
+    /// Interval of the progress reports in seconds (-stats_period)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats_period: Option<f64>,
+}
+
+/// Flags added to the arguments of ffmpeg requests.
+///
+/// Flags the request already sets (or contradicts, like `-n` for `-y`) are not added.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FlagConfig {
+    /// Overwrite existing output files without asking (-y)
+    #[serde(default = "default_true")]
+    pub overwrite: bool,
+
+    /// Disable interaction on stdin (-nostdin)
+    #[serde(default = "default_true")]
+    pub nostdin: bool,
+
+    /// Report the resources used (-benchmark), required for metering CPU time
+    #[serde(default = "default_true")]
+    pub benchmark: bool,
+
+    /// Hide the banner (-hide_banner)
+    #[serde(default)]
+    pub hide_banner: bool,
+
+    /// Log level (-loglevel), unless set by the request
+    #[serde(
+        default,
+        deserialize_with = "deserialize_loglevel",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub loglevel: Option<String>,
+
+    /// Most verbose log level requests may set (debug and trace logs can be huge)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_loglevel: Option<LogLevel>,
+
+    /// Shortest interval of progress reports requests may set in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_stats_period: Option<f64>,
+
+    /// Longest interval of progress reports requests may set in seconds
+    /// (the watchdog takes a silent process for a stalled one)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_stats_period: Option<f64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Rejects unknown log levels when the configuration is loaded (rather than failing every run).
+fn deserialize_loglevel<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let loglevel = Option::<String>::deserialize(deserializer)?;
+
+    if let Some(value) = &loglevel
+        && LogLevel::parse(value).is_none()
+    {
+        return Err(serde::de::Error::custom(format!(
+            "unknown log level {value:?}"
+        )));
+    }
+
+    Ok(loglevel)
+}
+
+impl Default for FlagConfig {
+    fn default() -> Self {
+        Self {
+            overwrite: true,
+            nostdin: true,
+            benchmark: true,
+            hide_banner: false,
+            loglevel: None,
+            max_loglevel: None,
+            min_stats_period: None,
+            max_stats_period: None,
+        }
+    }
+}
+
+impl FlagConfig {
+    /// Returns the flags added to the given arguments.
+    pub(crate) fn flags(&self, args: &[String]) -> Vec<String> {
+        let has = |names: &[&str]| args.iter().any(|arg| names.contains(&arg.as_str()));
+
+        let mut flags = Vec::new();
+
+        if self.nostdin && !has(&["-nostdin", "-stdin"]) {
+            flags.push("-nostdin".to_string());
+        }
+
+        // -n refuses to overwrite: adding -y would silently override it
+        if self.overwrite && !has(&["-y", "-n"]) {
+            flags.push("-y".to_string());
+        }
+
+        if self.benchmark && !has(&["-benchmark"]) {
+            flags.push("-benchmark".to_string());
+        }
+
+        if self.hide_banner && !has(&["-hide_banner"]) {
+            flags.push("-hide_banner".to_string());
+        }
+
+        if let Some(loglevel) = &self.loglevel
+            && !has(&["-loglevel", "-v"])
+        {
+            flags.extend(["-loglevel".to_string(), loglevel.clone()]);
+        }
+
+        flags
+    }
+
+    /// Checks the logging of a request (set by its fields or its arguments) against the bounds
+    /// and returns the arguments setting the fields.
+    pub(crate) fn logging(
+        &self,
+        options: &LogOptions,
+        args: &[String],
+    ) -> Result<Vec<String>, TerminalError> {
+        let invalid = |message: String| Err(TerminalError::new_with_code(400, message));
+
+        let arg = |names: &[&str]| {
+            args.windows(2)
+                .filter(|pair| names.contains(&pair[0].as_str()))
+                .map(|pair| pair[1].as_str())
+                .next_back()
+        };
+
+        let loglevel = match (options.loglevel, arg(&["-loglevel", "-v"])) {
+            (Some(_), Some(_)) => {
+                return invalid("loglevel is set both in the request and in the arguments".into());
+            }
+            (Some(level), None) => Some(level),
+            (None, Some(value)) => match LogLevel::parse(value) {
+                Some(level) => Some(level),
+                None => return invalid(format!("unknown log level {value:?}")),
+            },
+            (None, None) => None,
+        };
+
+        if let (Some(level), Some(max)) = (loglevel, self.max_loglevel)
+            && level > max
+        {
+            return invalid(format!(
+                "log level {} is more verbose than allowed ({})",
+                level.name(),
+                max.name()
+            ));
+        }
+
+        let stats_period = match (options.stats_period, arg(&["-stats_period"])) {
+            (Some(_), Some(_)) => {
+                return invalid(
+                    "statsPeriod is set both in the request and in the arguments".into(),
+                );
+            }
+            (Some(period), None) => Some(period),
+            (None, Some(value)) => match value.parse::<f64>() {
+                Ok(period) => Some(period),
+                Err(_) => return invalid(format!("invalid stats period {value:?}")),
+            },
+            (None, None) => None,
+        };
+
+        if let Some(period) = stats_period {
+            if period <= 0.0 {
+                return invalid("stats period must be positive".into());
+            }
+
+            if self.min_stats_period.is_some_and(|min| period < min)
+                || self.max_stats_period.is_some_and(|max| period > max)
+            {
+                return invalid(format!(
+                    "stats period {period}s is out of the allowed range ({}s to {}s)",
+                    self.min_stats_period.unwrap_or(0.0),
+                    self.max_stats_period.unwrap_or(f64::INFINITY)
+                ));
+            }
+        }
+
+        let mut logging = Vec::new();
+
+        if let Some(level) = options.loglevel {
+            logging.extend(["-loglevel".to_string(), level.name().to_string()]);
+        }
+
+        if let Some(period) = options.stats_period {
+            logging.extend(["-stats_period".to_string(), period.to_string()]);
+        }
+
+        Ok(logging)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &str) -> Vec<String> {
+        args.split(' ').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parse_loglevel() {
+        assert_eq!(LogLevel::parse("debug"), Some(LogLevel::Debug));
+        assert_eq!(LogLevel::parse("48"), Some(LogLevel::Debug));
+        assert_eq!(LogLevel::parse("-8"), Some(LogLevel::Quiet));
+        assert_eq!(
+            LogLevel::parse("repeat+level+warning"),
+            Some(LogLevel::Warning)
+        );
+        assert_eq!(LogLevel::parse("loud"), None);
+        assert_eq!(LogLevel::parse("42"), None);
+    }
+
+    #[test]
+    fn config_rejects_unknown_loglevel() {
+        let config: FlagConfig =
+            serde_json::from_value(serde_json::json!({ "loglevel": "level+error" })).unwrap();
+
+        assert_eq!(config.loglevel.as_deref(), Some("level+error"));
+        assert!(
+            serde_json::from_value::<FlagConfig>(serde_json::json!({ "loglevel": "loud" }))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn logging_rejects_levels_above_max() {
+        let config = FlagConfig {
+            max_loglevel: Some(LogLevel::Verbose),
+            ..Default::default()
+        };
+
+        let debug = LogOptions {
+            loglevel: Some(LogLevel::Debug),
+            stats_period: None,
+        };
+
+        assert!(config.logging(&debug, &[]).is_err());
+        assert!(
+            config
+                .logging(&Default::default(), &args("-v 56 out.mp4"))
+                .is_err()
+        );
+        assert_eq!(
+            config
+                .logging(&Default::default(), &args("-loglevel verbose out.mp4"))
+                .unwrap(),
+            Vec::<String>::new()
+        );
+
+        let info = LogOptions {
+            loglevel: Some(LogLevel::Info),
+            stats_period: None,
+        };
+
+        assert_eq!(config.logging(&info, &[]).unwrap(), ["-loglevel", "info"]);
+    }
+
+    #[test]
+    fn logging_checks_stats_period_range() {
+        let config = FlagConfig {
+            min_stats_period: Some(0.5),
+            max_stats_period: Some(10.0),
+            ..Default::default()
+        };
+
+        let period = |stats_period| LogOptions {
+            loglevel: None,
+            stats_period: Some(stats_period),
+        };
+
+        assert!(config.logging(&period(0.1), &[]).is_err());
+        assert!(config.logging(&period(30.0), &[]).is_err());
+        assert!(config.logging(&period(-1.0), &[]).is_err());
+        assert!(
+            config
+                .logging(&Default::default(), &args("-stats_period 60 out.mp4"))
+                .is_err()
+        );
+        assert_eq!(
+            config.logging(&period(2.0), &[]).unwrap(),
+            ["-stats_period", "2"]
+        );
+    }
+}
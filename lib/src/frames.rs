@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::process::Stdio;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::Command;
+use url::Url;
+
+use crate::service::ServiceImpl;
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_analyze_frames_request())]
+pub struct AnalyzeFramesRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Index of the video stream to analyze
+    #[serde(default)]
+    pub stream_index: u32,
+
+    /// Analyze only the given intervals (ffprobe -read_intervals syntax, e.g. "%+60")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_intervals: Option<String>,
+}
+
+fn example_analyze_frames_request() -> AnalyzeFramesRequest {
+    AnalyzeFramesRequest {
+        input: Url::parse("https://example.com/video.mp4").unwrap(),
+        stream_index: 0,
+        read_intervals: None,
+    }
+}
+
+/// Statistics of frames of the same picture type.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameTypeStats {
+    pub count: u64,
+
+    /// Average packet size in bytes
+    pub average_size: f64,
+
+    /// Largest packet size in bytes
+    pub max_size: u64,
+
+    #[serde(skip)]
+    total_size: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzeFramesResponse {
+    /// Number of frames analyzed
+    pub frames: u64,
+
+    /// Number of keyframes
+    pub keyframes: u64,
+
+    /// Statistics per picture type (I, P, B, ...)
+    pub types: BTreeMap<String, FrameTypeStats>,
+
+    /// Longest run of consecutive B-frames
+    pub max_consecutive_b_frames: u64,
+
+    /// Average number of frames between keyframes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub average_gop_length: Option<f64>,
+
+    /// Longest distance between keyframes in frames
+    pub max_gop_length: u64,
+}
+
+/// Frame fields of the compact writer (e.g. "key_frame=1|pkt_size=4213|pict_type=I").
+#[derive(Debug, Default)]
+struct Frame<'a> {
+    key_frame: bool,
+    size: u64,
+    pict_type: &'a str,
+}
+
+impl<'a> Frame<'a> {
+    fn parse(line: &'a str) -> Self {
+        let mut frame = Frame::default();
+
+        for (key, value) in line.split('|').filter_map(|field| field.split_once('=')) {
+            match key {
+                "key_frame" => frame.key_frame = value == "1",
+                "pkt_size" => frame.size = value.parse().unwrap_or_default(),
+                "pict_type" => frame.pict_type = value,
+                _ => {}
+            }
+        }
+
+        frame
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _analyze_frames(
+        &self,
+        request: AnalyzeFramesRequest,
+    ) -> HandlerResult<AnalyzeFramesResponse> {
+        // Every frame is read, a sparse probe copy isn't enough
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let mut cmd = Command::new("ffprobe");
+
+        cmd.args(["-v", "error"])
+            .args(["-select_streams", &format!("v:{}", request.stream_index)])
+            .args(["-show_entries", "frame=key_frame,pkt_size,pict_type"])
+            .args(["-of", "compact=p=0"]);
+
+        if let Some(intervals) = &request.read_intervals {
+            cmd.args(["-read_intervals", intervals]);
+        }
+
+        // Frames are aggregated while ffprobe runs, the frame list of a long input doesn't fit in memory
+        let mut child = cmd
+            .arg(&input)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stdout = child.stdout.take().expect("Failed to get stdout");
+        let mut stderr = child.stderr.take().expect("Failed to get stderr");
+
+        let mut response = AnalyzeFramesResponse {
+            frames: 0,
+            keyframes: 0,
+            types: BTreeMap::new(),
+            max_consecutive_b_frames: 0,
+            average_gop_length: None,
+            max_gop_length: 0,
+        };
+
+        let (status, errors, ()) = tokio::try_join!(
+            child.wait(),
+            async {
+                let mut s = String::new();
+                stderr.read_to_string(&mut s).await?;
+                Ok::<_, std::io::Error>(s)
+            },
+            async {
+                let mut lines = BufReader::new(stdout).lines();
+
+                let mut b_frames = 0;
+                let mut gop = 0;
+                let mut gops = Vec::new();
+
+                while let Some(line) = lines.next_line().await? {
+                    let frame = Frame::parse(&line);
+
+                    response.frames += 1;
+
+                    let stats = response
+                        .types
+                        .entry(frame.pict_type.to_string())
+                        .or_default();
+                    stats.count += 1;
+                    stats.total_size += frame.size;
+                    stats.max_size = stats.max_size.max(frame.size);
+
+                    if frame.pict_type == "B" {
+                        b_frames += 1;
+                        response.max_consecutive_b_frames =
+                            response.max_consecutive_b_frames.max(b_frames);
+                    } else {
+                        b_frames = 0;
+                    }
+
+                    if frame.key_frame {
+                        response.keyframes += 1;
+                        if gop > 0 {
+                            gops.push(gop);
+                        }
+                        gop = 0;
+                    }
+                    gop += 1;
+                }
+
+                // The last GOP is cut off at the end of the input (or the interval), it's not counted
+                response.max_gop_length = gops.iter().copied().max().unwrap_or_default();
+                response.average_gop_length =
+                    (!gops.is_empty()).then(|| gops.iter().sum::<u64>() as f64 / gops.len() as f64);
+
+                Ok(())
+            }
+        )?;
+
+        if !status.success() {
+            return Err(HandlerError::from(format!("ffprobe failed: {}", errors)));
+        }
+
+        if response.frames == 0 {
+            return Err(TerminalError::new_with_code(400, "input has no video frames").into());
+        }
+
+        for stats in response.types.values_mut() {
+            stats.average_size = stats.total_size as f64 / stats.count as f64;
+        }
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::service::ServiceImpl;
+
+const LIVE_PROTOCOLS: &[&str] = &["rtsp", "rtsps", "rtmp", "rtmps", "srt", "udp", "tcp"];
+
+/// Limits on the inputs a job may process.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GuardrailConfig {
+    /// Maximum size of an input in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_input_size: Option<u64>,
+
+    /// Maximum duration of an input
+    #[serde(default, with = "humantime_serde")]
+    pub max_input_duration: Option<Duration>,
+}
+
+impl GuardrailConfig {
+    fn is_enabled(&self) -> bool {
+        self.max_input_size.is_some() || self.max_input_duration.is_some()
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Probes the inputs of a job and rejects it when any of them exceeds the limits.
+    ///
+    /// Inputs that can't be probed are let through: they are not media files
+    /// (or the job fails on them anyway).
+    pub(crate) async fn check_guardrails(&self, inputs: &[String]) -> HandlerResult<()> {
+        let config = &self.guardrails;
+
+        if !config.is_enabled() {
+            return Ok(());
+        }
+
+        for input in inputs {
+            let Ok(url) = Url::parse(input) else {
+                continue;
+            };
+
+            // Live sources have neither a size nor a duration
+            if LIVE_PROTOCOLS.contains(&url.scheme()) {
+                continue;
+            }
+
+            let Ok(probe) = self.probe(&url).await else {
+                continue;
+            };
+
+            let size = probe
+                .format
+                .as_ref()
+                .and_then(|format| format.size.as_deref())
+                .and_then(|size| size.parse::<u64>().ok());
+
+            if let (Some(size), Some(max)) = (size, config.max_input_size)
+                && size > max
+            {
+                return Err(TerminalError::new_with_code(
+                    413,
+                    format!("input {input} is {size} bytes, the limit is {max} bytes"),
+                )
+                .into());
+            }
+
+            if let (Some(duration), Some(max)) = (probe.duration(), config.max_input_duration)
+                && duration > max.as_secs_f64()
+            {
+                return Err(TerminalError::new_with_code(
+                    413,
+                    format!(
+                        "input {input} is {duration:.0}s long, the limit is {}s",
+                        max.as_secs()
+                    ),
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
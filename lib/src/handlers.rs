@@ -0,0 +1,47 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::service::ServiceImpl;
+
+/// Handlers served by this worker.
+///
+/// Disabled handlers stay registered, but fail every invocation with a terminal error.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct HandlerConfig {
+    /// Only serve these handlers (e.g. ["ffprobe", "analyze_frames"] for an analysis tier)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<Vec<String>>,
+
+    /// Handlers not served (e.g. ["ffmpeg"] to only accept typed requests)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled: Vec<String>,
+}
+
+impl HandlerConfig {
+    fn is_enabled(&self, handler: &str) -> bool {
+        let enabled = self
+            .enabled
+            .as_ref()
+            .is_none_or(|enabled| enabled.iter().any(|h| h == handler));
+
+        enabled && !self.disabled.iter().any(|h| h == handler)
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Fails with a terminal error when the handler is disabled in the config.
+    pub(crate) fn check_enabled(&self, handler: &str) -> Result<(), TerminalError> {
+        if self.handlers.is_enabled(handler) {
+            return Ok(());
+        }
+
+        Err(TerminalError::new_with_code(
+            403,
+            format!("handler {handler} is disabled on this worker"),
+        ))
+    }
+}
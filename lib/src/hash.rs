@@ -0,0 +1,341 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::service::{ServiceImpl, run_ffmpeg_stdout};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Md5,
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+        }
+    }
+}
+
+/// What is hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HashLevel {
+    /// Encoded packets, as stored (verifies remuxes)
+    Packets,
+    /// Decoded frames (verifies lossless encodes, whatever the codec)
+    #[default]
+    Frames,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_hash_streams_request())]
+pub struct HashStreamsRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Source the streams of the input are compared to (e.g. the original of a remux)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<Url>,
+
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
+
+    #[serde(default)]
+    pub level: HashLevel,
+}
+
+fn example_hash_streams_request() -> HashStreamsRequest {
+    HashStreamsRequest {
+        input: Url::parse("s3://bucket/archive/film.mkv").unwrap(),
+        reference: Some(Url::parse("s3://bucket/masters/film.mov").unwrap()),
+        algorithm: HashAlgorithm::Sha256,
+        level: HashLevel::Frames,
+    }
+}
+
+/// Frame of a stream differing from the reference.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameMismatch {
+    /// Number of the frame in the stream (starting from zero)
+    pub frame: u64,
+
+    /// Presentation time of the frame in the input in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamHash {
+    pub index: u32,
+
+    /// Type of the stream (e.g. "video" or "audio")
+    pub codec_type: String,
+
+    pub hash: String,
+
+    /// Hash of the stream with the same index in the reference
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference_hash: Option<String>,
+
+    /// Whether the stream is identical to the one of the reference
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches: Option<bool>,
+
+    /// First frame differing from the reference (frame level only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_mismatch: Option<FrameMismatch>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HashStreamsResponse {
+    pub streams: Vec<StreamHash>,
+
+    /// Whether every stream is identical to the reference (with the same number of streams)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches: Option<bool>,
+}
+
+/// Arguments decoding (or copying) every stream of an input (a path or URL ffmpeg reads) into a
+/// hashing muxer.
+fn hash_args(input: &str, level: HashLevel, algorithm: HashAlgorithm, muxer: &str) -> Vec<String> {
+    let mut args = vec![
+        "-i".to_string(),
+        input.to_string(),
+        "-map".to_string(),
+        "0:v?".to_string(),
+        "-map".to_string(),
+        "0:a?".to_string(),
+    ];
+
+    match level {
+        HashLevel::Packets => args.extend(["-c".to_string(), "copy".to_string()]),
+        // 32-bit samples keep the precision of any source (the muxer defaults to 16-bit)
+        HashLevel::Frames => args.extend([
+            "-c:v".to_string(),
+            "rawvideo".to_string(),
+            "-c:a".to_string(),
+            "pcm_s32le".to_string(),
+        ]),
+    }
+
+    args.extend([
+        "-f".to_string(),
+        muxer.to_string(),
+        "-hash".to_string(),
+        algorithm.name().to_string(),
+        "-".to_string(),
+    ]);
+
+    args
+}
+
+/// Returns the value of a hash, without the algorithm prefix ("SHA256=...").
+fn hash_value(field: &str) -> String {
+    let field = field.trim();
+
+    field
+        .split_once('=')
+        .map_or(field, |(_, hash)| hash)
+        .to_string()
+}
+
+/// Parses the output of the streamhash muxer ("0,v,SHA256=...").
+fn parse_streamhash(output: &str) -> Vec<(u32, String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ',');
+            let index = fields.next()?.trim().parse().ok()?;
+            let codec_type = match fields.next()?.trim() {
+                "v" => "video",
+                "a" => "audio",
+                "s" => "subtitle",
+                _ => "data",
+            };
+
+            Some((index, codec_type.to_string(), hash_value(fields.next()?)))
+        })
+        .collect()
+}
+
+/// Frames of a stream from the output of the framehash muxer: presentation time and hash.
+fn parse_framehash(output: &str, stream: u32) -> Vec<(Option<f64>, String)> {
+    // "#tb 0: 1/25" gives the time base of the timestamps of the stream
+    let time_base = output.lines().find_map(|line| {
+        let (num, den) = line
+            .strip_prefix(&format!("#tb {stream}: "))?
+            .split_once('/')?;
+
+        Some(num.trim().parse::<f64>().ok()? / den.trim().parse::<f64>().ok()?)
+    });
+
+    output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            // stream, dts, pts, duration, size, hash
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+
+            if fields.len() < 6 || fields[0].parse::<u32>().ok()? != stream {
+                return None;
+            }
+
+            let time = fields[2]
+                .parse::<i64>()
+                .ok()
+                .zip(time_base)
+                .map(|(pts, time_base)| pts as f64 * time_base);
+
+            Some((time, hash_value(fields[5])))
+        })
+        .collect()
+}
+
+impl HashStreamsRequest {
+    /// Finds the first frame of a stream of an input differing from the reference.
+    async fn first_mismatch(
+        &self,
+        input: &str,
+        reference: &str,
+        stream: u32,
+    ) -> HandlerResult<FrameMismatch> {
+        let mut frames = Vec::new();
+
+        for input in [input, reference] {
+            let output =
+                run_ffmpeg_stdout(&hash_args(input, self.level, self.algorithm, "framehash"))
+                    .await?;
+
+            frames.push(parse_framehash(&output, stream));
+        }
+
+        let (input, reference) = (&frames[0], &frames[1]);
+
+        // A stream cut short differs at its first missing frame
+        let frame = input
+            .iter()
+            .zip(reference)
+            .position(|((_, hash), (_, reference))| hash != reference)
+            .unwrap_or(input.len().min(reference.len()));
+
+        Ok(FrameMismatch {
+            frame: frame as u64,
+            time: input
+                .get(frame)
+                .or_else(|| reference.get(frame))
+                .and_then(|(time, _)| *time),
+        })
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _hash_streams(
+        &self,
+        request: HashStreamsRequest,
+    ) -> HandlerResult<HashStreamsResponse> {
+        // The inputs may be read several times: storage inputs are downloaded once
+        let input_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, input_dir.path()).await?;
+
+        let hashes = parse_streamhash(
+            &run_ffmpeg_stdout(&hash_args(
+                &input,
+                request.level,
+                request.algorithm,
+                "streamhash",
+            ))
+            .await?,
+        );
+
+        if hashes.is_empty() {
+            return Err(
+                TerminalError::new_with_code(400, "input has no video or audio stream").into(),
+            );
+        }
+
+        let Some(reference) = &request.reference else {
+            return Ok(HashStreamsResponse {
+                streams: hashes
+                    .into_iter()
+                    .map(|(index, codec_type, hash)| StreamHash {
+                        index,
+                        codec_type,
+                        hash,
+                        reference_hash: None,
+                        matches: None,
+                        first_mismatch: None,
+                    })
+                    .collect(),
+                matches: None,
+            });
+        };
+
+        let reference_dir = TempDir::new()?;
+        let reference = self.local_input(reference, reference_dir.path()).await?;
+
+        let reference_hashes = parse_streamhash(
+            &run_ffmpeg_stdout(&hash_args(
+                &reference,
+                request.level,
+                request.algorithm,
+                "streamhash",
+            ))
+            .await?,
+        );
+
+        let mut streams = Vec::new();
+
+        for (index, codec_type, hash) in hashes {
+            let reference_hash = reference_hashes
+                .iter()
+                .find(|(reference_index, reference_type, _)| {
+                    *reference_index == index && *reference_type == codec_type
+                })
+                .map(|(_, _, hash)| hash.clone());
+
+            let matches = reference_hash.as_ref() == Some(&hash);
+
+            // Packets of different encodes can't be lined up, decoded frames can
+            let first_mismatch =
+                if !matches && reference_hash.is_some() && request.level == HashLevel::Frames {
+                    Some(request.first_mismatch(&input, &reference, index).await?)
+                } else {
+                    None
+                };
+
+            streams.push(StreamHash {
+                index,
+                codec_type,
+                hash,
+                reference_hash,
+                matches: Some(matches),
+                first_mismatch,
+            });
+        }
+
+        let matches = streams.len() == reference_hashes.len()
+            && streams.iter().all(|stream| stream.matches == Some(true));
+
+        Ok(HashStreamsResponse {
+            streams,
+            matches: Some(matches),
+        })
+    }
+}
@@ -0,0 +1,283 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::aspect::parse_ratio;
+use crate::service::{ServiceImpl, run_ffprobe};
+
+/// SMPTE ST 2086 mastering display color volume.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct MasteringDisplay {
+    pub red_x: Option<String>,
+    pub red_y: Option<String>,
+    pub green_x: Option<String>,
+    pub green_y: Option<String>,
+    pub blue_x: Option<String>,
+    pub blue_y: Option<String>,
+    pub white_point_x: Option<String>,
+    pub white_point_y: Option<String>,
+    pub min_luminance: Option<String>,
+    pub max_luminance: Option<String>,
+}
+
+impl MasteringDisplay {
+    fn values(&self) -> [(&'static str, Option<&str>); 10] {
+        [
+            ("red_x", self.red_x.as_deref()),
+            ("red_y", self.red_y.as_deref()),
+            ("green_x", self.green_x.as_deref()),
+            ("green_y", self.green_y.as_deref()),
+            ("blue_x", self.blue_x.as_deref()),
+            ("blue_y", self.blue_y.as_deref()),
+            ("white_point_x", self.white_point_x.as_deref()),
+            ("white_point_y", self.white_point_y.as_deref()),
+            ("min_luminance", self.min_luminance.as_deref()),
+            ("max_luminance", self.max_luminance.as_deref()),
+        ]
+    }
+}
+
+/// CTA-861.3 content light level.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct ContentLightLevel {
+    pub max_content: Option<u32>,
+    pub max_average: Option<u32>,
+}
+
+/// HDR signaling of the first video stream.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HdrMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_primaries: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_transfer: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_space: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mastering_display: Option<MasteringDisplay>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_light_level: Option<ContentLightLevel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SideData {
+    side_data_type: String,
+
+    #[serde(flatten)]
+    values: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbedStream {
+    color_primaries: Option<String>,
+    color_transfer: Option<String>,
+    color_space: Option<String>,
+
+    #[serde(default)]
+    side_data_list: Vec<SideData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbedFrame {
+    #[serde(default)]
+    side_data_list: Vec<SideData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Probe {
+    #[serde(default)]
+    streams: Vec<ProbedStream>,
+
+    #[serde(default)]
+    frames: Vec<ProbedFrame>,
+}
+
+fn find_side_data<T: DeserializeOwned>(side_data: &[SideData], kind: &str) -> Option<T> {
+    side_data
+        .iter()
+        .find(|data| data.side_data_type == kind)
+        .and_then(|data| {
+            serde_json::from_value(serde_json::Value::Object(data.values.clone())).ok()
+        })
+}
+
+/// Reads the HDR signaling of an input.
+///
+/// Static metadata lives either in the container (stream side data) or in the
+/// bitstream (SEI, exposed as frame side data), so the first frame is read as well.
+pub(crate) async fn hdr_metadata(input: &str) -> HandlerResult<HdrMetadata> {
+    let probe: Probe = run_ffprobe(&[
+        "-select_streams".to_string(),
+        "v:0".to_string(),
+        "-show_streams".to_string(),
+        "-show_frames".to_string(),
+        "-read_intervals".to_string(),
+        "%+#1".to_string(),
+        input.to_string(),
+    ])
+    .await?;
+
+    let Some(stream) = probe.streams.into_iter().next() else {
+        return Err(TerminalError::new_with_code(400, "input has no video stream").into());
+    };
+
+    let side_data: Vec<SideData> = stream
+        .side_data_list
+        .into_iter()
+        .chain(
+            probe
+                .frames
+                .into_iter()
+                .flat_map(|frame| frame.side_data_list),
+        )
+        .collect();
+
+    Ok(HdrMetadata {
+        color_primaries: stream.color_primaries,
+        color_transfer: stream.color_transfer,
+        color_space: stream.color_space,
+        mastering_display: find_side_data(&side_data, "Mastering display metadata"),
+        content_light_level: find_side_data(&side_data, "Content light level metadata"),
+    })
+}
+
+fn same_value(a: &str, b: &str) -> bool {
+    match (parse_ratio(a), parse_ratio(b)) {
+        (Some(a), Some(b)) => (a - b).abs() < 1e-4,
+        _ => a == b,
+    }
+}
+
+/// Lists the HDR signaling present in the source but missing or altered in the output.
+pub(crate) fn compare_hdr_metadata(source: &HdrMetadata, output: &HdrMetadata) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    let tags = [
+        (
+            "color primaries",
+            &source.color_primaries,
+            &output.color_primaries,
+        ),
+        (
+            "transfer characteristics",
+            &source.color_transfer,
+            &output.color_transfer,
+        ),
+        ("color space", &source.color_space, &output.color_space),
+    ];
+
+    for (name, source, output) in tags {
+        if source.is_some() && source != output {
+            issues.push(format!(
+                "{name} changed from {} to {}",
+                source.as_deref().unwrap_or("unset"),
+                output.as_deref().unwrap_or("unset")
+            ));
+        }
+    }
+
+    match (&source.mastering_display, &output.mastering_display) {
+        (Some(_), None) => issues.push("mastering display metadata was dropped".to_string()),
+        (Some(source), Some(output)) => {
+            for ((name, a), (_, b)) in source.values().into_iter().zip(output.values()) {
+                if let (Some(a), Some(b)) = (a, b)
+                    && !same_value(a, b)
+                {
+                    issues.push(format!("mastering display {name} changed from {a} to {b}"));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    match (&source.content_light_level, &output.content_light_level) {
+        (Some(_), None) => issues.push("content light level metadata was dropped".to_string()),
+        (Some(source), Some(output)) if source != output => issues.push(format!(
+            "content light level changed from {:?}/{:?} to {:?}/{:?}",
+            source.max_content, source.max_average, output.max_content, output.max_average
+        )),
+        _ => {}
+    }
+
+    issues
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_validate_hdr_request())]
+pub struct ValidateHdrRequest {
+    /// Path or URL to the source file
+    pub source: Url,
+
+    /// Path or URL to the encoded file
+    pub encoded: Url,
+
+    /// Fail with a terminal error when metadata is missing (otherwise only report it)
+    #[serde(default = "default_fail_on_mismatch")]
+    pub fail_on_mismatch: bool,
+}
+
+fn default_fail_on_mismatch() -> bool {
+    true
+}
+
+fn example_validate_hdr_request() -> ValidateHdrRequest {
+    ValidateHdrRequest {
+        source: Url::parse("s3://bucket/master.mov").unwrap(),
+        encoded: Url::parse("s3://bucket/hdr10.mp4").unwrap(),
+        fail_on_mismatch: true,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidateHdrResponse {
+    pub source: HdrMetadata,
+    pub encoded: HdrMetadata,
+
+    /// Metadata dropped or altered by the encode
+    pub issues: Vec<String>,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _validate_hdr(
+        &self,
+        request: ValidateHdrRequest,
+    ) -> HandlerResult<ValidateHdrResponse> {
+        // ffprobe can't read storage inputs, stage the parts it needs instead
+        let (source_dir, encoded_dir) = (TempDir::new()?, TempDir::new()?);
+        let (source, encoded) = tokio::try_join!(
+            self.probe_input(&request.source, source_dir.path()),
+            self.probe_input(&request.encoded, encoded_dir.path()),
+        )?;
+
+        let (source, encoded) = tokio::try_join!(hdr_metadata(&source), hdr_metadata(&encoded))?;
+
+        let issues = compare_hdr_metadata(&source, &encoded);
+
+        if request.fail_on_mismatch && !issues.is_empty() {
+            return Err(TerminalError::new_with_code(
+                422,
+                format!("HDR metadata was not preserved: {}", issues.join("; ")),
+            )
+            .into());
+        }
+
+        Ok(ValidateHdrResponse {
+            source,
+            encoded,
+            issues,
+        })
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+/// Number of recent durations kept per handler.
+const MAX_SAMPLES: usize = 1000;
+
+/// Speculative second attempts of slow jobs dispatched with hedging.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HedgingConfig {
+    /// Percentile of the recent durations of a handler after which a second attempt is launched
+    #[serde(default = "default_percentile")]
+    pub percentile: f64,
+
+    /// Recent durations of a handler required before its jobs are hedged
+    #[serde(default = "default_min_samples")]
+    pub min_samples: usize,
+}
+
+fn default_percentile() -> f64 {
+    95.0
+}
+
+fn default_min_samples() -> usize {
+    20
+}
+
+impl Default for HedgingConfig {
+    fn default() -> Self {
+        Self {
+            percentile: default_percentile(),
+            min_samples: default_min_samples(),
+        }
+    }
+}
+
+/// Attempt of a hedged job, reporting its outcome to the dispatcher through an awakeable.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptRequest {
+    /// Awakeable completed by the first attempt to finish
+    pub awakeable_id: String,
+
+    /// Awakeable completed by this attempt once it finishes
+    pub attempt_awakeable_id: String,
+
+    /// Index of the attempt (0 for the original one)
+    pub attempt: u8,
+
+    /// Service variant running the job
+    pub service: String,
+
+    pub handler: String,
+
+    pub request: Value,
+
+    /// Prefixes the attempt uploads its outputs under, removed when it fails (or is cancelled)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prefixes: Vec<Url>,
+}
+
+/// Outcome of an attempt: failures are reported too, so that the dispatcher
+/// can wait for the other attempt instead of failing the job.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct AttemptOutcome {
+    pub attempt: u8,
+    pub result: Result<Value, AttemptError>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct AttemptError {
+    pub code: u16,
+    pub message: String,
+}
+
+/// Recent durations of the jobs dispatched by this process (per handler).
+#[derive(Debug, Default)]
+pub(crate) struct Durations {
+    samples: Mutex<HashMap<String, VecDeque<Duration>>>,
+}
+
+impl Durations {
+    pub(crate) fn record(&self, handler: &str, duration: Duration) {
+        let mut samples = self.samples.lock().unwrap();
+        let samples = samples.entry(handler.to_string()).or_default();
+
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+
+        samples.push_back(duration);
+    }
+
+    /// Returns the time after which a job of the handler is hedged
+    /// (unless too few of its durations are known yet).
+    pub(crate) fn hedge_after(&self, handler: &str, config: &HedgingConfig) -> Option<Duration> {
+        let samples = self.samples.lock().unwrap();
+        let samples = samples.get(handler)?;
+
+        if samples.len() < config.min_samples.max(1) {
+            return None;
+        }
+
+        let mut sorted: Vec<_> = samples.iter().copied().collect();
+        sorted.sort();
+
+        let rank = (config.percentile.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil();
+
+        sorted.get((rank as usize).saturating_sub(1)).copied()
+    }
+}
+
+/// Rewrites the output locations of a request so that an attempt uploads its outputs
+/// under its own prefix, returning the request along with the prefixes.
+///
+/// The prefix is inserted before the first segment with a placeholder (e.g. "{jobId}"):
+/// "s3://bucket/encoded/{jobId}/" becomes "s3://bucket/encoded/.hedge-ID/{jobId}/",
+/// so that the prefix is known before the job resolves its placeholders.
+pub(crate) fn stage_outputs(request: &Value, marker: &str) -> (Value, Vec<Url>) {
+    let mut request = request.clone();
+    let mut prefixes = Vec::new();
+
+    if let Some(output) = request.get_mut("output") {
+        stage_output(output, marker, &mut prefixes);
+    }
+
+    if let Some(outputs) = request.get_mut("outputs").and_then(Value::as_array_mut) {
+        for output in outputs {
+            stage_output(output, marker, &mut prefixes);
+        }
+    }
+
+    (request, prefixes)
+}
+
+fn stage_output(output: &mut Value, marker: &str, prefixes: &mut Vec<Url>) {
+    let Some(location) = output.get_mut("location") else {
+        return;
+    };
+
+    if let Some((staged, prefix)) = location
+        .as_str()
+        .and_then(|location| stage_location(location, marker))
+    {
+        *location = Value::String(staged);
+        prefixes.push(prefix);
+    }
+}
+
+fn stage_location(location: &str, marker: &str) -> Option<(String, Url)> {
+    let mut url = Url::parse(location).ok()?;
+    let path = url.path().to_string();
+
+    let resolved = path.find("%7B").unwrap_or(path.len());
+    let split = path[..resolved].rfind('/')? + 1;
+
+    let mut prefix = url.clone();
+    prefix.set_path(&format!("{}{marker}/", &path[..split]));
+
+    url.set_path(&format!("{}{marker}/{}", &path[..split], &path[split..]));
+
+    Some((url.to_string(), prefix))
+}
+
+/// Returns the location an object uploaded under the prefix of an attempt is promoted to.
+pub(crate) fn unstage(location: &str, marker: &str) -> String {
+    location.replace(&format!("/{marker}/"), "/")
+}
+
+/// Rewrites the locations in the response of the winning attempt to the promoted ones.
+pub(crate) fn unstage_response(response: &Value, marker: &str) -> Value {
+    match response {
+        Value::String(s) => Value::String(unstage(s, marker)),
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| unstage_response(value, marker))
+                .collect(),
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), unstage_response(value, marker)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn stage_outputs_before_placeholders() {
+        let request = json!({
+            "args": ["-i", "https://example.com/input.mp4", "output.mp4"],
+            "output": { "location": "s3://bucket/encoded/{jobId}/" },
+            "outputs": [{ "pattern": "*.jpg", "location": "s3://bucket/thumbs/" }],
+        });
+
+        let (staged, prefixes) = stage_outputs(&request, ".hedge-1");
+
+        assert_eq!(
+            staged["output"]["location"],
+            "s3://bucket/encoded/.hedge-1/%7BjobId%7D/"
+        );
+        assert_eq!(
+            staged["outputs"][0]["location"],
+            "s3://bucket/thumbs/.hedge-1/"
+        );
+        assert_eq!(
+            prefixes,
+            vec![
+                Url::parse("s3://bucket/encoded/.hedge-1/").unwrap(),
+                Url::parse("s3://bucket/thumbs/.hedge-1/").unwrap(),
+            ]
+        );
+        assert_eq!(staged["args"], request["args"]);
+    }
+
+    #[test]
+    fn stage_file_outputs() {
+        let request = json!({ "output": { "location": "s3://bucket/output.mp4" } });
+
+        let (staged, _) = stage_outputs(&request, ".hedge-1");
+
+        assert_eq!(
+            staged["output"]["location"],
+            "s3://bucket/.hedge-1/output.mp4"
+        );
+    }
+
+    #[test]
+    fn unstage_promoted_locations() {
+        let response = json!({
+            "outputs": ["s3://bucket/encoded/.hedge-1/abc/output.mp4"],
+            "stderr": "",
+        });
+
+        assert_eq!(
+            unstage_response(&response, ".hedge-1"),
+            json!({ "outputs": ["s3://bucket/encoded/abc/output.mp4"], "stderr": "" })
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::service::{ServiceImpl, run_ffmpeg};
+
+/// Returns the value following `key` in a filter log line (e.g. "pts_time:" in showinfo output).
+pub(crate) fn log_value(line: &str, key: &str) -> Option<f64> {
+    let (_, rest) = line.split_once(key)?;
+
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+/// Returns the timestamps of the scene cuts of the first video stream.
+pub(crate) async fn scene_changes(input: &str, threshold: f64) -> HandlerResult<Vec<f64>> {
+    let log = run_ffmpeg(&[
+        "-i".to_string(),
+        input.to_string(),
+        "-map".to_string(),
+        "0:v:0".to_string(),
+        "-vf".to_string(),
+        format!("select='gt(scene,{threshold})',showinfo"),
+        "-f".to_string(),
+        "null".to_string(),
+        "-".to_string(),
+    ])
+    .await?;
+
+    Ok(log
+        .lines()
+        .filter(|line| line.contains("Parsed_showinfo"))
+        .filter_map(|line| log_value(line, "pts_time:"))
+        .collect())
+}
+
+/// Returns the momentary loudness (LUFS) of the first audio stream, sampled every 100ms.
+pub(crate) async fn momentary_loudness(input: &str) -> HandlerResult<Vec<(f64, f64)>> {
+    let log = run_ffmpeg(&[
+        "-i".to_string(),
+        input.to_string(),
+        "-map".to_string(),
+        "0:a:0".to_string(),
+        "-af".to_string(),
+        "ebur128=framelog=info".to_string(),
+        "-f".to_string(),
+        "null".to_string(),
+        "-".to_string(),
+    ])
+    .await?;
+
+    Ok(log
+        .lines()
+        .filter(|line| line.contains("Parsed_ebur128") && line.contains(" M:"))
+        .filter_map(|line| Some((log_value(line, "t:")?, log_value(line, " M:")?)))
+        .collect())
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_detect_highlights_request())]
+pub struct DetectHighlightsRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Length of the analyzed windows (and proposed highlights) in seconds
+    #[serde(default = "default_window")]
+    pub window: f64,
+
+    /// Maximum number of highlights returned
+    #[serde(default = "default_max_highlights")]
+    pub max_highlights: usize,
+
+    /// Scene change threshold (0-1)
+    #[serde(default = "default_scene_threshold")]
+    pub scene_threshold: f64,
+
+    /// Weight of loudness in the score, scene activity gets the rest (0-1)
+    #[serde(default = "default_loudness_weight")]
+    pub loudness_weight: f64,
+
+    /// Minimum score of a highlight (0-1)
+    #[serde(default)]
+    pub min_score: f64,
+}
+
+fn default_window() -> f64 {
+    10.0
+}
+
+fn default_max_highlights() -> usize {
+    5
+}
+
+pub(crate) fn default_scene_threshold() -> f64 {
+    0.3
+}
+
+fn default_loudness_weight() -> f64 {
+    0.5
+}
+
+fn example_detect_highlights_request() -> DetectHighlightsRequest {
+    DetectHighlightsRequest {
+        input: Url::parse("https://example.com/match.mp4").unwrap(),
+        window: default_window(),
+        max_highlights: default_max_highlights(),
+        scene_threshold: default_scene_threshold(),
+        loudness_weight: default_loudness_weight(),
+        min_score: 0.0,
+    }
+}
+
+/// A candidate highlight.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Highlight {
+    /// Start of the highlight in seconds
+    pub start: f64,
+
+    /// End of the highlight in seconds
+    pub end: f64,
+
+    /// Combined score (0-1)
+    pub score: f64,
+
+    /// Mean momentary loudness in LUFS
+    pub loudness: f64,
+
+    /// Number of scene cuts
+    pub scene_changes: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DetectHighlightsResponse {
+    /// Highlights ordered by score
+    pub highlights: Vec<Highlight>,
+}
+
+/// Scales values to 0-1, flat series score zero.
+fn normalize(values: &[f64]) -> Vec<f64> {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+    values
+        .iter()
+        .map(|value| {
+            if max > min {
+                (value - min) / (max - min)
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _detect_highlights(
+        &self,
+        request: DetectHighlightsRequest,
+    ) -> HandlerResult<DetectHighlightsResponse> {
+        if request.window <= 0.0 || !(0.0..=1.0).contains(&request.loudness_weight) {
+            return Err(TerminalError::new_with_code(
+                400,
+                "window must be positive and loudnessWeight between 0 and 1",
+            )
+            .into());
+        }
+
+        let probe = self.probe(&request.input).await?;
+        let duration = probe.duration().unwrap_or_default();
+
+        let has_audio = probe.stream("audio").is_some();
+
+        // Both analyses read the input: storage inputs are downloaded once
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let (cuts, loudness) =
+            tokio::try_join!(scene_changes(&input, request.scene_threshold), async {
+                if has_audio {
+                    momentary_loudness(&input).await
+                } else {
+                    Ok(Vec::new())
+                }
+            },)?;
+
+        // Windows overlap by half so that bursts on a window boundary aren't split
+        let step = request.window / 2.0;
+        let mut windows = Vec::new();
+        let mut start = 0.0;
+
+        while start + request.window <= duration.max(request.window) {
+            let end = start + request.window;
+
+            let levels: Vec<f64> = loudness
+                .iter()
+                .filter(|(t, _)| (start..end).contains(t))
+                // Digital silence is reported as -inf or -120 LUFS
+                .map(|(_, level)| level.max(-70.0))
+                .collect();
+
+            let mean = if levels.is_empty() {
+                -70.0
+            } else {
+                levels.iter().sum::<f64>() / levels.len() as f64
+            };
+
+            let count = cuts.iter().filter(|t| (start..end).contains(*t)).count();
+
+            windows.push((start, end, mean, count));
+            start += step;
+        }
+
+        let loudness_scores = normalize(&windows.iter().map(|w| w.2).collect::<Vec<_>>());
+        let scene_scores = normalize(&windows.iter().map(|w| w.3 as f64).collect::<Vec<_>>());
+
+        let loudness_weight = if has_audio {
+            request.loudness_weight
+        } else {
+            0.0
+        };
+
+        let mut candidates: Vec<Highlight> = windows
+            .iter()
+            .enumerate()
+            .map(|(i, (start, end, mean, count))| Highlight {
+                start: *start,
+                end: end.min(duration),
+                score: loudness_weight * loudness_scores[i]
+                    + (1.0 - loudness_weight) * scene_scores[i],
+                loudness: *mean,
+                scene_changes: *count,
+            })
+            .filter(|highlight| highlight.score > 0.0 && highlight.score >= request.min_score)
+            .collect();
+
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        let mut highlights: Vec<Highlight> = Vec::new();
+
+        for candidate in candidates {
+            if highlights.len() >= request.max_highlights {
+                break;
+            }
+
+            let overlaps = highlights
+                .iter()
+                .any(|h| candidate.start < h.end && h.start < candidate.end);
+
+            if !overlaps {
+                highlights.push(candidate);
+            }
+        }
+
+        Ok(DetectHighlightsResponse { highlights })
+    }
+}
@@ -0,0 +1,328 @@
+use std::time::Duration;
+
+use jiff::Timestamp;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::metering::Usage;
+
+/// Summary of a finished job.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobSummary {
+    /// ID of the job (the one given in the x-job-id header, if any)
+    #[serde(default)]
+    pub job_id: String,
+
+    /// Name of the handler that ran the job
+    pub handler: String,
+
+    /// Media read by the job
+    pub inputs: Vec<String>,
+
+    /// Media written by the job
+    pub outputs: Vec<String>,
+
+    pub finished_at: Timestamp,
+
+    pub usage: Usage,
+}
+
+/// Collects the URLs in a request or response, skipping the values under `skip` keys.
+pub(crate) fn collect_urls(value: &Value, skip: &[&str], urls: &mut Vec<String>) {
+    match value {
+        Value::String(s)
+            if s.contains("://") && url::Url::parse(s).is_ok() && !urls.contains(s) =>
+        {
+            urls.push(s.clone());
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_urls(value, skip, urls);
+            }
+        }
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                if !skip.contains(&key.as_str()) {
+                    collect_urls(value, skip, urls);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Limits on the number and the age of the jobs kept per caller.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HistoryRetention {
+    /// Maximum number of jobs kept
+    #[serde(default = "default_max_jobs")]
+    pub max_jobs: usize,
+
+    /// Jobs older than this are dropped
+    #[serde(default, with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+}
+
+fn default_max_jobs() -> usize {
+    1000
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        Self {
+            max_jobs: default_max_jobs(),
+            max_age: None,
+        }
+    }
+}
+
+/// Narrows down the jobs returned by `history`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handler: Option<String>,
+
+    /// Only jobs reading or writing media whose URL contains this value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+
+    /// Only jobs finished at or after this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<Timestamp>,
+
+    /// Only jobs finished before this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<Timestamp>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, job: &JobSummary) -> bool {
+        self.handler.as_ref().is_none_or(|h| *h == job.handler)
+            && self.asset.as_ref().is_none_or(|asset| {
+                job.inputs
+                    .iter()
+                    .chain(&job.outputs)
+                    .any(|url| url.contains(asset.as_str()))
+            })
+            && self.from.is_none_or(|from| job.finished_at >= from)
+            && self.to.is_none_or(|to| job.finished_at < to)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryRequest {
+    /// Page number starting from zero
+    #[serde(default)]
+    pub page: usize,
+
+    #[serde(default = "default_page_size")]
+    pub page_size: usize,
+
+    #[serde(default)]
+    pub filter: HistoryFilter,
+}
+
+fn default_page_size() -> usize {
+    50
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryResponse {
+    /// Matching jobs, most recent first
+    pub jobs: Vec<JobSummary>,
+
+    /// Number of matching jobs
+    pub total: usize,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_page: Option<usize>,
+}
+
+const JOBS: &str = "jobs";
+
+/// History of the jobs finished by a caller (keyed by caller).
+#[restate_sdk::object]
+#[name = "FFmpegHistory"]
+pub trait History {
+    /// Record a finished job.
+    async fn record(job: Json<JobSummary>) -> HandlerResult<()>;
+
+    /// List finished jobs.
+    #[shared]
+    async fn history(request: Json<HistoryRequest>) -> HandlerResult<Json<HistoryResponse>>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HistoryImpl {
+    retention: HistoryRetention,
+}
+
+impl HistoryImpl {
+    pub fn new(retention: HistoryRetention) -> Self {
+        Self { retention }
+    }
+}
+
+impl History for HistoryImpl {
+    async fn record(&self, ctx: ObjectContext<'_>, job: Json<JobSummary>) -> HandlerResult<()> {
+        let job = job.into_inner();
+
+        let mut jobs = ctx
+            .get::<Json<Vec<JobSummary>>>(JOBS)
+            .await?
+            .map(Json::into_inner)
+            .unwrap_or_default();
+
+        // Age is measured from the newest job so that pruning stays deterministic on replay
+        if let Some(max_age) = self.retention.max_age
+            && let Ok(cutoff) = job.finished_at.checked_sub(max_age)
+        {
+            jobs.retain(|job| job.finished_at >= cutoff);
+        }
+
+        jobs.push(job);
+
+        let excess = jobs.len().saturating_sub(self.retention.max_jobs);
+        jobs.drain(..excess);
+
+        ctx.set(JOBS, Json(jobs));
+
+        Ok(())
+    }
+
+    async fn history(
+        &self,
+        ctx: SharedObjectContext<'_>,
+        request: Json<HistoryRequest>,
+    ) -> HandlerResult<Json<HistoryResponse>> {
+        let request = request.into_inner();
+
+        if request.page_size == 0 {
+            return Err(TerminalError::new_with_code(400, "pageSize must be positive").into());
+        }
+
+        let jobs = ctx
+            .get::<Json<Vec<JobSummary>>>(JOBS)
+            .await?
+            .map(Json::into_inner)
+            .unwrap_or_default();
+
+        Ok(Json(history_page(jobs, &request)))
+    }
+}
+
+/// Returns the page of the matching jobs (most recent first) a request asks for.
+fn history_page(jobs: Vec<JobSummary>, request: &HistoryRequest) -> HistoryResponse {
+    let matching: Vec<JobSummary> = jobs
+        .into_iter()
+        .rev()
+        .filter(|job| request.filter.matches(job))
+        .collect();
+
+    let total = matching.len();
+    let start = request.page.saturating_mul(request.page_size);
+
+    let page: Vec<JobSummary> = matching
+        .into_iter()
+        .skip(start)
+        .take(request.page_size)
+        .collect();
+
+    HistoryResponse {
+        // Lazy: page numbers past the end may be as large as usize::MAX
+        next_page: (start + page.len() < total).then(|| request.page + 1),
+        jobs: page,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Jobs recorded one minute apart, oldest first (as stored).
+    fn jobs(handlers: &[&str]) -> Vec<JobSummary> {
+        let start: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+
+        handlers
+            .iter()
+            .enumerate()
+            .map(|(i, handler)| JobSummary {
+                job_id: format!("job-{i}"),
+                handler: handler.to_string(),
+                inputs: vec![format!("s3://bucket/in-{i}.mov")],
+                outputs: Vec::new(),
+                finished_at: start + jiff::SignedDuration::from_mins(i as i64),
+                usage: Usage::default(),
+            })
+            .collect()
+    }
+
+    fn request(page: usize, page_size: usize) -> HistoryRequest {
+        HistoryRequest {
+            page,
+            page_size,
+            filter: HistoryFilter::default(),
+        }
+    }
+
+    fn ids(response: &HistoryResponse) -> Vec<&str> {
+        response
+            .jobs
+            .iter()
+            .map(|job| job.job_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn pages_follow_each_other() {
+        let jobs = jobs(&["ffmpeg"; 5]);
+
+        let first = history_page(jobs.clone(), &request(0, 2));
+        assert_eq!(ids(&first), ["job-4", "job-3"]);
+        assert_eq!(first.total, 5);
+        assert_eq!(first.next_page, Some(1));
+
+        let second = history_page(jobs.clone(), &request(1, 2));
+        assert_eq!(ids(&second), ["job-2", "job-1"]);
+        assert_eq!(second.next_page, Some(2));
+
+        let last = history_page(jobs, &request(2, 2));
+        assert_eq!(ids(&last), ["job-0"]);
+        assert_eq!(last.next_page, None);
+    }
+
+    #[test]
+    fn page_past_the_end_is_empty() {
+        let response = history_page(jobs(&["ffmpeg"; 4]), &request(2, 2));
+
+        assert!(response.jobs.is_empty());
+        assert_eq!(response.total, 4);
+        assert_eq!(response.next_page, None);
+
+        let response = history_page(jobs(&["ffmpeg"; 4]), &request(usize::MAX, 2));
+
+        assert!(response.jobs.is_empty());
+    }
+
+    #[test]
+    fn pages_count_matching_jobs_only() {
+        let mut request = request(0, 2);
+        request.filter.handler = Some("thumbnail".to_string());
+
+        let response = history_page(
+            jobs(&["thumbnail", "ffmpeg", "thumbnail", "ffmpeg", "thumbnail"]),
+            &request,
+        );
+
+        assert_eq!(ids(&response), ["job-4", "job-2"]);
+        assert_eq!(response.total, 3);
+        assert_eq!(response.next_page, Some(1));
+    }
+}
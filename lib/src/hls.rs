@@ -0,0 +1,1428 @@
+use std::collections::BTreeMap;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::capabilities::require_encoder;
+use crate::captions::SubtitleFormat;
+use crate::encode::VideoCodec;
+use crate::inline::with_inlined;
+use crate::inputs::input_arg;
+use crate::metering::{Metered, metered};
+use crate::renditions::{
+    PackagedSubtitles, SubtitleRendition, SubtitleRole, read_playlists, segment_webvtt,
+    subtitle_playlist, validate_subtitles,
+};
+use crate::service::{
+    FfmpegRequest, FfprobeResponse, Output, ServiceClient, ServiceImpl, Stream, copy_files,
+    work_files,
+};
+use crate::storyboard::{Storyboard, ThumbnailTiles};
+use crate::templates::{JobMetadata, job_id, with_job};
+
+/// Name of the master playlist.
+const MASTER_PLAYLIST: &str = "master.m3u8";
+
+/// Directory of the thumbnail tiles.
+pub(crate) const THUMBNAILS_DIR: &str = "thumbnails";
+
+/// Group of the subtitle renditions in the master playlist.
+const SUBTITLES_GROUP: &str = "subs";
+
+/// CODECS attribute of AAC-LC renditions.
+const AAC_CODEC: &str = "mp4a.40.2";
+
+/// Largest number of renditions in a ladder.
+const MAX_RENDITIONS: usize = 10;
+
+/// Container of HLS segments.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HlsSegmentType {
+    /// MPEG transport stream segments (plays everywhere)
+    #[default]
+    Mpegts,
+    /// Fragmented MP4 segments (CMAF)
+    Fmp4,
+}
+
+impl HlsSegmentType {
+    fn name(&self) -> &'static str {
+        match self {
+            HlsSegmentType::Mpegts => "mpegts",
+            HlsSegmentType::Fmp4 => "fmp4",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            HlsSegmentType::Mpegts => "ts",
+            HlsSegmentType::Fmp4 => "m4s",
+        }
+    }
+
+    /// Protocol version the playlists need (fMP4 segments are referenced through EXT-X-MAP).
+    fn version(&self) -> u8 {
+        match self {
+            HlsSegmentType::Mpegts => 3,
+            HlsSegmentType::Fmp4 => 7,
+        }
+    }
+
+    /// MPEG-TS timestamp the start of the media is mapped to in WebVTT segments.
+    ///
+    /// ffmpeg's mpegts muxer starts the timestamps at 1.4s, fMP4 segments need no mapping.
+    fn webvtt_timestamp_map(&self) -> Option<u64> {
+        match self {
+            HlsSegmentType::Mpegts => Some(126_000),
+            HlsSegmentType::Fmp4 => None,
+        }
+    }
+
+    /// Muxer arguments writing the segments of every variant stream into a directory of its own.
+    pub(crate) fn args(&self, segment_duration: f64) -> Vec<String> {
+        let mut args = vec![
+            "-f".to_string(),
+            "hls".to_string(),
+            "-hls_time".to_string(),
+            segment_duration.to_string(),
+            "-hls_playlist_type".to_string(),
+            "vod".to_string(),
+            "-hls_flags".to_string(),
+            "independent_segments".to_string(),
+            "-hls_segment_type".to_string(),
+            self.name().to_string(),
+            "-hls_segment_filename".to_string(),
+            format!("%v/segment_%05d.{}", self.extension()),
+        ];
+
+        if *self == HlsSegmentType::Fmp4 {
+            args.extend([
+                "-hls_fmp4_init_filename".to_string(),
+                "init.mp4".to_string(),
+            ]);
+        }
+
+        args
+    }
+}
+
+/// Audio track of the input offered as an alternative rendition.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioTrack {
+    /// Index of the audio stream in the input (among audio streams)
+    #[serde(default)]
+    pub stream_index: u32,
+
+    /// Language of the track as an RFC 5646 tag (the language of the stream when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    /// Name shown by players (the language when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// Number of output channels (those of the stream when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u8>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_audio_hls_request())]
+pub struct AudioHlsRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Audio tracks offered as alternatives (the first audio stream when empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tracks: Vec<AudioTrack>,
+
+    /// AAC bitrates every track is encoded at (e.g. "64k")
+    #[serde(default = "default_audio_bitrates")]
+    pub bitrates: Vec<String>,
+
+    #[serde(default)]
+    pub segment_type: HlsSegmentType,
+
+    /// Target duration of the segments in seconds
+    #[serde(default = "default_segment_duration")]
+    pub segment_duration: f64,
+
+    /// Return the text of the generated playlists in the response (in addition to uploading them)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_playlists: bool,
+}
+
+fn default_audio_bitrates() -> Vec<String> {
+    vec!["64k".to_string(), "128k".to_string()]
+}
+
+pub(crate) fn default_segment_duration() -> f64 {
+    6.0
+}
+
+fn example_audio_hls_request() -> AudioHlsRequest {
+    AudioHlsRequest {
+        input: Url::parse("s3://bucket/podcasts/episode-42.wav").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/hls/episode-42/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        tracks: vec![AudioTrack {
+            stream_index: 0,
+            language: Some("en".to_string()),
+            name: Some("English".to_string()),
+            channels: Some(2),
+        }],
+        bitrates: default_audio_bitrates(),
+        segment_type: HlsSegmentType::Fmp4,
+        segment_duration: default_segment_duration(),
+        include_playlists: false,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioHlsResponse {
+    /// Location of the master playlist
+    pub master: Url,
+
+    pub renditions: Vec<HlsRendition>,
+
+    /// Text of the playlists keyed by their path in the output (when includePlaylists is set)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub playlist_contents: BTreeMap<String, String>,
+}
+
+/// Rendition listed in a master playlist.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HlsRendition {
+    /// Name of the directory of the rendition
+    pub name: String,
+
+    /// Location of the media playlist
+    pub playlist: Url,
+
+    /// Peak bandwidth advertised in the master playlist in bits per second
+    pub bandwidth: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// Rung of a bitrate ladder.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoRendition {
+    /// Height of the picture in pixels (the width follows the aspect ratio of the input)
+    pub height: u32,
+
+    /// Video bitrate (e.g. "3M")
+    pub bitrate: String,
+
+    /// Audio bitrate (e.g. "128k")
+    #[serde(default = "default_rendition_audio_bitrate")]
+    pub audio_bitrate: String,
+}
+
+impl VideoRendition {
+    /// Name of the variant stream (and the directory) of the rendition.
+    pub(crate) fn name(&self) -> String {
+        format!("{}p", self.height)
+    }
+}
+
+fn default_rendition_audio_bitrate() -> String {
+    "128k".to_string()
+}
+
+pub(crate) fn default_ladder() -> Vec<VideoRendition> {
+    [(1080, "5M"), (720, "3M"), (480, "1.5M"), (360, "800k")]
+        .into_iter()
+        .map(|(height, bitrate)| VideoRendition {
+            height,
+            bitrate: bitrate.to_string(),
+            audio_bitrate: default_rendition_audio_bitrate(),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_hls_request())]
+pub struct HlsRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Bitrate ladder (renditions taller than the input are left out)
+    #[serde(default = "default_ladder")]
+    pub renditions: Vec<VideoRendition>,
+
+    /// Video codec (H.264 or H.265, the latter requires fMP4 segments)
+    #[serde(default)]
+    pub codec: VideoCodec,
+
+    /// Encoder speed preset (e.g. "medium")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+
+    #[serde(default)]
+    pub segment_type: HlsSegmentType,
+
+    /// Target duration of the segments in seconds
+    #[serde(default = "default_segment_duration")]
+    pub segment_duration: f64,
+
+    /// Subtitles segmented as WebVTT and listed in the master playlist
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtitles: Vec<SubtitleRendition>,
+
+    /// Thumbnail tiles listed in the master playlist as an image stream (for scrubbing)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnails: Option<ThumbnailTiles>,
+
+    /// Encode every rendition in an invocation of hls_rendition of its own (possibly on other workers)
+    ///
+    /// The master playlist is written once all the renditions are encoded.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub parallel: bool,
+
+    /// Return the text of the generated playlists in the response (in addition to uploading them)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_playlists: bool,
+}
+
+fn example_hls_request() -> HlsRequest {
+    HlsRequest {
+        input: Url::parse("s3://bucket/masters/feature.mov").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/hls/feature/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        renditions: default_ladder(),
+        codec: VideoCodec::H264,
+        preset: None,
+        segment_type: HlsSegmentType::Mpegts,
+        segment_duration: default_segment_duration(),
+        subtitles: Vec::new(),
+        thumbnails: Some(ThumbnailTiles::default()),
+        parallel: false,
+        include_playlists: false,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HlsResponse {
+    /// Location of the master playlist
+    pub master: Url,
+
+    pub renditions: Vec<HlsRendition>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtitles: Vec<PackagedSubtitles>,
+
+    /// Location of the image playlist of the thumbnail tiles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnails: Option<Url>,
+
+    /// Text of the playlists keyed by their path in the output (when includePlaylists is set)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub playlist_contents: BTreeMap<String, String>,
+}
+
+impl HlsRequest {
+    async fn validate(&self) -> HandlerResult<()> {
+        validate_ladder(&self.renditions)?;
+        validate_subtitles(&self.subtitles)?;
+
+        if let Some(thumbnails) = &self.thumbnails {
+            thumbnails.validate()?;
+        }
+
+        validate_encoding(self.codec, self.segment_type, self.segment_duration).await
+    }
+
+    fn response(&self, ladder: &[&VideoRendition], audio: bool, thumbnails: bool) -> HlsResponse {
+        HlsResponse {
+            master: self.output.file_url(MASTER_PLAYLIST),
+            renditions: ladder
+                .iter()
+                .map(|rendition| {
+                    let name = rendition.name();
+                    let audio_bits = if audio {
+                        parse_bitrate(&rendition.audio_bitrate).unwrap_or_default()
+                    } else {
+                        0
+                    };
+
+                    HlsRendition {
+                        playlist: self.output.file_url(&format!("{name}/index.m3u8")),
+                        name,
+                        bandwidth: bandwidth(
+                            parse_bitrate(&rendition.bitrate).unwrap_or_default() + audio_bits,
+                        ),
+                        language: None,
+                    }
+                })
+                .collect(),
+            subtitles: self
+                .subtitles
+                .iter()
+                .map(|rendition| PackagedSubtitles {
+                    language: rendition.language.clone(),
+                    role: rendition.role,
+                    location: self
+                        .output
+                        .file_url(&format!("{}/index.m3u8", rendition.id())),
+                })
+                .collect(),
+            thumbnails: thumbnails.then(|| {
+                self.output
+                    .file_url(&format!("{THUMBNAILS_DIR}/index.m3u8"))
+            }),
+            playlist_contents: BTreeMap::new(),
+        }
+    }
+}
+
+/// Rendition of a ladder encoded on its own.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HlsRenditionRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Location of the package (the rendition is written in a directory named after its height)
+    pub output: Output,
+
+    pub rendition: VideoRendition,
+
+    #[serde(default)]
+    pub codec: VideoCodec,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+
+    #[serde(default)]
+    pub segment_type: HlsSegmentType,
+
+    #[serde(default = "default_segment_duration")]
+    pub segment_duration: f64,
+
+    /// Return the text of the media playlist in the response
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_playlist: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HlsRenditionResponse {
+    pub rendition: HlsRendition,
+
+    /// Attributes of the EXT-X-STREAM-INF tag listing the rendition in a master playlist
+    pub stream_inf: String,
+
+    /// Text of the media playlist (when includePlaylist is set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub playlist_content: Option<String>,
+}
+
+/// Checks the codec and the segmenting of HLS renditions.
+async fn validate_encoding(
+    codec: VideoCodec,
+    segment_type: HlsSegmentType,
+    segment_duration: f64,
+) -> HandlerResult<()> {
+    let invalid = |message: &str| -> HandlerResult<()> {
+        Err(TerminalError::new_with_code(400, message).into())
+    };
+
+    match codec {
+        VideoCodec::H264 => {}
+        VideoCodec::H265 if segment_type == HlsSegmentType::Fmp4 => {}
+        VideoCodec::H265 => return invalid("H.265 renditions require fmp4 segments"),
+        _ => return invalid("HLS renditions must be H.264 or H.265"),
+    }
+
+    if segment_duration <= 0.0 {
+        return invalid("segmentDuration must be positive");
+    }
+
+    require_encoder(codec.encoder()).await
+}
+
+/// Arguments encoding a ladder into variant streams named after the height of their rendition.
+///
+/// The input is the value of the `-i` option (see `input_arg`).
+fn ladder_args(
+    input: &str,
+    ladder: &[&VideoRendition],
+    audio: bool,
+    codec: VideoCodec,
+    preset: Option<&String>,
+    segment_type: HlsSegmentType,
+    segment_duration: f64,
+) -> Vec<String> {
+    let mut args = vec![
+        "-i".to_string(),
+        input.to_string(),
+        "-filter_complex".to_string(),
+        ladder_filter(ladder),
+    ];
+
+    for i in 0..ladder.len() {
+        args.extend(["-map".to_string(), format!("[v{i}]")]);
+
+        if audio {
+            args.extend(["-map".to_string(), "0:a:0".to_string()]);
+        }
+    }
+
+    args.extend(["-c:v".to_string(), codec.encoder().to_string()]);
+
+    if let Some(preset) = preset {
+        args.extend(["-preset".to_string(), preset.clone()]);
+    }
+
+    if codec == VideoCodec::H265 {
+        // Apple players only take HEVC tagged as hvc1
+        args.extend(["-tag:v".to_string(), "hvc1".to_string()]);
+    }
+
+    // Keyframes on segment boundaries, so that players can switch renditions between segments
+    args.extend([
+        "-force_key_frames".to_string(),
+        format!("expr:gte(t,n_forced*{segment_duration})"),
+    ]);
+
+    if audio {
+        args.extend(["-c:a".to_string(), "aac".to_string()]);
+    }
+
+    for (i, rendition) in ladder.iter().enumerate() {
+        let bits = parse_bitrate(&rendition.bitrate).unwrap_or_default();
+
+        args.extend([
+            format!("-b:v:{i}"),
+            rendition.bitrate.clone(),
+            format!("-maxrate:v:{i}"),
+            bandwidth(bits).to_string(),
+            format!("-bufsize:v:{i}"),
+            (bits * 2).to_string(),
+        ]);
+
+        if audio {
+            args.extend([format!("-b:a:{i}"), rendition.audio_bitrate.clone()]);
+        }
+    }
+
+    args.extend(segment_type.args(segment_duration));
+    args.extend([
+        "-master_pl_name".to_string(),
+        MASTER_PLAYLIST.to_string(),
+        "-var_stream_map".to_string(),
+        ladder
+            .iter()
+            .enumerate()
+            .map(|(i, rendition)| {
+                if audio {
+                    format!("v:{i},a:{i},name:{}", rendition.name())
+                } else {
+                    format!("v:{i},name:{}", rendition.name())
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        "%v/index.m3u8".to_string(),
+    ]);
+
+    args
+}
+
+/// Master playlist listing renditions encoded on their own.
+fn master_playlist(segment_type: HlsSegmentType, variants: &[HlsRenditionResponse]) -> String {
+    let mut lines = vec![
+        "#EXTM3U".to_string(),
+        format!("#EXT-X-VERSION:{}", segment_type.version()),
+    ];
+
+    for variant in variants {
+        lines.push(format!("#EXT-X-STREAM-INF:{}", variant.stream_inf));
+        lines.push(format!("{}/index.m3u8", variant.rendition.name));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Subtitle renditions and thumbnail tiles packaged next to the ladder.
+struct HlsExtras {
+    /// Directory the files of the extras are rendered in
+    dir: TempDir,
+    media_tags: Vec<String>,
+    image_tags: Vec<String>,
+    thumbnails: bool,
+}
+
+impl HlsExtras {
+    fn is_empty(&self) -> bool {
+        self.media_tags.is_empty() && self.image_tags.is_empty()
+    }
+}
+
+/// Checks the size and the settings of a ladder.
+pub(crate) fn validate_ladder(renditions: &[VideoRendition]) -> Result<(), TerminalError> {
+    let invalid = |message: String| Err(TerminalError::new_with_code(400, message));
+
+    if renditions.is_empty() || renditions.len() > MAX_RENDITIONS {
+        return invalid(format!(
+            "a ladder takes between 1 and {MAX_RENDITIONS} renditions"
+        ));
+    }
+
+    for rendition in renditions {
+        if rendition.height < 2 || rendition.height % 2 != 0 {
+            return invalid(format!(
+                "rendition height {} must be even",
+                rendition.height
+            ));
+        }
+
+        for bitrate in [&rendition.bitrate, &rendition.audio_bitrate] {
+            if parse_bitrate(bitrate).is_none() {
+                return invalid(format!("invalid bitrate {bitrate}"));
+            }
+        }
+    }
+
+    if let Some(duplicate) = renditions.iter().enumerate().find(|(i, rendition)| {
+        renditions[..*i]
+            .iter()
+            .any(|r| r.height == rendition.height)
+    }) {
+        return invalid(format!(
+            "more than one rendition is {}p",
+            duplicate.1.height
+        ));
+    }
+
+    Ok(())
+}
+
+/// Filter graph scaling the video of the input into [v0], [v1], ... for every rendition.
+pub(crate) fn ladder_filter(ladder: &[&VideoRendition]) -> String {
+    let splits: String = (0..ladder.len()).map(|i| format!("[s{i}]")).collect();
+    let mut graph = vec![format!("[0:v]split={}{splits}", ladder.len())];
+
+    for (i, rendition) in ladder.iter().enumerate() {
+        graph.push(format!(
+            "[s{i}]scale=-2:{},setsar=1,format=yuv420p[v{i}]",
+            rendition.height
+        ));
+    }
+
+    graph.join(";")
+}
+
+/// Renditions of a ladder not taller than the input, tallest first (the smallest one is always kept).
+pub(crate) fn fit_ladder(
+    renditions: &[VideoRendition],
+    height: Option<u32>,
+) -> Vec<&VideoRendition> {
+    let mut ladder: Vec<&VideoRendition> = renditions.iter().collect();
+    ladder.sort_by_key(|rendition| std::cmp::Reverse(rendition.height));
+
+    let Some(height) = height else {
+        return ladder;
+    };
+
+    let smallest = ladder.len() - 1;
+
+    ladder
+        .into_iter()
+        .enumerate()
+        .filter(|(i, rendition)| rendition.height <= height || *i == smallest)
+        .map(|(_, rendition)| rendition)
+        .collect()
+}
+
+/// EXT-X-MEDIA tag of a subtitle rendition.
+fn subtitle_media_tag(rendition: &SubtitleRendition) -> String {
+    let mut attributes = vec![
+        "TYPE=SUBTITLES".to_string(),
+        format!("GROUP-ID=\"{SUBTITLES_GROUP}\""),
+        format!("NAME=\"{}\"", rendition.display_name()),
+        format!("LANGUAGE=\"{}\"", rendition.language),
+        format!("DEFAULT={}", if rendition.default { "YES" } else { "NO" }),
+        "AUTOSELECT=YES".to_string(),
+        format!(
+            "FORCED={}",
+            if rendition.role == SubtitleRole::ForcedSubtitle {
+                "YES"
+            } else {
+                "NO"
+            }
+        ),
+    ];
+
+    if rendition.role == SubtitleRole::Caption {
+        attributes.push(
+            "CHARACTERISTICS=\"public.accessibility.transcribes-spoken-dialog,\
+             public.accessibility.describes-music-and-sound\""
+                .to_string(),
+        );
+    }
+
+    attributes.push(format!("URI=\"{}/index.m3u8\"", rendition.id()));
+
+    format!("#EXT-X-MEDIA:{}", attributes.join(","))
+}
+
+/// Image playlist of thumbnail tiles (Roku and Apple image stream extension).
+fn image_playlist(storyboard: &Storyboard, tiles: &ThumbnailTiles) -> String {
+    let tile_duration = tiles.tile_duration();
+
+    let mut lines = vec![
+        "#EXTM3U".to_string(),
+        "#EXT-X-VERSION:7".to_string(),
+        format!("#EXT-X-TARGETDURATION:{}", tile_duration.ceil() as u64),
+        "#EXT-X-MEDIA-SEQUENCE:1".to_string(),
+        "#EXT-X-PLAYLIST-TYPE:VOD".to_string(),
+        "#EXT-X-IMAGES-ONLY".to_string(),
+    ];
+
+    for (i, tile) in storyboard.tiles.iter().enumerate() {
+        let start = i as f64 * tile_duration;
+        let length = (storyboard.duration - start).clamp(0.0, tile_duration);
+
+        lines.extend([
+            format!("#EXTINF:{length:.3},"),
+            format!(
+                "#EXT-X-TILES:RESOLUTION={}x{},LAYOUT={}x{},DURATION={}",
+                storyboard.thumbnail.0,
+                storyboard.thumbnail.1,
+                tiles.columns,
+                tiles.rows,
+                tiles.interval
+            ),
+            tile.trim_start_matches(&format!("{THUMBNAILS_DIR}/"))
+                .to_string(),
+        ]);
+    }
+
+    lines.push("#EXT-X-ENDLIST".to_string());
+
+    lines.join("\n") + "\n"
+}
+
+/// EXT-X-IMAGE-STREAM-INF tag of thumbnail tiles.
+fn image_stream_tag(storyboard: &Storyboard, tiles: &ThumbnailTiles) -> String {
+    let (width, height) = storyboard.tile_size(tiles);
+
+    format!(
+        "#EXT-X-IMAGE-STREAM-INF:BANDWIDTH={},RESOLUTION={width}x{height},CODECS=\"jpeg\",URI=\"{THUMBNAILS_DIR}/index.m3u8\"",
+        storyboard.bandwidth()
+    )
+}
+
+/// Lists subtitle renditions and image streams in a master playlist written by ffmpeg.
+fn finish_master(master: &str, media_tags: &[String], image_tags: &[String]) -> String {
+    let mut lines = Vec::new();
+    let mut listed = media_tags.is_empty();
+
+    for line in master.lines() {
+        if media_tags.is_empty() {
+            lines.push(line.to_string());
+        } else if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            // Renditions are listed before the variants referencing their group
+            if !listed {
+                lines.extend(media_tags.iter().cloned());
+                listed = true;
+            }
+
+            lines.push(format!(
+                "#EXT-X-STREAM-INF:{attributes},SUBTITLES=\"{SUBTITLES_GROUP}\""
+            ));
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+
+    lines.extend(image_tags.iter().cloned());
+
+    lines.join("\n") + "\n"
+}
+
+/// Audio rendition resolved against the probed input.
+struct AudioVariant {
+    /// Name of the variant stream, also its directory
+    name: String,
+    group: String,
+    stream_index: u32,
+    bitrate: String,
+    bandwidth: u64,
+    language: Option<String>,
+    display_name: String,
+    channels: Option<u8>,
+    default: bool,
+}
+
+impl AudioVariant {
+    fn playlist(&self) -> String {
+        format!("{}/index.m3u8", self.name)
+    }
+
+    /// EXT-X-MEDIA tag of the rendition.
+    fn media_tag(&self) -> String {
+        let mut attributes = vec![
+            "TYPE=AUDIO".to_string(),
+            format!("GROUP-ID=\"{}\"", self.group),
+            format!("NAME=\"{}\"", self.display_name),
+        ];
+
+        if let Some(language) = &self.language {
+            attributes.push(format!("LANGUAGE=\"{language}\""));
+        }
+
+        attributes.extend([
+            format!("DEFAULT={}", if self.default { "YES" } else { "NO" }),
+            "AUTOSELECT=YES".to_string(),
+        ]);
+
+        if let Some(channels) = self.channels {
+            attributes.push(format!("CHANNELS=\"{channels}\""));
+        }
+
+        attributes.push(format!("URI=\"{}\"", self.playlist()));
+
+        format!("#EXT-X-MEDIA:{}", attributes.join(","))
+    }
+}
+
+/// Parses an ffmpeg bitrate (e.g. "128k" or "5M") into bits per second.
+pub(crate) fn parse_bitrate(bitrate: &str) -> Option<u64> {
+    let (number, multiplier) = match bitrate.strip_suffix(['k', 'K']) {
+        Some(number) => (number, 1_000.0),
+        None => match bitrate.strip_suffix('M') {
+            Some(number) => (number, 1_000_000.0),
+            None => (bitrate, 1.0),
+        },
+    };
+
+    let bits = number.parse::<f64>().ok()? * multiplier;
+
+    (bits.is_finite() && bits >= 1.0).then_some(bits as u64)
+}
+
+/// Peak bandwidth advertised for a stream encoded at a bitrate, leaving room for muxing overhead.
+pub(crate) fn bandwidth(bits: u64) -> u64 {
+    bits + bits / 10
+}
+
+/// Whether a value can be used in a variant stream map and a quoted playlist attribute.
+fn is_safe_name(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl AudioHlsRequest {
+    fn validate(&self) -> Result<(), TerminalError> {
+        let invalid = |message: String| Err(TerminalError::new_with_code(400, message));
+
+        if self.bitrates.is_empty() {
+            return invalid("at least one bitrate is required".to_string());
+        }
+
+        for bitrate in &self.bitrates {
+            if parse_bitrate(bitrate).is_none() {
+                return invalid(format!("invalid bitrate {bitrate}"));
+            }
+        }
+
+        for track in &self.tracks {
+            if track.language.as_deref().is_some_and(|l| !is_safe_name(l)) {
+                return invalid("languages must be RFC 5646 tags (e.g. \"en-US\")".to_string());
+            }
+
+            if track.name.as_deref().is_some_and(|name| name.contains('"')) {
+                return invalid("track names must not contain quotes".to_string());
+            }
+
+            if track.channels == Some(0) {
+                return invalid("channels must be positive".to_string());
+            }
+        }
+
+        if self.segment_duration <= 0.0 {
+            return invalid("segmentDuration must be positive".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Resolves every track at every bitrate, grouping the tracks of the same bitrate.
+    fn variants(&self, streams: &[&Stream]) -> Result<Vec<AudioVariant>, TerminalError> {
+        let default_tracks = [AudioTrack::default()];
+        let tracks = if self.tracks.is_empty() {
+            &default_tracks[..]
+        } else {
+            &self.tracks
+        };
+
+        let mut variants = Vec::new();
+
+        for bitrate in &self.bitrates {
+            for (i, track) in tracks.iter().enumerate() {
+                let stream = streams.get(track.stream_index as usize).ok_or_else(|| {
+                    TerminalError::new_with_code(
+                        400,
+                        format!("input has no audio stream {}", track.stream_index),
+                    )
+                })?;
+
+                let language = track.language.clone().or_else(|| {
+                    stream
+                        .tags
+                        .get("language")
+                        .filter(|language| *language != "und" && is_safe_name(language))
+                        .cloned()
+                });
+
+                let display_name = track
+                    .name
+                    .clone()
+                    .or_else(|| language.clone())
+                    .unwrap_or_else(|| format!("Audio {}", i + 1));
+
+                variants.push(AudioVariant {
+                    name: format!(
+                        "{}_{bitrate}",
+                        language.as_deref().unwrap_or(&format!("audio{i}"))
+                    ),
+                    group: format!("audio-{bitrate}"),
+                    stream_index: track.stream_index,
+                    bitrate: bitrate.clone(),
+                    bandwidth: bandwidth(parse_bitrate(bitrate).unwrap_or_default()),
+                    language,
+                    display_name,
+                    channels: track.channels.or_else(|| {
+                        stream
+                            .channels
+                            .and_then(|channels| u8::try_from(channels).ok())
+                    }),
+                    default: i == 0,
+                });
+            }
+        }
+
+        if let Some(duplicate) = variants
+            .iter()
+            .enumerate()
+            .find(|(i, variant)| variants[..*i].iter().any(|v| v.name == variant.name))
+        {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("more than one track is named {}", duplicate.1.name),
+            ));
+        }
+
+        Ok(variants)
+    }
+
+    /// Master playlist offering one variant per bitrate, with the tracks as alternatives.
+    fn master(&self, variants: &[AudioVariant]) -> String {
+        let mut lines = vec![
+            "#EXTM3U".to_string(),
+            format!("#EXT-X-VERSION:{}", self.segment_type.version()),
+            "#EXT-X-INDEPENDENT-SEGMENTS".to_string(),
+        ];
+
+        lines.extend(variants.iter().map(AudioVariant::media_tag));
+
+        // Players pick the variant by bandwidth, then the track within its group
+        for variant in variants.iter().filter(|variant| variant.default) {
+            lines.push(format!(
+                "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"{AAC_CODEC}\",AUDIO=\"{}\"",
+                variant.bandwidth, variant.group
+            ));
+            lines.push(variant.playlist());
+        }
+
+        lines.join("\n") + "\n"
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _audio_hls(
+        &self,
+        request: AudioHlsRequest,
+    ) -> HandlerResult<AudioHlsResponse> {
+        request.validate()?;
+
+        let probe = self.probe(&request.input).await?;
+        let streams: Vec<&Stream> = probe
+            .streams
+            .iter()
+            .flatten()
+            .filter(|stream| stream.codec_type == "audio")
+            .collect();
+
+        let variants = request.variants(&streams)?;
+
+        let mut inputs = Vec::new();
+        let mut args = vec!["-i".to_string(), input_arg(&request.input, &mut inputs)];
+
+        for variant in &variants {
+            args.extend(["-map".to_string(), format!("0:a:{}", variant.stream_index)]);
+        }
+
+        args.extend(["-c:a".to_string(), "aac".to_string()]);
+
+        for (i, variant) in variants.iter().enumerate() {
+            args.extend([format!("-b:a:{i}"), variant.bitrate.clone()]);
+
+            if let Some(channels) = variant.channels {
+                args.extend([format!("-ac:a:{i}"), channels.to_string()]);
+            }
+        }
+
+        args.extend(request.segment_type.args(request.segment_duration));
+        args.extend([
+            "-var_stream_map".to_string(),
+            variants
+                .iter()
+                .enumerate()
+                .map(|(i, variant)| format!("a:{i},name:{}", variant.name))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "%v/index.m3u8".to_string(),
+        ]);
+
+        let mut playlist_contents = BTreeMap::new();
+
+        self._ffmpeg_finishing(
+            FfmpegRequest {
+                args,
+                output: request.output.clone(),
+                dry_run: false,
+                // Playlists uploaded while ffmpeg is running can't be read back
+                incremental_upload: !request.include_playlists,
+                env: Default::default(),
+                inputs,
+                outputs: Vec::new(),
+                sanitize_dimensions: false,
+                logging: Default::default(),
+            },
+            |work_dir| {
+                if request.include_playlists {
+                    playlist_contents = read_playlists(work_dir, "m3u8")?;
+                }
+
+                Ok(())
+            },
+        )
+        .await?;
+
+        // ffmpeg only writes EXT-X-MEDIA tags for audio next to video, the master is written here
+        let master = request.master(&variants);
+        let dir = TempDir::new()?;
+        tokio::fs::write(dir.path().join(MASTER_PLAYLIST), &master).await?;
+        self.upload(dir.path(), &request.output).await?;
+
+        if request.include_playlists {
+            playlist_contents.insert(MASTER_PLAYLIST.to_string(), master);
+        }
+
+        Ok(AudioHlsResponse {
+            master: request.output.file_url(MASTER_PLAYLIST),
+            renditions: variants
+                .iter()
+                .map(|variant| HlsRendition {
+                    name: variant.name.clone(),
+                    playlist: request.output.file_url(&variant.playlist()),
+                    bandwidth: variant.bandwidth,
+                    language: variant.language.clone(),
+                })
+                .collect(),
+            playlist_contents,
+        })
+    }
+
+    /// Encodes the ladder in one ffmpeg run, the hls muxer writing the variant and master playlists.
+    pub(crate) async fn _hls(&self, request: HlsRequest) -> HandlerResult<HlsResponse> {
+        request.validate().await?;
+
+        let probe = self.probe(&request.input).await?;
+
+        let video = probe
+            .stream("video")
+            .ok_or_else(|| TerminalError::new_with_code(400, "input has no video stream"))?;
+        let audio = probe.stream("audio").is_some();
+
+        let ladder = fit_ladder(
+            &request.renditions,
+            video.height.and_then(|height| u32::try_from(height).ok()),
+        );
+
+        let extras = self.hls_extras(&request, &probe).await?;
+        let mut playlist_contents = BTreeMap::new();
+        let mut inputs = Vec::new();
+
+        self._ffmpeg_finishing(
+            FfmpegRequest {
+                args: ladder_args(
+                    &input_arg(&request.input, &mut inputs),
+                    &ladder,
+                    audio,
+                    request.codec,
+                    request.preset.as_ref(),
+                    request.segment_type,
+                    request.segment_duration,
+                ),
+                output: request.output.clone(),
+                dry_run: false,
+                // The master playlist has to stay in the work dir until the renditions are listed in it,
+                // playlists uploaded while ffmpeg is running can't be read back
+                incremental_upload: extras.is_empty() && !request.include_playlists,
+                env: Default::default(),
+                inputs,
+                outputs: Vec::new(),
+                sanitize_dimensions: false,
+                logging: Default::default(),
+            },
+            |work_dir| {
+                if !extras.is_empty() {
+                    copy_files(&work_files(extras.dir.path()), extras.dir.path(), work_dir)?;
+
+                    let master = work_dir.join(MASTER_PLAYLIST);
+                    let content = std::fs::read_to_string(&master)?;
+                    std::fs::write(
+                        master,
+                        finish_master(&content, &extras.media_tags, &extras.image_tags),
+                    )?;
+                }
+
+                if request.include_playlists {
+                    playlist_contents = read_playlists(work_dir, "m3u8")?;
+                }
+
+                Ok(())
+            },
+        )
+        .await?;
+
+        Ok(HlsResponse {
+            playlist_contents,
+            ..request.response(&ladder, audio, extras.thumbnails)
+        })
+    }
+
+    /// Encodes every rendition of the ladder in an invocation of hls_rendition of its own
+    /// (possibly on other workers), then writes the master playlist once they all completed.
+    pub(crate) async fn _hls_parallel(
+        &self,
+        ctx: &mut Context<'_>,
+        request: HlsRequest,
+    ) -> HandlerResult<HlsResponse> {
+        let inputs = vec![request.input.to_string()];
+        let job_id = job_id(ctx)?;
+
+        // The output location is resolved once: the renditions are encoded by jobs of their own
+        let Metered {
+            response: (ladder, audio, location, metadata),
+            mut usage,
+            ..
+        } = ctx
+            .run(|| {
+                metered(async {
+                    request.validate().await?;
+                    self.check_guardrails(&inputs).await?;
+
+                    let probe = self.probe(&request.input).await?;
+
+                    let video = probe.stream("video").ok_or_else(|| {
+                        TerminalError::new_with_code(400, "input has no video stream")
+                    })?;
+
+                    let ladder: Vec<VideoRendition> = fit_ladder(
+                        &request.renditions,
+                        video.height.and_then(|height| u32::try_from(height).ok()),
+                    )
+                    .into_iter()
+                    .cloned()
+                    .collect();
+
+                    let metadata =
+                        JobMetadata::new(job_id.clone(), &inputs, &serde_json::to_value(&request)?);
+
+                    let location =
+                        with_job(metadata.clone(), async { request.output.location() }).await;
+
+                    Ok((ladder, probe.stream("audio").is_some(), location, metadata))
+                })
+            })
+            .name("probe")
+            .await?
+            .into_inner();
+
+        let caller = self.limiter.caller(ctx.headers());
+
+        let calls: Vec<_> = ladder
+            .iter()
+            .map(|rendition| {
+                ctx.service_client::<ServiceClient>()
+                    .hls_rendition(Json(HlsRenditionRequest {
+                        input: request.input.clone(),
+                        output: Output {
+                            location: location.clone(),
+                            ..request.output.clone()
+                        },
+                        rendition: rendition.clone(),
+                        codec: request.codec,
+                        preset: request.preset.clone(),
+                        segment_type: request.segment_type,
+                        segment_duration: request.segment_duration,
+                        include_playlist: request.include_playlists,
+                    }))
+                    .header(self.limiter.caller_header().to_string(), caller.clone())
+                    .call()
+            })
+            .collect();
+
+        let mut variants = Vec::with_capacity(calls.len());
+
+        for call in calls {
+            variants.push(call.await?.into_inner());
+        }
+
+        let Metered {
+            response,
+            usage: master_usage,
+            finished_at,
+        } = ctx
+            .run(|| {
+                metered(with_inlined(with_job(metadata.clone(), async {
+                    let probe = self.probe(&request.input).await?;
+                    let extras = self.hls_extras(&request, &probe).await?;
+
+                    let master = finish_master(
+                        &master_playlist(request.segment_type, &variants),
+                        &extras.media_tags,
+                        &extras.image_tags,
+                    );
+
+                    tokio::fs::write(extras.dir.path().join(MASTER_PLAYLIST), master).await?;
+
+                    let mut playlist_contents = BTreeMap::new();
+
+                    if request.include_playlists {
+                        playlist_contents = read_playlists(extras.dir.path(), "m3u8")?;
+
+                        for variant in &variants {
+                            if let Some(content) = &variant.playlist_content {
+                                playlist_contents.insert(
+                                    format!("{}/index.m3u8", variant.rendition.name),
+                                    content.clone(),
+                                );
+                            }
+                        }
+                    }
+
+                    self.upload(extras.dir.path(), &request.output).await?;
+
+                    let ladder: Vec<&VideoRendition> = ladder.iter().collect();
+
+                    Ok(HlsResponse {
+                        playlist_contents,
+                        ..request.response(&ladder, audio, extras.thumbnails)
+                    })
+                })))
+            })
+            .name("master")
+            .await?
+            .into_inner();
+
+        usage.add(&master_usage);
+        usage.jobs = 1;
+
+        self.record_job(ctx, "hls", &metadata, &response, usage, finished_at)?;
+
+        Ok(response)
+    }
+
+    /// Encodes a single rendition, leaving out the master playlist written by ffmpeg.
+    pub(crate) async fn _hls_rendition(
+        &self,
+        request: HlsRenditionRequest,
+    ) -> HandlerResult<HlsRenditionResponse> {
+        validate_ladder(std::slice::from_ref(&request.rendition))?;
+        validate_encoding(
+            request.codec,
+            request.segment_type,
+            request.segment_duration,
+        )
+        .await?;
+
+        let probe = self.probe(&request.input).await?;
+
+        if probe.stream("video").is_none() {
+            return Err(TerminalError::new_with_code(400, "input has no video stream").into());
+        }
+
+        let audio = probe.stream("audio").is_some();
+        let name = request.rendition.name();
+        let mut stream_inf = None;
+        let mut playlist_content = None;
+        let mut inputs = Vec::new();
+
+        self._ffmpeg_finishing(
+            FfmpegRequest {
+                args: ladder_args(
+                    &input_arg(&request.input, &mut inputs),
+                    &[&request.rendition],
+                    audio,
+                    request.codec,
+                    request.preset.as_ref(),
+                    request.segment_type,
+                    request.segment_duration,
+                ),
+                output: request.output.clone(),
+                dry_run: false,
+                incremental_upload: false,
+                env: Default::default(),
+                inputs,
+                outputs: Vec::new(),
+                sanitize_dimensions: false,
+                logging: Default::default(),
+            },
+            |work_dir| {
+                let master = work_dir.join(MASTER_PLAYLIST);
+                let content = std::fs::read_to_string(&master)?;
+                std::fs::remove_file(master)?;
+
+                stream_inf = content
+                    .lines()
+                    .find_map(|line| line.strip_prefix("#EXT-X-STREAM-INF:"))
+                    .map(str::to_string);
+
+                if request.include_playlist {
+                    playlist_content = Some(std::fs::read_to_string(
+                        work_dir.join(&name).join("index.m3u8"),
+                    )?);
+                }
+
+                Ok(())
+            },
+        )
+        .await?;
+
+        let stream_inf = stream_inf
+            .ok_or_else(|| TerminalError::new("ffmpeg listed no variant stream in the master"))?;
+
+        let audio_bits = if audio {
+            parse_bitrate(&request.rendition.audio_bitrate).unwrap_or_default()
+        } else {
+            0
+        };
+
+        Ok(HlsRenditionResponse {
+            rendition: HlsRendition {
+                playlist: request.output.file_url(&format!("{name}/index.m3u8")),
+                name,
+                bandwidth: bandwidth(
+                    parse_bitrate(&request.rendition.bitrate).unwrap_or_default() + audio_bits,
+                ),
+                language: None,
+            },
+            stream_inf,
+            playlist_content,
+        })
+    }
+
+    /// Renders the subtitle renditions and the thumbnail tiles of a package into a directory.
+    async fn hls_extras(
+        &self,
+        request: &HlsRequest,
+        probe: &FfprobeResponse,
+    ) -> HandlerResult<HlsExtras> {
+        let dir = TempDir::new()?;
+        let mut media_tags = Vec::new();
+        let mut image_tags = Vec::new();
+        let mut thumbnails = false;
+
+        if !request.subtitles.is_empty() {
+            let duration = probe
+                .duration()
+                .ok_or_else(|| TerminalError::new_with_code(400, "unknown input duration"))?;
+
+            for rendition in &request.subtitles {
+                let vtt = self
+                    .convert_subtitle_rendition(&request.input, rendition, SubtitleFormat::Webvtt)
+                    .await?;
+
+                let segments = segment_webvtt(
+                    &vtt,
+                    duration,
+                    request.segment_duration,
+                    request.segment_type.webvtt_timestamp_map(),
+                );
+
+                let rendition_dir = dir.path().join(rendition.id());
+                tokio::fs::create_dir_all(&rendition_dir).await?;
+
+                tokio::fs::write(
+                    rendition_dir.join("index.m3u8"),
+                    subtitle_playlist(duration, request.segment_duration, segments.len()),
+                )
+                .await?;
+
+                for (i, segment) in segments.into_iter().enumerate() {
+                    tokio::fs::write(rendition_dir.join(format!("segment_{i:05}.vtt")), segment)
+                        .await?;
+                }
+
+                media_tags.push(subtitle_media_tag(rendition));
+            }
+        }
+
+        if let Some(tiles) = &request.thumbnails {
+            let storyboard = self
+                .render_storyboard(&request.input, probe, tiles, dir.path(), THUMBNAILS_DIR)
+                .await?;
+
+            tokio::fs::write(
+                dir.path().join(THUMBNAILS_DIR).join("index.m3u8"),
+                image_playlist(&storyboard, tiles),
+            )
+            .await?;
+
+            image_tags.push(image_stream_tag(&storyboard, tiles));
+            thumbnails = true;
+        }
+
+        Ok(HlsExtras {
+            dir,
+            media_tags,
+            image_tags,
+            thumbnails,
+        })
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use opendal_util::{Copier, CopyOptions, OperatorFactory};
+use restate_sdk::prelude::*;
+use tokio::sync::Notify;
+
+use crate::metering;
+use crate::service::{
+    Output, ServiceImpl, UploadStatus, UploadedObject, check_conflicts, local_dir, parse_uri,
+    work_files,
+};
+
+/// Time between two scans of the work dir.
+const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Splits a file (path relative to the work dir) into the sequence it belongs to and its number
+/// in the sequence, taken from the last run of digits of its name.
+///
+/// Files of a sequence share their directory and the rest of their name (e.g. `720p/seg_%d.ts`).
+fn sequence(file: &str) -> Option<(String, u64)> {
+    let (dir, name) = file.rsplit_once('/').unwrap_or(("", file));
+    let stem = name.split_once('.').map_or(name, |(stem, _)| stem);
+
+    let end = stem.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = stem[..end]
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |i| i + 1);
+
+    let number = stem[start..end].parse().ok()?;
+
+    Some((
+        format!("{dir}/{}{{}}{}", &name[..start], &name[end..]),
+        number,
+    ))
+}
+
+/// Returns the files ffmpeg is done writing among the files of a work dir.
+///
+/// A file of a sequence (an HLS segment, a frame of an image sequence) is complete once
+/// ffmpeg has moved on to a later file of the same sequence. Sequences written at the same time
+/// (e.g. the variant streams of an HLS ladder) are tracked separately.
+/// Single files (e.g. playlists rewritten on every segment) are only complete when ffmpeg exits.
+fn completed(files: Vec<String>) -> Vec<String> {
+    let mut sequences: HashMap<String, Vec<(u64, String)>> = HashMap::new();
+
+    for file in files {
+        if let Some((key, number)) = sequence(&file) {
+            sequences.entry(key).or_default().push((number, file));
+        }
+    }
+
+    let mut files: Vec<String> = sequences
+        .into_values()
+        .flat_map(|mut files| {
+            files.sort();
+            files.pop();
+            files.into_iter().map(|(_, file)| file)
+        })
+        .collect();
+
+    files.sort();
+
+    files
+}
+
+/// Returns the files of a work dir that ffmpeg is done writing.
+fn completed_files(work_dir: &Path) -> Vec<String> {
+    completed(work_files(work_dir))
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Runs a job while uploading the files it completes in the work dir.
+    ///
+    /// Uploaded files are removed from the work dir: the rest is left to the regular upload.
+    pub(crate) async fn uploading_completed<T>(
+        &self,
+        work_dir: &Path,
+        output: &Output,
+        job: impl Future<Output = T>,
+    ) -> HandlerResult<(T, Vec<UploadedObject>)> {
+        let done = Notify::new();
+
+        let job = async {
+            let result = job.await;
+            done.notify_one();
+            result
+        };
+
+        let uploads = async {
+            let mut uploaded = Vec::new();
+
+            loop {
+                tokio::select! {
+                    _ = done.notified() => return Ok::<_, HandlerError>(uploaded),
+                    _ = tokio::time::sleep(SCAN_INTERVAL) => {}
+                }
+
+                let files = completed_files(work_dir);
+
+                if !files.is_empty() {
+                    uploaded.extend(self.upload_files(work_dir, files, output).await?);
+                }
+            }
+        };
+
+        let (result, uploaded) = tokio::join!(job, uploads);
+
+        Ok((result, uploaded?))
+    }
+
+    /// Uploads some files of a work dir (one by one) and removes them.
+    async fn upload_files(
+        &self,
+        work_dir: &Path,
+        files: Vec<String>,
+        output: &Output,
+    ) -> HandlerResult<Vec<UploadedObject>> {
+        let (uri, _) = parse_uri(output.location());
+        let operator = self.factory.load(uri.as_str())?;
+
+        let objects = check_conflicts(work_dir, files.clone(), output, &operator).await?;
+
+        let source = opendal::Operator::new(
+            opendal::services::Fs::default().root(work_dir.to_string_lossy().as_ref()),
+        )?
+        .finish();
+
+        let copier = Copier::new(source, operator.clone());
+
+        for (file, object) in files.iter().zip(&objects) {
+            if object.status == UploadStatus::Skipped {
+                continue;
+            }
+
+            let local = work_dir.join(file);
+            let size = tokio::fs::metadata(&local).await?.len();
+            let (_, path) = parse_uri(object.location.clone());
+
+            if let Some(destination) = local_dir(&operator, &path) {
+                if let Some(parent) = destination.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+
+                if tokio::fs::rename(&local, &destination).await.is_err() {
+                    tokio::fs::copy(&local, &destination).await?;
+                    tokio::fs::remove_file(&local).await?;
+                }
+            } else {
+                copier
+                    .copy_options(
+                        file.as_str(),
+                        path,
+                        CopyOptions {
+                            disable_glob: true,
+                            ..Default::default()
+                        },
+                    )
+                    .await?;
+
+                tokio::fs::remove_file(&local).await?;
+            }
+
+            metering::record(|usage| usage.bytes_out += size);
+        }
+
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn concurrent_sequences_are_tracked_separately() {
+        let work_dir = files(&[
+            "0/index.m3u8",
+            "0/seg_002.ts",
+            "0/seg_003.ts",
+            "1/index.m3u8",
+            "1/seg_002.ts",
+            "1/seg_003.ts",
+            "master.m3u8",
+            "stream_0_seg_1.ts",
+            "stream_1_seg_1.ts",
+        ]);
+
+        assert_eq!(
+            completed(work_dir),
+            files(&["0/seg_002.ts", "1/seg_002.ts"])
+        );
+    }
+
+    #[test]
+    fn sequences_are_ordered_by_number() {
+        let work_dir = files(&["frame_9.jpg", "frame_10.jpg", "frame_8.jpg"]);
+
+        assert_eq!(completed(work_dir), files(&["frame_8.jpg", "frame_9.jpg"]));
+    }
+
+    #[test]
+    fn single_files_are_never_complete() {
+        assert!(completed(files(&["index.m3u8", "output.mp4", "seg_000.ts"])).is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use restate_sdk::prelude::*;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::service::{Output, work_files};
+
+/// Maximum total size of the files returned in a response.
+///
+/// Responses are stored in the Restate journal, which is not meant for large payloads.
+const MAX_INLINE_SIZE: u64 = 4 << 20;
+
+tokio::task_local! {
+    /// Data URLs of the files inlined by the current job, keyed by the URL they would be uploaded to.
+    static INLINED: RefCell<HashMap<String, String>>;
+}
+
+fn media_type(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        "gif" => "image/gif",
+        "json" => "application/json",
+        "vtt" => "text/vtt",
+        "srt" => "application/x-subrip",
+        "ass" | "ssa" => "text/x-ssa",
+        "csv" => "text/csv",
+        "txt" => "text/plain",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Reads the files of the work dir as data URLs instead of uploading them.
+pub(crate) async fn inline_files(work_dir: &Path, output: &Output) -> HandlerResult<()> {
+    let files = work_files(work_dir);
+
+    let mut size = 0;
+    for name in &files {
+        size += tokio::fs::metadata(work_dir.join(name)).await?.len();
+    }
+
+    if size > MAX_INLINE_SIZE {
+        return Err(TerminalError::new_with_code(
+            413,
+            format!("outputs are {size} bytes, at most {MAX_INLINE_SIZE} bytes can be inlined"),
+        )
+        .into());
+    }
+
+    for name in files {
+        let data = tokio::fs::read(work_dir.join(&name)).await?;
+        let url = format!(
+            "data:{};base64,{}",
+            media_type(&name),
+            STANDARD.encode(data)
+        );
+
+        let _ = INLINED.try_with(|inlined| {
+            inlined
+                .borrow_mut()
+                .insert(output.file_url(&name).to_string(), url)
+        });
+    }
+
+    Ok(())
+}
+
+fn replace(value: &mut Value, inlined: &HashMap<String, String>) {
+    match value {
+        Value::String(s) => {
+            if let Some(url) = inlined.get(s.as_str()) {
+                *s = url.clone();
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| replace(value, inlined)),
+        Value::Object(fields) => fields
+            .values_mut()
+            .for_each(|value| replace(value, inlined)),
+        _ => {}
+    }
+}
+
+/// Runs a job and replaces the output URLs of inlined files in its response with data URLs.
+pub(crate) async fn with_inlined<T>(job: impl Future<Output = HandlerResult<T>>) -> HandlerResult<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let (response, inlined) = INLINED
+        .scope(RefCell::new(HashMap::new()), async {
+            let response = job.await;
+            (response, INLINED.with(|inlined| inlined.take()))
+        })
+        .await;
+
+    let response = response?;
+
+    if inlined.is_empty() {
+        return Ok(response);
+    }
+
+    let mut value = serde_json::to_value(&response)?;
+    replace(&mut value, &inlined);
+
+    Ok(serde_json::from_value(value)?)
+}
@@ -0,0 +1,228 @@
+use std::path::Path;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::service::{ServiceImpl, input_extension};
+use crate::staging::{InputChecksum, is_storage_input};
+
+/// Input downloaded from storage before ffmpeg runs.
+///
+/// ffmpeg only reads public protocols (e.g. HTTP) by itself: private buckets are read
+/// through the storage operators instead and ffmpeg is given the local copy.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Input {
+    /// Name of the input: "{name}" in the arguments is replaced with the path of the local copy
+    pub name: String,
+
+    /// Storage URL of the input (e.g. "s3://bucket/input.mp4")
+    pub location: Url,
+
+    /// Expected checksum of the input, verified after downloading it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<InputChecksum>,
+}
+
+impl Input {
+    /// File name of the local copy (the extension is kept: ffmpeg picks demuxers based on it).
+    fn filename(&self) -> String {
+        match input_extension(&self.location) {
+            Some(extension) => format!("{}.{extension}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+fn validate(inputs: &[Input]) -> Result<(), TerminalError> {
+    for (i, input) in inputs.iter().enumerate() {
+        let valid = !input.name.is_empty()
+            && input
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+        if !valid {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!(
+                    "input name {:?} may only contain letters, digits, _ and -",
+                    input.name
+                ),
+            ));
+        }
+
+        if inputs[..i].iter().any(|other| other.name == input.name) {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("input {:?} is given more than once", input.name),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the placeholders of the inputs in the arguments with the paths of their local copies.
+pub(crate) fn substitute(args: &[String], staged: &[(String, String)]) -> Vec<String> {
+    args.iter()
+        .map(|arg| {
+            staged.iter().fold(arg.clone(), |arg, (name, path)| {
+                arg.replace(&format!("{{{name}}}"), path)
+            })
+        })
+        .collect()
+}
+
+/// Returns the names of the inputs along with their storage URLs, resolving the arguments without staging them.
+pub(crate) fn locations(inputs: &[Input]) -> Result<Vec<(String, String)>, TerminalError> {
+    validate(inputs)?;
+
+    Ok(inputs
+        .iter()
+        .map(|input| (input.name.clone(), input.location.to_string()))
+        .collect())
+}
+
+/// Returns the value of the `-i` option reading an input.
+///
+/// Storage inputs are added to the staged inputs and referenced by their placeholder,
+/// so that ffmpeg reads the local copy.
+pub(crate) fn input_arg(input: &Url, inputs: &mut Vec<Input>) -> String {
+    if !is_storage_input(input) {
+        return input.to_string();
+    }
+
+    if let Some(staged) = inputs.iter().find(|staged| staged.location == *input) {
+        return format!("{{{}}}", staged.name);
+    }
+
+    let name = format!("input{}", inputs.len());
+
+    inputs.push(Input {
+        name: name.clone(),
+        location: input.clone(),
+        checksum: None,
+    });
+
+    format!("{{{name}}}")
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Downloads the inputs into a directory and returns their names along with the paths of their copies.
+    ///
+    /// The directory has to be separate from the work dir: everything in there is uploaded.
+    pub(crate) async fn stage_inputs(
+        &self,
+        inputs: &[Input],
+        dir: &Path,
+    ) -> HandlerResult<Vec<(String, String)>> {
+        validate(inputs)?;
+
+        futures::future::try_join_all(inputs.iter().map(|input| async move {
+            let path = dir.join(input.filename());
+
+            self.download(&input.location, &path, input.checksum.as_ref())
+                .await?;
+
+            Ok::<_, HandlerError>((input.name.clone(), path.display().to_string()))
+        }))
+        .await
+    }
+
+    /// Makes sparse copies of the inputs (see [`ServiceImpl::stage_probe`]) and returns their names
+    /// along with the paths of the copies.
+    ///
+    /// The copies are enough for ffmpeg to open the inputs, not to decode them.
+    pub(crate) async fn stage_probes(
+        &self,
+        inputs: &[Input],
+        dir: &Path,
+    ) -> HandlerResult<Vec<(String, String)>> {
+        validate(inputs)?;
+
+        futures::future::try_join_all(inputs.iter().map(|input| async move {
+            let input_dir = dir.join(&input.name);
+            tokio::fs::create_dir(&input_dir).await?;
+
+            let path = self.stage_probe(&input.location, &input_dir).await?;
+
+            Ok::<_, HandlerError>((input.name.clone(), path.display().to_string()))
+        }))
+        .await
+    }
+
+    /// Returns the path ffmpeg reads an input from when it is run directly,
+    /// downloading storage inputs into a directory first.
+    pub(crate) async fn local_input(&self, input: &Url, dir: &Path) -> HandlerResult<String> {
+        if !is_storage_input(input) {
+            return Ok(input.to_string());
+        }
+
+        let path = dir.join(match input_extension(input) {
+            Some(extension) => format!("input.{extension}"),
+            None => "input".to_string(),
+        });
+
+        self.download(input, &path, None).await?;
+
+        Ok(path.display().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_arg_stages_storage_inputs() {
+        let mut inputs = Vec::new();
+
+        let s3 = Url::parse("s3://bucket/masters/feature.mov").unwrap();
+        let https = Url::parse("https://example.com/feature.mov").unwrap();
+
+        assert_eq!(input_arg(&s3, &mut inputs), "{input0}");
+        assert_eq!(input_arg(&https, &mut inputs), https.to_string());
+        assert_eq!(input_arg(&s3, &mut inputs), "{input0}");
+
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].name, "input0");
+        assert_eq!(inputs[0].location, s3);
+        assert_eq!(inputs[0].filename(), "input0.mov");
+    }
+
+    #[test]
+    fn locations_resolve_to_storage_urls() {
+        let mut inputs = Vec::new();
+        let s3 = Url::parse("s3://bucket/masters/feature.mov").unwrap();
+        let args = vec!["-i".to_string(), input_arg(&s3, &mut inputs)];
+
+        let locations = locations(&inputs).unwrap();
+
+        assert_eq!(
+            substitute(&args, &locations),
+            vec!["-i", "s3://bucket/masters/feature.mov"]
+        );
+    }
+
+    #[test]
+    fn substitute_replaces_placeholders() {
+        let args = vec![
+            "-i".to_string(),
+            "{input0}".to_string(),
+            "out.mp4".to_string(),
+        ];
+        let staged = vec![("input0".to_string(), "/tmp/input0.mov".to_string())];
+
+        assert_eq!(
+            substitute(&args, &staged),
+            vec!["-i", "/tmp/input0.mov", "out.mp4"]
+        );
+    }
+}
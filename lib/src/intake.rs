@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use jiff::Timestamp;
+use opendal_util::OperatorFactory;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::service::ServiceImpl;
+
+/// Job admitted by this worker and still running.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InFlightJob {
+    /// Name of the handler running the job
+    pub handler: String,
+
+    /// Caller the job is run for
+    pub caller: String,
+
+    pub started_at: Timestamp,
+}
+
+/// Intake state of the worker.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IntakeStatus {
+    /// Whether new jobs are refused (and retried by Restate) instead of started
+    pub paused: bool,
+
+    /// Jobs running on this worker (oldest first)
+    pub jobs: Vec<InFlightJob>,
+}
+
+/// Tracks the jobs running in this process and whether new ones are started.
+///
+/// Pausing the intake (e.g. before a rolling upgrade) lets the running jobs finish
+/// while new ones stay queued in Restate.
+#[derive(Debug, Default)]
+pub(crate) struct Intake {
+    paused: AtomicBool,
+    next_id: AtomicU64,
+    jobs: Mutex<HashMap<u64, InFlightJob>>,
+}
+
+/// Keeps a job in the in-flight list until dropped.
+pub(crate) struct Tracked<'a> {
+    intake: &'a Intake,
+    id: u64,
+}
+
+impl Drop for Tracked<'_> {
+    fn drop(&mut self) {
+        self.intake.jobs.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Intake {
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    pub(crate) fn track(&self, handler: &str, caller: String) -> Tracked<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        self.jobs.lock().unwrap().insert(
+            id,
+            InFlightJob {
+                handler: handler.to_string(),
+                caller,
+                started_at: Timestamp::now(),
+            },
+        );
+
+        Tracked { intake: self, id }
+    }
+
+    fn status(&self) -> IntakeStatus {
+        let mut jobs: Vec<_> = self.jobs.lock().unwrap().values().cloned().collect();
+        jobs.sort_by_key(|job| job.started_at);
+
+        IntakeStatus {
+            paused: self.is_paused(),
+            jobs,
+        }
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) fn _pause_intake(&self) -> IntakeStatus {
+        self.intake.set_paused(true);
+        self.intake.status()
+    }
+
+    pub(crate) fn _resume_intake(&self) -> IntakeStatus {
+        self.intake.set_paused(false);
+        self.intake.status()
+    }
+
+    pub(crate) fn _in_flight_jobs(&self) -> IntakeStatus {
+        self.intake.status()
+    }
+}
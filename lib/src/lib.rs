@@ -1,2 +1,208 @@
 pub mod service;
 pub use service::*;
+
+pub mod encode;
+pub use encode::*;
+
+mod capabilities;
+
+mod inline;
+
+mod uploads;
+
+mod incremental;
+
+mod templates;
+
+mod termination;
+
+mod sanitize;
+
+pub mod staging;
+pub use staging::*;
+
+pub mod inputs;
+pub use inputs::*;
+
+pub mod routes;
+pub use routes::*;
+
+pub mod limits;
+pub use limits::*;
+
+pub mod metering;
+pub use metering::*;
+
+pub mod history;
+pub use history::*;
+
+pub mod guardrails;
+pub use guardrails::*;
+
+pub mod color;
+pub use color::*;
+
+pub mod crop;
+pub use crop::*;
+
+pub mod aspect;
+pub use aspect::*;
+
+pub mod mezzanine;
+pub use mezzanine::*;
+
+pub mod archive;
+pub use archive::*;
+
+pub mod screen;
+pub use screen::*;
+
+pub mod spherical;
+pub use spherical::*;
+
+pub mod hdr;
+pub use hdr::*;
+
+pub mod captions;
+pub use captions::*;
+
+pub mod subtitles;
+pub use subtitles::*;
+
+pub mod streams;
+pub use streams::*;
+
+pub mod compat;
+pub use compat::*;
+
+pub mod audio;
+pub use audio::*;
+
+pub mod highlights;
+pub use highlights::*;
+
+pub mod chapters;
+pub use chapters::*;
+
+pub mod credits;
+pub use credits::*;
+
+pub mod frames;
+pub use frames::*;
+
+pub mod ts;
+pub use ts::*;
+
+pub mod rtsp;
+pub use rtsp::*;
+
+pub mod radio;
+pub use radio::*;
+
+pub mod watch;
+pub use watch::*;
+
+pub mod batch;
+pub use batch::*;
+
+pub mod segmented;
+pub use segmented::*;
+
+pub mod routing;
+pub use routing::*;
+
+pub mod explain;
+pub use explain::*;
+
+pub mod lint;
+pub use lint::*;
+
+pub mod flags;
+pub use flags::*;
+
+pub mod handlers;
+pub use handlers::*;
+
+pub mod load;
+pub use load::*;
+
+pub mod streaming;
+pub use streaming::*;
+
+pub mod transcode;
+pub use transcode::*;
+
+pub mod intake;
+pub use intake::*;
+
+pub mod env;
+pub use env::*;
+
+pub mod diagnose;
+pub use diagnose::*;
+
+pub mod progress;
+pub use progress::*;
+
+pub mod watchdog;
+pub use watchdog::*;
+
+pub mod hedging;
+pub use hedging::*;
+
+pub mod mosaic;
+pub use mosaic::*;
+
+pub mod pip;
+pub use pip::*;
+
+pub mod ducking;
+pub use ducking::*;
+
+pub mod concat;
+pub use concat::*;
+
+pub mod reverse;
+pub use reverse::*;
+
+pub mod editorial;
+pub use editorial::*;
+
+pub mod cutlist;
+pub use cutlist::*;
+
+pub mod storyboard;
+pub use storyboard::*;
+
+pub mod renditions;
+pub use renditions::*;
+
+pub mod hls;
+pub use hls::*;
+
+pub mod dash;
+pub use dash::*;
+
+pub mod thumbnail;
+pub use thumbnail::*;
+
+pub mod sprites;
+pub use sprites::*;
+
+pub mod preview;
+pub use preview::*;
+
+pub mod benchmark;
+pub use benchmark::*;
+
+pub mod repair;
+pub use repair::*;
+
+pub mod timestamps;
+pub use timestamps::*;
+
+pub mod clip;
+pub use clip::*;
+
+pub mod hash;
+pub use hash::*;
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use restate_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits applied to a single caller.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CallerLimits {
+    /// Maximum number of requests accepted per minute
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+
+    /// Maximum number of jobs running at the same time
+    #[serde(default)]
+    pub concurrent_jobs: Option<u32>,
+}
+
+/// Per-caller rate limits.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RateLimitConfig {
+    /// Request header identifying the caller (requests without it are made by the "anonymous" caller)
+    #[serde(default = "default_caller_header")]
+    pub caller_header: String,
+
+    /// Limits of callers without an override
+    #[serde(default, flatten)]
+    pub default: CallerLimits,
+
+    /// Limits of specific callers
+    #[serde(default)]
+    pub callers: HashMap<String, CallerLimits>,
+}
+
+fn default_caller_header() -> String {
+    "x-tenant-id".to_string()
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            caller_header: default_caller_header(),
+            default: CallerLimits::default(),
+            callers: HashMap::new(),
+        }
+    }
+}
+
+/// Error returned when a caller exceeds its limits.
+///
+/// It is a retryable error on purpose: Restate retries the invocation with backoff
+/// until the caller is within its limits again.
+#[derive(Debug)]
+pub struct ThrottledError {
+    pub caller: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ThrottledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "throttled: caller {} exceeded {}",
+            self.caller, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ThrottledError {}
+
+#[derive(Debug)]
+struct CallerState {
+    window_start: Instant,
+    requests: u32,
+    running: u32,
+}
+
+/// Tracks request rates and running jobs of callers in this process.
+///
+/// Share a single limiter between the services of an endpoint (e.g. variants),
+/// otherwise each of them enforces the limits on its own.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    callers: Mutex<HashMap<String, CallerState>>,
+}
+
+/// Holds a concurrent job slot of a caller until dropped.
+pub(crate) struct Permit<'a> {
+    limiter: &'a RateLimiter,
+    caller: String,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut callers = self.limiter.callers.lock().unwrap();
+
+        if let Some(state) = callers.get_mut(&self.caller) {
+            state.running = state.running.saturating_sub(1);
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            callers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Name of the header identifying the caller.
+    pub(crate) fn caller_header(&self) -> &str {
+        &self.config.caller_header
+    }
+
+    /// Number of jobs running across all callers.
+    pub(crate) fn running(&self) -> u32 {
+        self.callers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|state| state.running)
+            .sum()
+    }
+
+    /// Identifies the caller of a request.
+    pub(crate) fn caller(&self, headers: &HeaderMap) -> String {
+        headers
+            .get(self.config.caller_header.as_str())
+            .filter(|caller| !caller.is_empty())
+            .cloned()
+            .unwrap_or_else(|| "anonymous".to_string())
+    }
+
+    /// Counts a request of the caller against its request rate or fails with a [`ThrottledError`].
+    ///
+    /// Callers whose window expired and who have no running jobs are forgotten.
+    pub(crate) fn count(&self, caller: &str) -> Result<(), ThrottledError> {
+        let limits = self.limits(caller);
+
+        let mut callers = self.callers.lock().unwrap();
+        let now = Instant::now();
+
+        callers.retain(|_, state| {
+            state.running > 0 || now.duration_since(state.window_start) < WINDOW
+        });
+
+        let state = callers.entry(caller.to_string()).or_insert(CallerState {
+            window_start: now,
+            requests: 0,
+            running: 0,
+        });
+
+        if now.duration_since(state.window_start) >= WINDOW {
+            state.window_start = now;
+            state.requests = 0;
+        }
+
+        if limits
+            .requests_per_minute
+            .is_some_and(|limit| state.requests >= limit)
+        {
+            return Err(ThrottledError {
+                caller: caller.to_string(),
+                reason: "its request rate limit",
+            });
+        }
+
+        state.requests += 1;
+
+        Ok(())
+    }
+
+    /// Takes a concurrent job slot of the caller or fails with a [`ThrottledError`].
+    pub(crate) fn acquire(&self, caller: String) -> Result<Permit<'_>, ThrottledError> {
+        let limits = self.limits(&caller);
+
+        let mut callers = self.callers.lock().unwrap();
+
+        let state = callers.entry(caller.clone()).or_insert(CallerState {
+            window_start: Instant::now(),
+            requests: 0,
+            running: 0,
+        });
+
+        if limits
+            .concurrent_jobs
+            .is_some_and(|limit| state.running >= limit)
+        {
+            return Err(ThrottledError {
+                caller,
+                reason: "its concurrent job limit",
+            });
+        }
+
+        state.running += 1;
+
+        Ok(Permit {
+            limiter: self,
+            caller,
+        })
+    }
+
+    fn limits(&self, caller: &str) -> &CallerLimits {
+        self.config
+            .callers
+            .get(caller)
+            .unwrap_or(&self.config.default)
+    }
+}
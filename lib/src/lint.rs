@@ -0,0 +1,290 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::explain::FLAG_OPTIONS;
+use crate::service::FfmpegRequest;
+
+/// Containers played progressively by browsers and devices.
+const WEB_CONTAINERS: &[&str] = &["mp4", "m4v", "mov", "ts", "m3u8"];
+
+/// Containers whose index (moov atom) is written at the end of the file by default.
+const MOOV_CONTAINERS: &[&str] = &["mp4", "m4v", "mov"];
+
+/// Largest frame size (in 16x16 macroblocks) of the H.264 levels.
+const H264_LEVELS: &[(u32, u32)] = &[
+    (10, 99),
+    (11, 396),
+    (12, 396),
+    (13, 396),
+    (20, 396),
+    (21, 792),
+    (22, 1620),
+    (30, 1620),
+    (31, 3600),
+    (32, 5120),
+    (40, 8192),
+    (41, 8192),
+    (42, 8704),
+    (50, 22080),
+    (51, 36864),
+    (52, 36864),
+    (60, 139264),
+    (61, 139264),
+    (62, 139264),
+];
+
+/// Likely mistake in the encoding settings of a command.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LintWarning {
+    /// Check that failed (e.g. "missing-pix-fmt")
+    pub code: String,
+
+    /// Output file the warning is about
+    pub output: String,
+
+    pub message: String,
+}
+
+/// Options given to an output file of a command.
+struct OutputOptions<'a> {
+    file: &'a str,
+
+    /// Options in order, flags have an empty value
+    options: Vec<(&'a str, &'a str)>,
+
+    /// Filter graphs of the command (-filter_complex applies to every output)
+    complex_filters: &'a [&'a str],
+}
+
+impl<'a> OutputOptions<'a> {
+    /// Value of the last of the options (ffmpeg takes the last one).
+    fn get(&self, names: &[&str]) -> Option<&'a str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(name, _)| names.contains(name))
+            .map(|(_, value)| *value)
+    }
+
+    fn extension(&self) -> String {
+        self.file
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// Video encoder of the output (the default of the container when not given).
+    fn video_codec(&self) -> Option<&'a str> {
+        self.get(&["-c:v", "-codec:v", "-vcodec", "-c", "-codec"])
+            .or_else(|| {
+                MOOV_CONTAINERS
+                    .contains(&self.extension().as_str())
+                    .then_some("libx264")
+            })
+    }
+
+    fn filters(&self) -> Vec<&'a str> {
+        self.options
+            .iter()
+            .filter(|(name, _)| ["-vf", "-filter:v"].contains(name))
+            .map(|(_, value)| *value)
+            .chain(self.complex_filters.iter().copied())
+            .collect()
+    }
+
+    /// Sizes set with -s or scale filters, as given ("-1" and "-2" follow the aspect ratio).
+    fn sizes(&self) -> Vec<(&'a str, &'a str)> {
+        let mut sizes: Vec<_> = self
+            .filters()
+            .into_iter()
+            .flat_map(|graph| graph.split([',', ';']))
+            .filter_map(|filter| {
+                let options = filter
+                    .trim()
+                    .split(']')
+                    .next_back()?
+                    .strip_prefix("scale=")?;
+                let mut options = options.split(':');
+
+                Some((options.next()?, options.next()?))
+            })
+            .collect();
+
+        if let Some(size) = self
+            .get(&["-s", "-s:v"])
+            .and_then(|size| size.split_once('x'))
+        {
+            sizes.push(size);
+        }
+
+        sizes
+    }
+}
+
+fn is_h264(codec: &str) -> bool {
+    codec == "libx264" || codec == "h264" || codec.starts_with("h264_")
+}
+
+fn is_h265(codec: &str) -> bool {
+    codec == "libx265" || codec == "hevc" || codec.starts_with("hevc_")
+}
+
+/// Parses an H.264 level as given to -level ("4.1", "41" or "4").
+fn parse_level(level: &str) -> Option<u32> {
+    match level.split_once('.') {
+        Some((major, minor)) => Some(major.parse::<u32>().ok()? * 10 + minor.parse::<u32>().ok()?),
+        None => {
+            let level = level.parse::<u32>().ok()?;
+
+            Some(if level < 10 { level * 10 } else { level })
+        }
+    }
+}
+
+impl FfmpegRequest {
+    /// Returns the options of every output file of the command.
+    fn output_options<'a>(&'a self, complex_filters: &'a [&'a str]) -> Vec<OutputOptions<'a>> {
+        let mut outputs = Vec::new();
+        let mut options = Vec::new();
+        let mut args = self.args.iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "-" || !arg.starts_with('-') {
+                outputs.push(OutputOptions {
+                    file: arg,
+                    options: std::mem::take(&mut options),
+                    complex_filters,
+                });
+            } else if FLAG_OPTIONS.contains(&arg.as_str()) {
+                options.push((arg.as_str(), ""));
+            } else if arg == "-i" {
+                // Options before an input are input options
+                args.next();
+                options.clear();
+            } else {
+                options.push((arg.as_str(), args.next().map_or("", String::as_str)));
+            }
+        }
+
+        outputs
+    }
+
+    /// Checks the command for common encoding mistakes (players failing on the output, encoders
+    /// rejecting the settings, files that can't be played before they're downloaded).
+    pub(crate) fn lint(&self) -> Vec<LintWarning> {
+        let complex_filters: Vec<&str> = self
+            .args
+            .windows(2)
+            .filter(|pair| pair[0] == "-filter_complex" || pair[0] == "-lavfi")
+            .map(|pair| pair[1].as_str())
+            .collect();
+
+        let mut warnings = Vec::new();
+
+        for output in self.output_options(&complex_filters) {
+            let mut warn = |code: &str, message: String| {
+                warnings.push(LintWarning {
+                    code: code.to_string(),
+                    output: output.file.to_string(),
+                    message,
+                })
+            };
+
+            let extension = output.extension();
+            let codec = output.video_codec().unwrap_or_default();
+            let pix_fmt = output.get(&["-pix_fmt", "-pix_fmt:v"]);
+
+            if is_h264(codec)
+                && WEB_CONTAINERS.contains(&extension.as_str())
+                && pix_fmt.is_none()
+                && !output
+                    .filters()
+                    .iter()
+                    .any(|graph| graph.contains("format=yuv420p") || graph.contains("format=nv12"))
+            {
+                warn(
+                    "missing-pix-fmt",
+                    "H.264 keeps the pixel format of the input (e.g. 4:2:2 or 4:4:4 from cameras \
+                     and screen captures), which browsers can't play: add -pix_fmt yuv420p"
+                        .to_string(),
+                );
+            }
+
+            if let (Some(profile), Some(pix_fmt)) =
+                (output.get(&["-profile:v", "-profile"]), pix_fmt)
+                && is_h264(codec)
+                && ["baseline", "main", "high"].contains(&profile)
+                && !["yuv420p", "yuvj420p", "nv12"].contains(&pix_fmt)
+            {
+                warn(
+                    "profile-pix-fmt",
+                    format!("the {profile} profile only takes 8-bit 4:2:0 video, not {pix_fmt}"),
+                );
+            }
+
+            let sizes = output.sizes();
+
+            if is_h264(codec) || is_h265(codec) {
+                for (width, height) in &sizes {
+                    let odd = |size: &str| size.parse::<u32>().is_ok_and(|size| size % 2 == 1);
+
+                    if odd(width) || odd(height) {
+                        warn(
+                            "odd-dimensions",
+                            format!("{width}x{height} is odd: 4:2:0 encoders need even dimensions"),
+                        );
+                    } else if (*width == "-1" || *height == "-1") && width != height {
+                        warn(
+                            "odd-dimensions",
+                            format!(
+                                "scale={width}:{height} can compute an odd size: use -2 to keep it even"
+                            ),
+                        );
+                    }
+                }
+            }
+
+            if let Some(level) = output.get(&["-level", "-level:v"])
+                && is_h264(codec)
+            {
+                let max_macroblocks = parse_level(level).and_then(|level| {
+                    H264_LEVELS
+                        .iter()
+                        .find(|(known, _)| *known == level)
+                        .map(|(_, macroblocks)| *macroblocks)
+                });
+
+                let frame = sizes.last().and_then(|(width, height)| {
+                    Some((width.parse::<u32>().ok()?, height.parse::<u32>().ok()?))
+                });
+
+                if let (Some(max_macroblocks), Some((width, height))) = (max_macroblocks, frame)
+                    && width.div_ceil(16) * height.div_ceil(16) > max_macroblocks
+                {
+                    warn(
+                        "level-size",
+                        format!("{width}x{height} frames are too large for level {level}"),
+                    );
+                }
+            }
+
+            if MOOV_CONTAINERS.contains(&extension.as_str())
+                && !output.get(&["-movflags"]).is_some_and(|flags| {
+                    ["faststart", "frag_keyframe", "empty_moov"]
+                        .iter()
+                        .any(|flag| flags.contains(flag))
+                })
+            {
+                warn(
+                    "missing-faststart",
+                    "the index is written at the end of the file, players have to download it \
+                     all before starting: add -movflags +faststart"
+                        .to_string(),
+                );
+            }
+        }
+
+        warnings
+    }
+}
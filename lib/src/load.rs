@@ -0,0 +1,175 @@
+use std::fmt;
+use std::time::Duration;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::benchmark::WorkerScore;
+use crate::intake::Tracked;
+use crate::limits::Permit;
+use crate::service::ServiceImpl;
+
+/// Thresholds above which the worker refuses new jobs.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LoadConfig {
+    /// Maximum number of jobs running at the same time (across all callers)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_jobs: Option<u32>,
+
+    /// Minimum free space in the staging dir in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_free_disk: Option<u64>,
+
+    /// Time after which refused jobs are suggested to be retried
+    #[serde(default = "default_retry_after", with = "humantime_serde")]
+    pub retry_after: Duration,
+}
+
+fn default_retry_after() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        Self {
+            max_jobs: None,
+            min_free_disk: None,
+            retry_after: default_retry_after(),
+        }
+    }
+}
+
+/// Error returned when the worker is overloaded.
+///
+/// Like [`ThrottledError`](crate::ThrottledError) it is retryable: the job is retried
+/// (possibly on another worker) instead of queueing up here.
+/// Restate only keeps the message of retryable errors: schedulers read the suggested
+/// retry-after from the `retryAfter` field of the load report instead.
+#[derive(Debug)]
+pub struct BusyError {
+    pub reason: String,
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for BusyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "busy: {}, retry after {}s",
+            self.reason,
+            self.retry_after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for BusyError {}
+
+/// Current load of the worker.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadReport {
+    /// Number of jobs running
+    pub running_jobs: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_jobs: Option<u32>,
+
+    /// Free space in the staging dir in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub free_disk: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_free_disk: Option<u64>,
+
+    /// Whether the intake of new jobs is paused
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub paused: bool,
+
+    /// Whether new jobs are refused
+    pub busy: bool,
+
+    /// Seconds after which refused jobs are suggested to be retried (when busy)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+
+    /// Encoding performance measured by the last `benchmark_encode` job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<WorkerScore>,
+}
+
+impl LoadReport {
+    fn busy_reason(&self) -> Option<String> {
+        if self.paused {
+            return Some("intake is paused".to_string());
+        }
+
+        if let Some(max_jobs) = self.max_jobs.filter(|max| self.running_jobs >= *max) {
+            return Some(format!("{max_jobs} jobs are running"));
+        }
+
+        match (self.free_disk, self.min_free_disk) {
+            (Some(free), Some(min)) if free < min => {
+                Some(format!("{free} bytes free in the staging dir"))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) fn load_report(&self) -> LoadReport {
+        let mut report = LoadReport {
+            running_jobs: self.limiter.running(),
+            max_jobs: self.load.max_jobs,
+            free_disk: self.staging.free_space(),
+            min_free_disk: self.load.min_free_disk,
+            paused: self.intake.is_paused(),
+            busy: false,
+            retry_after: None,
+            score: self.scores.get(),
+        };
+
+        report.busy = report.busy_reason().is_some();
+        report.retry_after = report.busy.then_some(self.load.retry_after.as_secs());
+
+        report
+    }
+
+    /// Admits a job of an enabled handler when the worker is not overloaded (or paused)
+    /// and the caller is within its limits.
+    ///
+    /// The request is counted against the rate limit of the caller once per invocation:
+    /// the admission is journaled, so retries and replays only take a job slot again.
+    ///
+    /// The job is listed as in flight until the returned guards are dropped.
+    pub(crate) async fn admit<'a>(
+        &'a self,
+        ctx: &Context<'_>,
+        handler: &str,
+    ) -> HandlerResult<(Permit<'a>, Tracked<'a>)> {
+        self.check_enabled(handler)?;
+
+        if let Some(reason) = self.load_report().busy_reason() {
+            return Err(BusyError {
+                reason,
+                retry_after: self.load.retry_after,
+            }
+            .into());
+        }
+
+        let caller = self.limiter.caller(ctx.headers());
+
+        ctx.run(|| async { Ok(self.limiter.count(&caller)?) })
+            .name("admit")
+            .await?;
+
+        let permit = self.limiter.acquire(caller.clone())?;
+
+        Ok((permit, self.intake.track(handler, caller)))
+    }
+}
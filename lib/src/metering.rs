@@ -0,0 +1,581 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use jiff::Timestamp;
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::service::{Output, ServiceImpl};
+
+const HOUR: i64 = 3600;
+
+tokio::task_local! {
+    static CURRENT: RefCell<Usage>;
+}
+
+/// Resources consumed by jobs.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    /// CPU time (user and system) spent in ffmpeg
+    pub cpu_seconds: f64,
+
+    /// Wall-clock time of ffmpeg runs using hardware acceleration
+    pub gpu_seconds: f64,
+
+    /// Bytes read from storage by the service (ffmpeg reading URLs directly is not included)
+    pub bytes_in: u64,
+
+    /// Bytes written to storage
+    pub bytes_out: u64,
+
+    /// Number of finished jobs
+    pub jobs: u64,
+
+    /// Estimated cost from the configured hourly rates
+    #[serde(default)]
+    pub cost: f64,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.cpu_seconds += other.cpu_seconds;
+        self.gpu_seconds += other.gpu_seconds;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.jobs += other.jobs;
+        self.cost += other.cost;
+    }
+}
+
+/// Hourly rates the resources of jobs are charged at.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CostRates {
+    /// Price of an hour of CPU time
+    #[serde(default)]
+    pub cpu_hour: f64,
+
+    /// Price of an hour of hardware accelerated processing
+    #[serde(default)]
+    pub gpu_hour: f64,
+
+    /// Currency of the rates (e.g. "USD")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+}
+
+impl CostRates {
+    /// Whether no rate is set (jobs are not charged).
+    pub fn is_empty(&self) -> bool {
+        self.cpu_hour == 0.0 && self.gpu_hour == 0.0
+    }
+
+    fn cost(&self, usage: &Usage) -> f64 {
+        (usage.cpu_seconds * self.cpu_hour + usage.gpu_seconds * self.gpu_hour) / HOUR as f64
+    }
+}
+
+/// Estimated cost of a job.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobCost {
+    pub amount: f64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+
+    /// CPU time (user and system) charged
+    pub cpu_seconds: f64,
+
+    /// Hardware accelerated time charged
+    pub gpu_seconds: f64,
+}
+
+impl JobCost {
+    /// Prices the usage of a job, returns nothing when no rate is set.
+    pub(crate) fn charge(rates: &CostRates, usage: &mut Usage) -> Option<Self> {
+        if rates.is_empty() {
+            return None;
+        }
+
+        usage.cost = rates.cost(usage);
+
+        Some(Self {
+            amount: usage.cost,
+            currency: rates.currency.clone(),
+            cpu_seconds: usage.cpu_seconds,
+            gpu_seconds: usage.gpu_seconds,
+        })
+    }
+}
+
+/// Adds to the usage of the job running in the current task (if it's metered).
+pub(crate) fn record(f: impl FnOnce(&mut Usage)) {
+    let _ = CURRENT.try_with(|usage| f(&mut usage.borrow_mut()));
+}
+
+/// Times reported by `-benchmark` in seconds.
+pub(crate) struct BenchReport {
+    pub user: f64,
+    pub system: f64,
+    pub real: f64,
+}
+
+impl BenchReport {
+    /// Parses the last report printed by `-benchmark`.
+    pub(crate) fn parse(stderr: &str) -> Option<Self> {
+        let bench = stderr
+            .lines()
+            .rev()
+            .find_map(|line| line.trim().strip_prefix("bench: utime="))?;
+
+        let value = |key: &str| -> f64 {
+            bench
+                .split_whitespace()
+                .find_map(|part| part.strip_prefix(key))
+                .and_then(|value| value.trim_end_matches('s').parse().ok())
+                .unwrap_or_default()
+        };
+
+        let user = bench
+            .split_whitespace()
+            .next()
+            .and_then(|value| value.trim_end_matches('s').parse::<f64>().ok())
+            .unwrap_or_default();
+
+        Some(Self {
+            user,
+            system: value("stime="),
+            real: value("rtime="),
+        })
+    }
+
+    /// CPU time (user and system) in seconds.
+    pub(crate) fn cpu(&self) -> f64 {
+        self.user + self.system
+    }
+}
+
+/// Records the resources of an ffmpeg run from the report printed by `-benchmark`.
+pub(crate) fn record_ffmpeg(args: &[String], stderr: &str) {
+    let Some(bench) = BenchReport::parse(stderr) else {
+        return;
+    };
+
+    let hardware = args.iter().any(|arg| {
+        arg == "-hwaccel"
+            || ["_nvenc", "_vaapi", "_qsv", "_videotoolbox", "_amf"]
+                .iter()
+                .any(|suffix| arg.ends_with(suffix))
+    });
+
+    record(|usage| {
+        usage.cpu_seconds += bench.cpu();
+        if hardware {
+            usage.gpu_seconds += bench.real;
+        }
+    });
+}
+
+/// Records the size of the files in a directory as written to storage.
+pub(crate) fn record_upload(dir: &Path) {
+    fn size(dir: &Path) -> u64 {
+        std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => size(&entry.path()),
+                Ok(metadata) => metadata.len(),
+                Err(_) => 0,
+            })
+            .sum()
+    }
+
+    let bytes = size(dir);
+
+    record(|usage| usage.bytes_out += bytes);
+}
+
+/// Result of a job along with the resources it consumed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct Metered<T> {
+    pub response: T,
+    pub usage: Usage,
+    pub finished_at: Timestamp,
+}
+
+/// Runs a job while collecting its usage.
+pub(crate) async fn metered<T>(
+    job: impl Future<Output = HandlerResult<T>>,
+) -> HandlerResult<Json<Metered<T>>> {
+    let (response, mut usage) = CURRENT
+        .scope(RefCell::new(Usage::default()), async {
+            let response = job.await;
+            (response, CURRENT.with(|usage| usage.take()))
+        })
+        .await;
+
+    usage.jobs = 1;
+
+    Ok(Json(Metered {
+        response: response?,
+        usage,
+        finished_at: Timestamp::now(),
+    }))
+}
+
+/// Usage of a finished job.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRecord {
+    /// Name of the handler that ran the job
+    pub handler: String,
+
+    pub finished_at: Timestamp,
+
+    pub usage: Usage,
+}
+
+/// A time window (start inclusive and rounded down to the hour, end exclusive).
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageWindow {
+    pub from: Timestamp,
+    pub to: Timestamp,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantUsage {
+    pub tenant: String,
+
+    /// Usage per handler
+    pub handlers: BTreeMap<String, Usage>,
+
+    /// Total usage
+    pub total: Usage,
+}
+
+/// Usage of a tenant aggregated per hour and handler.
+type UsageBuckets = BTreeMap<i64, BTreeMap<String, Usage>>;
+
+const BUCKETS: &str = "buckets";
+
+/// Usage metering of tenants (keyed by tenant).
+#[restate_sdk::object]
+#[name = "FFmpegMetering"]
+pub trait Metering {
+    /// Record the usage of a finished job.
+    async fn record(record: Json<UsageRecord>) -> HandlerResult<()>;
+
+    /// Return the usage in a time window (aggregated per hour).
+    #[shared]
+    async fn usage(window: Json<UsageWindow>) -> HandlerResult<Json<TenantUsage>>;
+}
+
+pub struct MeteringImpl;
+
+impl Metering for MeteringImpl {
+    async fn record(&self, ctx: ObjectContext<'_>, record: Json<UsageRecord>) -> HandlerResult<()> {
+        let record = record.into_inner();
+
+        let mut buckets = ctx
+            .get::<Json<UsageBuckets>>(BUCKETS)
+            .await?
+            .map(Json::into_inner)
+            .unwrap_or_default();
+
+        let hour = record.finished_at.as_second().div_euclid(HOUR) * HOUR;
+
+        buckets
+            .entry(hour)
+            .or_default()
+            .entry(record.handler)
+            .or_default()
+            .add(&record.usage);
+
+        ctx.set(BUCKETS, Json(buckets));
+
+        Ok(())
+    }
+
+    async fn usage(
+        &self,
+        ctx: SharedObjectContext<'_>,
+        window: Json<UsageWindow>,
+    ) -> HandlerResult<Json<TenantUsage>> {
+        let window = window.into_inner();
+
+        let buckets = ctx
+            .get::<Json<UsageBuckets>>(BUCKETS)
+            .await?
+            .map(Json::into_inner)
+            .unwrap_or_default();
+
+        Ok(Json(tenant_usage(ctx.key(), &buckets, &window)))
+    }
+}
+
+/// Sums the usage of a tenant over the hourly buckets of a window.
+fn tenant_usage(tenant: &str, buckets: &UsageBuckets, window: &UsageWindow) -> TenantUsage {
+    let mut usage = TenantUsage {
+        tenant: tenant.to_string(),
+        handlers: BTreeMap::new(),
+        total: Usage::default(),
+    };
+
+    // Buckets are keyed by the start of their hour
+    let from = window.from.as_second().div_euclid(HOUR) * HOUR;
+
+    for handlers in buckets
+        .range(from..window.to.as_second())
+        .map(|(_, handlers)| handlers)
+    {
+        for (handler, handler_usage) in handlers {
+            usage
+                .handlers
+                .entry(handler.clone())
+                .or_default()
+                .add(handler_usage);
+            usage.total.add(handler_usage);
+        }
+    }
+
+    usage
+}
+
+/// File format of usage exports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_export_usage_request())]
+pub struct ExportUsageRequest {
+    /// Tenants included in the export
+    pub tenants: Vec<String>,
+
+    /// Start of the window (inclusive, rounded down to the hour)
+    pub from: Timestamp,
+
+    /// End of the window (exclusive)
+    pub to: Timestamp,
+
+    #[serde(default)]
+    pub format: UsageFormat,
+
+    pub output: Output,
+}
+
+fn example_export_usage_request() -> ExportUsageRequest {
+    ExportUsageRequest {
+        tenants: vec!["acme".to_string()],
+        from: "2025-01-01T00:00:00Z".parse().unwrap(),
+        to: "2025-02-01T00:00:00Z".parse().unwrap(),
+        format: UsageFormat::Csv,
+        output: Output {
+            location: Url::parse("s3://bucket/usage/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportUsageResponse {
+    pub usage: Vec<TenantUsage>,
+
+    /// Location of the export
+    pub output: Url,
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Renders usage as CSV with one row per tenant and handler.
+fn usage_csv(usage: &[TenantUsage]) -> String {
+    let mut csv =
+        "tenant,handler,cpu_seconds,gpu_seconds,bytes_in,bytes_out,jobs,cost\n".to_string();
+
+    for tenant in usage {
+        for (handler, usage) in &tenant.handlers {
+            csv.push_str(&format!(
+                "{},{},{:.3},{:.3},{},{},{},{:.6}\n",
+                csv_field(&tenant.tenant),
+                csv_field(handler),
+                usage.cpu_seconds,
+                usage.gpu_seconds,
+                usage.bytes_in,
+                usage.bytes_out,
+                usage.jobs,
+                usage.cost,
+            ));
+        }
+    }
+
+    csv
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Writes the usage collected from the metering objects to storage.
+    pub(crate) async fn _export_usage(
+        &self,
+        request: ExportUsageRequest,
+        usage: Vec<TenantUsage>,
+    ) -> HandlerResult<ExportUsageResponse> {
+        let work_dir = TempDir::new()?;
+
+        let (extension, content) = match request.format {
+            UsageFormat::Csv => ("csv", usage_csv(&usage)),
+            UsageFormat::Json => ("json", serde_json::to_string_pretty(&usage)?),
+        };
+
+        let filename = format!(
+            "usage-{}-{}.{extension}",
+            request.from.as_second(),
+            request.to.as_second()
+        );
+
+        tokio::fs::write(work_dir.path().join(&filename), content).await?;
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        Ok(ExportUsageResponse {
+            usage,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jobs(jobs: u64) -> Usage {
+        Usage {
+            jobs,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_benchmark_report() {
+        let stderr = "frame=  250 fps=112 q=-1.0 Lsize=    1024kB time=00:00:10.00 bitrate= 838.9kbits/s speed=4.48x\n\
+                      video:980kB audio:40kB subtitle:0kB other streams:0kB global headers:0kB muxing overhead: 0.398438%\n\
+                      bench: utime=8.532s stime=0.412s rtime=2.231s\n\
+                      bench: maxrss=412864KiB\n";
+
+        let bench = BenchReport::parse(stderr).unwrap();
+
+        assert_eq!(bench.user, 8.532);
+        assert_eq!(bench.system, 0.412);
+        assert_eq!(bench.real, 2.231);
+        assert_eq!(bench.cpu(), 8.532 + 0.412);
+    }
+
+    #[test]
+    fn parse_last_benchmark_report() {
+        let stderr = "bench: utime=1.000s stime=0.100s rtime=0.500s\n\
+                      bench: utime=3.250s stime=0.250s rtime=1.750s\n";
+
+        let bench = BenchReport::parse(stderr).unwrap();
+
+        assert_eq!(bench.user, 3.25);
+        assert_eq!(bench.real, 1.75);
+    }
+
+    #[test]
+    fn parse_missing_benchmark_report() {
+        assert!(BenchReport::parse("bench: maxrss=412864KiB\n").is_none());
+    }
+
+    fn window(from: &str, to: &str) -> UsageWindow {
+        UsageWindow {
+            from: from.parse().unwrap(),
+            to: to.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn usage_includes_the_hour_of_the_window_start() {
+        let hour: i64 = "2025-01-01T10:00:00Z"
+            .parse::<Timestamp>()
+            .unwrap()
+            .as_second();
+
+        let buckets = UsageBuckets::from([
+            (
+                hour - HOUR,
+                BTreeMap::from([("ffmpeg".to_string(), jobs(1))]),
+            ),
+            (hour, BTreeMap::from([("ffmpeg".to_string(), jobs(2))])),
+            (
+                hour + HOUR,
+                BTreeMap::from([
+                    ("ffmpeg".to_string(), jobs(4)),
+                    ("thumbnail".to_string(), jobs(8)),
+                ]),
+            ),
+            (
+                hour + 2 * HOUR,
+                BTreeMap::from([("ffmpeg".to_string(), jobs(16))]),
+            ),
+        ]);
+
+        let usage = tenant_usage(
+            "acme",
+            &buckets,
+            &window("2025-01-01T10:30:00Z", "2025-01-01T12:00:00Z"),
+        );
+
+        assert_eq!(usage.tenant, "acme");
+        assert_eq!(usage.handlers["ffmpeg"].jobs, 6);
+        assert_eq!(usage.handlers["thumbnail"].jobs, 8);
+        assert_eq!(usage.total.jobs, 14);
+    }
+
+    #[test]
+    fn usage_csv_has_a_row_per_handler() {
+        let usage = [TenantUsage {
+            tenant: "acme, inc".to_string(),
+            handlers: BTreeMap::from([(
+                "ffmpeg".to_string(),
+                Usage {
+                    cpu_seconds: 1.5,
+                    gpu_seconds: 0.0,
+                    bytes_in: 100,
+                    bytes_out: 50,
+                    jobs: 2,
+                    cost: 0.25,
+                },
+            )]),
+            total: Usage::default(),
+        }];
+
+        assert_eq!(
+            usage_csv(&usage),
+            "tenant,handler,cpu_seconds,gpu_seconds,bytes_in,bytes_out,jobs,cost\n\
+             \"acme, inc\",ffmpeg,1.500,0.000,100,50,2,0.250000\n"
+        );
+    }
+}
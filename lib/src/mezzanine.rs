@@ -0,0 +1,223 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::inputs::{Input, input_arg};
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem};
+
+/// Intermediate codec profiles for post-production.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum MezzaninePreset {
+    ProresProxy,
+    ProresLt,
+    ProresStandard,
+    ProresHq,
+    Prores4444,
+    Prores4444Xq,
+    DnxhrLb,
+    DnxhrSq,
+    DnxhrHq,
+    DnxhrHqx,
+    Dnxhr444,
+}
+
+impl MezzaninePreset {
+    fn is_prores(&self) -> bool {
+        matches!(
+            self,
+            MezzaninePreset::ProresProxy
+                | MezzaninePreset::ProresLt
+                | MezzaninePreset::ProresStandard
+                | MezzaninePreset::ProresHq
+                | MezzaninePreset::Prores4444
+                | MezzaninePreset::Prores4444Xq
+        )
+    }
+
+    /// Returns the encoder, profile and pixel format of the preset.
+    ///
+    /// Pixel formats are fixed per profile: encoders silently pick an unexpected
+    /// profile (or refuse to encode) when fed a different bit depth or chroma layout.
+    ///
+    /// There is no level to select: unlike H.264 or HEVC levels, the data rate of
+    /// ProRes and DNxHR follows from the profile, the resolution and the frame rate,
+    /// and neither encoder has a `-level` option.
+    fn settings(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            MezzaninePreset::ProresProxy => ("prores_ks", "0", "yuv422p10le"),
+            MezzaninePreset::ProresLt => ("prores_ks", "1", "yuv422p10le"),
+            MezzaninePreset::ProresStandard => ("prores_ks", "2", "yuv422p10le"),
+            MezzaninePreset::ProresHq => ("prores_ks", "3", "yuv422p10le"),
+            MezzaninePreset::Prores4444 => ("prores_ks", "4", "yuva444p10le"),
+            MezzaninePreset::Prores4444Xq => ("prores_ks", "5", "yuva444p10le"),
+            MezzaninePreset::DnxhrLb => ("dnxhd", "dnxhr_lb", "yuv422p"),
+            MezzaninePreset::DnxhrSq => ("dnxhd", "dnxhr_sq", "yuv422p"),
+            MezzaninePreset::DnxhrHq => ("dnxhd", "dnxhr_hq", "yuv422p"),
+            MezzaninePreset::DnxhrHqx => ("dnxhd", "dnxhr_hqx", "yuv422p10le"),
+            MezzaninePreset::Dnxhr444 => ("dnxhd", "dnxhr_444", "yuv444p10le"),
+        }
+    }
+}
+
+/// Container of mezzanine files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MezzanineContainer {
+    #[default]
+    Mov,
+    Mxf,
+}
+
+impl MezzanineContainer {
+    fn extension(&self) -> &'static str {
+        match self {
+            MezzanineContainer::Mov => "mov",
+            MezzanineContainer::Mxf => "mxf",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_mezzanine_request())]
+pub struct MezzanineRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    pub preset: MezzaninePreset,
+
+    #[serde(default)]
+    pub container: MezzanineContainer,
+
+    /// Encode audio as 24-bit PCM (otherwise audio is copied)
+    #[serde(default = "default_pcm_audio")]
+    pub pcm_audio: bool,
+}
+
+fn default_pcm_audio() -> bool {
+    true
+}
+
+fn example_mezzanine_request() -> MezzanineRequest {
+    MezzanineRequest {
+        input: Url::parse("https://example.com/camera.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/mezzanine/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        preset: MezzaninePreset::ProresHq,
+        container: MezzanineContainer::Mov,
+        pcm_audio: true,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MezzanineResponse {
+    /// Pixel format of the encoded video
+    pub pix_fmt: String,
+
+    /// Location of the mezzanine file
+    pub output: Url,
+}
+
+impl MezzanineRequest {
+    fn args(&self, filename: &str, inputs: &mut Vec<Input>) -> Vec<String> {
+        let (encoder, profile, pix_fmt) = self.preset.settings();
+
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&self.input, inputs),
+            "-map".to_string(),
+            "0:v:0".to_string(),
+            "-map".to_string(),
+            "0:a?".to_string(),
+            "-c:v".to_string(),
+            encoder.to_string(),
+            "-profile:v".to_string(),
+            profile.to_string(),
+            "-pix_fmt".to_string(),
+            pix_fmt.to_string(),
+        ];
+
+        if self.preset.is_prores() {
+            // Apple's vendor tag, some NLEs refuse files without it
+            args.extend(["-vendor".to_string(), "apl0".to_string()]);
+        }
+
+        args.extend([
+            "-c:a".to_string(),
+            if self.pcm_audio { "pcm_s24le" } else { "copy" }.to_string(),
+            filename.to_string(),
+        ]);
+
+        args
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _mezzanine(
+        &self,
+        request: MezzanineRequest,
+    ) -> HandlerResult<MezzanineResponse> {
+        if request.preset.is_prores() && request.container == MezzanineContainer::Mxf {
+            return Err(
+                TerminalError::new_with_code(400, "ProRes can only be stored in MOV").into(),
+            );
+        }
+
+        let (_, _, pix_fmt) = request.preset.settings();
+
+        let filename = format!(
+            "{}.{}",
+            input_stem(&request.input),
+            request.container.extension()
+        );
+
+        let mut inputs = Vec::new();
+
+        self._ffmpeg(FfmpegRequest {
+            args: request.args(&filename, &mut inputs),
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(MezzanineResponse {
+            pix_fmt: pix_fmt.to_string(),
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_stage_storage_inputs() {
+        let mut request = example_mezzanine_request();
+        request.input = Url::parse("s3://bucket/camera.mp4").unwrap();
+        let mut inputs = Vec::new();
+
+        let args = request.args("camera.mov", &mut inputs);
+
+        assert_eq!(args[..2], ["-i", "{input0}"]);
+        assert_eq!(inputs[0].location, request.input);
+    }
+}
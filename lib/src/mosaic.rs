@@ -0,0 +1,341 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::encode::{VideoEncoding, default_container};
+use crate::inputs::input_arg;
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem};
+
+/// Largest number of tiles in a mosaic.
+const MAX_INPUTS: usize = 36;
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MosaicInput {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// Text drawn in the corner of the tile
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Audio track of a mosaic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MosaicAudio {
+    /// Mix the audio of every input
+    #[default]
+    Mix,
+    /// Keep the audio of the input selected by audioInput
+    Select,
+    /// No audio
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_mosaic_request())]
+pub struct MosaicRequest {
+    /// Inputs tiled left to right, top to bottom (starting at the same time)
+    pub inputs: Vec<MosaicInput>,
+
+    pub output: Output,
+
+    /// Number of columns (derived from the number of inputs when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub columns: Option<u32>,
+
+    /// Width of a tile in pixels
+    #[serde(default = "default_tile_width")]
+    pub tile_width: u32,
+
+    /// Height of a tile in pixels
+    #[serde(default = "default_tile_height")]
+    pub tile_height: u32,
+
+    /// Frame rate the inputs are synchronized at
+    #[serde(default = "default_frame_rate")]
+    pub frame_rate: f64,
+
+    /// Stop with the shortest input instead of the longest one
+    #[serde(default)]
+    pub shortest: bool,
+
+    #[serde(default)]
+    pub audio: MosaicAudio,
+
+    /// Index of the input whose audio is kept by the select mode
+    #[serde(default)]
+    pub audio_input: usize,
+
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output container extension
+    #[serde(default = "default_container")]
+    pub container: String,
+}
+
+fn default_tile_width() -> u32 {
+    640
+}
+
+fn default_tile_height() -> u32 {
+    360
+}
+
+fn default_frame_rate() -> f64 {
+    30.0
+}
+
+fn example_mosaic_request() -> MosaicRequest {
+    MosaicRequest {
+        inputs: ["cam1", "cam2", "cam3", "cam4"]
+            .into_iter()
+            .map(|camera| MosaicInput {
+                input: Url::parse(&format!("s3://bucket/shoot/{camera}.mp4")).unwrap(),
+                label: Some(camera.to_uppercase()),
+            })
+            .collect(),
+        output: Output {
+            location: Url::parse("s3://bucket/review/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        columns: None,
+        tile_width: default_tile_width(),
+        tile_height: default_tile_height(),
+        frame_rate: default_frame_rate(),
+        shortest: false,
+        audio: MosaicAudio::Select,
+        audio_input: 0,
+        video: VideoEncoding::default(),
+        container: default_container(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MosaicResponse {
+    /// Location of the mosaic
+    pub output: Url,
+
+    pub columns: u32,
+
+    pub rows: u32,
+
+    /// Output width in pixels
+    pub width: u32,
+
+    /// Output height in pixels
+    pub height: u32,
+}
+
+impl MosaicRequest {
+    fn validate(&self) -> Result<(), TerminalError> {
+        let invalid = |message: &str| Err(TerminalError::new_with_code(400, message));
+
+        if self.inputs.is_empty() || self.inputs.len() > MAX_INPUTS {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("a mosaic takes between 1 and {MAX_INPUTS} inputs"),
+            ));
+        }
+
+        if self.columns == Some(0) {
+            return invalid("columns must be positive");
+        }
+
+        if self.tile_width < 2 || self.tile_height < 2 {
+            return invalid("tiles must be at least 2x2 pixels");
+        }
+
+        if self.frame_rate <= 0.0 {
+            return invalid("frameRate must be positive");
+        }
+
+        if self.audio == MosaicAudio::Select && self.audio_input >= self.inputs.len() {
+            return invalid("audioInput is out of range");
+        }
+
+        Ok(())
+    }
+
+    fn grid(&self) -> (u32, u32) {
+        let tiles = self.inputs.len() as u32;
+        let columns = self
+            .columns
+            .unwrap_or_else(|| (tiles as f64).sqrt().ceil() as u32)
+            .min(tiles);
+
+        (columns, tiles.div_ceil(columns))
+    }
+
+    /// Builds the filter graph tiling the inputs into [v], labels are read from the given files.
+    fn filter(&self, columns: u32, labels: &[Option<String>]) -> String {
+        let (width, height) = (self.tile_width, self.tile_height);
+
+        let mut graph: Vec<String> = labels
+            .iter()
+            .enumerate()
+            .map(|(i, label)| {
+                // Timestamps start at zero so that the tiles are in sync
+                let mut tile = format!(
+                    "[{i}:v]setpts=PTS-STARTPTS,fps={},\
+                     scale={width}:{height}:force_original_aspect_ratio=decrease,\
+                     pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1",
+                    self.frame_rate
+                );
+
+                if let Some(textfile) = label {
+                    tile.push_str(&format!(
+                        ",drawtext=textfile='{textfile}':x=10:y=10:fontsize={}:fontcolor=white:box=1:boxcolor=black@0.5:boxborderw=6",
+                        (height / 15).max(12)
+                    ));
+                }
+
+                format!("{tile}[v{i}]")
+            })
+            .collect();
+
+        let tiles: String = (0..labels.len()).map(|i| format!("[v{i}]")).collect();
+
+        if labels.len() == 1 {
+            graph.push(format!("{tiles}null[v]"));
+        } else {
+            let layout: Vec<_> = (0..labels.len() as u32)
+                .map(|i| format!("{}_{}", i % columns * width, i / columns * height))
+                .collect();
+
+            graph.push(format!(
+                "{tiles}xstack=inputs={}:layout={}:fill=black:shortest={}[v]",
+                labels.len(),
+                layout.join("|"),
+                self.shortest as u8
+            ));
+        }
+
+        graph.join(";")
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _mosaic(&self, request: MosaicRequest) -> HandlerResult<MosaicResponse> {
+        request.validate()?;
+        request.video.validate().await?;
+
+        // Labels are passed in files: drawtext escaping inside a filter graph is fragile
+        let label_dir = TempDir::new()?;
+        let mut labels = Vec::new();
+
+        for (i, input) in request.inputs.iter().enumerate() {
+            let Some(label) = &input.label else {
+                labels.push(None);
+                continue;
+            };
+
+            let path = label_dir.path().join(format!("{i}.txt"));
+            tokio::fs::write(&path, label).await?;
+
+            labels.push(Some(path.display().to_string()));
+        }
+
+        let mut audio = Vec::new();
+
+        if request.audio == MosaicAudio::Mix {
+            for (i, input) in request.inputs.iter().enumerate() {
+                if self.probe(&input.input).await?.stream("audio").is_some() {
+                    audio.push(i);
+                }
+            }
+        }
+
+        let (columns, rows) = request.grid();
+        let mut filter = request.filter(columns, &labels);
+
+        let mut inputs = Vec::new();
+        let mut args = Vec::new();
+
+        for input in &request.inputs {
+            args.extend(["-i".to_string(), input_arg(&input.input, &mut inputs)]);
+        }
+
+        // A single track with audio is mapped as is
+        let mixed = audio.len() > 1;
+
+        if mixed {
+            let pads: String = audio.iter().map(|i| format!("[{i}:a]")).collect();
+
+            filter.push_str(&format!(
+                ";{pads}amix=inputs={}:duration={}:normalize=0[a]",
+                audio.len(),
+                if request.shortest {
+                    "shortest"
+                } else {
+                    "longest"
+                }
+            ));
+        }
+
+        args.extend([
+            "-filter_complex".to_string(),
+            filter,
+            "-map".to_string(),
+            "[v]".to_string(),
+        ]);
+
+        match request.audio {
+            MosaicAudio::Mix if mixed => {
+                args.extend(["-map".to_string(), "[a]".to_string()]);
+            }
+            MosaicAudio::Mix => {
+                args.extend(
+                    audio
+                        .iter()
+                        .flat_map(|i| ["-map".to_string(), format!("{i}:a:0")]),
+                );
+            }
+            MosaicAudio::Select => {
+                args.extend(["-map".to_string(), format!("{}:a:0?", request.audio_input)]);
+            }
+            MosaicAudio::None => args.push("-an".to_string()),
+        }
+
+        let filename = format!(
+            "{}_mosaic.{}",
+            input_stem(&request.inputs[0].input),
+            request.container
+        );
+
+        args.extend(request.video.args());
+        args.extend(["-c:a".to_string(), "aac".to_string(), filename.clone()]);
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(MosaicResponse {
+            output: request.output.file_url(&filename),
+            columns,
+            rows,
+            width: columns * request.tile_width,
+            height: rows * request.tile_height,
+        })
+    }
+}
@@ -0,0 +1,309 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::aspect::{even, valid_color};
+use crate::encode::{VideoEncoding, default_container};
+use crate::inputs::input_arg;
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem};
+
+/// Corner (or center) of the main video the overlay is placed in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum PipPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+impl PipPosition {
+    /// Overlay coordinates of the position (W/H are the main video, w/h the overlay).
+    fn coordinates(&self, margin: u32) -> (String, String) {
+        let left = margin.to_string();
+        let top = margin.to_string();
+        let right = format!("W-w-{margin}");
+        let bottom = format!("H-h-{margin}");
+
+        match self {
+            PipPosition::TopLeft => (left, top),
+            PipPosition::TopRight => (right, top),
+            PipPosition::BottomLeft => (left, bottom),
+            PipPosition::BottomRight => (right, bottom),
+            PipPosition::Center => ("(W-w)/2".to_string(), "(H-h)/2".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PipBorder {
+    /// Width of the border in pixels
+    pub width: u32,
+
+    /// Color of the border (e.g. "white" or "0x202020")
+    #[serde(default = "default_border_color")]
+    pub color: String,
+}
+
+fn default_border_color() -> String {
+    "white".to_string()
+}
+
+/// Audio track of the composed output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PipAudio {
+    /// Keep the audio of the main video
+    #[default]
+    Main,
+    /// Keep the audio of the overlay
+    Overlay,
+    /// Mix both
+    Mix,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_pip_request())]
+pub struct PipRequest {
+    /// Path or URL to the main video (e.g. a screen share)
+    pub input: Url,
+
+    /// Path or URL to the video overlaid on the main one (e.g. a camera), in sync with it
+    pub overlay: Url,
+
+    pub output: Output,
+
+    #[serde(default)]
+    pub position: PipPosition,
+
+    /// Width of the overlay relative to the main video (0-1)
+    #[serde(default = "default_size")]
+    pub size: f64,
+
+    /// Distance of the overlay from the edges in pixels
+    #[serde(default = "default_margin")]
+    pub margin: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub border: Option<PipBorder>,
+
+    /// Time the overlay appears at in seconds (from the start when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<f64>,
+
+    /// Time the overlay disappears at in seconds (at the end when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<f64>,
+
+    #[serde(default)]
+    pub audio: PipAudio,
+
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output container extension
+    #[serde(default = "default_container")]
+    pub container: String,
+}
+
+fn default_size() -> f64 {
+    0.25
+}
+
+fn default_margin() -> u32 {
+    20
+}
+
+fn example_pip_request() -> PipRequest {
+    PipRequest {
+        input: Url::parse("s3://bucket/meetings/screen.mp4").unwrap(),
+        overlay: Url::parse("s3://bucket/meetings/camera.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/composed/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        position: PipPosition::BottomRight,
+        size: default_size(),
+        margin: default_margin(),
+        border: Some(PipBorder {
+            width: 4,
+            color: default_border_color(),
+        }),
+        start: None,
+        end: None,
+        audio: PipAudio::Mix,
+        video: VideoEncoding::default(),
+        container: default_container(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PipResponse {
+    /// Location of the composed file
+    pub output: Url,
+
+    /// Width of the overlay in pixels (border included)
+    pub overlay_width: u32,
+
+    /// Height of the overlay in pixels (border included)
+    pub overlay_height: u32,
+}
+
+impl PipRequest {
+    fn validate(&self) -> Result<(), TerminalError> {
+        let invalid = |message: &str| Err(TerminalError::new_with_code(400, message));
+
+        if self.size <= 0.0 || self.size > 1.0 {
+            return invalid("size must be between 0 and 1");
+        }
+
+        if self.start.is_some_and(|start| start < 0.0) {
+            return invalid("start must not be negative");
+        }
+
+        if let (Some(start), Some(end)) = (self.start, self.end)
+            && end <= start
+        {
+            return invalid("end must be after start");
+        }
+
+        if let Some(border) = &self.border
+            && !valid_color(&border.color)
+        {
+            return invalid("invalid border color");
+        }
+
+        Ok(())
+    }
+
+    /// Expression enabling the overlay during its time window (if any).
+    fn enable(&self) -> Option<String> {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) => Some(format!("between(t,{start},{end})")),
+            (Some(start), None) => Some(format!("gte(t,{start})")),
+            (None, Some(end)) => Some(format!("lte(t,{end})")),
+            (None, None) => None,
+        }
+    }
+
+    fn filter(&self, width: u32, height: u32) -> String {
+        let mut overlay = format!("[1:v]scale={width}:{height},setsar=1");
+
+        if let Some(border) = &self.border {
+            overlay.push_str(&format!(
+                ",pad=iw+{0}:ih+{0}:{1}:{1}:color={2}",
+                border.width * 2,
+                border.width,
+                border.color
+            ));
+        }
+
+        let (x, y) = self.position.coordinates(self.margin);
+
+        // The main video keeps going when the overlay ends early
+        let mut compose = format!("[0:v][pip]overlay=x={x}:y={y}:eof_action=pass");
+
+        if let Some(enable) = self.enable() {
+            compose.push_str(&format!(":enable='{enable}'"));
+        }
+
+        let mut filter = format!("{overlay}[pip];{compose}[v]");
+
+        if self.audio == PipAudio::Mix {
+            filter.push_str(";[0:a][1:a]amix=inputs=2:duration=first:normalize=0[a]");
+        }
+
+        filter
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _pip(&self, mut request: PipRequest) -> HandlerResult<PipResponse> {
+        request.validate()?;
+        request.video.validate().await?;
+
+        let main = self.probe(&request.input).await?;
+        let overlay = self.probe(&request.overlay).await?;
+
+        let main_width = main
+            .stream("video")
+            .and_then(|stream| stream.width)
+            .ok_or_else(|| TerminalError::new_with_code(400, "input has no video stream"))?;
+
+        let (Some(overlay_width), Some(overlay_height)) = overlay
+            .stream("video")
+            .map(|stream| (stream.width, stream.height))
+            .unwrap_or_default()
+        else {
+            return Err(TerminalError::new_with_code(400, "overlay has no video stream").into());
+        };
+
+        // Mixing needs both tracks: fall back to the one that exists
+        if request.audio == PipAudio::Mix {
+            match (main.stream("audio"), overlay.stream("audio")) {
+                (_, None) => request.audio = PipAudio::Main,
+                (None, Some(_)) => request.audio = PipAudio::Overlay,
+                (Some(_), Some(_)) => {}
+            }
+        }
+
+        let width = even(main_width as f64 * request.size);
+        let height = even(width as f64 * overlay_height as f64 / overlay_width as f64);
+
+        let filename = format!("{}_pip.{}", input_stem(&request.input), request.container);
+
+        let mut inputs = Vec::new();
+
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-i".to_string(),
+            input_arg(&request.overlay, &mut inputs),
+            "-filter_complex".to_string(),
+            request.filter(width, height),
+            "-map".to_string(),
+            "[v]".to_string(),
+            "-map".to_string(),
+            match request.audio {
+                PipAudio::Main => "0:a?".to_string(),
+                PipAudio::Overlay => "1:a?".to_string(),
+                PipAudio::Mix => "[a]".to_string(),
+            },
+        ];
+
+        args.extend(request.video.args());
+        args.extend(["-c:a".to_string(), "aac".to_string(), filename.clone()]);
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        let border = request.border.as_ref().map_or(0, |border| border.width * 2);
+
+        Ok(PipResponse {
+            output: request.output.file_url(&filename),
+            overlay_width: width + border,
+            overlay_height: height + border,
+        })
+    }
+}
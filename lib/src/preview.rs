@@ -0,0 +1,222 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::capabilities::require_encoder;
+use crate::inputs::input_arg;
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem};
+
+/// Longest preview in seconds.
+const MAX_DURATION: f64 = 60.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    /// Animated GIF with a palette generated for the clip
+    #[default]
+    Gif,
+    /// Animated WebP (smaller, full color)
+    Webp,
+}
+
+impl PreviewFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            PreviewFormat::Gif => "gif",
+            PreviewFormat::Webp => "webp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_preview_request())]
+pub struct PreviewRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Start of the clip in seconds
+    #[serde(default)]
+    pub start: f64,
+
+    /// Length of the clip in seconds
+    #[serde(default = "default_duration")]
+    pub duration: f64,
+
+    /// Frames per second of the preview
+    #[serde(default = "default_fps")]
+    pub fps: u32,
+
+    /// Width of the preview in pixels (the height follows the aspect ratio of the input)
+    #[serde(default = "default_width")]
+    pub width: u32,
+
+    #[serde(default)]
+    pub format: PreviewFormat,
+}
+
+fn default_duration() -> f64 {
+    3.0
+}
+
+fn default_fps() -> u32 {
+    10
+}
+
+fn default_width() -> u32 {
+    320
+}
+
+fn example_preview_request() -> PreviewRequest {
+    PreviewRequest {
+        input: Url::parse("s3://bucket/uploads/video.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/previews/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        start: 12.0,
+        duration: default_duration(),
+        fps: default_fps(),
+        width: default_width(),
+        format: PreviewFormat::Gif,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewResponse {
+    /// Location of the preview
+    pub output: Url,
+
+    /// Length of the preview in seconds (shorter than requested at the end of the input)
+    pub duration: f64,
+}
+
+impl PreviewRequest {
+    async fn validate(&self) -> HandlerResult<()> {
+        let invalid = |message: String| -> HandlerResult<()> {
+            Err(TerminalError::new_with_code(400, message).into())
+        };
+
+        if self.start < 0.0 {
+            return invalid("start must not be negative".to_string());
+        }
+
+        if self.duration <= 0.0 || self.duration > MAX_DURATION {
+            return invalid(format!(
+                "duration must be positive and at most {MAX_DURATION}s"
+            ));
+        }
+
+        if !(1..=50).contains(&self.fps) {
+            return invalid("fps must be between 1 and 50".to_string());
+        }
+
+        if self.width < 2 || !self.width.is_multiple_of(2) {
+            return invalid("width must be even".to_string());
+        }
+
+        match self.format {
+            PreviewFormat::Gif => Ok(()),
+            PreviewFormat::Webp => require_encoder("libwebp").await,
+        }
+    }
+
+    /// Filter graph of the preview.
+    ///
+    /// GIFs take 256 colors: a palette is generated from the clip itself instead of the default
+    /// web palette, only the changed rectangles are dithered again from a frame to the next.
+    fn filter(&self) -> String {
+        let scale = format!("fps={},scale={}:-2:flags=lanczos", self.fps, self.width);
+
+        match self.format {
+            PreviewFormat::Gif => format!(
+                "[0:v]{scale},split[a][b];[a]palettegen=stats_mode=diff[p];\
+                 [b][p]paletteuse=dither=bayer:bayer_scale=5:diff_mode=rectangle"
+            ),
+            PreviewFormat::Webp => format!("[0:v]{scale}"),
+        }
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _preview(&self, request: PreviewRequest) -> HandlerResult<PreviewResponse> {
+        request.validate().await?;
+
+        let probe = self.probe(&request.input).await?;
+
+        if probe.stream("video").is_none() {
+            return Err(TerminalError::new_with_code(400, "input has no video stream").into());
+        }
+
+        let duration = match probe.duration() {
+            Some(input_duration) if request.start >= input_duration => {
+                return Err(TerminalError::new_with_code(
+                    400,
+                    format!("start is past the end of the input ({input_duration:.3}s)"),
+                )
+                .into());
+            }
+            Some(input_duration) => request.duration.min(input_duration - request.start),
+            None => request.duration,
+        };
+
+        let filename = format!(
+            "{}_preview.{}",
+            input_stem(&request.input),
+            request.format.extension()
+        );
+
+        let mut inputs = Vec::new();
+
+        let mut args = vec![
+            "-ss".to_string(),
+            format!("{:.3}", request.start),
+            "-t".to_string(),
+            format!("{:.3}", request.duration),
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-filter_complex".to_string(),
+            request.filter(),
+            "-an".to_string(),
+        ];
+
+        if request.format == PreviewFormat::Webp {
+            args.extend([
+                "-c:v".to_string(),
+                "libwebp".to_string(),
+                "-quality".to_string(),
+                "75".to_string(),
+            ]);
+        }
+
+        // Loop forever
+        args.extend(["-loop".to_string(), "0".to_string(), filename.clone()]);
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(PreviewResponse {
+            output: request.output.file_url(&filename),
+            duration,
+        })
+    }
+}
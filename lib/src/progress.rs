@@ -0,0 +1,132 @@
+use opendal_util::OperatorFactory;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::diagnose::Registered;
+use crate::service::ServiceImpl;
+
+/// Arguments making ffmpeg report its progress to stdout.
+pub(crate) fn args() -> Vec<String> {
+    vec!["-progress".to_string(), "pipe:1".to_string()]
+}
+
+/// Progress is reported unless stdout is the output or the request reports it elsewhere.
+pub(crate) fn reportable(args: &[String], output_to_stdout: bool) -> bool {
+    !output_to_stdout && !args.iter().any(|arg| arg == "-progress")
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressRequest {
+    /// Job to report the progress of, as given in the x-job-id header of its invocation
+    /// (every job running on the worker when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressResponse {
+    pub processes: Vec<ProcessProgress>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessProgress {
+    /// Job the process runs for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+
+    pub pid: u32,
+
+    /// Latest progress reported by ffmpeg (empty until the first report)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<Progress>,
+}
+
+/// Snapshot of the progress reported by ffmpeg (see -progress).
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Progress {
+    /// Number of frames encoded so far
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame: Option<u64>,
+
+    /// Frames encoded per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fps: Option<f64>,
+
+    /// Output time reached (e.g. "00:01:02.500000")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub out_time: Option<String>,
+
+    /// Output time reached in microseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub out_time_us: Option<u64>,
+
+    /// Encoding speed relative to real time (e.g. 2.0 when encoding twice as fast as playback)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+
+    /// Size of the output written so far in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_size: Option<u64>,
+
+    /// Whether ffmpeg reported the last block (it may still be finishing the outputs)
+    pub ended: bool,
+}
+
+impl Progress {
+    /// Applies a key/value line of a progress block.
+    fn apply(&mut self, key: &str, value: &str) {
+        // Unknown values are reported as "N/A"
+        match key {
+            "frame" => self.frame = value.parse().ok(),
+            "fps" => self.fps = value.parse().ok(),
+            "out_time" => self.out_time = Some(value.to_string()),
+            "out_time_us" => self.out_time_us = value.parse().ok(),
+            "speed" => self.speed = value.trim().trim_end_matches('x').parse().ok(),
+            "total_size" => self.total_size = value.parse().ok(),
+            "progress" => self.ended = value == "end",
+            _ => {}
+        }
+    }
+}
+
+impl Registered<'_> {
+    /// Reads the progress blocks of the process, publishing each one when complete.
+    pub(crate) async fn read_progress(
+        &self,
+        output: impl AsyncRead + Unpin,
+    ) -> std::io::Result<()> {
+        let mut lines = BufReader::new(output).lines();
+        let mut progress = Progress::default();
+
+        while let Some(line) = lines.next_line().await? {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            progress.apply(key, value);
+
+            // Every block ends with the progress key
+            if key == "progress" {
+                self.set_progress(progress.clone());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) fn _progress(&self, request: ProgressRequest) -> ProgressResponse {
+        ProgressResponse {
+            processes: self.processes.progress(request.job_id.as_deref()),
+        }
+    }
+}
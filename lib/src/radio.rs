@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::service::{Output, ServiceImpl, input_stem, run_ffmpeg_in, work_files};
+
+/// Longest recording a single job may make.
+const MAX_RECORDING_DURATION: f64 = 24.0 * 3600.0;
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_record_stream_request())]
+pub struct RecordStreamRequest {
+    /// HTTP URL of the audio stream
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Length of the recording in seconds (at most a day)
+    pub max_duration: f64,
+
+    /// Split the recording into segments of this many seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segment_duration: Option<f64>,
+
+    /// Output file extension (derived from the stream codec when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+}
+
+fn example_record_stream_request() -> RecordStreamRequest {
+    RecordStreamRequest {
+        input: Url::parse("https://radio.example.com/live.mp3").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/recordings/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        max_duration: 3600.0,
+        segment_duration: Some(600.0),
+        container: None,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordStreamResponse {
+    /// ICY headers of the station (e.g. icy-name, icy-genre)
+    pub station: BTreeMap<String, String>,
+
+    /// Stream titles announced during the recording, in order
+    pub titles: Vec<String>,
+
+    /// Location of the recorded files
+    pub outputs: Vec<Url>,
+}
+
+/// Returns the container audio of a codec is stored in without re-encoding.
+fn container_for(codec: Option<&str>) -> &'static str {
+    match codec {
+        Some("mp3") => "mp3",
+        Some("aac") => "aac",
+        Some("opus") | Some("vorbis") | Some("flac") => "ogg",
+        _ => "mka",
+    }
+}
+
+/// Parses the stream titles from the ffmpeg log (printed as metadata updates and input metadata).
+fn stream_titles(log: &str) -> Vec<String> {
+    let mut titles: Vec<String> = Vec::new();
+
+    for line in log.lines() {
+        let Some((_, value)) = line.split_once("StreamTitle") else {
+            continue;
+        };
+
+        // "StreamTitle     : Artist - Song" or "StreamTitle='Artist - Song';"
+        let title = value
+            .trim_start_matches([' ', ':', '='])
+            .trim_start_matches('\'')
+            .trim_end_matches(';')
+            .trim_end_matches('\'')
+            .trim();
+
+        if !title.is_empty() && titles.last().is_none_or(|last| last != title) {
+            titles.push(title.to_string());
+        }
+    }
+
+    titles
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _record_stream(
+        &self,
+        request: RecordStreamRequest,
+    ) -> HandlerResult<RecordStreamResponse> {
+        if !matches!(request.input.scheme(), "http" | "https") {
+            return Err(TerminalError::new_with_code(400, "input must be an HTTP stream").into());
+        }
+
+        if request.max_duration <= 0.0 || request.max_duration > MAX_RECORDING_DURATION {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("maxDuration must be between 0 and {MAX_RECORDING_DURATION} seconds"),
+            )
+            .into());
+        }
+
+        if request
+            .segment_duration
+            .is_some_and(|duration| duration <= 0.0)
+        {
+            return Err(
+                TerminalError::new_with_code(400, "segmentDuration must be positive").into(),
+            );
+        }
+
+        let probe = self.probe(&request.input).await?;
+
+        let station = probe
+            .format
+            .as_ref()
+            .map(|format| {
+                format
+                    .tags
+                    .iter()
+                    .filter(|(key, _)| key.starts_with("icy-"))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let container = request.container.clone().unwrap_or_else(|| {
+            container_for(
+                probe
+                    .stream("audio")
+                    .and_then(|stream| stream.codec_name.as_deref()),
+            )
+            .to_string()
+        });
+
+        let work_dir = TempDir::new()?;
+        let stem = input_stem(&request.input);
+
+        let mut args = vec![
+            // Metadata updates are logged at the verbose level
+            "-loglevel".to_string(),
+            "verbose".to_string(),
+            "-icy".to_string(),
+            "1".to_string(),
+            "-reconnect".to_string(),
+            "1".to_string(),
+            "-reconnect_streamed".to_string(),
+            "1".to_string(),
+            "-reconnect_delay_max".to_string(),
+            "10".to_string(),
+            "-i".to_string(),
+            request.input.to_string(),
+            "-t".to_string(),
+            request.max_duration.to_string(),
+            "-map".to_string(),
+            "0:a".to_string(),
+            "-c".to_string(),
+            "copy".to_string(),
+        ];
+
+        match request.segment_duration {
+            Some(duration) => args.extend([
+                "-f".to_string(),
+                "segment".to_string(),
+                "-segment_time".to_string(),
+                duration.to_string(),
+                "-reset_timestamps".to_string(),
+                "1".to_string(),
+                format!("{stem}_%05d.{container}"),
+            ]),
+            None => args.push(format!("{stem}.{container}")),
+        }
+
+        let log = run_ffmpeg_in(work_dir.path(), &args).await?;
+
+        let outputs = work_files(work_dir.path())
+            .iter()
+            .map(|name| request.output.file_url(name))
+            .collect();
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        Ok(RecordStreamResponse {
+            station,
+            titles: stream_titles(&log),
+            outputs,
+        })
+    }
+}
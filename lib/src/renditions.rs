@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::captions::SubtitleFormat;
+use crate::service::{ServiceImpl, run_ffmpeg_in, work_files};
+
+/// Largest number of subtitle renditions of a package.
+const MAX_SUBTITLES: usize = 20;
+
+/// Purpose of a subtitle rendition.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubtitleRole {
+    /// Translation of the dialogue
+    #[default]
+    Subtitle,
+    /// Transcription of the dialogue and the sounds for the deaf and hard of hearing
+    Caption,
+    /// Only the foreign or on-screen text parts, shown even when subtitles are off
+    ForcedSubtitle,
+}
+
+impl SubtitleRole {
+    /// Value of the role descriptor in DASH manifests (urn:mpeg:dash:role:2011).
+    pub(crate) fn dash_role(&self) -> &'static str {
+        match self {
+            SubtitleRole::Subtitle => "subtitle",
+            SubtitleRole::Caption => "caption",
+            SubtitleRole::ForcedSubtitle => "forced-subtitle",
+        }
+    }
+}
+
+/// Subtitles offered next to the audio and video of a package.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtitleRendition {
+    /// Path or URL to a subtitle file (the subtitle streams of the input when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<Url>,
+
+    /// Index of the stream among the subtitle streams of the source
+    #[serde(default)]
+    pub stream_index: u32,
+
+    /// Language of the subtitles as an RFC 5646 tag (e.g. "en-US")
+    pub language: String,
+
+    /// Name shown by players (the language when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(default)]
+    pub role: SubtitleRole,
+
+    /// Select the rendition when the player has no language preference
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub default: bool,
+
+    /// Character encoding of the subtitle file (detected when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+}
+
+impl SubtitleRendition {
+    pub(crate) fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.language)
+    }
+
+    /// Name of the files of the rendition, unique within a package.
+    pub(crate) fn id(&self) -> String {
+        format!("subtitles_{}_{}", self.language, self.role.dash_role())
+    }
+}
+
+/// Subtitle rendition written to the output.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PackagedSubtitles {
+    pub language: String,
+
+    pub role: SubtitleRole,
+
+    /// Location of the media playlist (HLS) or the subtitle file (DASH)
+    pub location: Url,
+}
+
+/// Checks the subtitle renditions of a package.
+pub(crate) fn validate_subtitles(subtitles: &[SubtitleRendition]) -> Result<(), TerminalError> {
+    let invalid = |message: String| Err(TerminalError::new_with_code(400, message));
+
+    if subtitles.len() > MAX_SUBTITLES {
+        return invalid(format!(
+            "a package takes at most {MAX_SUBTITLES} subtitle renditions"
+        ));
+    }
+
+    for (i, rendition) in subtitles.iter().enumerate() {
+        let language = &rendition.language;
+
+        if language.is_empty()
+            || !language
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return invalid(format!(
+                "subtitle language {language:?} is not an RFC 5646 tag"
+            ));
+        }
+
+        // Names end up in quoted playlist attributes and in XML
+        if rendition
+            .name
+            .as_deref()
+            .is_some_and(|name| name.contains(['"', '<', '>', '&']))
+        {
+            return invalid(format!(
+                "subtitle name of {language} must not contain quotes or markup"
+            ));
+        }
+
+        if subtitles[..i]
+            .iter()
+            .any(|other| other.id() == rendition.id())
+        {
+            return invalid(format!(
+                "more than one {language} {} rendition",
+                rendition.role.dash_role()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Cue of a WebVTT file with its timing in seconds.
+struct Cue<'a> {
+    start: f64,
+    end: f64,
+    block: &'a str,
+}
+
+/// Parses a WebVTT timestamp ("01:02:03.456" or "02:03.456").
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let (time, millis) = timestamp.trim().split_once('.')?;
+
+    let seconds = time.split(':').try_fold(0.0, |total, part| {
+        Some(total * 60.0 + part.parse::<f64>().ok()?)
+    })?;
+
+    Some(seconds + millis.parse::<f64>().ok()? / 1000.0)
+}
+
+/// Returns the cues of a WebVTT file (blocks with a timing line).
+fn parse_cues(vtt: &str) -> Vec<Cue<'_>> {
+    vtt.split("\n\n")
+        .filter_map(|block| {
+            let block = block.trim_matches('\n');
+            let timing = block.lines().find(|line| line.contains("-->"))?;
+            let (start, end) = timing.split_once("-->")?;
+            let end = end.split_whitespace().next()?;
+
+            Some(Cue {
+                start: parse_timestamp(start)?,
+                end: parse_timestamp(end)?,
+                block,
+            })
+        })
+        .collect()
+}
+
+/// Splits a WebVTT file into segments of the media playlist.
+///
+/// Cues spanning a segment boundary are repeated in every segment they are shown in.
+/// `mpegts` maps the cue times to the timestamps of transport stream segments.
+pub(crate) fn segment_webvtt(
+    vtt: &str,
+    duration: f64,
+    segment_duration: f64,
+    mpegts: Option<u64>,
+) -> Vec<String> {
+    let vtt = vtt.replace("\r\n", "\n");
+    let cues = parse_cues(&vtt);
+    let segments = (duration / segment_duration).ceil().max(1.0) as usize;
+
+    (0..segments)
+        .map(|i| {
+            let start = i as f64 * segment_duration;
+            let end = start + segment_duration;
+
+            let mut segment = "WEBVTT\n".to_string();
+
+            if let Some(mpegts) = mpegts {
+                segment.push_str(&format!(
+                    "X-TIMESTAMP-MAP=MPEGTS:{mpegts},LOCAL:00:00:00.000\n"
+                ));
+            }
+
+            for cue in cues.iter().filter(|cue| cue.start < end && cue.end > start) {
+                segment.push('\n');
+                segment.push_str(cue.block);
+                segment.push('\n');
+            }
+
+            segment
+        })
+        .collect()
+}
+
+/// Media playlist of subtitle segments named `segment_00000.vtt`, ...
+pub(crate) fn subtitle_playlist(duration: f64, segment_duration: f64, segments: usize) -> String {
+    let mut lines = vec![
+        "#EXTM3U".to_string(),
+        "#EXT-X-VERSION:3".to_string(),
+        format!("#EXT-X-TARGETDURATION:{}", segment_duration.ceil() as u64),
+        "#EXT-X-MEDIA-SEQUENCE:0".to_string(),
+        "#EXT-X-PLAYLIST-TYPE:VOD".to_string(),
+    ];
+
+    for i in 0..segments {
+        let start = i as f64 * segment_duration;
+        let length = (duration - start).clamp(0.0, segment_duration);
+
+        lines.push(format!("#EXTINF:{length:.3},"));
+        lines.push(format!("segment_{i:05}.vtt"));
+    }
+
+    lines.push("#EXT-X-ENDLIST".to_string());
+
+    lines.join("\n") + "\n"
+}
+
+/// Reads the playlists (or manifests) of a package, keyed by their path in the package.
+pub(crate) fn read_playlists(
+    dir: &Path,
+    extension: &str,
+) -> std::io::Result<BTreeMap<String, String>> {
+    work_files(dir)
+        .into_iter()
+        .filter(|file| {
+            Path::new(file)
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+        })
+        .map(|file| {
+            let content = std::fs::read_to_string(dir.join(&file))?;
+            Ok((file, content))
+        })
+        .collect()
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Converts the subtitles of a rendition (from its source or the input) and returns them.
+    pub(crate) async fn convert_subtitle_rendition(
+        &self,
+        input: &Url,
+        rendition: &SubtitleRendition,
+        format: SubtitleFormat,
+    ) -> HandlerResult<String> {
+        let staging_dir = TempDir::new()?;
+        let work_dir = TempDir::new()?;
+
+        let mut args = match &rendition.source {
+            Some(source) => {
+                self.stage_subtitles(
+                    source,
+                    rendition.charset.as_deref(),
+                    None,
+                    staging_dir.path(),
+                )
+                .await?
+                .0
+            }
+            None => vec![
+                "-i".to_string(),
+                self.local_input(input, staging_dir.path()).await?,
+            ],
+        };
+
+        let filename = format!("subtitles.{}", format.extension());
+
+        args.extend([
+            "-map".to_string(),
+            format!("0:s:{}", rendition.stream_index),
+            "-c:s".to_string(),
+            format.encoder().to_string(),
+            filename.clone(),
+        ]);
+
+        run_ffmpeg_in(work_dir.path(), &args).await?;
+
+        Ok(tokio::fs::read_to_string(work_dir.path().join(filename)).await?)
+    }
+}
@@ -0,0 +1,359 @@
+use std::path::Path;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use url::Url;
+
+use crate::service::{
+    FfprobeResponse, Output, ServiceImpl, input_extension, input_stem, run_ffmpeg_in, run_ffprobe,
+};
+
+/// Containers made of ISO BMFF boxes (scanned for truncated fragments).
+const MP4_CONTAINERS: &[&str] = &["mp4", "m4v", "m4a", "mov", "3gp"];
+
+/// Most corruption messages returned.
+const MAX_ERRORS: usize = 20;
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_repair_request())]
+pub struct RepairRequest {
+    /// Path or URL to the damaged media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Container of the repaired file (the one of the input when empty, e.g. "mkv" tolerates
+    /// timestamp gaps better)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+fn example_repair_request() -> RepairRequest {
+    RepairRequest {
+        input: Url::parse("s3://bucket/uploads/recording.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/repaired/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        format: None,
+    }
+}
+
+/// Fix applied to the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RepairFix {
+    /// The incomplete fragment at the end of a truncated MP4 was dropped
+    TruncatedFragment,
+    /// Corrupt packets were skipped instead of failing the remux
+    IgnoredErrors,
+    /// Missing presentation timestamps were regenerated
+    RegeneratedTimestamps,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RepairedStream {
+    /// Index of the stream in the input
+    pub index: u32,
+
+    pub codec_type: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec_name: Option<String>,
+
+    /// The stream is in the repaired file
+    pub recovered: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RepairResponse {
+    /// Location of the repaired file
+    pub output: Url,
+
+    pub fixes: Vec<RepairFix>,
+
+    /// Duration of the repaired file in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+
+    /// Duration declared by the input in seconds (unknown when its index is damaged)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_duration: Option<f64>,
+
+    /// Seconds of the input missing from the repaired file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lost_seconds: Option<f64>,
+
+    /// Bytes dropped from the end of a truncated input
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub truncated_bytes: u64,
+
+    /// Streams of the input (empty when the input could not be probed)
+    pub streams: Vec<RepairedStream>,
+
+    /// Corruption reported by ffmpeg while remuxing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Layout of the top-level boxes of an MP4 file.
+#[derive(Debug, Default)]
+struct Mp4Scan {
+    /// The file has an index (moov box)
+    moov: bool,
+
+    /// The file is made of movie fragments (moof boxes)
+    fragmented: bool,
+
+    /// Length of the file up to the end of its last complete box (or fragment)
+    complete: u64,
+
+    len: u64,
+}
+
+/// Walks the top-level boxes of an MP4 file, stopping at the first incomplete one.
+///
+/// An incomplete fragment is not counted as complete: its moof box describes
+/// samples that are not in the file.
+async fn scan_mp4(path: &Path) -> std::io::Result<Mp4Scan> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+
+    let mut scan = Mp4Scan {
+        len,
+        ..Default::default()
+    };
+
+    let mut offset = 0;
+    let mut fragment = None;
+
+    while offset + 8 <= len {
+        let mut header = [0u8; 16];
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        file.read_exact(&mut header[..8]).await?;
+
+        let kind: [u8; 4] = header[4..8].try_into().unwrap();
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // Extends to the end of the file
+            0 => len - offset,
+            // 64-bit size follows the type
+            1 if offset + 16 <= len => {
+                file.read_exact(&mut header[8..]).await?;
+                u64::from_be_bytes(header[8..].try_into().unwrap())
+            }
+            size => u64::from(size),
+        };
+
+        if size < 8 || offset + size > len {
+            break;
+        }
+
+        match &kind {
+            b"moov" => scan.moov = true,
+            b"moof" => {
+                scan.fragmented = true;
+                fragment = Some(offset);
+            }
+            b"mdat" => fragment = None,
+            _ => {}
+        }
+
+        offset += size;
+
+        if fragment.is_none() {
+            scan.complete = offset;
+        }
+    }
+
+    Ok(scan)
+}
+
+/// Returns the distinct corruption messages of an ffmpeg log.
+fn corruption_errors(log: &str) -> Vec<String> {
+    let mut errors: Vec<String> = Vec::new();
+
+    for line in log.lines().map(str::trim) {
+        let lower = line.to_lowercase();
+
+        let corrupt = ["corrupt", "invalid", "error while decoding", "non monoton"]
+            .iter()
+            .any(|marker| lower.contains(marker));
+
+        if corrupt && !errors.iter().any(|error| error == line) {
+            errors.push(line.to_string());
+        }
+
+        if errors.len() == MAX_ERRORS {
+            break;
+        }
+    }
+
+    errors
+}
+
+/// Matches the streams of the input to the streams of the repaired file by type and codec.
+fn match_streams(source: &FfprobeResponse, repaired: &FfprobeResponse) -> Vec<RepairedStream> {
+    let mut remaining: Vec<_> = repaired.streams.iter().flatten().collect();
+
+    source
+        .streams
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(index, stream)| {
+            let position = remaining.iter().position(|repaired| {
+                repaired.codec_type == stream.codec_type && repaired.codec_name == stream.codec_name
+            });
+
+            RepairedStream {
+                index: index as u32,
+                codec_type: stream.codec_type.clone(),
+                codec_name: stream.codec_name.clone(),
+                recovered: position
+                    .map(|position| remaining.remove(position))
+                    .is_some(),
+            }
+        })
+        .collect()
+}
+
+/// Probes the format and streams of a local file.
+pub(crate) async fn probe_file(path: &Path) -> HandlerResult<FfprobeResponse> {
+    run_ffprobe(&[
+        "-show_format".to_string(),
+        "-show_streams".to_string(),
+        path.to_string_lossy().to_string(),
+    ])
+    .await
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _repair(&self, request: RepairRequest) -> HandlerResult<RepairResponse> {
+        let extension = input_extension(&request.input).unwrap_or_else(|| "mkv".to_string());
+        let format = request
+            .format
+            .clone()
+            .unwrap_or_else(|| extension.clone())
+            .to_lowercase();
+
+        if format.is_empty() || !format.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(TerminalError::new_with_code(400, "invalid format").into());
+        }
+
+        // The whole file is needed: damaged indexes make ffmpeg seek around
+        let staging_dir = TempDir::new()?;
+        let input = staging_dir.path().join(format!("input.{extension}"));
+
+        self.download(&request.input, &input, None).await?;
+
+        let mut fixes = Vec::new();
+        let mut truncated_bytes = 0;
+
+        if MP4_CONTAINERS.contains(&extension.as_str()) {
+            let scan = scan_mp4(&input).await?;
+
+            if !scan.moov {
+                return Err(TerminalError::new_with_code(
+                    422,
+                    if scan.fragmented {
+                        "the initialization segment (moov box) is missing: the fragments can't be \
+                         decoded"
+                    } else {
+                        "the index (moov box) is missing, the file was most likely cut off while \
+                         recording: samples can't be located without it"
+                    },
+                )
+                .into());
+            }
+
+            // ffmpeg reads the samples of a truncated media box up to the end of the file,
+            // only fragments describe samples as a whole
+            if scan.fragmented && scan.complete < scan.len {
+                truncated_bytes = scan.len - scan.complete;
+
+                tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&input)
+                    .await?
+                    .set_len(scan.complete)
+                    .await?;
+
+                fixes.push(RepairFix::TruncatedFragment);
+            }
+        }
+
+        // Damaged inputs may not be probed at all, the remux is attempted anyway
+        let source = probe_file(&input).await.ok();
+
+        let work_dir = TempDir::new()?;
+        let filename = format!("{}_repaired.{format}", input_stem(&request.input));
+
+        let log = run_ffmpeg_in(
+            work_dir.path(),
+            &[
+                "-err_detect".to_string(),
+                "ignore_err".to_string(),
+                "-fflags".to_string(),
+                "+genpts+discardcorrupt".to_string(),
+                "-i".to_string(),
+                input.to_string_lossy().to_string(),
+                "-map".to_string(),
+                "0".to_string(),
+                "-c".to_string(),
+                "copy".to_string(),
+                "-ignore_unknown".to_string(),
+                "-avoid_negative_ts".to_string(),
+                "make_zero".to_string(),
+                filename.clone(),
+            ],
+        )
+        .await?;
+
+        let errors = corruption_errors(&log);
+
+        if !errors.is_empty() {
+            fixes.push(RepairFix::IgnoredErrors);
+        }
+
+        if log.contains("Timestamps are unset") || log.contains("pts has no value") {
+            fixes.push(RepairFix::RegeneratedTimestamps);
+        }
+
+        let repaired = probe_file(&work_dir.path().join(&filename)).await?;
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        let duration = repaired.duration();
+        let source_duration = source.as_ref().and_then(FfprobeResponse::duration);
+
+        Ok(RepairResponse {
+            output: request.output.file_url(&filename),
+            fixes,
+            duration,
+            source_duration,
+            lost_seconds: source_duration
+                .zip(duration)
+                .map(|(source, repaired)| (source - repaired).max(0.0)),
+            truncated_bytes,
+            streams: source
+                .map(|source| match_streams(&source, &repaired))
+                .unwrap_or_default(),
+            errors,
+        })
+    }
+}
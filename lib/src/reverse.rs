@@ -0,0 +1,328 @@
+use std::path::Path;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::encode::{VideoEncoding, default_container};
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem, run_ffmpeg_in};
+
+/// Shortest segment reversed at once (in seconds), whatever the memory budget.
+const MIN_SEGMENT_DURATION: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReverseMode {
+    /// Play the input backwards
+    #[default]
+    Reverse,
+    /// Play the input forwards, then backwards
+    Boomerang,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_reverse_request())]
+pub struct ReverseRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    #[serde(default)]
+    pub mode: ReverseMode,
+
+    /// Reverse the audio as well (dropped otherwise)
+    #[serde(default = "default_audio")]
+    pub audio: bool,
+
+    /// Memory the decoded frames of a segment may take in MiB
+    ///
+    /// The reverse filters buffer their whole input: longer inputs are reversed
+    /// in segments that fit in this budget.
+    #[serde(default = "default_memory_budget")]
+    pub memory_budget: u64,
+
+    /// Length of the segments reversed at once in seconds (derived from the memory budget when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segment_duration: Option<f64>,
+
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output container extension
+    #[serde(default = "default_container")]
+    pub container: String,
+}
+
+fn default_audio() -> bool {
+    true
+}
+
+fn default_memory_budget() -> u64 {
+    512
+}
+
+fn example_reverse_request() -> ReverseRequest {
+    ReverseRequest {
+        input: Url::parse("s3://bucket/clips/jump.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/shorts/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        mode: ReverseMode::Boomerang,
+        audio: false,
+        memory_budget: default_memory_budget(),
+        segment_duration: None,
+        video: VideoEncoding::default(),
+        container: default_container(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReverseResponse {
+    /// Location of the reversed file
+    pub output: Url,
+
+    /// Number of segments the input was reversed in
+    pub segments: u32,
+
+    /// Duration of the output in seconds
+    pub duration: f64,
+}
+
+impl ReverseRequest {
+    fn validate(&self) -> Result<(), TerminalError> {
+        if self.memory_budget == 0 {
+            return Err(TerminalError::new_with_code(
+                400,
+                "memoryBudget must be positive",
+            ));
+        }
+
+        if self
+            .segment_duration
+            .is_some_and(|duration| duration < MIN_SEGMENT_DURATION)
+        {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("segmentDuration must be at least {MIN_SEGMENT_DURATION}s"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Length of the segments whose decoded frames fit in the memory budget.
+    fn segment_duration(&self, width: u32, height: u32, frame_rate: f64) -> f64 {
+        if let Some(duration) = self.segment_duration {
+            return duration;
+        }
+
+        // Decoders mostly output 4:2:0 frames: 1.5 bytes per pixel
+        let frame_size = width as f64 * height as f64 * 1.5;
+        let frames = (self.memory_budget * 1024 * 1024) as f64 / frame_size.max(1.0);
+
+        (frames / frame_rate).max(MIN_SEGMENT_DURATION)
+    }
+
+    fn filename(&self) -> String {
+        let suffix = match self.mode {
+            ReverseMode::Reverse => "reversed",
+            ReverseMode::Boomerang => "boomerang",
+        };
+
+        format!("{}_{suffix}.{}", input_stem(&self.input), self.container)
+    }
+}
+
+/// How the input is cut into segments.
+struct Segmenting {
+    /// Path or URL ffmpeg reads the input from
+    input: String,
+
+    /// Length of a segment in seconds
+    duration: f64,
+    audio: bool,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Reverses the input one segment at a time, then joins the reversed segments in reverse order.
+    ///
+    /// Segments are kept in a local work dir: they are intermediate files
+    /// encoded with lossless audio so that the boundaries stay seamless.
+    pub(crate) async fn _reverse(&self, request: ReverseRequest) -> HandlerResult<ReverseResponse> {
+        request.validate()?;
+        request.video.validate().await?;
+
+        let probe = self.probe(&request.input).await?;
+
+        let duration = probe
+            .duration()
+            .ok_or_else(|| TerminalError::new_with_code(400, "unknown input duration"))?;
+
+        let video = probe
+            .stream("video")
+            .ok_or_else(|| TerminalError::new_with_code(400, "input has no video stream"))?;
+
+        let frame_rate = video.frame_rate().unwrap_or(30.0);
+
+        let segment_duration = request.segment_duration(
+            video.width.unwrap_or_default() as u32,
+            video.height.unwrap_or_default() as u32,
+            frame_rate,
+        );
+
+        let audio = request.audio && probe.stream("audio").is_some();
+        let segments = (duration / segment_duration).ceil().max(1.0) as u32;
+
+        // Every segment reads the input: storage inputs are downloaded once
+        let staging_dir = TempDir::new()?;
+        let work_dir = TempDir::new()?;
+        let mut list = Vec::new();
+
+        let segmenting = Segmenting {
+            input: self.local_input(&request.input, staging_dir.path()).await?,
+            duration: segment_duration,
+            audio,
+        };
+
+        if request.mode == ReverseMode::Boomerang {
+            for index in 0..segments {
+                list.push(
+                    self.encode_reverse_segment(
+                        &request,
+                        work_dir.path(),
+                        &segmenting,
+                        index,
+                        false,
+                    )
+                    .await?,
+                );
+            }
+        }
+
+        let mut reversed = Vec::new();
+
+        for index in 0..segments {
+            reversed.push(
+                self.encode_reverse_segment(&request, work_dir.path(), &segmenting, index, true)
+                    .await?,
+            );
+        }
+
+        list.extend(reversed.into_iter().rev());
+
+        let list_path = work_dir.path().join("segments.txt");
+        let list: String = list
+            .iter()
+            .map(|name| format!("file '{}'\n", work_dir.path().join(name).display()))
+            .collect();
+
+        tokio::fs::write(&list_path, list).await?;
+
+        let filename = request.filename();
+
+        let mut args = vec![
+            "-f".to_string(),
+            "concat".to_string(),
+            "-safe".to_string(),
+            "0".to_string(),
+            "-i".to_string(),
+            list_path.display().to_string(),
+            "-c:v".to_string(),
+            "copy".to_string(),
+        ];
+
+        if audio {
+            args.extend(["-c:a".to_string(), "aac".to_string()]);
+        }
+
+        args.push(filename.clone());
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        let duration = match request.mode {
+            ReverseMode::Reverse => duration,
+            ReverseMode::Boomerang => duration * 2.0,
+        };
+
+        Ok(ReverseResponse {
+            output: request.output.file_url(&filename),
+            segments,
+            duration,
+        })
+    }
+
+    /// Encodes a segment (reversed or not) into the work dir and returns its name.
+    async fn encode_reverse_segment(
+        &self,
+        request: &ReverseRequest,
+        work_dir: &Path,
+        segmenting: &Segmenting,
+        index: u32,
+        reverse: bool,
+    ) -> HandlerResult<String> {
+        let start = segmenting.duration * index as f64;
+        let name = format!(
+            "{}_{index:05}.mkv",
+            if reverse { "reverse" } else { "forward" }
+        );
+
+        let mut args = vec![
+            "-ss".to_string(),
+            format!("{start:.3}"),
+            "-t".to_string(),
+            format!("{:.3}", segmenting.duration),
+            "-i".to_string(),
+            segmenting.input.clone(),
+            "-map".to_string(),
+            "0:v:0".to_string(),
+        ];
+
+        if reverse {
+            args.extend(["-vf".to_string(), "reverse".to_string()]);
+        }
+
+        if segmenting.audio {
+            args.extend([
+                "-map".to_string(),
+                "0:a:0".to_string(),
+                "-c:a".to_string(),
+                "pcm_s16le".to_string(),
+            ]);
+
+            if reverse {
+                args.extend(["-af".to_string(), "areverse".to_string()]);
+            }
+        } else {
+            args.push("-an".to_string());
+        }
+
+        args.extend(request.video.args());
+        args.push(name.clone());
+
+        run_ffmpeg_in(work_dir, &args).await?;
+
+        Ok(name)
+    }
+}
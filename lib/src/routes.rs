@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use globset::{Glob, GlobMatcher};
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use crate::service::{FfmpegRequest, Output, work_files};
+
+/// Destination of the output files matching a pattern (e.g. thumbnails uploaded to another bucket).
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputRoute {
+    /// Glob matched against the paths of the files in the work dir (e.g. "*.jpg" or "hls/**")
+    pub pattern: String,
+
+    #[serde(flatten)]
+    pub output: Output,
+}
+
+impl OutputRoute {
+    fn matcher(&self) -> Result<GlobMatcher, TerminalError> {
+        Glob::new(&self.pattern)
+            .map(|glob| glob.compile_matcher())
+            .map_err(|err| {
+                TerminalError::new_with_code(
+                    400,
+                    format!("invalid pattern {}: {err}", self.pattern),
+                )
+            })
+    }
+}
+
+impl FfmpegRequest {
+    /// Fails with a terminal error when a pattern of the outputs is invalid.
+    pub(crate) fn validate_routes(&self) -> Result<(), TerminalError> {
+        for route in &self.outputs {
+            route.matcher()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns where a file of the work dir is uploaded to: the first output matching it or the default one.
+    pub(crate) fn destination(&self, name: &str) -> &Output {
+        self.outputs
+            .iter()
+            .find(|route| route.matcher().is_ok_and(|matcher| matcher.is_match(name)))
+            .map_or(&self.output, |route| &route.output)
+    }
+
+    /// Moves the files matching the outputs out of the work dir, into a directory per output.
+    ///
+    /// Files matching none of them are left in the work dir (for the default output).
+    pub(crate) fn split_outputs(&self, work_dir: &Path) -> HandlerResult<Vec<(TempDir, &Output)>> {
+        let mut dirs = Vec::new();
+
+        for route in &self.outputs {
+            let matcher = route.matcher()?;
+            let dir = TempDir::new()?;
+
+            for name in work_files(work_dir) {
+                if !matcher.is_match(&name) {
+                    continue;
+                }
+
+                let target = dir.path().join(&name);
+
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                std::fs::rename(work_dir.join(&name), &target)
+                    .or_else(|_| std::fs::copy(work_dir.join(&name), &target).map(|_| ()))?;
+            }
+
+            dirs.push((dir, &route.output));
+        }
+
+        Ok(dirs)
+    }
+}
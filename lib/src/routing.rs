@@ -0,0 +1,567 @@
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use jiff::Timestamp;
+use opendal_util::OperatorFactory;
+use restate_sdk::context::{Request, RequestTarget};
+use restate_sdk::endpoint::ContextInternal;
+use restate_sdk::prelude::*;
+use restate_sdk::service::{Discoverable, Service};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use url::Url;
+
+use crate::hedging::*;
+use crate::service::parse_uri;
+
+/// Variants of the FFmpeg service, registered by workers with matching capabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Variant {
+    /// General purpose workers (FFmpeg)
+    Default,
+    /// Workers with hardware accelerated encoding (FFmpegGpu)
+    Gpu,
+    /// Workers with enough memory for large frames (FFmpegHighMem)
+    HighMem,
+}
+
+impl Variant {
+    /// Name the variant of the service is registered under.
+    pub fn service_name(&self) -> &'static str {
+        match self {
+            Variant::Default => "FFmpeg",
+            Variant::Gpu => GpuVariant::NAME,
+            Variant::HighMem => HighMemVariant::NAME,
+        }
+    }
+}
+
+/// Names a variant of a service at the type level.
+pub trait ServiceVariant {
+    const NAME: &'static str;
+}
+
+pub struct GpuVariant;
+
+impl ServiceVariant for GpuVariant {
+    const NAME: &'static str = "FFmpegGpu";
+}
+
+pub struct HighMemVariant;
+
+impl ServiceVariant for HighMemVariant {
+    const NAME: &'static str = "FFmpegHighMem";
+}
+
+/// Registers a service under the name of a variant.
+///
+/// Restate derives the name of a service from its type, so the same implementation
+/// can only be bound more than once under different wrapper types.
+pub struct VariantService<S, V> {
+    inner: S,
+    variant: PhantomData<fn() -> V>,
+}
+
+impl<S, V> VariantService<S, V> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            variant: PhantomData,
+        }
+    }
+}
+
+impl<S, V> Service for VariantService<S, V>
+where
+    S: Service,
+{
+    type Future = S::Future;
+
+    fn handle(&self, req: ContextInternal) -> Self::Future {
+        self.inner.handle(req)
+    }
+}
+
+impl<S, V> Discoverable for VariantService<S, V>
+where
+    S: Discoverable,
+    V: ServiceVariant,
+{
+    fn discover() -> restate_sdk::discovery::Service {
+        let mut service = S::discover();
+        service.name = V::NAME.try_into().expect("invalid service name");
+        service
+    }
+}
+
+/// Rules of the dispatcher forwarding jobs to the service variants.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoutingConfig {
+    /// Register the FFmpegRouter service
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Variants deployed in the fleet (jobs fall back to FFmpeg when their variant is missing)
+    #[serde(default)]
+    pub variants: Vec<Variant>,
+
+    /// Jobs producing frames with more pixels are routed to FFmpegHighMem
+    #[serde(default = "default_high_mem_pixels")]
+    pub high_mem_pixels: u64,
+
+    /// Video codecs routed to FFmpegHighMem (e.g. "av1" or "libsvtav1")
+    #[serde(default)]
+    pub high_mem_codecs: Vec<String>,
+
+    /// Second attempts of slow jobs dispatched with hedging
+    #[serde(default)]
+    pub hedging: HedgingConfig,
+}
+
+fn default_high_mem_pixels() -> u64 {
+    3840 * 2160
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            variants: Vec::new(),
+            high_mem_pixels: default_high_mem_pixels(),
+            high_mem_codecs: Vec::new(),
+            hedging: HedgingConfig::default(),
+        }
+    }
+}
+
+const HW_ENCODERS: &[&str] = &[
+    "_nvenc",
+    "_qsv",
+    "_vaapi",
+    "_videotoolbox",
+    "_amf",
+    "_v4l2m2m",
+];
+
+/// Properties of a job relevant to routing, collected from its request.
+#[derive(Debug, Default)]
+struct Requirements {
+    hwaccel: bool,
+    codecs: Vec<String>,
+    pixels: u64,
+}
+
+impl Requirements {
+    fn inspect(request: &Value) -> Self {
+        let mut requirements = Requirements::default();
+        requirements.walk(request);
+        requirements
+    }
+
+    fn walk(&mut self, value: &Value) {
+        match value {
+            Value::Object(fields) => {
+                if fields
+                    .get("hwaccel")
+                    .is_some_and(|v| !v.is_null() && *v != json!(false))
+                {
+                    self.hwaccel = true;
+                }
+
+                if let Some(Value::String(codec)) = fields.get("codec") {
+                    self.codec(codec);
+                }
+
+                if let (Some(width), Some(height)) = (
+                    fields.get("width").and_then(Value::as_u64),
+                    fields.get("height").and_then(Value::as_u64),
+                ) {
+                    self.pixels = self.pixels.max(width * height);
+                }
+
+                if let Some(Value::Array(args)) = fields.get("args") {
+                    self.args(args.iter().filter_map(Value::as_str).collect());
+                }
+
+                fields.values().for_each(|value| self.walk(value));
+            }
+            Value::Array(values) => values.iter().for_each(|value| self.walk(value)),
+            _ => {}
+        }
+    }
+
+    fn codec(&mut self, codec: &str) {
+        if HW_ENCODERS.iter().any(|suffix| codec.ends_with(suffix)) {
+            self.hwaccel = true;
+        }
+
+        self.codecs.push(codec.to_string());
+    }
+
+    /// Inspects raw ffmpeg arguments.
+    fn args(&mut self, args: Vec<&str>) {
+        for (i, arg) in args.iter().enumerate() {
+            let value = args.get(i + 1).copied().unwrap_or_default();
+
+            match *arg {
+                "-hwaccel" | "-init_hw_device" => self.hwaccel = true,
+                "-c:v" | "-vcodec" | "-codec:v" => self.codec(value),
+                "-s" | "-s:v" => self.size(value.split_once('x')),
+                _ => {}
+            }
+
+            if let Some((_, scale)) = arg.split_once("scale=") {
+                let mut parts = scale.split([':', ',']);
+                self.size(parts.next().zip(parts.next()));
+            }
+        }
+    }
+
+    fn size(&mut self, size: Option<(&str, &str)>) {
+        if let Some((Ok(width), Ok(height))) =
+            size.map(|(w, h)| (w.parse::<u64>(), h.parse::<u64>()))
+        {
+            self.pixels = self.pixels.max(width * height);
+        }
+    }
+}
+
+impl RoutingConfig {
+    /// Picks the variant of the service a job is forwarded to.
+    fn route(&self, request: &Value) -> Variant {
+        let requirements = Requirements::inspect(request);
+
+        if requirements.hwaccel && self.variants.contains(&Variant::Gpu) {
+            return Variant::Gpu;
+        }
+
+        let high_mem = requirements.pixels > self.high_mem_pixels
+            || requirements
+                .codecs
+                .iter()
+                .any(|codec| self.high_mem_codecs.contains(codec));
+
+        if high_mem && self.variants.contains(&Variant::HighMem) {
+            return Variant::HighMem;
+        }
+
+        Variant::Default
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_dispatch_request())]
+pub struct DispatchRequest {
+    /// FFmpeg handler running the job (e.g. "convert_color")
+    pub handler: String,
+
+    /// Request sent to the handler
+    pub request: Value,
+
+    /// Launch a second attempt when the job runs longer than most jobs of the handler
+    /// (the first one to finish wins, the other one is cancelled)
+    ///
+    /// Racing attempts upload their outputs under prefixes of their own: the outputs of the winner
+    /// are moved to the requested locations, those of the loser are removed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hedge: bool,
+}
+
+fn example_dispatch_request() -> DispatchRequest {
+    DispatchRequest {
+        handler: "ffmpeg".to_string(),
+        request: json!({
+            "args": ["-hwaccel", "cuda", "-i", "https://example.com/input.mp4", "-c:v", "h264_nvenc", "output.mp4"],
+            "output": { "location": "s3://bucket/encoded/" },
+        }),
+        hedge: false,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DispatchResponse {
+    /// Service the job was forwarded to
+    pub service: String,
+
+    /// Response of the handler
+    pub response: Value,
+}
+
+/// Forwards jobs to the FFmpeg variant matching their requirements.
+#[restate_sdk::service]
+#[name = "FFmpegRouter"]
+pub trait Router {
+    /// Run a job on the variant of the FFmpeg service matching its codec, resolution and hwaccel.
+    async fn dispatch(request: Json<DispatchRequest>) -> HandlerResult<Json<DispatchResponse>>;
+
+    /// Run an attempt of a hedged job (internal).
+    async fn attempt(request: Json<AttemptRequest>) -> HandlerResult<()>;
+}
+
+pub struct RouterImpl<F>
+where
+    F: OperatorFactory,
+{
+    factory: F,
+    config: RoutingConfig,
+    caller_header: String,
+    durations: Durations,
+}
+
+impl<F> RouterImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Creates a dispatcher forwarding the caller (identified by the given header) to the jobs.
+    pub fn new(factory: F, config: RoutingConfig, caller_header: String) -> Self {
+        Self {
+            factory,
+            config,
+            caller_header,
+            durations: Durations::default(),
+        }
+    }
+
+    /// Forwards the caller of the current invocation to a request.
+    fn forward_caller<'a, Req, Res>(
+        &self,
+        ctx: &Context<'_>,
+        request: Request<'a, Req, Res>,
+    ) -> Request<'a, Req, Res> {
+        match ctx.headers().get(self.caller_header.as_str()) {
+            Some(caller) => request.header(self.caller_header.clone(), caller.clone()),
+            None => request,
+        }
+    }
+
+    /// Runs a job in attempts racing each other: the second one is launched
+    /// when the first one takes longer than most jobs of the handler.
+    async fn hedged(
+        &self,
+        ctx: &Context<'_>,
+        service: &str,
+        handler: &str,
+        request: Value,
+    ) -> HandlerResult<Value> {
+        let hedge_after = ctx
+            .run(|| async {
+                Ok(Json(
+                    self.durations.hedge_after(handler, &self.config.hedging),
+                ))
+            })
+            .name("hedge after")
+            .await?
+            .into_inner();
+
+        // Attempts are only staged when they race each other
+        let marker = |attempt: u8| format!(".hedge-{:016x}-{attempt}", ctx.random_seed());
+        let stage = |attempt: u8| match hedge_after {
+            Some(_) => stage_outputs(&request, &marker(attempt)),
+            None => (request.clone(), Vec::new()),
+        };
+
+        let (awakeable_id, outcome) = ctx.awakeable::<Json<AttemptOutcome>>();
+        let (first_awakeable_id, first_outcome) = ctx.awakeable::<Json<AttemptOutcome>>();
+        let (second_awakeable_id, second_outcome) = ctx.awakeable::<Json<AttemptOutcome>>();
+
+        let attempt = |attempt, attempt_awakeable_id: &String| {
+            let (request, prefixes) = stage(attempt);
+
+            self.forward_caller(
+                ctx,
+                ctx.service_client::<RouterClient>()
+                    .attempt(Json(AttemptRequest {
+                        awakeable_id: awakeable_id.clone(),
+                        attempt_awakeable_id: attempt_awakeable_id.clone(),
+                        attempt,
+                        service: service.to_string(),
+                        handler: handler.to_string(),
+                        request,
+                        prefixes,
+                    })),
+            )
+        };
+
+        let first = attempt(0, &first_awakeable_id).send();
+        let second = hedge_after.map(|delay| attempt(1, &second_awakeable_id).send_after(delay));
+
+        let mut outcome = outcome.await?.into_inner();
+
+        // The job only fails once every launched attempt has failed
+        if outcome.result.is_err() && second.is_some() {
+            outcome = match outcome.attempt {
+                0 => second_outcome.await?,
+                _ => first_outcome.await?,
+            }
+            .into_inner();
+        }
+
+        let winner = outcome.result.is_ok().then_some(outcome.attempt);
+
+        // The loser does not upload its outputs once it's cancelled
+        if winner == Some(1) {
+            first.cancel().await?;
+        }
+        if let Some(second) = second
+            && winner == Some(0)
+        {
+            second.cancel().await?;
+        }
+
+        // A loser still running removes its partial outputs itself once it's cancelled
+        if let Some(winner) = winner
+            && hedge_after.is_some()
+        {
+            let (_, losing) = stage(1 - winner);
+            ctx.run(|| self.discard(&losing)).name("discard").await?;
+
+            let (_, winning) = stage(winner);
+            let marker = marker(winner);
+            ctx.run(|| self.promote(&winning, &marker))
+                .name("promote")
+                .await?;
+
+            outcome.result = outcome
+                .result
+                .map(|response| unstage_response(&response, &marker));
+        }
+
+        outcome
+            .result
+            .map_err(|err| TerminalError::new_with_code(err.code, err.message).into())
+    }
+
+    /// Moves the outputs uploaded under the prefixes of the winning attempt to the requested locations.
+    async fn promote(&self, prefixes: &[Url], marker: &str) -> HandlerResult<()> {
+        for prefix in prefixes {
+            let (uri, path) = parse_uri(prefix.clone());
+            let operator = self.factory.load(uri.as_str())?;
+
+            let entries: Vec<_> = operator
+                .lister_with(&path)
+                .recursive(true)
+                .await?
+                .try_collect()
+                .await?;
+
+            for entry in entries.iter().filter(|entry| entry.metadata().is_file()) {
+                let path = format!("/{}", entry.path().trim_start_matches('/'));
+                operator.copy(&path, &unstage(&path, marker)).await?;
+            }
+
+            operator.remove_all(&path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the outputs uploaded under the prefixes of an attempt.
+    async fn discard(&self, prefixes: &[Url]) -> HandlerResult<()> {
+        for prefix in prefixes {
+            let (uri, path) = parse_uri(prefix.clone());
+            self.factory.load(uri.as_str())?.remove_all(&path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the current time, journaled to measure the duration of jobs.
+    async fn now(&self, ctx: &Context<'_>, name: &str) -> HandlerResult<Timestamp> {
+        Ok(ctx
+            .run(|| async { Ok(Json(Timestamp::now())) })
+            .name(name)
+            .await?
+            .into_inner())
+    }
+}
+
+impl<F> Router for RouterImpl<F>
+where
+    F: OperatorFactory,
+{
+    async fn dispatch(
+        &self,
+        ctx: Context<'_>,
+        request: Json<DispatchRequest>,
+    ) -> HandlerResult<Json<DispatchResponse>> {
+        let DispatchRequest {
+            handler,
+            request,
+            hedge,
+        } = request.into_inner();
+
+        let service = self.config.route(&request).service_name();
+
+        let started = self.now(&ctx, "started").await?;
+
+        let response = if hedge {
+            self.hedged(&ctx, service, &handler, request).await?
+        } else {
+            let call = ctx.request::<_, Json<Value>>(
+                RequestTarget::service(service, &handler),
+                Json(request),
+            );
+
+            self.forward_caller(&ctx, call).call().await?.into_inner()
+        };
+
+        // Recorded once: replays of the invocation skip the journaled step
+        ctx.run(|| async {
+            if let Ok(duration) = Duration::try_from(Timestamp::now().duration_since(started)) {
+                self.durations.record(&handler, duration);
+            }
+
+            Ok(())
+        })
+        .name("finished")
+        .await?;
+
+        Ok(Json(DispatchResponse {
+            service: service.to_string(),
+            response,
+        }))
+    }
+
+    async fn attempt(&self, ctx: Context<'_>, request: Json<AttemptRequest>) -> HandlerResult<()> {
+        let AttemptRequest {
+            awakeable_id,
+            attempt_awakeable_id,
+            attempt,
+            service,
+            handler,
+            request,
+            prefixes,
+        } = request.into_inner();
+
+        let call = ctx
+            .request::<_, Json<Value>>(RequestTarget::service(&service, &handler), Json(request));
+
+        let result = self
+            .forward_caller(&ctx, call)
+            .call()
+            .await
+            .map(Json::into_inner)
+            .map_err(|err| AttemptError {
+                code: err.code(),
+                message: err.message().to_string(),
+            });
+
+        // A failed (or cancelled) attempt leaves no partial outputs behind
+        if result.is_err() {
+            ctx.run(|| self.discard(&prefixes)).name("discard").await?;
+        }
+
+        let outcome = AttemptOutcome { attempt, result };
+
+        // Completing the first awakeable again (the loser finishing anyway) has no effect
+        ctx.resolve_awakeable(&attempt_awakeable_id, Json(outcome.clone()));
+        ctx.resolve_awakeable(&awakeable_id, Json(outcome));
+
+        Ok(())
+    }
+}
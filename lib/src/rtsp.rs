@@ -0,0 +1,214 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::encode::VideoEncoding;
+use crate::service::{
+    FfprobeResponse, Output, ServiceImpl, input_stem, run_ffmpeg_in, run_ffprobe,
+};
+
+/// Longest clip a single capture may record.
+const MAX_CAPTURE_DURATION: f64 = 3600.0;
+
+/// Lower level transport of RTSP media.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RtspTransport {
+    /// Interleaved in the RTSP connection (works through NAT and firewalls)
+    #[default]
+    Tcp,
+    /// Separate RTP/UDP ports (lower latency, may lose packets)
+    Udp,
+}
+
+impl RtspTransport {
+    fn name(&self) -> &'static str {
+        match self {
+            RtspTransport::Tcp => "tcp",
+            RtspTransport::Udp => "udp",
+        }
+    }
+}
+
+fn default_timeout() -> f64 {
+    10.0
+}
+
+/// Returns the input options connecting to an RTSP source.
+fn rtsp_args(input: &Url, transport: RtspTransport, timeout: f64) -> HandlerResult<Vec<String>> {
+    if !matches!(input.scheme(), "rtsp" | "rtsps") {
+        return Err(
+            TerminalError::new_with_code(400, "input must be an rtsp:// or rtsps:// URL").into(),
+        );
+    }
+
+    if timeout <= 0.0 {
+        return Err(TerminalError::new_with_code(400, "timeout must be positive").into());
+    }
+
+    Ok(vec![
+        "-rtsp_transport".to_string(),
+        transport.name().to_string(),
+        // Socket timeout in microseconds, covers connecting as well
+        "-timeout".to_string(),
+        ((timeout * 1_000_000.0) as u64).to_string(),
+        "-i".to_string(),
+        input.to_string(),
+    ])
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_probe_rtsp_request())]
+pub struct ProbeRtspRequest {
+    /// RTSP URL of the camera
+    pub input: Url,
+
+    #[serde(default)]
+    pub transport: RtspTransport,
+
+    /// Seconds to wait for the camera to connect and respond
+    #[serde(default = "default_timeout")]
+    pub timeout: f64,
+}
+
+fn example_probe_rtsp_request() -> ProbeRtspRequest {
+    ProbeRtspRequest {
+        input: Url::parse("rtsp://camera.local:554/stream1").unwrap(),
+        transport: RtspTransport::Tcp,
+        timeout: default_timeout(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_capture_request())]
+pub struct CaptureRequest {
+    /// RTSP URL of the camera
+    pub input: Url,
+
+    pub output: Output,
+
+    #[serde(default)]
+    pub transport: RtspTransport,
+
+    /// Seconds to wait for the camera to connect and respond
+    #[serde(default = "default_timeout")]
+    pub timeout: f64,
+
+    /// Length of the clip in seconds (at most an hour)
+    pub duration: f64,
+
+    /// Encoding settings (streams are copied as received when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video: Option<VideoEncoding>,
+
+    /// Output container extension (use mkv for cameras sending PCM audio)
+    #[serde(default = "default_capture_container")]
+    pub container: String,
+}
+
+fn default_capture_container() -> String {
+    "mkv".to_string()
+}
+
+fn example_capture_request() -> CaptureRequest {
+    CaptureRequest {
+        input: Url::parse("rtsp://camera.local:554/stream1").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/captures/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        transport: RtspTransport::Tcp,
+        timeout: default_timeout(),
+        duration: 30.0,
+        video: None,
+        container: default_capture_container(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureResponse {
+    /// Duration of the recorded clip in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+
+    /// Location of the clip
+    pub output: Url,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _probe_rtsp(
+        &self,
+        request: ProbeRtspRequest,
+    ) -> HandlerResult<FfprobeResponse> {
+        let mut args = rtsp_args(&request.input, request.transport, request.timeout)?;
+        args.extend(["-show_format".to_string(), "-show_streams".to_string()]);
+
+        run_ffprobe(&args).await
+    }
+
+    pub(crate) async fn _capture(&self, request: CaptureRequest) -> HandlerResult<CaptureResponse> {
+        if request.duration <= 0.0 || request.duration > MAX_CAPTURE_DURATION {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("duration must be between 0 and {MAX_CAPTURE_DURATION} seconds"),
+            )
+            .into());
+        }
+
+        if let Some(video) = &request.video {
+            video.validate().await?;
+        }
+
+        let work_dir = TempDir::new()?;
+
+        let filename = format!("{}.{}", input_stem(&request.input), request.container);
+
+        let mut args = rtsp_args(&request.input, request.transport, request.timeout)?;
+
+        args.extend([
+            "-t".to_string(),
+            request.duration.to_string(),
+            "-map".to_string(),
+            "0:v?".to_string(),
+            "-map".to_string(),
+            "0:a?".to_string(),
+        ]);
+
+        match &request.video {
+            Some(video) => {
+                args.extend(video.args());
+                args.extend(["-c:a".to_string(), "aac".to_string()]);
+            }
+            None => args.extend(["-c".to_string(), "copy".to_string()]),
+        }
+
+        args.push(filename.clone());
+
+        run_ffmpeg_in(work_dir.path(), &args).await?;
+
+        let duration = self
+            .probe(
+                &Url::from_file_path(work_dir.path().join(&filename))
+                    .map_err(|_| HandlerError::from("invalid capture path".to_string()))?,
+            )
+            .await?
+            .duration();
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        Ok(CaptureResponse {
+            duration,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
@@ -0,0 +1,153 @@
+use crate::explain::FLAG_OPTIONS;
+
+/// Filters making the frame size even and the pixels square.
+///
+/// The expressions evaluate to the size of the incoming frames when they're even with
+/// square pixels already: frames are left untouched then.
+const SANITIZE_FILTERS: &str = "scale=trunc(iw*sar/2)*2:trunc(ih/2)*2,setsar=1";
+
+/// Extensions of audio-only outputs (a video filter fails on them).
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "m4a", "aac", "wav", "flac", "opus", "ogg", "oga", "ac3", "eac3",
+];
+
+/// Options of a complex filtergraph (which ffmpeg refuses to combine with simple video filters).
+const COMPLEX_FILTER_OPTIONS: &[&str] = &["-filter_complex", "-lavfi", "-filter_complex_script"];
+
+/// Appends even-dimension and square-pixel corrections to the video filters of every output.
+///
+/// Outputs whose video is copied, disabled or fed from a complex filtergraph are left as is.
+/// Commands with a complex filtergraph are left as is altogether.
+pub(crate) fn sanitize_dimensions(args: &[String]) -> Vec<String> {
+    if args
+        .iter()
+        .any(|arg| COMPLEX_FILTER_OPTIONS.contains(&arg.as_str()))
+    {
+        return args.to_vec();
+    }
+
+    let mut sanitized: Vec<String> = Vec::with_capacity(args.len() + 2);
+
+    // Options of the current output: index of the option (in the sanitized arguments) and its value
+    let mut options: Vec<(usize, &str)> = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "-" || !arg.starts_with('-') {
+            let get = |names: &[&str]| {
+                options
+                    .iter()
+                    .rev()
+                    .find(|(index, _)| names.contains(&sanitized[*index].as_str()))
+                    .map(|(index, value)| (*index, *value))
+            };
+
+            let extension = arg
+                .rsplit_once('.')
+                .map(|(_, extension)| extension.to_lowercase());
+
+            let skipped = options.iter().any(|(index, _)| sanitized[*index] == "-vn")
+                || get(&["-c:v", "-codec:v", "-vcodec", "-c", "-codec"])
+                    .is_some_and(|(_, codec)| codec == "copy")
+                || options
+                    .iter()
+                    .any(|(index, value)| sanitized[*index] == "-map" && value.starts_with('['))
+                || extension
+                    .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.as_str()));
+
+            if !skipped {
+                match get(&["-vf", "-filter:v"]) {
+                    Some((index, filters)) => {
+                        sanitized[index + 1] = format!("{filters},{SANITIZE_FILTERS}");
+                    }
+                    None => {
+                        sanitized.extend(["-vf".to_string(), SANITIZE_FILTERS.to_string()]);
+                    }
+                }
+            }
+
+            options.clear();
+            sanitized.push(arg.clone());
+        } else if FLAG_OPTIONS.contains(&arg.as_str()) {
+            options.push((sanitized.len(), ""));
+            sanitized.push(arg.clone());
+        } else {
+            let value = args.next();
+
+            if arg == "-i" {
+                // Options before an input are input options
+                options.clear();
+            } else {
+                options.push((sanitized.len(), value.map_or("", String::as_str)));
+            }
+
+            sanitized.push(arg.clone());
+            sanitized.extend(value.cloned());
+        }
+    }
+
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(args: &str) -> String {
+        let args: Vec<String> = args.split(' ').map(str::to_string).collect();
+
+        sanitize_dimensions(&args).join(" ")
+    }
+
+    #[test]
+    fn appends_filters() {
+        assert_eq!(
+            sanitize("-i in.mov -c:v libx264 out.mp4"),
+            format!("-i in.mov -c:v libx264 -vf {SANITIZE_FILTERS} out.mp4")
+        );
+    }
+
+    #[test]
+    fn extends_video_filters() {
+        assert_eq!(
+            sanitize("-i in.mov -vf hflip out.mp4"),
+            format!("-i in.mov -vf hflip,{SANITIZE_FILTERS} out.mp4")
+        );
+    }
+
+    #[test]
+    fn skips_copied_video() {
+        assert_eq!(
+            sanitize("-i in.mov -c:v copy out.mp4 -y"),
+            "-i in.mov -c:v copy out.mp4 -y"
+        );
+    }
+
+    #[test]
+    fn skips_mapped_filtergraph_outputs() {
+        assert_eq!(
+            sanitize("-i in.mov -map [v] out.mp4"),
+            "-i in.mov -map [v] out.mp4"
+        );
+    }
+
+    #[test]
+    fn skips_complex_filtergraphs() {
+        assert_eq!(
+            sanitize("-i in.mov -filter_complex [0:v]hflip out.mp4"),
+            "-i in.mov -filter_complex [0:v]hflip out.mp4"
+        );
+        assert_eq!(
+            sanitize("-i in.mov -lavfi hflip out.mp4"),
+            "-i in.mov -lavfi hflip out.mp4"
+        );
+    }
+
+    #[test]
+    fn sanitizes_every_output() {
+        assert_eq!(
+            sanitize("-i in.mov -c:v copy copy.mp4 small.mp4"),
+            format!("-i in.mov -c:v copy copy.mp4 -vf {SANITIZE_FILTERS} small.mp4")
+        );
+    }
+}
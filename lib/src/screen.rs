@@ -0,0 +1,203 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::crop::CropRect;
+use crate::inputs::{Input, input_arg};
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem};
+
+/// Sharpening applied to text and cursor details blurred by scaling and compression.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Sharpen {
+    /// Area of the source frame to sharpen (the whole frame when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<CropRect>,
+
+    /// Sharpening strength passed to unsharp (typically 0.5-1.5)
+    #[serde(default = "default_sharpen_amount")]
+    pub amount: f64,
+}
+
+fn default_sharpen_amount() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_normalize_screencast_request())]
+pub struct NormalizeScreencastRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Constant frame rate of the output
+    #[serde(default = "default_fps")]
+    pub fps: u32,
+
+    /// Downscale recordings wider than this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sharpen: Option<Sharpen>,
+
+    /// Constant rate factor of the H.264 encode
+    #[serde(default = "default_crf")]
+    pub crf: u8,
+}
+
+fn default_fps() -> u32 {
+    30
+}
+
+fn default_crf() -> u8 {
+    23
+}
+
+fn example_normalize_screencast_request() -> NormalizeScreencastRequest {
+    NormalizeScreencastRequest {
+        input: Url::parse("https://example.com/screencast.mkv").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/screencasts/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        fps: default_fps(),
+        max_width: Some(1920),
+        sharpen: Some(Sharpen {
+            region: None,
+            amount: default_sharpen_amount(),
+        }),
+        crf: default_crf(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NormalizeScreencastResponse {
+    /// Location of the normalized file
+    pub output: Url,
+}
+
+impl NormalizeScreencastRequest {
+    fn filter(&self) -> String {
+        let mut chain = Vec::new();
+
+        // Screen recorders produce variable frame rate, which breaks editors and some players
+        chain.push(format!("[0:v]fps={}", self.fps));
+
+        if let Some(sharpen) = &self.sharpen {
+            let unsharp = format!("unsharp=5:5:{}:5:5:0", sharpen.amount);
+
+            match sharpen.region {
+                Some(region) => chain.push(format!(
+                    "split[base][detail];[detail]{},{unsharp}[sharp];[base][sharp]overlay={}:{}",
+                    region.filter(),
+                    region.x,
+                    region.y
+                )),
+                None => chain.push(unsharp),
+            }
+        }
+
+        // Odd dimensions (common with window captures) are rejected by yuv420p encoders
+        match self.max_width {
+            Some(max_width) => chain.push(format!(
+                "scale='trunc(min(iw,{max_width})/2)*2':'trunc(ih*min(iw,{max_width})/iw/2)*2':flags=lanczos"
+            )),
+            None => chain.push("scale=trunc(iw/2)*2:trunc(ih/2)*2:flags=lanczos".to_string()),
+        }
+
+        chain.push("setsar=1,format=yuv420p[v]".to_string());
+
+        chain.join(",")
+    }
+
+    fn args(&self, filename: &str, inputs: &mut Vec<Input>) -> Vec<String> {
+        vec![
+            "-i".to_string(),
+            input_arg(&self.input, inputs),
+            "-filter_complex".to_string(),
+            self.filter(),
+            "-map".to_string(),
+            "[v]".to_string(),
+            "-map".to_string(),
+            "0:a?".to_string(),
+            "-fps_mode".to_string(),
+            "cfr".to_string(),
+            "-c:v".to_string(),
+            "libx264".to_string(),
+            "-profile:v".to_string(),
+            "high".to_string(),
+            "-crf".to_string(),
+            self.crf.to_string(),
+            "-tune".to_string(),
+            "stillimage".to_string(),
+            "-c:a".to_string(),
+            "aac".to_string(),
+            "-b:a".to_string(),
+            "128k".to_string(),
+            "-ar".to_string(),
+            "48000".to_string(),
+            "-movflags".to_string(),
+            "+faststart".to_string(),
+            filename.to_string(),
+        ]
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _normalize_screencast(
+        &self,
+        request: NormalizeScreencastRequest,
+    ) -> HandlerResult<NormalizeScreencastResponse> {
+        if request.fps == 0 {
+            return Err(TerminalError::new_with_code(400, "fps must be positive").into());
+        }
+
+        let filename = format!("{}.mp4", input_stem(&request.input));
+
+        let mut inputs = Vec::new();
+
+        self._ffmpeg(FfmpegRequest {
+            args: request.args(&filename, &mut inputs),
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(NormalizeScreencastResponse {
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_stage_storage_inputs() {
+        let mut request = example_normalize_screencast_request();
+        request.input = Url::parse("s3://bucket/screencast.mov").unwrap();
+        let mut inputs = Vec::new();
+
+        let args = request.args("screencast.mp4", &mut inputs);
+
+        assert_eq!(args[..2], ["-i", "{input0}"]);
+        assert_eq!(inputs[0].location, request.input);
+    }
+}
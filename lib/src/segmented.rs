@@ -0,0 +1,291 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::encode::{VideoEncoding, default_container};
+use crate::inline::with_inlined;
+use crate::inputs::input_arg;
+use crate::metering::{Metered, metered};
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem, parse_uri};
+use crate::templates::{JobMetadata, job_id, with_job};
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_encode_segmented_request())]
+pub struct EncodeSegmentedRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output container extension
+    #[serde(default = "default_container")]
+    pub container: String,
+
+    /// Length of the independently encoded segments in seconds
+    #[serde(default = "default_segment_duration")]
+    pub segment_duration: f64,
+}
+
+fn default_segment_duration() -> f64 {
+    300.0
+}
+
+fn example_encode_segmented_request() -> EncodeSegmentedRequest {
+    EncodeSegmentedRequest {
+        input: Url::parse("s3://bucket/masters/feature.mov").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/encoded/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        video: VideoEncoding::default(),
+        container: default_container(),
+        segment_duration: default_segment_duration(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeSegmentedResponse {
+    /// Location of the encoded file
+    pub output: Url,
+
+    /// Number of segments the input was encoded in
+    pub segments: u32,
+}
+
+impl EncodeSegmentedRequest {
+    /// Storage location the encoded segments are kept at until they are concatenated.
+    fn segments_output(&self) -> Output {
+        Output {
+            location: self
+                .output
+                .file_url(&format!(".segments/{}/", input_stem(&self.input))),
+            inline: false,
+            overwrite: Default::default(),
+        }
+    }
+}
+
+fn segment_name(index: u32) -> String {
+    format!("{index:05}.mkv")
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Encodes the input in fixed-duration segments, each in its own durable step,
+    /// then concatenates them into the output.
+    ///
+    /// A failure (or a crashed worker) only repeats the segment that was running:
+    /// the segments already encoded are kept in storage next to the output.
+    pub(crate) async fn _encode_segmented(
+        &self,
+        ctx: &mut Context<'_>,
+        request: EncodeSegmentedRequest,
+    ) -> HandlerResult<EncodeSegmentedResponse> {
+        if request.segment_duration <= 0.0 {
+            return Err(
+                TerminalError::new_with_code(400, "segmentDuration must be positive").into(),
+            );
+        }
+
+        let inputs = vec![request.input.to_string()];
+        let job_id = job_id(ctx)?;
+
+        // The metadata is journaled along with the duration, so that every step
+        // resolves the output location the same way
+        let Metered {
+            response: (duration, metadata),
+            mut usage,
+            ..
+        } = ctx
+            .run(|| {
+                metered(async {
+                    request.video.validate().await?;
+                    self.check_guardrails(&inputs).await?;
+
+                    let probe = self.probe(&request.input).await?;
+
+                    let duration = probe.duration().ok_or_else(|| {
+                        TerminalError::new_with_code(400, "unknown input duration")
+                    })?;
+
+                    let metadata =
+                        JobMetadata::new(job_id.clone(), &inputs, &serde_json::to_value(&request)?);
+
+                    Ok((duration, metadata))
+                })
+            })
+            .name("probe")
+            .await?
+            .into_inner();
+
+        let segments = (duration / request.segment_duration).ceil().max(1.0) as u32;
+        let segments_output = request.segments_output();
+
+        for index in 0..segments {
+            let Metered {
+                usage: segment_usage,
+                ..
+            } = ctx
+                .run(|| {
+                    metered(with_job(
+                        metadata.clone(),
+                        self.encode_segment(&request, &segments_output, index),
+                    ))
+                })
+                .name(format!("segment {index}"))
+                .await?
+                .into_inner();
+
+            usage.add(&segment_usage);
+        }
+
+        let Metered {
+            response,
+            usage: concat_usage,
+            finished_at,
+        } = ctx
+            .run(|| {
+                metered(with_inlined(with_job(
+                    metadata.clone(),
+                    self.concat_segments(&request, &segments_output, segments),
+                )))
+            })
+            .name("concat")
+            .await?
+            .into_inner();
+
+        usage.add(&concat_usage);
+        usage.jobs = 1;
+
+        self.record_job(
+            ctx,
+            "encode_segmented",
+            &metadata,
+            &response,
+            usage,
+            finished_at,
+        )?;
+
+        Ok(response)
+    }
+
+    async fn encode_segment(
+        &self,
+        request: &EncodeSegmentedRequest,
+        segments_output: &Output,
+        index: u32,
+    ) -> HandlerResult<()> {
+        let start = request.segment_duration * index as f64;
+        let mut inputs = Vec::new();
+
+        // Audio is taken from the input as a whole when concatenating: encoding it
+        // per segment would leave gaps at the boundaries
+        let mut args = vec![
+            "-ss".to_string(),
+            format!("{start:.3}"),
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-t".to_string(),
+            request.segment_duration.to_string(),
+            "-map".to_string(),
+            "0:v:0".to_string(),
+            "-an".to_string(),
+            "-sn".to_string(),
+        ];
+
+        args.extend(request.video.args());
+        args.push(segment_name(index));
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: segments_output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn concat_segments(
+        &self,
+        request: &EncodeSegmentedRequest,
+        segments_output: &Output,
+        segments: u32,
+    ) -> HandlerResult<EncodeSegmentedResponse> {
+        let dir = TempDir::new()?;
+
+        let mut list = String::new();
+
+        for index in 0..segments {
+            let name = segment_name(index);
+            let path = dir.path().join(&name);
+
+            self.download(&segments_output.file_url(&name), &path, None)
+                .await?;
+
+            list.push_str(&format!("file '{}'\n", path.display()));
+        }
+
+        let list_path = dir.path().join("segments.txt");
+        tokio::fs::write(&list_path, list).await?;
+
+        let filename = format!("{}.{}", input_stem(&request.input), request.container);
+        let mut inputs = Vec::new();
+
+        self._ffmpeg(FfmpegRequest {
+            args: vec![
+                "-f".to_string(),
+                "concat".to_string(),
+                "-safe".to_string(),
+                "0".to_string(),
+                "-i".to_string(),
+                list_path.display().to_string(),
+                "-i".to_string(),
+                input_arg(&request.input, &mut inputs),
+                "-map".to_string(),
+                "0:v".to_string(),
+                "-map".to_string(),
+                "1:a?".to_string(),
+                "-c:v".to_string(),
+                "copy".to_string(),
+                "-c:a".to_string(),
+                "aac".to_string(),
+                filename.clone(),
+            ],
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        let (uri, path) = parse_uri(segments_output.location());
+        self.factory.load(uri.as_str())?.remove_all(&path).await?;
+
+        Ok(EncodeSegmentedResponse {
+            output: request.output.file_url(&filename),
+            segments,
+        })
+    }
+}
@@ -1,20 +1,87 @@
-use std::{collections::HashMap, process::Stdio};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+};
 
 use anyhow::Result;
-use futures::io::AsyncWriteExt as _;
+use futures::{StreamExt, TryStreamExt};
+use jiff::Timestamp;
 use opendal::Operator;
 use opendal::services::Fs;
 use opendal_util::{Copier, OperatorFactory};
 use restate_sdk::prelude::*;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use tempfile::TempDir;
-use tokio::io::AsyncReadExt;
-use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
-use tokio_util::compat::FuturesAsyncWriteCompatExt;
 use url::Url;
 
+use crate::archive::*;
+use crate::aspect::*;
+use crate::audio::*;
+use crate::batch::*;
+use crate::benchmark::*;
+use crate::captions::*;
+use crate::chapters::*;
+use crate::clip::*;
+use crate::color::*;
+use crate::compat::*;
+use crate::concat::*;
+use crate::credits::*;
+use crate::crop::*;
+use crate::cutlist::*;
+use crate::dash::*;
+use crate::diagnose::*;
+use crate::ducking::*;
+use crate::editorial::*;
+use crate::env::*;
+use crate::explain::*;
+use crate::flags::*;
+use crate::frames::*;
+use crate::guardrails::*;
+use crate::handlers::*;
+use crate::hash::*;
+use crate::hdr::*;
+use crate::highlights::*;
+use crate::history::*;
+use crate::hls::*;
+use crate::inline::{inline_files, with_inlined};
+use crate::inputs::{self, *};
+use crate::intake::*;
+use crate::limits::RateLimiter;
+use crate::lint::LintWarning;
+use crate::load::*;
+use crate::metering::{self, *};
+use crate::mezzanine::*;
+use crate::mosaic::*;
+use crate::pip::*;
+use crate::preview::*;
+use crate::progress::{self, *};
+use crate::radio::*;
+use crate::repair::*;
+use crate::reverse::*;
+use crate::routes::*;
+use crate::rtsp::*;
+use crate::sanitize::sanitize_dimensions;
+use crate::screen::*;
+use crate::segmented::*;
+use crate::spherical::*;
+use crate::sprites::*;
+use crate::staging::*;
+use crate::streaming::*;
+use crate::streams::*;
+use crate::subtitles::*;
+use crate::templates::{JobMetadata, job_id, resolve, with_job};
+use crate::termination::Terminating;
+use crate::thumbnail::*;
+use crate::timestamps::*;
+use crate::transcode::*;
+use crate::ts::*;
+use crate::uploads::{PendingUpload, Staged, defer, deferred, move_entries};
+use crate::watchdog::{self, Watch, WatchdogConfig};
+
 #[restate_sdk::service]
 #[name = "FFmpeg"]
 pub trait Service {
@@ -23,14 +90,303 @@ pub trait Service {
 
     /// Run ffprobe command.
     async fn ffprobe(request: Json<FfprobeRequest>) -> HandlerResult<Json<FfprobeResponse>>;
+
+    /// Convert the color space of a video.
+    async fn convert_color(
+        request: Json<ConvertColorRequest>,
+    ) -> HandlerResult<Json<ConvertColorResponse>>;
+
+    /// Detect the crop rectangle of letterboxed video.
+    async fn detect_crop(
+        request: Json<DetectCropRequest>,
+    ) -> HandlerResult<Json<DetectCropResponse>>;
+
+    /// Detect the crop rectangle of a video and encode it with the crop applied.
+    async fn autocrop(request: Json<AutocropRequest>) -> HandlerResult<Json<AutocropResponse>>;
+
+    /// Convert a video to a different aspect ratio.
+    async fn convert_aspect(
+        request: Json<ConvertAspectRequest>,
+    ) -> HandlerResult<Json<ConvertAspectResponse>>;
+
+    /// Encode a ProRes or DNxHR mezzanine copy of a video.
+    async fn mezzanine(request: Json<MezzanineRequest>) -> HandlerResult<Json<MezzanineResponse>>;
+
+    /// Archive a video losslessly as FFV1 and FLAC in Matroska.
+    async fn archive(request: Json<ArchiveRequest>) -> HandlerResult<Json<ArchiveResponse>>;
+
+    /// Normalize a screen recording into web-friendly H.264.
+    async fn normalize_screencast(
+        request: Json<NormalizeScreencastRequest>,
+    ) -> HandlerResult<Json<NormalizeScreencastResponse>>;
+
+    /// Inject or preserve 360 spherical video metadata.
+    async fn spherical(request: Json<SphericalRequest>) -> HandlerResult<Json<SphericalResponse>>;
+
+    /// Check that an encode preserved the HDR metadata of its source.
+    async fn validate_hdr(
+        request: Json<ValidateHdrRequest>,
+    ) -> HandlerResult<Json<ValidateHdrResponse>>;
+
+    /// Extract embedded CEA-608/708 captions as subtitle files.
+    async fn extract_captions(
+        request: Json<ExtractCaptionsRequest>,
+    ) -> HandlerResult<Json<ExtractCaptionsResponse>>;
+
+    /// Extract DVB and teletext subtitles from a broadcast transport stream.
+    async fn extract_broadcast_subtitles(
+        request: Json<ExtractBroadcastSubtitlesRequest>,
+    ) -> HandlerResult<Json<ExtractBroadcastSubtitlesResponse>>;
+
+    /// Convert a subtitle file to a different format.
+    async fn convert_subtitles(
+        request: Json<ConvertSubtitlesRequest>,
+    ) -> HandlerResult<Json<ConvertSubtitlesResponse>>;
+
+    /// Shift and scale subtitle timestamps.
+    async fn retime_subtitles(
+        request: Json<RetimeSubtitlesRequest>,
+    ) -> HandlerResult<Json<RetimeSubtitlesResponse>>;
+
+    /// Identify subtitle tracks containing only forced narrative subtitles.
+    async fn detect_forced_subtitles(
+        request: Json<DetectForcedSubtitlesRequest>,
+    ) -> HandlerResult<Json<DetectForcedSubtitlesResponse>>;
+
+    /// Set language and title metadata of streams without re-encoding.
+    async fn tag_streams(
+        request: Json<TagStreamsRequest>,
+    ) -> HandlerResult<Json<TagStreamsResponse>>;
+
+    /// Change default, forced and hearing impaired flags of streams without re-encoding.
+    async fn set_disposition(
+        request: Json<SetDispositionRequest>,
+    ) -> HandlerResult<Json<SetDispositionResponse>>;
+
+    /// Remove streams from a media file without re-encoding.
+    async fn strip_streams(
+        request: Json<StripStreamsRequest>,
+    ) -> HandlerResult<Json<StripStreamsResponse>>;
+
+    /// Transcode only the streams a device profile can't play and copy the rest.
+    async fn make_compatible(
+        request: Json<MakeCompatibleRequest>,
+    ) -> HandlerResult<Json<MakeCompatibleResponse>>;
+
+    /// Encode audio to Opus for voice and low bitrate streaming.
+    async fn encode_opus(
+        request: Json<EncodeOpusRequest>,
+    ) -> HandlerResult<Json<EncodeOpusResponse>>;
+
+    /// Encode audio to AAC or MP3, with gapless playback information by default.
+    async fn encode_audio(
+        request: Json<EncodeAudioRequest>,
+    ) -> HandlerResult<Json<EncodeAudioResponse>>;
+
+    /// Verify that Dolby and DTS audio streams were copied bit-exact.
+    async fn validate_passthrough(
+        request: Json<ValidatePassthroughRequest>,
+    ) -> HandlerResult<Json<ValidatePassthroughResponse>>;
+
+    /// Calculate ReplayGain track gain and peak values.
+    async fn replaygain(
+        request: Json<ReplaygainRequest>,
+    ) -> HandlerResult<Json<ReplaygainResponse>>;
+
+    /// Measure EBU R128 loudness (integrated, range and true peak).
+    async fn analyze_loudness(
+        request: Json<AnalyzeLoudnessRequest>,
+    ) -> HandlerResult<Json<AnalyzeLoudnessResponse>>;
+
+    /// Calculate the Chromaprint fingerprint of audio.
+    async fn fingerprint_audio(
+        request: Json<FingerprintAudioRequest>,
+    ) -> HandlerResult<Json<FingerprintAudioResponse>>;
+
+    /// Propose highlights based on loudness bursts and scene activity.
+    async fn detect_highlights(
+        request: Json<DetectHighlightsRequest>,
+    ) -> HandlerResult<Json<DetectHighlightsResponse>>;
+
+    /// Generate chapters from scene cuts.
+    async fn generate_chapters(
+        request: Json<GenerateChaptersRequest>,
+    ) -> HandlerResult<Json<GenerateChaptersResponse>>;
+
+    /// Detect leading slates and trailing credits to find the content boundaries.
+    async fn detect_content_bounds(
+        request: Json<DetectContentBoundsRequest>,
+    ) -> HandlerResult<Json<DetectContentBoundsResponse>>;
+
+    /// Export the usage of tenants in a time window.
+    async fn export_usage(
+        request: Json<ExportUsageRequest>,
+    ) -> HandlerResult<Json<ExportUsageResponse>>;
+
+    /// Summarize frame types, sizes and GOP structure of a video stream.
+    async fn analyze_frames(
+        request: Json<AnalyzeFramesRequest>,
+    ) -> HandlerResult<Json<AnalyzeFramesResponse>>;
+
+    /// Report the PID map, continuity errors and PCR timing of an MPEG transport stream.
+    async fn analyze_ts(request: Json<AnalyzeTsRequest>) -> HandlerResult<Json<AnalyzeTsResponse>>;
+
+    /// Probe the streams of an RTSP camera.
+    async fn probe_rtsp(request: Json<ProbeRtspRequest>) -> HandlerResult<Json<FfprobeResponse>>;
+
+    /// Record a clip from an RTSP camera.
+    async fn capture(request: Json<CaptureRequest>) -> HandlerResult<Json<CaptureResponse>>;
+
+    /// Record an HTTP/ICY audio stream to storage.
+    async fn record_stream(
+        request: Json<RecordStreamRequest>,
+    ) -> HandlerResult<Json<RecordStreamResponse>>;
+
+    /// Run a request template against every matching file under a storage prefix.
+    async fn process_prefix(
+        request: Json<ProcessPrefixRequest>,
+    ) -> HandlerResult<Json<ProcessPrefixResponse>>;
+
+    /// Encode a long input in durable segments, resuming from the last finished one after a failure.
+    async fn encode_segmented(
+        request: Json<EncodeSegmentedRequest>,
+    ) -> HandlerResult<Json<EncodeSegmentedResponse>>;
+
+    /// Report the current load of the worker.
+    async fn load() -> HandlerResult<Json<LoadReport>>;
+
+    /// Resolve the command of an ffmpeg request without running it.
+    async fn explain(request: Json<FfmpegRequest>) -> HandlerResult<Json<FfmpegPlan>>;
+
+    /// Transcode a video from typed settings or a named preset, optionally in two passes.
+    async fn transcode(request: Json<TranscodeRequest>) -> HandlerResult<Json<TranscodeResponse>>;
+
+    /// Stop starting new jobs on the worker (they are retried until the intake is resumed).
+    async fn pause_intake() -> HandlerResult<Json<IntakeStatus>>;
+
+    /// Start new jobs on the worker again.
+    async fn resume_intake() -> HandlerResult<Json<IntakeStatus>>;
+
+    /// List the jobs running on the worker.
+    async fn in_flight_jobs() -> HandlerResult<Json<IntakeStatus>>;
+
+    /// Capture the state of the ffmpeg processes of a running job (to tell stuck jobs from slow ones).
+    async fn diagnose(request: Json<DiagnoseRequest>) -> HandlerResult<Json<DiagnoseResponse>>;
+
+    /// Report the latest encoding progress of the ffmpeg processes of a running job.
+    async fn progress(request: Json<ProgressRequest>) -> HandlerResult<Json<ProgressResponse>>;
+
+    /// Tile several inputs into a synchronized grid.
+    async fn mosaic(request: Json<MosaicRequest>) -> HandlerResult<Json<MosaicResponse>>;
+
+    /// Overlay a secondary video on a main one (picture-in-picture).
+    async fn pip(request: Json<PipRequest>) -> HandlerResult<Json<PipResponse>>;
+
+    /// Lower background audio under a voiceover (sidechain compression).
+    async fn duck_audio(request: Json<DuckAudioRequest>) -> HandlerResult<Json<DuckAudioResponse>>;
+
+    /// Join clips, copying compatible ones or re-encoding them (optionally with crossfade transitions).
+    async fn concat(request: Json<ConcatRequest>) -> HandlerResult<Json<ConcatResponse>>;
+
+    /// Play a clip backwards (or forwards then backwards as a boomerang).
+    async fn reverse(request: Json<ReverseRequest>) -> HandlerResult<Json<ReverseResponse>>;
+
+    /// Extract frames at source timecodes along with their exact frame metadata.
+    async fn editorial_snapshot(
+        request: Json<EditorialSnapshotRequest>,
+    ) -> HandlerResult<Json<EditorialSnapshotResponse>>;
+
+    /// Render a timeline assembled from parts of one or more sources.
+    async fn render_cutlist(
+        request: Json<RenderCutlistRequest>,
+    ) -> HandlerResult<Json<RenderCutlistResponse>>;
+
+    /// Package audio into HLS renditions for every track and bitrate, with a master playlist.
+    async fn audio_hls(request: Json<AudioHlsRequest>) -> HandlerResult<Json<AudioHlsResponse>>;
+
+    /// Package a video into an HLS bitrate ladder with variant and master playlists.
+    async fn hls(request: Json<HlsRequest>) -> HandlerResult<Json<HlsResponse>>;
+
+    /// Encode a single rendition of an HLS ladder (its variant playlist and segments).
+    async fn hls_rendition(
+        request: Json<HlsRenditionRequest>,
+    ) -> HandlerResult<Json<HlsRenditionResponse>>;
+
+    /// Package a video into an MPEG-DASH manifest with fMP4 segments.
+    async fn dash(request: Json<DashRequest>) -> HandlerResult<Json<DashResponse>>;
+
+    /// Extract frames at given positions (or evenly spaced) as thumbnail images.
+    async fn thumbnail(request: Json<ThumbnailRequest>) -> HandlerResult<Json<ThumbnailResponse>>;
+
+    /// Tile thumbnails into sprite images with a WebVTT track of their regions (for scrub previews).
+    async fn sprites(request: Json<SpritesRequest>) -> HandlerResult<Json<SpritesResponse>>;
+
+    /// Make a short looping GIF or animated WebP from a clip of a video.
+    async fn preview(request: Json<PreviewRequest>) -> HandlerResult<Json<PreviewResponse>>;
+
+    /// Measure the decoding throughput of an input on this worker (e.g. to check hardware decoding).
+    async fn benchmark_decode(
+        request: Json<BenchmarkDecodeRequest>,
+    ) -> HandlerResult<Json<BenchmarkDecodeResponse>>;
+
+    /// Measure the encoding throughput of this worker on generated video and store its score.
+    async fn benchmark_encode(
+        request: Json<BenchmarkEncodeRequest>,
+    ) -> HandlerResult<Json<BenchmarkEncodeResponse>>;
+
+    /// Remux a damaged file skipping corrupt data, reporting what was recovered.
+    async fn repair(request: Json<RepairRequest>) -> HandlerResult<Json<RepairResponse>>;
+
+    /// Remux a file with zero-based, monotonic timestamps.
+    async fn normalize_timestamps(
+        request: Json<NormalizeTimestampsRequest>,
+    ) -> HandlerResult<Json<NormalizeTimestampsResponse>>;
+
+    /// Cut a clip out of a file, by copying from keyframes or re-encoding from the exact frame.
+    async fn clip(request: Json<ClipRequest>) -> HandlerResult<Json<ClipResponse>>;
+
+    /// Hash every stream of a file, optionally verifying them bit-exact against a reference.
+    async fn hash_streams(
+        request: Json<HashStreamsRequest>,
+    ) -> HandlerResult<Json<HashStreamsResponse>>;
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 #[schemars(example = example_ffmpeg_request())]
 pub struct FfmpegRequest {
-    args: Vec<String>,
-    output: Output,
+    pub args: Vec<String>,
+    pub output: Output,
+
+    /// Check the command without producing and uploading outputs
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+
+    /// Upload files (e.g. HLS segments) as soon as ffmpeg completes them
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub incremental_upload: bool,
+
+    /// Environment variables of ffmpeg (e.g. "AV_LOG_FORCE_COLOR"), limited to the ones allowed in the config
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+
+    /// Inputs downloaded from storage before ffmpeg runs (referenced as "{name}" in the arguments)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<Input>,
+
+    /// Destinations of the files matching a pattern (the first match wins, the rest goes to output)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<OutputRoute>,
+
+    /// Append scale and setsar filters making the frame size of every output even and its pixels square
+    ///
+    /// Prevents "width not divisible by 2" failures of scale filters computing an odd size.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sanitize_dimensions: bool,
+
+    /// Log level and progress interval, within the bounds of the config
+    #[serde(flatten)]
+    pub logging: LogOptions,
 }
 
 fn example_ffmpeg_request() -> FfmpegRequest {
@@ -41,7 +397,16 @@ fn example_ffmpeg_request() -> FfmpegRequest {
             .collect(),
         output: Output {
             location: Url::parse("s3://bucket/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
         },
+        dry_run: false,
+        incremental_upload: false,
+        env: Default::default(),
+        inputs: Vec::new(),
+        outputs: Vec::new(),
+        sanitize_dimensions: false,
+        logging: Default::default(),
     }
 }
 
@@ -49,26 +414,139 @@ fn example_ffmpeg_request() -> FfmpegRequest {
 #[serde(rename_all = "camelCase")]
 #[schemars(example = example_ffmpeg_response())]
 pub struct FfmpegResponse {
-    stderr: String,
+    pub stderr: String,
+
+    /// Location of the files written by ffmpeg
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<Url>,
+
+    /// Outcome of uploading the files written by ffmpeg
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uploads: Vec<UploadedObject>,
+
+    /// Resolved command (dry runs only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<FfmpegPlan>,
+
+    /// Throughput of the output written to stdout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<StreamStats>,
+
+    /// Likely mistakes in the encoding settings of the command
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<LintWarning>,
+
+    /// Estimated cost of the job (when rates are configured)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<JobCost>,
+
+    /// ID of the job (to look it up in the history, or given in the x-job-id header to
+    /// diagnose the job while it runs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
 }
 
 fn example_ffmpeg_response() -> FfmpegResponse {
     FfmpegResponse {
         stderr: String::new(),
+        outputs: Vec::new(),
+        uploads: Vec::new(),
+        plan: None,
+        stream: None,
+        warnings: Vec::new(),
+        cost: None,
+        job_id: None,
     }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Output {
-    location: Url,
+    /// Location the outputs are uploaded to
+    ///
+    /// "{jobId}", "{date}", "{inputBasename}" and "{preset}" are replaced with the metadata of the job.
+    pub location: Url,
+
+    /// Return small files in the response as data URLs instead of uploading them
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inline: bool,
+
+    /// What happens to objects already at the location of an output file
+    #[serde(default)]
+    pub overwrite: OverwritePolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OverwritePolicy {
+    /// Replace existing objects
+    #[default]
+    Overwrite,
+    /// Keep existing objects and don't upload the file
+    Skip,
+    /// Fail the job
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadStatus {
+    Created,
+    Overwritten,
+    Skipped,
+}
+
+/// Outcome of uploading an output file.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadedObject {
+    pub location: Url,
+
+    pub status: UploadStatus,
+
+    /// ETag of the object that was already at the location
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_etag: Option<String>,
+}
+
+impl Output {
+    /// Returns the location with its placeholders resolved (within a job).
+    pub fn location(&self) -> Url {
+        resolve(&self.location)
+    }
+
+    /// Returns the URL a file produced in the work dir is uploaded to.
+    pub fn file_url(&self, name: &str) -> Url {
+        let mut location = self.location();
+
+        if !location.path().ends_with('/') {
+            location.set_path(&format!("{}/", location.path()));
+        }
+
+        location.join(name).unwrap_or(location)
+    }
 }
 
 pub struct ServiceImpl<F>
 where
     F: OperatorFactory,
 {
-    factory: F,
+    pub(crate) factory: F,
+    pub(crate) limiter: Arc<RateLimiter>,
+    metering: bool,
+    rates: CostRates,
+    history: bool,
+    pub(crate) staging: Staging,
+    pub(crate) guardrails: GuardrailConfig,
+    pub(crate) load: LoadConfig,
+    pub(crate) handlers: HandlerConfig,
+    flags: FlagConfig,
+    streaming: StreamingConfig,
+    env: EnvConfig,
+    pub(crate) processes: Processes,
+    watchdog: WatchdogConfig,
+    pub(crate) intake: Intake,
+    pub(crate) scores: WorkerScores,
 }
 
 impl<F> ServiceImpl<F>
@@ -76,7 +554,100 @@ where
     F: OperatorFactory,
 {
     pub fn new(factory: F) -> Self {
-        Self { factory }
+        Self {
+            factory,
+            limiter: Arc::default(),
+            metering: false,
+            rates: CostRates::default(),
+            history: false,
+            staging: Staging::default(),
+            guardrails: GuardrailConfig::default(),
+            load: LoadConfig::default(),
+            handlers: HandlerConfig::default(),
+            flags: FlagConfig::default(),
+            streaming: StreamingConfig::default(),
+            env: EnvConfig::default(),
+            processes: Processes::default(),
+            watchdog: WatchdogConfig::default(),
+            intake: Intake::default(),
+            scores: WorkerScores::default(),
+        }
+    }
+
+    /// Records the usage of every job in the metering object of the caller.
+    ///
+    /// The [`Metering`] object has to be bound to the same endpoint.
+    pub fn with_metering(mut self, enabled: bool) -> Self {
+        self.metering = enabled;
+        self
+    }
+
+    /// Charges the resources of jobs at hourly rates (in responses and metering).
+    pub fn with_cost_rates(mut self, rates: CostRates) -> Self {
+        self.rates = rates;
+        self
+    }
+
+    /// Configures how inputs are staged from storage.
+    pub fn with_staging(mut self, config: StagingConfig) -> Self {
+        self.staging = Staging::new(config);
+        self
+    }
+
+    /// Records a summary of every finished job in the history object of the caller.
+    ///
+    /// The [`History`] object has to be bound to the same endpoint.
+    pub fn with_history(mut self, enabled: bool) -> Self {
+        self.history = enabled;
+        self
+    }
+
+    /// Rejects jobs with inputs exceeding the limits before processing them.
+    pub fn with_guardrails(mut self, config: GuardrailConfig) -> Self {
+        self.guardrails = config;
+        self
+    }
+
+    /// Refuses new jobs while the worker is overloaded.
+    pub fn with_load(mut self, config: LoadConfig) -> Self {
+        self.load = config;
+        self
+    }
+
+    /// Serves only the handlers enabled in the config.
+    pub fn with_handlers(mut self, config: HandlerConfig) -> Self {
+        self.handlers = config;
+        self
+    }
+
+    /// Adds flags to the arguments of ffmpeg requests.
+    pub fn with_flags(mut self, config: FlagConfig) -> Self {
+        self.flags = config;
+        self
+    }
+
+    /// Tunes the upload of outputs written to stdout.
+    pub fn with_streaming(mut self, config: StreamingConfig) -> Self {
+        self.streaming = config;
+        self
+    }
+
+    /// Allows requests to set environment variables of ffmpeg.
+    pub fn with_env(mut self, config: EnvConfig) -> Self {
+        self.env = config;
+        self
+    }
+
+    /// Kills ffmpeg processes whose progress stopped.
+    pub fn with_watchdog(mut self, config: WatchdogConfig) -> Self {
+        self.watchdog = config;
+        self
+    }
+
+    /// Limits the request rate and the concurrent jobs of callers.
+    pub fn with_rate_limits(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.limiter = limiter;
+        self
     }
 }
 
@@ -84,53 +655,79 @@ impl<F> ServiceImpl<F>
 where
     F: OperatorFactory,
 {
-    async fn _ffmpeg(&self, request: FfmpegRequest) -> HandlerResult<FfmpegResponse> {
+    pub(crate) async fn _ffmpeg(&self, request: FfmpegRequest) -> HandlerResult<FfmpegResponse> {
+        self._ffmpeg_finishing(request, |_| Ok(())).await
+    }
+
+    /// Runs ffmpeg, letting `finish` add or rewrite files in the work dir before they are uploaded.
+    ///
+    /// Files uploaded while ffmpeg is running (see `incremental_upload`) are no longer in the work dir.
+    pub(crate) async fn _ffmpeg_finishing(
+        &self,
+        mut request: FfmpegRequest,
+        finish: impl FnOnce(&Path) -> HandlerResult<()>,
+    ) -> HandlerResult<FfmpegResponse> {
+        self.env.check(&request.env)?;
+        request.validate_routes()?;
+
+        // Kept until ffmpeg is done reading the local copies
+        let input_dir = TempDir::new()?;
+
+        if request.dry_run {
+            // ffmpeg only opens the inputs (see `-t 0`): sparse copies are enough
+            let staged = self.stage_probes(&request.inputs, input_dir.path()).await?;
+            self.resolve(&mut request, &staged)?;
+
+            return self.dry_run(request).await;
+        }
+
+        let staged = self.stage_inputs(&request.inputs, input_dir.path()).await?;
+        self.resolve(&mut request, &staged)?;
+
+        let warnings = request.lint();
+
         // Check if output is stdout (indicated by "-" as last arg or output file)
-        let output_to_stdout = request.args.last().map_or(false, |s| s == "-");
+        let output_to_stdout = request.args.last().is_some_and(|s| s == "-");
 
         let work_dir = TempDir::new()?;
+        let reported = progress::reportable(&request.args, output_to_stdout);
+        let watch = self.watchdog.watch(&request.args, reported)?;
 
-        let mut cmd = Command::new("ffmpeg")
+        let cmd = Command::new("ffmpeg")
             .current_dir(work_dir.path())
-            .arg("-nostdin")
-            .arg("-y")
+            .args(self.flags.flags(&request.args))
+            .args(if reported {
+                progress::args()
+            } else {
+                Vec::new()
+            })
+            .args(watch.iter().flat_map(Watch::args))
             .args(&request.args)
+            .envs(&request.env)
             .stderr(Stdio::piped())
-            .stdout(if output_to_stdout {
+            .stdout(if output_to_stdout || reported {
                 Stdio::piped()
             } else {
                 Stdio::null()
             })
             .spawn()?;
 
+        let mut cmd = Terminating::new(cmd, work_dir.path());
+        let process = self.processes.register(cmd.id());
+
         let mut stderr = cmd.stderr.take().expect("Failed to get stderr");
 
-        let (uri, path) = parse_uri(request.output.location);
+        let (uri, path) = parse_uri(request.output.location());
 
         let operator = self.factory.load(uri.as_str())?;
 
         if output_to_stdout {
-            let mut writer = operator
-                .writer(&path)
-                .await?
-                .into_futures_async_write()
-                .compat_write();
-
-            let mut stdout = cmd.stdout.take().expect("Failed to get stdout");
-
-            let (status, stderr_string, _) = tokio::try_join!(
-                cmd.wait(),
-                async {
-                    let mut s = String::new();
-                    stderr.read_to_string(&mut s).await?;
-                    Ok::<_, std::io::Error>(s)
-                },
-                async {
-                    tokio::io::copy(&mut stdout, &mut writer).await?;
-                    writer.flush().await?;
-                    writer.into_inner().close().await?;
-                    Ok::<_, std::io::Error>(())
-                }
+            let stdout = cmd.stdout.take().expect("Failed to get stdout");
+
+            let (status, stderr_string, stats) = tokio::try_join!(
+                watchdog::wait(&mut cmd, watch.as_ref(), &process),
+                process.read_stderr(&mut stderr),
+                self.streaming.stream(stdout, &operator, &path)
             )?;
 
             if !status.success() {
@@ -140,22 +737,45 @@ where
                 )));
             }
 
+            metering::record_ffmpeg(&request.args, &stderr_string);
+            metering::record(|usage| usage.bytes_out += stats.bytes);
+
             Ok(FfmpegResponse {
                 stderr: stderr_string,
+                outputs: Vec::new(),
+                uploads: Vec::new(),
+                plan: None,
+                stream: Some(stats),
+                warnings,
+                cost: None,
+                job_id: None,
             })
         } else {
-            // Output to file - extract filename from args
-            // let output_file = request
-            //     .args
-            //     .last()
-            //     .filter(|arg| !arg.starts_with('-'))
-            //     .ok_or("No output file found in args")?;
-
-            let (status, stderr_string) = tokio::try_join!(cmd.wait(), async {
-                let mut s = String::new();
-                stderr.read_to_string(&mut s).await?;
-                Ok::<_, std::io::Error>(s)
-            })?;
+            let stdout = cmd.stdout.take();
+
+            let run = async {
+                let (status, stderr, _) = tokio::try_join!(
+                    watchdog::wait(&mut cmd, watch.as_ref(), &process),
+                    process.read_stderr(&mut stderr),
+                    async {
+                        match stdout {
+                            Some(stdout) => process.read_progress(stdout).await,
+                            None => Ok(()),
+                        }
+                    }
+                )?;
+
+                Ok::<_, std::io::Error>((status, stderr))
+            };
+
+            let (result, mut uploads) = if request.incremental_upload && !request.output.inline {
+                self.uploading_completed(work_dir.path(), &request.output, run)
+                    .await?
+            } else {
+                (run.await, Vec::new())
+            };
+
+            let (status, stderr_string) = result?;
 
             if !status.success() {
                 return Err(HandlerError::from(format!(
@@ -164,109 +784,113 @@ where
                 )));
             }
 
-            let source = Operator::new(
-                Fs::default().root(work_dir.path().to_string_lossy().to_string().as_str()),
-            )?
-            .finish();
+            metering::record_ffmpeg(&request.args, &stderr_string);
 
-            let copier = Copier::new(source, operator);
+            finish(work_dir.path())?;
 
-            copier.copy("*", path).await?;
+            let outputs = uploads
+                .iter()
+                .map(|object| object.location.clone())
+                .chain(
+                    work_files(work_dir.path())
+                        .iter()
+                        .map(|name| request.destination(name).file_url(name)),
+                )
+                .collect();
 
-            // Stream the file to OpenDAL
-            // let mut file = tokio::fs::File::open(&output_file).await?;
+            let routed = request.split_outputs(work_dir.path())?;
 
-            // tokio::io::copy(&mut file, &mut writer).await?;
-            // writer.flush().await?;
-            // writer.into_inner().close().await?;
+            uploads.extend(self.upload(work_dir.path(), &request.output).await?);
 
-            // Clean up local file
-            // tokio::fs::remove_file(&output_file).await?;
+            for (dir, output) in &routed {
+                uploads.extend(self.upload(dir.path(), output).await?);
+            }
 
             Ok(FfmpegResponse {
                 stderr: stderr_string,
+                outputs,
+                uploads,
+                plan: None,
+                stream: None,
+                warnings,
+                cost: None,
+                job_id: None,
             })
         }
     }
+
+    /// Runs the command without processing any media to check its syntax and inputs.
+    ///
+    /// ffmpeg still opens the inputs, builds the filter graphs and writes the headers
+    /// of the outputs in a throwaway work dir: these files are reported (but not uploaded).
+    /// Resolves the arguments ffmpeg is run with, replacing the placeholders of the inputs with `staged`.
+    pub(crate) fn resolve(
+        &self,
+        request: &mut FfmpegRequest,
+        staged: &[(String, String)],
+    ) -> HandlerResult<()> {
+        request.args = inputs::substitute(&request.args, staged);
+
+        if request.sanitize_dimensions {
+            request.args = sanitize_dimensions(&request.args);
+        }
+
+        let logging = self.flags.logging(&request.logging, &request.args)?;
+        request.args.splice(0..0, logging);
+
+        Ok(())
+    }
+
+    async fn dry_run(&self, request: FfmpegRequest) -> HandlerResult<FfmpegResponse> {
+        let mut args = request.args.clone();
+        let output = args.pop().unwrap_or_default();
+
+        // Limits the duration of the (last) output file
+        args.extend(["-t".to_string(), "0".to_string()]);
+
+        if output == "-" {
+            args.extend(["-f".to_string(), "null".to_string()]);
+        }
+
+        args.push(output);
+
+        let work_dir = TempDir::new()?;
+
+        let result = Command::new("ffmpeg")
+            .current_dir(work_dir.path())
+            .args(self.flags.flags(&args))
+            .args(&args)
+            .envs(&request.env)
+            .stdin(Stdio::null())
+            .output()
+            .await?;
+
+        let stderr = String::from_utf8_lossy(&result.stderr).into_owned();
+
+        // A command failing here fails the same way when run for real: no point in retrying
+        if !result.status.success() {
+            return Err(
+                TerminalError::new_with_code(400, format!("ffmpeg failed: {stderr}")).into(),
+            );
+        }
+
+        let outputs = work_files(work_dir.path())
+            .iter()
+            .map(|name| request.destination(name).file_url(name))
+            .collect();
+
+        Ok(FfmpegResponse {
+            stderr,
+            outputs,
+            uploads: Vec::new(),
+            plan: Some(request.plan(self.flags.flags(&request.args))),
+            stream: None,
+            warnings: request.lint(),
+            cost: None,
+            job_id: None,
+        })
+    }
 }
-// async fn _ffmpeg(&self, request: FfmpegRequest) -> HandlerResult<FfmpegResponse> {
-//     let mut cmd = Command::new("ffmpeg")
-//         .args(request.args)
-//         .stdout(Stdio::piped())
-//         .stderr(Stdio::piped())
-//         .spawn()?;
-
-//     let mut stdout = cmd.stdout.take().expect("Failed to get stdout");
-//     let mut stderr = cmd.stderr.take().expect("Failed to get stderr");
-
-//     use tokio::io::AsyncReadExt;
-
-//     // Wait for everything concurrently
-//     let (status, stderr_string, stdout_bytes) = tokio::try_join!(
-//         cmd.wait(),
-//         async {
-//             let mut s = String::new();
-//             stderr.read_to_string(&mut s).await?;
-//             Ok::<_, std::io::Error>(s)
-//         },
-//         async {
-//             let mut b = Vec::new();
-//             stdout.read_to_end(&mut b).await?;
-//             Ok::<_, std::io::Error>(b)
-//         }
-//     )?;
-
-//     if !status.success() {
-//         return Err(HandlerError::from(format!(
-//             "ffmpeg failed: {}",
-//             stderr_string
-//         )));
-//     }
-
-//     Ok(FfmpegResponse {})
-// }
-
-// async fn _ffmpeg(&self, request: FfmpegRequest) -> HandlerResult<FfmpegResponse> {
-//     let mut cmd = Command::new("ffmpeg")
-//         .args(request.args)
-//         .stdout(Stdio::piped())
-//         .stderr(Stdio::piped())
-//         .spawn()?;
-
-//     let stdout = cmd.stdout.take().expect("Failed to get stdout");
-//     let stderr = cmd.stderr.take().expect("Failed to get stderr");
-
-//     use tokio::io::AsyncReadExt;
-
-//     // Read both streams concurrently
-//     let stderr_handle = tokio::spawn(async move {
-//         let mut stderr_string = String::new();
-//         let mut stderr_reader = tokio::io::BufReader::new(stderr);
-//         stderr_reader.read_to_string(&mut stderr_string).await?;
-//         Ok::<String, std::io::Error>(stderr_string)
-//     });
-
-//     let stdout_handle = tokio::spawn(async move {
-//         let mut stdout_bytes = Vec::new();
-//         let mut stdout_reader = tokio::io::BufReader::new(stdout);
-//         stdout_reader.read_to_end(&mut stdout_bytes).await?;
-//         Ok::<Vec<u8>, std::io::Error>(stdout_bytes)
-//     });
-
-//     // Wait for both streams and the process
-//     let stderr_string = stderr_handle.await??;
-//     let stdout_bytes = stdout_handle.await??;
-//     let status = cmd.wait().await?;
-
-//     if !status.success() {
-//         return Err(HandlerError::from(format!(
-//             "ffmpeg failed: {}",
-//             stderr_string
-//         )));
-//     }
-
-//     Ok(FfmpegResponse {})
-// }
 
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
@@ -282,6 +906,36 @@ pub struct FfprobeRequest {
     /// Include stream information
     #[serde(default)]
     pub show_streams: bool,
+
+    /// Output format of ffprobe (anything other than JSON is returned as raw text)
+    #[serde(default)]
+    pub print_format: ProbeFormat,
+}
+
+/// Writers of ffprobe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeFormat {
+    #[default]
+    Json,
+    Flat,
+    Csv,
+    Xml,
+    Ini,
+    Compact,
+}
+
+impl ProbeFormat {
+    fn writer(&self) -> &'static str {
+        match self {
+            ProbeFormat::Json => "json",
+            ProbeFormat::Flat => "flat",
+            ProbeFormat::Csv => "csv",
+            ProbeFormat::Xml => "xml",
+            ProbeFormat::Ini => "ini",
+            ProbeFormat::Compact => "compact",
+        }
+    }
 }
 
 fn example_ffprobe_request() -> FfprobeRequest {
@@ -292,6 +946,7 @@ fn example_ffprobe_request() -> FfprobeRequest {
         .unwrap(),
         show_format: true,
         show_streams: true,
+        print_format: ProbeFormat::Json,
     }
 }
 
@@ -304,10 +959,13 @@ pub struct FfprobeResponse {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub streams: Option<Vec<Stream>>,
+
+    /// Output of ffprobe when a format other than JSON is requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
-// #[serde(rename_all = "camelCase")]
 pub struct Format {
     pub filename: String,
     pub nb_streams: i32,
@@ -335,7 +993,6 @@ pub struct Format {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
-// #[serde(rename_all = "camelCase")]
 pub struct Stream {
     pub index: i32,
 
@@ -352,6 +1009,9 @@ pub struct Stream {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub codec_tag: Option<String>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
     // Video-specific fields
     #[serde(skip_serializing_if = "Option::is_none")]
     pub width: Option<i32>,
@@ -365,6 +1025,12 @@ pub struct Stream {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub coded_height: Option<i32>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_aspect_ratio: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_aspect_ratio: Option<String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub r_frame_rate: Option<String>,
 
@@ -383,6 +1049,12 @@ pub struct Stream {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub color_space: Option<String>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_transfer: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_primaries: Option<String>,
+
     // Audio-specific fields
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sample_fmt: Option<String>,
@@ -430,7 +1102,6 @@ pub struct Stream {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
-// #[serde(rename_all = "camelCase")]
 pub struct Disposition {
     #[serde(default)]
     pub default: i32,
@@ -470,6 +1141,7 @@ fn example_ffprobe_response() -> FfprobeResponse {
     FfprobeResponse {
         format: None,
         streams: None,
+        raw: None,
     }
 }
 
@@ -477,12 +1149,17 @@ impl<F> ServiceImpl<F>
 where
     F: OperatorFactory,
 {
-    async fn _ffprobe(&self, request: FfprobeRequest) -> HandlerResult<FfprobeResponse> {
+    pub(crate) async fn _ffprobe(&self, request: FfprobeRequest) -> HandlerResult<FfprobeResponse> {
+        let staging_dir = TempDir::new()?;
+
+        // ffprobe can't read storage inputs, stage the parts it needs instead
+        let input = self.probe_input(&request.input, staging_dir.path()).await?;
+
         let mut cmd = Command::new("ffprobe");
 
-        // Force JSON output, suppress banner
+        // Suppress banner
         cmd.args(["-v", "quiet"]);
-        cmd.args(["-print_format", "json"]);
+        cmd.args(["-print_format", request.print_format.writer()]);
 
         // Add requested sections
         if request.show_format {
@@ -493,7 +1170,7 @@ where
         }
 
         // Input file
-        cmd.arg(request.input.as_str());
+        cmd.arg(input);
 
         // Execute
         let output = cmd.output().await?;
@@ -504,11 +1181,367 @@ where
             return Err(HandlerError::from(format!("ffprobe failed: {}", stderr)));
         }
 
+        if request.print_format != ProbeFormat::Json {
+            return Ok(FfprobeResponse {
+                format: None,
+                streams: None,
+                raw: Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+            });
+        }
+
         Ok(serde_json::from_slice(&output.stdout)?)
     }
+
+    /// Probes the format and streams of an input.
+    pub(crate) async fn probe(&self, input: &Url) -> HandlerResult<FfprobeResponse> {
+        self._ffprobe(FfprobeRequest {
+            input: input.clone(),
+            show_format: true,
+            show_streams: true,
+            print_format: ProbeFormat::Json,
+        })
+        .await
+    }
+}
+
+impl FfprobeResponse {
+    /// Duration of the media in seconds.
+    pub fn duration(&self) -> Option<f64> {
+        self.format
+            .as_ref()
+            .and_then(|format| format.duration.as_deref())
+            .and_then(|duration| duration.parse().ok())
+    }
+
+    /// Returns the first stream of the given type (e.g. "video").
+    pub fn stream(&self, codec_type: &str) -> Option<&Stream> {
+        self.streams
+            .iter()
+            .flatten()
+            .find(|stream| stream.codec_type == codec_type)
+    }
+}
+
+impl Stream {
+    /// Average frame rate of the stream (falling back to the base one).
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.avg_frame_rate
+            .as_deref()
+            .and_then(parse_frame_rate)
+            .or_else(|| self.r_frame_rate.as_deref().and_then(parse_frame_rate))
+    }
+}
+
+/// Parses a frame rate reported by ffprobe (e.g. "30000/1001").
+fn parse_frame_rate(rate: &str) -> Option<f64> {
+    let rate = match rate.split_once('/') {
+        Some((num, den)) => num.parse::<f64>().ok()? / den.parse::<f64>().ok()?,
+        None => rate.parse().ok()?,
+    };
+
+    (rate.is_finite() && rate > 0.0).then_some(rate)
+}
+
+/// Runs ffmpeg without uploading anything and returns its stderr.
+///
+/// Analysis handlers use this to parse the log output of filters like cropdetect.
+pub(crate) async fn run_ffmpeg(args: &[String]) -> HandlerResult<String> {
+    let output = exec_ffmpeg(Command::new("ffmpeg").args(args)).await?;
+
+    Ok(String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/// Runs ffmpeg in the given work dir and returns its stderr.
+pub(crate) async fn run_ffmpeg_in(work_dir: &Path, args: &[String]) -> HandlerResult<String> {
+    let output = exec_ffmpeg(
+        Command::new("ffmpeg")
+            .current_dir(work_dir)
+            .arg("-y")
+            .args(args),
+    )
+    .await?;
+
+    Ok(String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/// Runs ffmpeg and returns its stdout (e.g. the output of the hash muxers).
+pub(crate) async fn run_ffmpeg_stdout(args: &[String]) -> HandlerResult<String> {
+    let output = exec_ffmpeg(Command::new("ffmpeg").args(args)).await?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Runs ffprobe with JSON output and deserializes the result.
+pub(crate) async fn run_ffprobe<T: DeserializeOwned>(args: &[String]) -> HandlerResult<T> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "quiet"])
+        .args(["-print_format", "json"])
+        .args(args)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        return Err(HandlerError::from(format!("ffprobe failed: {}", stderr)));
+    }
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+async fn exec_ffmpeg(cmd: &mut Command) -> HandlerResult<std::process::Output> {
+    let output = cmd
+        .arg("-nostdin")
+        .arg("-hide_banner")
+        .arg("-benchmark")
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    if !output.status.success() {
+        return Err(HandlerError::from(format!("ffmpeg failed: {}", stderr)));
+    }
+
+    let args: Vec<String> = cmd
+        .as_std()
+        .get_args()
+        .map(|arg| arg.to_string_lossy().to_string())
+        .collect();
+
+    metering::record_ffmpeg(&args, &stderr);
+
+    Ok(output)
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Uploads every file in the work dir to the output location (or inlines them in the response).
+    pub(crate) async fn upload(
+        &self,
+        work_dir: &Path,
+        output: &Output,
+    ) -> HandlerResult<Vec<UploadedObject>> {
+        if output.inline {
+            inline_files(work_dir, output).await?;
+
+            return Ok(Vec::new());
+        }
+
+        let (uri, path) = parse_uri(output.location());
+
+        let operator = self.factory.load(uri.as_str())?;
+
+        let objects = check_conflicts(work_dir, work_files(work_dir), output, &operator).await?;
+
+        if !defer(work_dir, output)? {
+            self.upload_to(work_dir, operator, path).await?;
+        }
+
+        Ok(objects)
+    }
+
+    async fn upload_to(
+        &self,
+        work_dir: &Path,
+        operator: Operator,
+        path: String,
+    ) -> HandlerResult<()> {
+        // Outputs on the same host are moved in place instead of being streamed through OpenDAL
+        if let Some(dir) = local_dir(&operator, &path) {
+            metering::record_upload(work_dir);
+            move_entries(work_dir, &dir)?;
+
+            return Ok(());
+        }
+
+        let source =
+            Operator::new(Fs::default().root(work_dir.to_string_lossy().to_string().as_str()))?
+                .finish();
+
+        let copier = Copier::new(source, operator);
+
+        copier.copy("*", path).await?;
+
+        metering::record_upload(work_dir);
+
+        Ok(())
+    }
+
+    /// Uploads the outputs a job left in its job dir.
+    ///
+    /// Uploaded directories are marked, so that a retry continues with the rest.
+    async fn upload_pending(&self, job_dir: &Path, uploads: &[PendingUpload]) -> HandlerResult<()> {
+        for upload in uploads {
+            let dir = job_dir.join(&upload.dir);
+            let done = job_dir.join(format!("{}.done", upload.dir));
+
+            if tokio::fs::try_exists(&done).await? {
+                continue;
+            }
+
+            if !tokio::fs::try_exists(&dir).await? {
+                return Err(TerminalError::new(format!(
+                    "outputs of the job are missing from {} (was the job retried on another host?)",
+                    job_dir.display()
+                ))
+                .into());
+            }
+
+            let (uri, path) = parse_uri(upload.location.clone());
+            let operator = self.factory.load(uri.as_str())?;
+
+            self.upload_to(&dir, operator, path).await?;
+
+            tokio::fs::rename(&dir, &done).await?;
+        }
+
+        tokio::fs::remove_dir_all(job_dir).await?;
+
+        Ok(())
+    }
+}
+
+/// Checks the files of a work dir against the objects at the output location.
+///
+/// Files skipped by the overwrite policy are removed from the work dir.
+pub(crate) async fn check_conflicts(
+    work_dir: &Path,
+    files: Vec<String>,
+    output: &Output,
+    operator: &Operator,
+) -> HandlerResult<Vec<UploadedObject>> {
+    let checks = files.into_iter().map(|file| async move {
+        let location = output.file_url(&file);
+        let (_, path) = parse_uri(location.clone());
+
+        let previous = match operator.stat(&path).await {
+            Ok(metadata) => Some(metadata),
+            Err(err) if err.kind() == opendal::ErrorKind::NotFound => None,
+            Err(err) => return Err(HandlerError::from(err)),
+        };
+
+        let status = match (&previous, output.overwrite) {
+            (None, _) => UploadStatus::Created,
+            (Some(_), OverwritePolicy::Overwrite) => UploadStatus::Overwritten,
+            (Some(_), OverwritePolicy::Skip) => {
+                tokio::fs::remove_file(work_dir.join(&file)).await?;
+
+                UploadStatus::Skipped
+            }
+            (Some(_), OverwritePolicy::Fail) => {
+                return Err(TerminalError::new_with_code(
+                    409,
+                    format!("{location} already exists"),
+                )
+                .into());
+            }
+        };
+
+        Ok(UploadedObject {
+            location,
+            status,
+            previous_etag: previous.and_then(|metadata| metadata.etag().map(str::to_string)),
+        })
+    });
+
+    futures::stream::iter(checks)
+        .buffered(16)
+        .try_collect()
+        .await
+}
+
+/// Returns the local directory behind a path of a file system operator.
+pub(crate) fn local_dir(operator: &Operator, path: &str) -> Option<PathBuf> {
+    let info = operator.info();
+
+    (info.scheme() == "fs").then(|| Path::new(&info.root()).join(path.trim_start_matches('/')))
+}
+
+/// Returns the paths of the files in a work dir relative to it.
+pub(crate) fn work_files(work_dir: &Path) -> Vec<String> {
+    fn walk(dir: &Path, prefix: &str, files: &mut Vec<String>) {
+        for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+            let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => {
+                    walk(&entry.path(), &format!("{name}/"), files)
+                }
+                Ok(_) => files.push(name),
+                Err(_) => {}
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    walk(work_dir, "", &mut files);
+    files.sort();
+
+    files
+}
+
+/// Copies files (paths relative to a directory) into another directory.
+pub(crate) fn copy_files(files: &[String], from: &Path, to: &Path) -> std::io::Result<()> {
+    for file in files {
+        let destination = to.join(file);
+
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::copy(from.join(file), destination)?;
+    }
+
+    Ok(())
+}
+
+/// Escapes a value (e.g. a file name) used as a filter option inside a filtergraph.
+///
+/// Values are parsed twice (once as a filter option, once by the graph parser),
+/// so special characters are escaped for both levels.
+pub(crate) fn escape_filter_value(value: &str) -> String {
+    fn escape(value: &str, special: &[char]) -> String {
+        value.chars().fold(String::new(), |mut escaped, c| {
+            if special.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            escaped
+        })
+    }
+
+    escape(
+        &escape(value, &['\\', '\'', ':']),
+        &['\\', '\'', '[', ']', ',', ';'],
+    )
+}
+
+/// Returns the file name of the input without its extension, used to name typed outputs.
+pub(crate) fn input_stem(input: &Url) -> String {
+    input
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .map(|name| name.rsplit_once('.').map_or(name, |(stem, _)| stem))
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or("output")
+        .to_string()
+}
+
+/// Returns the extension of the input file name, used to keep the container when copying streams.
+pub(crate) fn input_extension(input: &Url) -> Option<String> {
+    input
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_lowercase())
+        .filter(|extension| !extension.is_empty())
 }
 
-fn parse_uri(uri: Url) -> (String, String) {
+pub(crate) fn parse_uri(uri: Url) -> (String, String) {
     let mut uri = uri;
     let path = uri.path().to_string();
     uri.set_path("");
@@ -516,27 +1549,1007 @@ fn parse_uri(uri: Url) -> (String, String) {
     (uri.to_string(), path)
 }
 
+/// Response of a job run by [`ServiceImpl::execute_charged`] along with its ID and cost.
+struct Executed<T> {
+    response: T,
+    job_id: String,
+    cost: Option<JobCost>,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Runs a job as a durable step, recording its usage and summary when enabled.
+    ///
+    /// The inputs of the job are checked against the guardrails in the same step.
+    /// Outputs are uploaded in a separate step: a failing upload is retried
+    /// without running ffmpeg again.
+    async fn execute<R, T, Fut>(
+        &self,
+        ctx: &mut Context<'_>,
+        handler: &str,
+        request: Json<R>,
+        job: impl FnOnce(R) -> Fut,
+    ) -> HandlerResult<Json<T>>
+    where
+        R: Serialize,
+        T: Serialize + DeserializeOwned + Send + 'static,
+        Fut: Future<Output = HandlerResult<T>> + Send,
+    {
+        let Executed { response, .. } = self.execute_charged(ctx, handler, request, job).await?;
+
+        Ok(Json(response))
+    }
+
+    /// Runs a job like [`Self::execute`], returning its ID and cost along with the response.
+    async fn execute_charged<R, T, Fut>(
+        &self,
+        ctx: &mut Context<'_>,
+        handler: &str,
+        request: Json<R>,
+        job: impl FnOnce(R) -> Fut,
+    ) -> HandlerResult<Executed<T>>
+    where
+        R: Serialize,
+        T: Serialize + DeserializeOwned + Send + 'static,
+        Fut: Future<Output = HandlerResult<T>> + Send,
+    {
+        let request = request.into_inner();
+
+        let value = serde_json::to_value(&request)?;
+
+        let mut inputs = Vec::new();
+        collect_urls(&value, &["output", "outputs"], &mut inputs);
+
+        let job = job(request);
+
+        let job_id = job_id(ctx)?;
+
+        // Derived from the invocation: the same on every retry (unlike job IDs, never shared)
+        let job_dir = self.staging.job_dir(&ctx.rand_uuid().to_string());
+
+        // Journaled, so that retries resolve the output locations with the same date
+        let metadata = ctx
+            .run(|| async { Ok(Json(JobMetadata::new(job_id.clone(), &inputs, &value))) })
+            .name("metadata")
+            .await?
+            .into_inner();
+
+        let Metered {
+            response: Staged { response, uploads },
+            usage,
+            finished_at,
+        } = ctx
+            .run(|| {
+                metered(deferred(
+                    job_dir.clone(),
+                    with_inlined(with_job(metadata.clone(), async {
+                        self.check_guardrails(&inputs).await?;
+
+                        job.await
+                    })),
+                ))
+            })
+            .name("job")
+            .await?
+            .into_inner();
+
+        if !uploads.is_empty() {
+            let uploaded = ctx
+                .run(|| self.upload_pending(&job_dir, &uploads))
+                .name("upload")
+                .await;
+
+            if let Err(err) = uploaded {
+                // Cancelled (e.g. the losing attempt of a hedged job): the outputs are never uploaded
+                if err.code() == 409 {
+                    let _ = tokio::fs::remove_dir_all(&job_dir).await;
+                }
+
+                return Err(err.into());
+            }
+        }
+
+        let cost = self.record_job(ctx, handler, &metadata, &response, usage, finished_at)?;
+
+        Ok(Executed {
+            response,
+            job_id: metadata.job_id().to_string(),
+            cost,
+        })
+    }
+
+    /// Records the usage and the summary of a finished job when enabled.
+    ///
+    /// Returns the cost of the job when rates are configured.
+    pub(crate) fn record_job(
+        &self,
+        ctx: &Context<'_>,
+        handler: &str,
+        job: &JobMetadata,
+        response: &impl Serialize,
+        mut usage: Usage,
+        finished_at: Timestamp,
+    ) -> HandlerResult<Option<JobCost>> {
+        let cost = JobCost::charge(&self.rates, &mut usage);
+
+        let caller = self.limiter.caller(ctx.headers());
+
+        if self.metering {
+            ctx.object_client::<MeteringClient>(caller.clone())
+                .record(Json(UsageRecord {
+                    handler: handler.to_string(),
+                    finished_at,
+                    usage: usage.clone(),
+                }))
+                .send();
+        }
+
+        if self.history {
+            let mut outputs = Vec::new();
+            collect_urls(&serde_json::to_value(response)?, &[], &mut outputs);
+
+            ctx.object_client::<HistoryClient>(caller)
+                .record(Json(JobSummary {
+                    job_id: job.job_id().to_string(),
+                    handler: handler.to_string(),
+                    inputs: job.inputs().to_vec(),
+                    outputs,
+                    finished_at,
+                    usage,
+                }))
+                .send();
+        }
+
+        Ok(cost)
+    }
+}
+
 impl<F> Service for ServiceImpl<F>
 where
     F: OperatorFactory,
 {
     async fn ffmpeg(
         &self,
-        ctx: Context<'_>,
+        mut ctx: Context<'_>,
         request: Json<FfmpegRequest>,
     ) -> HandlerResult<Json<FfmpegResponse>> {
-        Ok(ctx
-            .run(async || Ok(self._ffmpeg(request.into_inner()).await.map(Json)?))
-            .await?)
+        let _permit = self.admit(&ctx, "ffmpeg").await?;
+
+        let Executed {
+            mut response,
+            job_id,
+            cost,
+        } = self
+            .execute_charged(&mut ctx, "ffmpeg", request, |request| self._ffmpeg(request))
+            .await?;
+
+        response.job_id = Some(job_id);
+        response.cost = cost;
+
+        Ok(Json(response))
     }
 
     async fn ffprobe(
         &self,
-        ctx: Context<'_>,
+        mut ctx: Context<'_>,
         request: Json<FfprobeRequest>,
     ) -> HandlerResult<Json<FfprobeResponse>> {
-        Ok(ctx
-            .run(async || Ok(self._ffprobe(request.into_inner()).await.map(Json)?))
-            .await?)
+        let _permit = self.admit(&ctx, "ffprobe").await?;
+
+        self.execute(&mut ctx, "ffprobe", request, |request| {
+            self._ffprobe(request)
+        })
+        .await
+    }
+
+    async fn convert_color(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ConvertColorRequest>,
+    ) -> HandlerResult<Json<ConvertColorResponse>> {
+        let _permit = self.admit(&ctx, "convert_color").await?;
+
+        self.execute(&mut ctx, "convert_color", request, |request| {
+            self._convert_color(request)
+        })
+        .await
+    }
+
+    async fn detect_crop(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<DetectCropRequest>,
+    ) -> HandlerResult<Json<DetectCropResponse>> {
+        let _permit = self.admit(&ctx, "detect_crop").await?;
+
+        self.execute(&mut ctx, "detect_crop", request, |request| {
+            self._detect_crop(request)
+        })
+        .await
+    }
+
+    async fn autocrop(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<AutocropRequest>,
+    ) -> HandlerResult<Json<AutocropResponse>> {
+        let _permit = self.admit(&ctx, "autocrop").await?;
+
+        self.execute(&mut ctx, "autocrop", request, |request| {
+            self._autocrop(request)
+        })
+        .await
+    }
+
+    async fn convert_aspect(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ConvertAspectRequest>,
+    ) -> HandlerResult<Json<ConvertAspectResponse>> {
+        let _permit = self.admit(&ctx, "convert_aspect").await?;
+
+        self.execute(&mut ctx, "convert_aspect", request, |request| {
+            self._convert_aspect(request)
+        })
+        .await
+    }
+
+    async fn mezzanine(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<MezzanineRequest>,
+    ) -> HandlerResult<Json<MezzanineResponse>> {
+        let _permit = self.admit(&ctx, "mezzanine").await?;
+
+        self.execute(&mut ctx, "mezzanine", request, |request| {
+            self._mezzanine(request)
+        })
+        .await
+    }
+
+    async fn archive(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ArchiveRequest>,
+    ) -> HandlerResult<Json<ArchiveResponse>> {
+        let _permit = self.admit(&ctx, "archive").await?;
+
+        self.execute(&mut ctx, "archive", request, |request| {
+            self._archive(request)
+        })
+        .await
+    }
+
+    async fn normalize_screencast(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<NormalizeScreencastRequest>,
+    ) -> HandlerResult<Json<NormalizeScreencastResponse>> {
+        let _permit = self.admit(&ctx, "normalize_screencast").await?;
+
+        self.execute(&mut ctx, "normalize_screencast", request, |request| {
+            self._normalize_screencast(request)
+        })
+        .await
+    }
+
+    async fn spherical(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<SphericalRequest>,
+    ) -> HandlerResult<Json<SphericalResponse>> {
+        let _permit = self.admit(&ctx, "spherical").await?;
+
+        self.execute(&mut ctx, "spherical", request, |request| {
+            self._spherical(request)
+        })
+        .await
+    }
+
+    async fn validate_hdr(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ValidateHdrRequest>,
+    ) -> HandlerResult<Json<ValidateHdrResponse>> {
+        let _permit = self.admit(&ctx, "validate_hdr").await?;
+
+        self.execute(&mut ctx, "validate_hdr", request, |request| {
+            self._validate_hdr(request)
+        })
+        .await
+    }
+
+    async fn extract_captions(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ExtractCaptionsRequest>,
+    ) -> HandlerResult<Json<ExtractCaptionsResponse>> {
+        let _permit = self.admit(&ctx, "extract_captions").await?;
+
+        self.execute(&mut ctx, "extract_captions", request, |request| {
+            self._extract_captions(request)
+        })
+        .await
+    }
+
+    async fn extract_broadcast_subtitles(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ExtractBroadcastSubtitlesRequest>,
+    ) -> HandlerResult<Json<ExtractBroadcastSubtitlesResponse>> {
+        let _permit = self.admit(&ctx, "extract_broadcast_subtitles").await?;
+
+        self.execute(
+            &mut ctx,
+            "extract_broadcast_subtitles",
+            request,
+            |request| self._extract_broadcast_subtitles(request),
+        )
+        .await
+    }
+
+    async fn convert_subtitles(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ConvertSubtitlesRequest>,
+    ) -> HandlerResult<Json<ConvertSubtitlesResponse>> {
+        let _permit = self.admit(&ctx, "convert_subtitles").await?;
+
+        self.execute(&mut ctx, "convert_subtitles", request, |request| {
+            self._convert_subtitles(request)
+        })
+        .await
+    }
+
+    async fn retime_subtitles(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<RetimeSubtitlesRequest>,
+    ) -> HandlerResult<Json<RetimeSubtitlesResponse>> {
+        let _permit = self.admit(&ctx, "retime_subtitles").await?;
+
+        self.execute(&mut ctx, "retime_subtitles", request, |request| {
+            self._retime_subtitles(request)
+        })
+        .await
+    }
+
+    async fn detect_forced_subtitles(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<DetectForcedSubtitlesRequest>,
+    ) -> HandlerResult<Json<DetectForcedSubtitlesResponse>> {
+        let _permit = self.admit(&ctx, "detect_forced_subtitles").await?;
+
+        self.execute(&mut ctx, "detect_forced_subtitles", request, |request| {
+            self._detect_forced_subtitles(request)
+        })
+        .await
+    }
+
+    async fn tag_streams(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<TagStreamsRequest>,
+    ) -> HandlerResult<Json<TagStreamsResponse>> {
+        let _permit = self.admit(&ctx, "tag_streams").await?;
+
+        self.execute(&mut ctx, "tag_streams", request, |request| {
+            self._tag_streams(request)
+        })
+        .await
+    }
+
+    async fn set_disposition(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<SetDispositionRequest>,
+    ) -> HandlerResult<Json<SetDispositionResponse>> {
+        let _permit = self.admit(&ctx, "set_disposition").await?;
+
+        self.execute(&mut ctx, "set_disposition", request, |request| {
+            self._set_disposition(request)
+        })
+        .await
+    }
+
+    async fn strip_streams(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<StripStreamsRequest>,
+    ) -> HandlerResult<Json<StripStreamsResponse>> {
+        let _permit = self.admit(&ctx, "strip_streams").await?;
+
+        self.execute(&mut ctx, "strip_streams", request, |request| {
+            self._strip_streams(request)
+        })
+        .await
+    }
+
+    async fn make_compatible(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<MakeCompatibleRequest>,
+    ) -> HandlerResult<Json<MakeCompatibleResponse>> {
+        let _permit = self.admit(&ctx, "make_compatible").await?;
+
+        self.execute(&mut ctx, "make_compatible", request, |request| {
+            self._make_compatible(request)
+        })
+        .await
+    }
+
+    async fn encode_opus(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<EncodeOpusRequest>,
+    ) -> HandlerResult<Json<EncodeOpusResponse>> {
+        let _permit = self.admit(&ctx, "encode_opus").await?;
+
+        self.execute(&mut ctx, "encode_opus", request, |request| {
+            self._encode_opus(request)
+        })
+        .await
+    }
+
+    async fn encode_audio(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<EncodeAudioRequest>,
+    ) -> HandlerResult<Json<EncodeAudioResponse>> {
+        let _permit = self.admit(&ctx, "encode_audio").await?;
+
+        self.execute(&mut ctx, "encode_audio", request, |request| {
+            self._encode_audio(request)
+        })
+        .await
+    }
+
+    async fn validate_passthrough(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ValidatePassthroughRequest>,
+    ) -> HandlerResult<Json<ValidatePassthroughResponse>> {
+        let _permit = self.admit(&ctx, "validate_passthrough").await?;
+
+        self.execute(&mut ctx, "validate_passthrough", request, |request| {
+            self._validate_passthrough(request)
+        })
+        .await
+    }
+
+    async fn replaygain(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ReplaygainRequest>,
+    ) -> HandlerResult<Json<ReplaygainResponse>> {
+        let _permit = self.admit(&ctx, "replaygain").await?;
+
+        self.execute(&mut ctx, "replaygain", request, |request| {
+            self._replaygain(request)
+        })
+        .await
+    }
+
+    async fn analyze_loudness(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<AnalyzeLoudnessRequest>,
+    ) -> HandlerResult<Json<AnalyzeLoudnessResponse>> {
+        let _permit = self.admit(&ctx, "analyze_loudness").await?;
+
+        self.execute(&mut ctx, "analyze_loudness", request, |request| {
+            self._analyze_loudness(request)
+        })
+        .await
+    }
+
+    async fn fingerprint_audio(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<FingerprintAudioRequest>,
+    ) -> HandlerResult<Json<FingerprintAudioResponse>> {
+        let _permit = self.admit(&ctx, "fingerprint_audio").await?;
+
+        self.execute(&mut ctx, "fingerprint_audio", request, |request| {
+            self._fingerprint_audio(request)
+        })
+        .await
+    }
+
+    async fn detect_highlights(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<DetectHighlightsRequest>,
+    ) -> HandlerResult<Json<DetectHighlightsResponse>> {
+        let _permit = self.admit(&ctx, "detect_highlights").await?;
+
+        self.execute(&mut ctx, "detect_highlights", request, |request| {
+            self._detect_highlights(request)
+        })
+        .await
+    }
+
+    async fn generate_chapters(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<GenerateChaptersRequest>,
+    ) -> HandlerResult<Json<GenerateChaptersResponse>> {
+        let _permit = self.admit(&ctx, "generate_chapters").await?;
+
+        self.execute(&mut ctx, "generate_chapters", request, |request| {
+            self._generate_chapters(request)
+        })
+        .await
+    }
+
+    async fn detect_content_bounds(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<DetectContentBoundsRequest>,
+    ) -> HandlerResult<Json<DetectContentBoundsResponse>> {
+        let _permit = self.admit(&ctx, "detect_content_bounds").await?;
+
+        self.execute(&mut ctx, "detect_content_bounds", request, |request| {
+            self._detect_content_bounds(request)
+        })
+        .await
+    }
+
+    async fn export_usage(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ExportUsageRequest>,
+    ) -> HandlerResult<Json<ExportUsageResponse>> {
+        let _permit = self.admit(&ctx, "export_usage").await?;
+
+        let request = request.into_inner();
+
+        if request.from >= request.to {
+            return Err(TerminalError::new_with_code(400, "from must be before to").into());
+        }
+
+        let window = UsageWindow {
+            from: request.from,
+            to: request.to,
+        };
+
+        let mut usage = Vec::with_capacity(request.tenants.len());
+
+        for tenant in &request.tenants {
+            usage.push(
+                ctx.object_client::<MeteringClient>(tenant)
+                    .usage(Json(window.clone()))
+                    .call()
+                    .await?
+                    .into_inner(),
+            );
+        }
+
+        self.execute(&mut ctx, "export_usage", Json(request), |request| {
+            self._export_usage(request, usage)
+        })
+        .await
+    }
+
+    async fn analyze_frames(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<AnalyzeFramesRequest>,
+    ) -> HandlerResult<Json<AnalyzeFramesResponse>> {
+        let _permit = self.admit(&ctx, "analyze_frames").await?;
+
+        self.execute(&mut ctx, "analyze_frames", request, |request| {
+            self._analyze_frames(request)
+        })
+        .await
+    }
+
+    async fn analyze_ts(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<AnalyzeTsRequest>,
+    ) -> HandlerResult<Json<AnalyzeTsResponse>> {
+        let _permit = self.admit(&ctx, "analyze_ts").await?;
+
+        self.execute(&mut ctx, "analyze_ts", request, |request| {
+            self._analyze_ts(request)
+        })
+        .await
+    }
+
+    async fn probe_rtsp(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ProbeRtspRequest>,
+    ) -> HandlerResult<Json<FfprobeResponse>> {
+        let _permit = self.admit(&ctx, "probe_rtsp").await?;
+
+        self.execute(&mut ctx, "probe_rtsp", request, |request| {
+            self._probe_rtsp(request)
+        })
+        .await
+    }
+
+    async fn capture(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<CaptureRequest>,
+    ) -> HandlerResult<Json<CaptureResponse>> {
+        let _permit = self.admit(&ctx, "capture").await?;
+
+        self.execute(&mut ctx, "capture", request, |request| {
+            self._capture(request)
+        })
+        .await
+    }
+
+    async fn record_stream(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<RecordStreamRequest>,
+    ) -> HandlerResult<Json<RecordStreamResponse>> {
+        let _permit = self.admit(&ctx, "record_stream").await?;
+
+        self.execute(&mut ctx, "record_stream", request, |request| {
+            self._record_stream(request)
+        })
+        .await
+    }
+
+    async fn process_prefix(
+        &self,
+        ctx: Context<'_>,
+        request: Json<ProcessPrefixRequest>,
+    ) -> HandlerResult<Json<ProcessPrefixResponse>> {
+        self.check_enabled("process_prefix")?;
+
+        // Not admitted: holding a job slot while waiting for the files would starve them
+        Ok(Json(
+            self._process_prefix(&ctx, request.into_inner()).await?,
+        ))
+    }
+
+    async fn encode_segmented(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<EncodeSegmentedRequest>,
+    ) -> HandlerResult<Json<EncodeSegmentedResponse>> {
+        let _permit = self.admit(&ctx, "encode_segmented").await?;
+
+        Ok(Json(
+            self._encode_segmented(&mut ctx, request.into_inner())
+                .await?,
+        ))
+    }
+
+    async fn load(&self, _ctx: Context<'_>) -> HandlerResult<Json<LoadReport>> {
+        self.check_enabled("load")?;
+
+        Ok(Json(self.load_report()))
+    }
+
+    async fn explain(
+        &self,
+        _ctx: Context<'_>,
+        request: Json<FfmpegRequest>,
+    ) -> HandlerResult<Json<FfmpegPlan>> {
+        self.check_enabled("explain")?;
+
+        let mut request = request.into_inner();
+
+        self.env.check(&request.env)?;
+        request.validate_routes()?;
+
+        // Nothing is downloaded: placeholders resolve to the storage URLs of the inputs
+        let locations = inputs::locations(&request.inputs)?;
+        self.resolve(&mut request, &locations)?;
+
+        Ok(Json(request.plan(self.flags.flags(&request.args))))
+    }
+
+    async fn transcode(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<TranscodeRequest>,
+    ) -> HandlerResult<Json<TranscodeResponse>> {
+        let _permit = self.admit(&ctx, "transcode").await?;
+
+        Ok(Json(self._transcode(&mut ctx, request.into_inner()).await?))
+    }
+
+    async fn pause_intake(&self, _ctx: Context<'_>) -> HandlerResult<Json<IntakeStatus>> {
+        self.check_enabled("pause_intake")?;
+
+        Ok(Json(self._pause_intake()))
+    }
+
+    async fn resume_intake(&self, _ctx: Context<'_>) -> HandlerResult<Json<IntakeStatus>> {
+        self.check_enabled("resume_intake")?;
+
+        Ok(Json(self._resume_intake()))
+    }
+
+    async fn in_flight_jobs(&self, _ctx: Context<'_>) -> HandlerResult<Json<IntakeStatus>> {
+        self.check_enabled("in_flight_jobs")?;
+
+        Ok(Json(self._in_flight_jobs()))
+    }
+
+    async fn diagnose(
+        &self,
+        _ctx: Context<'_>,
+        request: Json<DiagnoseRequest>,
+    ) -> HandlerResult<Json<DiagnoseResponse>> {
+        self.check_enabled("diagnose")?;
+
+        Ok(Json(self._diagnose(request.into_inner())))
+    }
+
+    async fn progress(
+        &self,
+        _ctx: Context<'_>,
+        request: Json<ProgressRequest>,
+    ) -> HandlerResult<Json<ProgressResponse>> {
+        self.check_enabled("progress")?;
+
+        Ok(Json(self._progress(request.into_inner())))
+    }
+
+    async fn mosaic(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<MosaicRequest>,
+    ) -> HandlerResult<Json<MosaicResponse>> {
+        let _permit = self.admit(&ctx, "mosaic").await?;
+
+        self.execute(&mut ctx, "mosaic", request, |request| self._mosaic(request))
+            .await
+    }
+
+    async fn pip(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<PipRequest>,
+    ) -> HandlerResult<Json<PipResponse>> {
+        let _permit = self.admit(&ctx, "pip").await?;
+
+        self.execute(&mut ctx, "pip", request, |request| self._pip(request))
+            .await
+    }
+
+    async fn duck_audio(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<DuckAudioRequest>,
+    ) -> HandlerResult<Json<DuckAudioResponse>> {
+        let _permit = self.admit(&ctx, "duck_audio").await?;
+
+        self.execute(&mut ctx, "duck_audio", request, |request| {
+            self._duck_audio(request)
+        })
+        .await
+    }
+
+    async fn concat(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ConcatRequest>,
+    ) -> HandlerResult<Json<ConcatResponse>> {
+        let _permit = self.admit(&ctx, "concat").await?;
+
+        self.execute(&mut ctx, "concat", request, |request| self._concat(request))
+            .await
+    }
+
+    async fn reverse(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ReverseRequest>,
+    ) -> HandlerResult<Json<ReverseResponse>> {
+        let _permit = self.admit(&ctx, "reverse").await?;
+
+        self.execute(&mut ctx, "reverse", request, |request| {
+            self._reverse(request)
+        })
+        .await
+    }
+
+    async fn editorial_snapshot(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<EditorialSnapshotRequest>,
+    ) -> HandlerResult<Json<EditorialSnapshotResponse>> {
+        let _permit = self.admit(&ctx, "editorial_snapshot").await?;
+
+        self.execute(&mut ctx, "editorial_snapshot", request, |request| {
+            self._editorial_snapshot(request)
+        })
+        .await
+    }
+
+    async fn render_cutlist(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<RenderCutlistRequest>,
+    ) -> HandlerResult<Json<RenderCutlistResponse>> {
+        let _permit = self.admit(&ctx, "render_cutlist").await?;
+
+        self.execute(&mut ctx, "render_cutlist", request, |request| {
+            self._render_cutlist(request)
+        })
+        .await
+    }
+
+    async fn audio_hls(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<AudioHlsRequest>,
+    ) -> HandlerResult<Json<AudioHlsResponse>> {
+        let _permit = self.admit(&ctx, "audio_hls").await?;
+
+        self.execute(&mut ctx, "audio_hls", request, |request| {
+            self._audio_hls(request)
+        })
+        .await
+    }
+
+    async fn hls(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<HlsRequest>,
+    ) -> HandlerResult<Json<HlsResponse>> {
+        let request = request.into_inner();
+
+        if request.parallel {
+            self.check_enabled("hls")?;
+
+            // Not admitted: holding a job slot while waiting for the renditions would starve them
+            return Ok(Json(self._hls_parallel(&mut ctx, request).await?));
+        }
+
+        let _permit = self.admit(&ctx, "hls").await?;
+
+        self.execute(&mut ctx, "hls", Json(request), |request| self._hls(request))
+            .await
+    }
+
+    async fn hls_rendition(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<HlsRenditionRequest>,
+    ) -> HandlerResult<Json<HlsRenditionResponse>> {
+        let _permit = self.admit(&ctx, "hls_rendition").await?;
+
+        self.execute(&mut ctx, "hls_rendition", request, |request| {
+            self._hls_rendition(request)
+        })
+        .await
+    }
+
+    async fn dash(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<DashRequest>,
+    ) -> HandlerResult<Json<DashResponse>> {
+        let _permit = self.admit(&ctx, "dash").await?;
+
+        self.execute(&mut ctx, "dash", request, |request| self._dash(request))
+            .await
+    }
+
+    async fn thumbnail(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ThumbnailRequest>,
+    ) -> HandlerResult<Json<ThumbnailResponse>> {
+        let _permit = self.admit(&ctx, "thumbnail").await?;
+
+        self.execute(&mut ctx, "thumbnail", request, |request| {
+            self._thumbnail(request)
+        })
+        .await
+    }
+
+    async fn sprites(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<SpritesRequest>,
+    ) -> HandlerResult<Json<SpritesResponse>> {
+        let _permit = self.admit(&ctx, "sprites").await?;
+
+        self.execute(&mut ctx, "sprites", request, |request| {
+            self._sprites(request)
+        })
+        .await
+    }
+
+    async fn preview(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<PreviewRequest>,
+    ) -> HandlerResult<Json<PreviewResponse>> {
+        let _permit = self.admit(&ctx, "preview").await?;
+
+        self.execute(&mut ctx, "preview", request, |request| {
+            self._preview(request)
+        })
+        .await
+    }
+
+    async fn benchmark_decode(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<BenchmarkDecodeRequest>,
+    ) -> HandlerResult<Json<BenchmarkDecodeResponse>> {
+        let _permit = self.admit(&ctx, "benchmark_decode").await?;
+
+        self.execute(&mut ctx, "benchmark_decode", request, |request| {
+            self._benchmark_decode(request)
+        })
+        .await
+    }
+
+    async fn benchmark_encode(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<BenchmarkEncodeRequest>,
+    ) -> HandlerResult<Json<BenchmarkEncodeResponse>> {
+        let _permit = self.admit(&ctx, "benchmark_encode").await?;
+
+        self.execute(&mut ctx, "benchmark_encode", request, |request| {
+            self._benchmark_encode(request)
+        })
+        .await
+    }
+
+    async fn repair(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<RepairRequest>,
+    ) -> HandlerResult<Json<RepairResponse>> {
+        let _permit = self.admit(&ctx, "repair").await?;
+
+        self.execute(&mut ctx, "repair", request, |request| self._repair(request))
+            .await
+    }
+
+    async fn normalize_timestamps(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<NormalizeTimestampsRequest>,
+    ) -> HandlerResult<Json<NormalizeTimestampsResponse>> {
+        let _permit = self.admit(&ctx, "normalize_timestamps").await?;
+
+        self.execute(&mut ctx, "normalize_timestamps", request, |request| {
+            self._normalize_timestamps(request)
+        })
+        .await
+    }
+
+    async fn clip(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<ClipRequest>,
+    ) -> HandlerResult<Json<ClipResponse>> {
+        let _permit = self.admit(&ctx, "clip").await?;
+
+        self.execute(&mut ctx, "clip", request, |request| self._clip(request))
+            .await
+    }
+
+    async fn hash_streams(
+        &self,
+        mut ctx: Context<'_>,
+        request: Json<HashStreamsRequest>,
+    ) -> HandlerResult<Json<HashStreamsResponse>> {
+        let _permit = self.admit(&ctx, "hash_streams").await?;
+
+        self.execute(&mut ctx, "hash_streams", request, |request| {
+            self._hash_streams(request)
+        })
+        .await
     }
 }
@@ -0,0 +1,412 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::encode::VideoEncoding;
+use crate::service::{Output, ServiceImpl, input_stem, run_ffmpeg_in};
+
+/// UUID of the Spherical Video V1 box (as written by Google's spatial-media tool).
+const SPHERICAL_UUID: [u8; 16] = [
+    0xff, 0xcc, 0x82, 0x63, 0xf8, 0x55, 0x4a, 0x93, 0x88, 0x14, 0x58, 0x7a, 0x02, 0x52, 0x1f, 0xdd,
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SphericalProjection {
+    #[default]
+    Equirectangular,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum StereoMode {
+    #[default]
+    Mono,
+    TopBottom,
+    LeftRight,
+}
+
+/// Spherical metadata injected into the output.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SphericalMetadata {
+    #[serde(default)]
+    pub projection: SphericalProjection,
+
+    #[serde(default)]
+    pub stereo_mode: StereoMode,
+}
+
+impl SphericalMetadata {
+    fn xml(&self) -> String {
+        let projection = match self.projection {
+            SphericalProjection::Equirectangular => "equirectangular",
+        };
+
+        let stereo = match self.stereo_mode {
+            StereoMode::Mono => String::new(),
+            StereoMode::TopBottom => {
+                "<GSpherical:StereoMode>top-bottom</GSpherical:StereoMode>".to_string()
+            }
+            StereoMode::LeftRight => {
+                "<GSpherical:StereoMode>left-right</GSpherical:StereoMode>".to_string()
+            }
+        };
+
+        format!(
+            "<?xml version=\"1.0\"?>\
+             <rdf:SphericalVideo xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\" \
+             xmlns:GSpherical=\"http://ns.google.com/videos/1.0/spherical/\">\
+             <GSpherical:Spherical>true</GSpherical:Spherical>\
+             <GSpherical:Stitched>true</GSpherical:Stitched>\
+             <GSpherical:StitchingSoftware>restate-ffmpeg</GSpherical:StitchingSoftware>\
+             <GSpherical:ProjectionType>{projection}</GSpherical:ProjectionType>\
+             {stereo}\
+             </rdf:SphericalVideo>"
+        )
+    }
+
+    fn uuid_box(&self) -> Vec<u8> {
+        let xml = self.xml();
+        let size = (8 + SPHERICAL_UUID.len() + xml.len()) as u32;
+
+        let mut data = Vec::with_capacity(size as usize);
+        data.extend(size.to_be_bytes());
+        data.extend(b"uuid");
+        data.extend(SPHERICAL_UUID);
+        data.extend(xml.as_bytes());
+
+        data
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_spherical_request())]
+pub struct SphericalRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Metadata to inject (existing spherical metadata is preserved when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inject: Option<SphericalMetadata>,
+
+    /// Video encoding settings (the video is copied when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video: Option<VideoEncoding>,
+}
+
+fn example_spherical_request() -> SphericalRequest {
+    SphericalRequest {
+        input: Url::parse("https://example.com/360.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/360/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        inject: Some(SphericalMetadata::default()),
+        video: None,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SphericalResponse {
+    /// Whether spherical metadata was injected (false when the output already carried it)
+    pub injected: bool,
+
+    /// Location of the output file
+    pub output: Url,
+}
+
+struct BoxHeader {
+    offset: u64,
+    size: u64,
+    header_size: u64,
+    kind: [u8; 4],
+}
+
+fn read_box_header(
+    reader: &mut (impl Read + Seek),
+    offset: u64,
+    end: u64,
+) -> io::Result<BoxHeader> {
+    reader.seek(SeekFrom::Start(offset))?;
+
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+
+    let kind = [header[4], header[5], header[6], header[7]];
+
+    let (size, header_size) = match u32::from_be_bytes([header[0], header[1], header[2], header[3]])
+    {
+        0 => (end - offset, 8),
+        1 => {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+
+            (u64::from_be_bytes(large), 16)
+        }
+        size => (size as u64, 8),
+    };
+
+    if size < header_size || offset + size > end {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid MP4 box size",
+        ));
+    }
+
+    Ok(BoxHeader {
+        offset,
+        size,
+        header_size,
+        kind,
+    })
+}
+
+/// Iterates over the child boxes in a buffer: (offset, size, type).
+fn children(data: &[u8]) -> Vec<(usize, usize, [u8; 4])> {
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+
+    while offset + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        let kind = data[offset + 4..offset + 8].try_into().unwrap();
+
+        if size < 8 || offset + size > data.len() {
+            break;
+        }
+
+        boxes.push((offset, size, kind));
+        offset += size;
+    }
+
+    boxes
+}
+
+fn find_child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    children(data)
+        .into_iter()
+        .find(|(_, _, k)| k == kind)
+        .map(|(offset, size, _)| &data[offset + 8..offset + size])
+}
+
+fn is_video_trak(trak: &[u8]) -> bool {
+    find_child(trak, b"mdia")
+        .and_then(|mdia| find_child(mdia, b"hdlr"))
+        .is_some_and(|hdlr| hdlr.len() >= 12 && &hdlr[8..12] == b"vide")
+}
+
+fn has_spherical_metadata(trak: &[u8]) -> bool {
+    children(trak).into_iter().any(|(offset, size, kind)| {
+        &kind == b"uuid" && size >= 24 && trak[offset + 8..offset + 24] == SPHERICAL_UUID
+    })
+}
+
+/// Adds `delta` to every chunk offset (stco/co64) of a trak.
+fn shift_chunk_offsets(trak: &mut [u8], delta: u64) {
+    let path: [&[u8; 4]; 3] = [b"mdia", b"minf", b"stbl"];
+
+    let mut start = 0;
+    let mut end = trak.len();
+
+    for kind in path {
+        let Some((offset, size, _)) = children(&trak[start..end])
+            .into_iter()
+            .find(|(_, _, k)| k == kind)
+        else {
+            return;
+        };
+
+        start += offset + 8;
+        end = start + size - 8;
+    }
+
+    for (offset, size, kind) in children(&trak[start..end]) {
+        let table = &mut trak[start + offset + 8..start + offset + size];
+
+        if table.len() < 8 {
+            continue;
+        }
+
+        let count = u32::from_be_bytes(table[4..8].try_into().unwrap()) as usize;
+
+        match &kind {
+            b"stco" => {
+                for entry in table[8..].chunks_exact_mut(4).take(count) {
+                    let value = u32::from_be_bytes(entry.try_into().unwrap()) as u64 + delta;
+                    entry.copy_from_slice(&(value as u32).to_be_bytes());
+                }
+            }
+            b"co64" => {
+                for entry in table[8..].chunks_exact_mut(8).take(count) {
+                    let value = u64::from_be_bytes(entry.try_into().unwrap()) + delta;
+                    entry.copy_from_slice(&value.to_be_bytes());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Injects Spherical Video V1 metadata into the video track of an MP4/MOV file.
+///
+/// Returns false when the file already carries spherical metadata.
+fn inject_spherical_metadata(path: &Path, metadata: &SphericalMetadata) -> io::Result<bool> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let end = reader.get_ref().metadata()?.len();
+
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+
+    while offset < end {
+        let header = read_box_header(&mut reader, offset, end)?;
+        offset += header.size;
+        boxes.push(header);
+    }
+
+    let moov = boxes
+        .iter()
+        .find(|b| &b.kind == b"moov")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no moov box found"))?;
+
+    if moov.header_size != 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "64-bit moov boxes are not supported",
+        ));
+    }
+
+    let mut content = vec![0u8; (moov.size - moov.header_size) as usize];
+    reader.seek(SeekFrom::Start(moov.offset + moov.header_size))?;
+    reader.read_exact(&mut content)?;
+
+    let Some((trak_offset, trak_size, _)) =
+        children(&content).into_iter().find(|(offset, size, kind)| {
+            kind == b"trak" && is_video_trak(&content[offset + 8..offset + size])
+        })
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no video track found",
+        ));
+    };
+
+    if has_spherical_metadata(&content[trak_offset + 8..trak_offset + trak_size]) {
+        return Ok(false);
+    }
+
+    let uuid = metadata.uuid_box();
+    let delta = uuid.len() as u64;
+
+    // Media data after the moov box (faststart) moves by the size of the new box
+    let mdat_after_moov = boxes
+        .iter()
+        .any(|b| &b.kind == b"mdat" && b.offset > moov.offset);
+
+    if mdat_after_moov {
+        for (offset, size, kind) in children(&content) {
+            if &kind == b"trak" {
+                shift_chunk_offsets(&mut content[offset + 8..offset + size], delta);
+            }
+        }
+    }
+
+    let trak_end = trak_offset + trak_size;
+    content.splice(trak_end..trak_end, uuid);
+    content[trak_offset..trak_offset + 4]
+        .copy_from_slice(&((trak_size as u64 + delta) as u32).to_be_bytes());
+
+    let tmp = path.with_extension("spherical");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+
+    for b in &boxes {
+        if b.offset == moov.offset {
+            writer.write_all(&((moov.size + delta) as u32).to_be_bytes())?;
+            writer.write_all(b"moov")?;
+            writer.write_all(&content)?;
+        } else {
+            reader.seek(SeekFrom::Start(b.offset))?;
+            io::copy(&mut (&mut reader).take(b.size), &mut writer)?;
+        }
+    }
+
+    writer.flush()?;
+    drop(writer);
+
+    std::fs::rename(tmp, path)?;
+
+    Ok(true)
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _spherical(
+        &self,
+        request: SphericalRequest,
+    ) -> HandlerResult<SphericalResponse> {
+        if let Some(video) = &request.video {
+            video.validate().await?;
+        }
+
+        // Separate from the work dir: everything in there is uploaded
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let work_dir = TempDir::new()?;
+
+        let filename = format!("{}.mp4", input_stem(&request.input));
+
+        let mut args = vec!["-i".to_string(), input, "-map".to_string(), "0".to_string()];
+
+        match &request.video {
+            Some(video) => args.extend(video.args()),
+            None => args.extend(["-c:v".to_string(), "copy".to_string()]),
+        }
+
+        // The mov muxer only writes spherical (sv3d/st3d) boxes in unofficial mode
+        args.extend([
+            "-c:a".to_string(),
+            "copy".to_string(),
+            "-strict".to_string(),
+            "unofficial".to_string(),
+            "-movflags".to_string(),
+            "+faststart".to_string(),
+            filename.clone(),
+        ]);
+
+        run_ffmpeg_in(work_dir.path(), &args).await?;
+
+        let injected = match request.inject {
+            Some(metadata) => {
+                let path = work_dir.path().join(&filename);
+
+                tokio::task::spawn_blocking(move || inject_spherical_metadata(&path, &metadata))
+                    .await?
+                    .map_err(|e| {
+                        TerminalError::new(format!("failed to inject spherical metadata: {e}"))
+                    })?
+            }
+            None => false,
+        };
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        Ok(SphericalResponse {
+            injected,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
@@ -0,0 +1,139 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::service::{Output, ServiceImpl, input_stem};
+use crate::storyboard::{Storyboard, ThumbnailTiles};
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_sprites_request())]
+pub struct SpritesRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Interval, size and layout of the thumbnails in the sprite images
+    #[serde(default)]
+    pub tiles: ThumbnailTiles,
+}
+
+fn example_sprites_request() -> SpritesRequest {
+    SpritesRequest {
+        input: Url::parse("s3://bucket/uploads/video.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/previews/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        tiles: ThumbnailTiles::default(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SpritesResponse {
+    /// Locations of the sprite images
+    pub sprites: Vec<Url>,
+
+    /// Location of the WebVTT file mapping time ranges to regions of the sprites
+    pub vtt: Url,
+
+    /// Number of thumbnails in the sprites
+    pub thumbnails: u32,
+}
+
+/// Formats seconds as a WebVTT timestamp.
+fn vtt_timestamp(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as u64;
+
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// WebVTT file whose cues point at the region of a sprite showing the thumbnail of their time range
+/// (the "#xywh" media fragment read by most players).
+fn thumbnails_vtt(storyboard: &Storyboard, tiles: &ThumbnailTiles, thumbnails: u32) -> String {
+    let (width, height) = storyboard.thumbnail;
+    let per_tile = tiles.columns * tiles.rows;
+
+    let mut vtt = "WEBVTT\n".to_string();
+
+    for i in 0..thumbnails {
+        let Some(sprite) = storyboard.tiles.get((i / per_tile) as usize) else {
+            break;
+        };
+
+        let position = i % per_tile;
+        let start = i as f64 * tiles.interval;
+        let end = (start + tiles.interval).min(storyboard.duration);
+
+        vtt.push_str(&format!(
+            "\n{} --> {}\n{sprite}#xywh={},{},{width},{height}\n",
+            vtt_timestamp(start),
+            vtt_timestamp(end),
+            position % tiles.columns * width,
+            position / tiles.columns * height,
+        ));
+    }
+
+    vtt
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Tiles thumbnails into sprite images, then writes the WebVTT track of their regions next to them.
+    pub(crate) async fn _sprites(&self, request: SpritesRequest) -> HandlerResult<SpritesResponse> {
+        request.tiles.validate()?;
+
+        let probe = self.probe(&request.input).await?;
+
+        let work_dir = TempDir::new()?;
+        let stem = input_stem(&request.input);
+
+        let storyboard = self
+            .render_storyboard(
+                &request.input,
+                &probe,
+                &request.tiles,
+                work_dir.path(),
+                &format!("{stem}_sprites"),
+            )
+            .await?;
+
+        let thumbnails = (storyboard.duration / request.tiles.interval)
+            .ceil()
+            .max(1.0) as u32;
+
+        let vtt_name = format!("{stem}_thumbnails.vtt");
+
+        tokio::fs::write(
+            work_dir.path().join(&vtt_name),
+            thumbnails_vtt(&storyboard, &request.tiles, thumbnails),
+        )
+        .await?;
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        Ok(SpritesResponse {
+            sprites: storyboard
+                .tiles
+                .iter()
+                .map(|tile| request.output.file_url(tile))
+                .collect(),
+            vtt: request.output.file_url(&vtt_name),
+            thumbnails,
+        })
+    }
+}
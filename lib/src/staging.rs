@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use futures::StreamExt;
+use md5::{Digest, Md5};
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use url::Url;
+
+use crate::metering;
+use crate::service::{ServiceImpl, input_extension, parse_uri};
+
+/// Protocols ffmpeg reads by itself, inputs with any other scheme are read from storage.
+const FFMPEG_PROTOCOLS: &[&str] = &[
+    "file", "http", "https", "ftp", "rtmp", "rtmps", "rtsp", "srt", "udp", "tcp", "data",
+];
+
+/// Returns whether an input has to be read through the storage operators.
+pub(crate) fn is_storage_input(input: &Url) -> bool {
+    !FFMPEG_PROTOCOLS.contains(&input.scheme())
+}
+
+/// Settings of input staging.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StagingConfig {
+    /// Directory keeping partial downloads across retries (defaults to the system temp dir)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+
+    /// Bytes read from the start of storage inputs when probing
+    #[serde(default = "default_probe_head_size")]
+    pub probe_head_size: u64,
+
+    /// Bytes read from the end of storage inputs when probing (e.g. an MP4 moov atom written last)
+    #[serde(default = "default_probe_tail_size")]
+    pub probe_tail_size: u64,
+
+    /// Size of the ranges large inputs are downloaded in
+    #[serde(default = "default_download_chunk_size")]
+    pub download_chunk_size: u64,
+
+    /// Number of ranges downloaded at the same time
+    #[serde(default = "default_download_concurrency")]
+    pub download_concurrency: usize,
+}
+
+fn default_probe_head_size() -> u64 {
+    4 << 20
+}
+
+fn default_probe_tail_size() -> u64 {
+    1 << 20
+}
+
+fn default_download_chunk_size() -> u64 {
+    16 << 20
+}
+
+fn default_download_concurrency() -> usize {
+    4
+}
+
+impl Default for StagingConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            probe_head_size: default_probe_head_size(),
+            probe_tail_size: default_probe_tail_size(),
+            download_chunk_size: default_download_chunk_size(),
+            download_concurrency: default_download_concurrency(),
+        }
+    }
+}
+
+/// Directory keeping partial downloads across retries.
+#[derive(Debug)]
+pub(crate) struct Staging {
+    dir: PathBuf,
+    config: StagingConfig,
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Default for Staging {
+    fn default() -> Self {
+        Self::new(StagingConfig::default())
+    }
+}
+
+impl Staging {
+    pub(crate) fn new(config: StagingConfig) -> Self {
+        Self {
+            dir: config
+                .dir
+                .clone()
+                .unwrap_or_else(|| std::env::temp_dir().join("restate-ffmpeg")),
+            config,
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the directory keeping the outputs of a job until they are uploaded.
+    pub(crate) fn job_dir(&self, id: &str) -> PathBuf {
+        self.dir.join("jobs").join(id)
+    }
+
+    /// Returns the space available in the staging dir (or the closest existing parent).
+    pub(crate) fn free_space(&self) -> Option<u64> {
+        self.dir
+            .ancestors()
+            .find_map(|dir| fs4::available_space(dir).ok())
+    }
+
+    /// Serializes downloads of the same input within the process, since they share the partial file.
+    fn lock(&self, key: &str) -> Arc<tokio::sync::Mutex<()>> {
+        self.locks
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+/// Returns a file name for an input that stays the same across restarts (FNV-1a of the URL).
+fn staging_key(input: &Url) -> String {
+    let hash = input
+        .as_str()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+
+    format!("{hash:016x}")
+}
+
+/// Expected checksum of an input (hex encoded).
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InputChecksum {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+}
+
+impl InputChecksum {
+    /// Verifies a staged input.
+    ///
+    /// A mismatch is a terminal error: the source itself is corrupt (or was tampered with),
+    /// downloading it again would not help.
+    async fn verify(&self, input: &Url, path: &Path) -> HandlerResult<()> {
+        if self.sha256.is_none() && self.md5.is_none() {
+            return Err(
+                TerminalError::new_with_code(400, "checksum requires sha256 or md5").into(),
+            );
+        }
+
+        let mut sha256 = Sha256::new();
+        let mut md5 = Md5::new();
+
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut buffer = vec![0; 1 << 20];
+
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            if self.sha256.is_some() {
+                sha256.update(&buffer[..read]);
+            }
+            if self.md5.is_some() {
+                md5.update(&buffer[..read]);
+            }
+        }
+
+        let checks = [
+            ("sha256", &self.sha256, format!("{:x}", sha256.finalize())),
+            ("md5", &self.md5, format!("{:x}", md5.finalize())),
+        ];
+
+        for (algorithm, expected, actual) in checks {
+            if let Some(expected) = expected
+                && !expected.eq_ignore_ascii_case(&actual)
+            {
+                return Err(TerminalError::new_with_code(
+                    422,
+                    format!("{algorithm} mismatch for {input}: expected {expected}, got {actual}"),
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Remote object a partial download belongs to.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+struct PartialDownload {
+    size: u64,
+    etag: Option<String>,
+}
+
+/// Returns the MD5 checksum of an object when the storage exposes it.
+///
+/// S3 and compatible stores use the MD5 as ETag for objects uploaded in a single part.
+fn remote_md5(etag: Option<&str>, content_md5: Option<&str>) -> Option<String> {
+    [content_md5, etag.map(|etag| etag.trim_matches('"'))]
+        .into_iter()
+        .flatten()
+        .find(|value| value.len() == 32 && value.bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(str::to_lowercase)
+}
+
+/// Feeds the content of a file into the hasher (used when resuming a partial download).
+async fn hash_file(path: &Path, hasher: &mut Md5) -> HandlerResult<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = vec![0; 1 << 20];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Downloads an input from storage into a local file.
+    ///
+    /// Data is written to a partial file in the staging dir first: when a download fails
+    /// half-way, the Restate retry continues where the previous attempt stopped,
+    /// as long as the remote object did not change in the meantime.
+    ///
+    /// The expected checksum (when given) is verified once the download is complete.
+    pub(crate) async fn download(
+        &self,
+        input: &Url,
+        destination: &Path,
+        expected: Option<&InputChecksum>,
+    ) -> HandlerResult<()> {
+        let (uri, path) = parse_uri(input.clone());
+
+        let operator = self.factory.load(uri.as_str())?;
+        let metadata = operator.stat(&path).await?;
+
+        let remote = PartialDownload {
+            size: metadata.content_length(),
+            etag: metadata.etag().map(str::to_string),
+        };
+        let checksum = remote_md5(metadata.etag(), metadata.content_md5());
+
+        let key = staging_key(input);
+        let lock = self.staging.lock(&key);
+        let _guard = lock.lock().await;
+
+        tokio::fs::create_dir_all(&self.staging.dir).await?;
+
+        let part = self.staging.dir.join(format!("{key}.part"));
+        let state = self.staging.dir.join(format!("{key}.json"));
+
+        let previous: Option<PartialDownload> = tokio::fs::read(&state)
+            .await
+            .ok()
+            .and_then(|state| serde_json::from_slice(&state).ok());
+
+        let mut offset = match tokio::fs::metadata(&part).await {
+            Ok(part) if previous.as_ref() == Some(&remote) && part.len() <= remote.size => {
+                part.len()
+            }
+            _ => 0,
+        };
+
+        if offset == 0 {
+            tokio::fs::write(&state, serde_json::to_vec(&remote)?).await?;
+        }
+
+        let mut hasher = Md5::new();
+        if checksum.is_some() && offset > 0 {
+            hash_file(&part, &mut hasher).await?;
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(offset > 0)
+            .write(true)
+            .truncate(offset == 0)
+            .open(&part)
+            .await?;
+
+        let config = &self.staging.config;
+        let chunk_size = config.download_chunk_size.max(1);
+
+        // Chunks are fetched in parallel, but written in order: the partial file stays contiguous
+        // and can be resumed, while memory use is bounded by concurrency * chunk size
+        let mut chunks = futures::stream::iter((offset..remote.size).step_by(chunk_size as usize))
+            .map(|start| {
+                let range = start..(start + chunk_size).min(remote.size);
+
+                let (operator, path) = (&operator, &path);
+
+                async move { operator.read_with(path).range(range).await }
+            })
+            .buffered(config.download_concurrency.max(1));
+
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?.to_bytes();
+
+            file.write_all(&chunk).await?;
+            if checksum.is_some() {
+                hasher.update(&chunk);
+            }
+
+            offset += chunk.len() as u64;
+            metering::record(|usage| usage.bytes_in += chunk.len() as u64);
+        }
+
+        file.flush().await?;
+        drop(file);
+
+        let verified = if offset != remote.size {
+            Err(format!(
+                "downloaded {offset} bytes of {input}, expected {}",
+                remote.size
+            ))
+        } else if let Some(expected) = checksum
+            && format!("{:x}", hasher.finalize()) != expected
+        {
+            Err(format!("checksum mismatch downloading {input}"))
+        } else {
+            Ok(())
+        };
+
+        if let Err(err) = verified {
+            // Corrupt data can't be resumed, start over on the next attempt
+            let _ = tokio::fs::remove_file(&part).await;
+            let _ = tokio::fs::remove_file(&state).await;
+
+            return Err(HandlerError::from(err));
+        }
+
+        if tokio::fs::rename(&part, destination).await.is_err() {
+            // Staging and work dirs may live on different file systems
+            tokio::fs::copy(&part, destination).await?;
+            tokio::fs::remove_file(&part).await?;
+        }
+
+        let _ = tokio::fs::remove_file(&state).await;
+
+        if let Some(expected) = expected {
+            expected.verify(input, destination).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Stages a storage input for probing and returns the path of the local copy.
+    ///
+    /// Only the head and the tail of large objects are downloaded: the local copy is a sparse file
+    /// of the original size, so that demuxers find indexes at the end and report the right bitrate.
+    pub(crate) async fn stage_probe(
+        &self,
+        input: &Url,
+        staging_dir: &Path,
+    ) -> HandlerResult<PathBuf> {
+        let filename = match input_extension(input) {
+            Some(extension) => format!("probe.{extension}"),
+            None => "probe".to_string(),
+        };
+
+        let destination = staging_dir.join(filename);
+
+        let (uri, path) = parse_uri(input.clone());
+
+        let operator = self.factory.load(uri.as_str())?;
+        let size = operator.stat(&path).await?.content_length();
+
+        let config = &self.staging.config;
+        let head = config.probe_head_size;
+        let tail = config.probe_tail_size;
+
+        if size <= head + tail {
+            self.download(input, &destination, None).await?;
+
+            return Ok(destination);
+        }
+
+        let mut file = tokio::fs::File::create(&destination).await?;
+        file.set_len(size).await?;
+
+        for range in [0..head, size - tail..size] {
+            let buffer = operator.read_with(&path).range(range.clone()).await?;
+
+            file.seek(std::io::SeekFrom::Start(range.start)).await?;
+            file.write_all(&buffer.to_bytes()).await?;
+
+            metering::record(|usage| usage.bytes_in += buffer.len() as u64);
+        }
+
+        file.flush().await?;
+
+        Ok(destination)
+    }
+
+    /// Returns what ffprobe reads an input from: storage inputs are staged for probing.
+    pub(crate) async fn probe_input(
+        &self,
+        input: &Url,
+        staging_dir: &Path,
+    ) -> HandlerResult<String> {
+        if !is_storage_input(input) {
+            return Ok(input.to_string());
+        }
+
+        Ok(self
+            .stage_probe(input, staging_dir)
+            .await?
+            .to_string_lossy()
+            .to_string())
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::Path;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::service::{FfprobeResponse, ServiceImpl, run_ffmpeg_in, work_files};
+
+/// Largest number of thumbnails in a storyboard.
+const MAX_THUMBNAILS: f64 = 10_000.0;
+
+/// Thumbnails of a video tiled into images.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ThumbnailTiles {
+    /// Seconds between two thumbnails
+    #[serde(default = "default_thumbnail_interval")]
+    pub interval: f64,
+
+    /// Width of a thumbnail in pixels (the height follows the aspect ratio of the input)
+    #[serde(default = "default_thumbnail_width")]
+    pub width: u32,
+
+    /// Thumbnails in a row of a tile
+    #[serde(default = "default_tile_size")]
+    pub columns: u32,
+
+    /// Rows of thumbnails in a tile
+    #[serde(default = "default_tile_size")]
+    pub rows: u32,
+}
+
+fn default_thumbnail_interval() -> f64 {
+    5.0
+}
+
+fn default_thumbnail_width() -> u32 {
+    160
+}
+
+fn default_tile_size() -> u32 {
+    5
+}
+
+impl Default for ThumbnailTiles {
+    fn default() -> Self {
+        Self {
+            interval: default_thumbnail_interval(),
+            width: default_thumbnail_width(),
+            columns: default_tile_size(),
+            rows: default_tile_size(),
+        }
+    }
+}
+
+impl ThumbnailTiles {
+    pub(crate) fn validate(&self) -> Result<(), TerminalError> {
+        let invalid = |message: &str| Err(TerminalError::new_with_code(400, message));
+
+        if self.interval <= 0.0 {
+            return invalid("thumbnail interval must be positive");
+        }
+
+        if self.width < 2 || !self.width.is_multiple_of(2) {
+            return invalid("thumbnail width must be even");
+        }
+
+        if !(1..=20).contains(&self.columns) || !(1..=20).contains(&self.rows) {
+            return invalid("tiles take between 1 and 20 columns and rows");
+        }
+
+        Ok(())
+    }
+
+    /// Seconds covered by a tile.
+    pub(crate) fn tile_duration(&self) -> f64 {
+        self.interval * (self.columns * self.rows) as f64
+    }
+}
+
+/// Tiles rendered from a video.
+pub(crate) struct Storyboard {
+    /// Paths of the tiles relative to the directory they were rendered in
+    pub tiles: Vec<String>,
+
+    /// Size of a thumbnail in pixels
+    pub thumbnail: (u32, u32),
+
+    /// Duration of the video in seconds
+    pub duration: f64,
+
+    /// Size of the tiles in bytes
+    pub bytes: u64,
+}
+
+impl Storyboard {
+    /// Size of a tile in pixels.
+    pub(crate) fn tile_size(&self, tiles: &ThumbnailTiles) -> (u32, u32) {
+        (
+            self.thumbnail.0 * tiles.columns,
+            self.thumbnail.1 * tiles.rows,
+        )
+    }
+
+    /// Average bitrate of the tiles in bits per second.
+    pub(crate) fn bandwidth(&self) -> u64 {
+        (self.bytes as f64 * 8.0 / self.duration.max(1.0)).ceil() as u64
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Renders the thumbnail tiles of the input as `{prefix}/tile_00001.jpg`, ... in a directory.
+    pub(crate) async fn render_storyboard(
+        &self,
+        input: &Url,
+        probe: &FfprobeResponse,
+        tiles: &ThumbnailTiles,
+        dir: &Path,
+        prefix: &str,
+    ) -> HandlerResult<Storyboard> {
+        let video = probe
+            .stream("video")
+            .ok_or_else(|| TerminalError::new_with_code(400, "input has no video stream"))?;
+
+        let (width, height) = video
+            .width
+            .zip(video.height)
+            .filter(|(width, height)| *width > 0 && *height > 0)
+            .ok_or_else(|| TerminalError::new_with_code(400, "unknown input dimensions"))?;
+
+        let duration = probe
+            .duration()
+            .ok_or_else(|| TerminalError::new_with_code(400, "unknown input duration"))?;
+
+        if duration / tiles.interval > MAX_THUMBNAILS {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("thumbnail interval is too short for a {duration:.0}s input"),
+            )
+            .into());
+        }
+
+        // The height is set explicitly, so that the tile size is known without probing the tiles
+        let thumbnail_height =
+            ((tiles.width as f64 * height as f64 / width as f64 / 2.0).round() as u32).max(1) * 2;
+
+        tokio::fs::create_dir_all(dir.join(prefix)).await?;
+
+        // Kept out of the tiles dir, which is uploaded as a whole
+        let staging_dir = TempDir::new()?;
+
+        run_ffmpeg_in(
+            dir,
+            &[
+                "-i".to_string(),
+                self.local_input(input, staging_dir.path()).await?,
+                "-map".to_string(),
+                "0:v:0".to_string(),
+                "-vf".to_string(),
+                format!(
+                    "fps=1/{},scale={}:{thumbnail_height},setsar=1,tile={}x{}",
+                    tiles.interval, tiles.width, tiles.columns, tiles.rows
+                ),
+                "-q:v".to_string(),
+                "4".to_string(),
+                format!("{prefix}/tile_%05d.jpg"),
+            ],
+        )
+        .await?;
+
+        let files: Vec<String> = work_files(&dir.join(prefix))
+            .into_iter()
+            .map(|file| format!("{prefix}/{file}"))
+            .collect();
+        let mut bytes = 0;
+
+        for file in &files {
+            bytes += tokio::fs::metadata(dir.join(file)).await?.len();
+        }
+
+        Ok(Storyboard {
+            tiles: files,
+            thumbnail: (tiles.width, thumbnail_height),
+            duration,
+            bytes,
+        })
+    }
+}
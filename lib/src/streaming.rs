@@ -0,0 +1,112 @@
+use std::time::Instant;
+
+use opendal::Operator;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Tuning of the upload of outputs ffmpeg writes to stdout.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StreamingConfig {
+    /// Bytes read from stdout before they are handed to the writer
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+
+    /// Size of the chunks (e.g. multipart upload parts) written to the destination
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+
+    /// Number of chunks written to the destination at the same time
+    #[serde(default = "default_concurrent")]
+    pub concurrent: usize,
+}
+
+fn default_buffer_size() -> usize {
+    1024 * 1024
+}
+
+fn default_chunk_size() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_concurrent() -> usize {
+    4
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            buffer_size: default_buffer_size(),
+            chunk_size: default_chunk_size(),
+            concurrent: default_concurrent(),
+        }
+    }
+}
+
+/// Throughput of an output streamed from stdout.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamStats {
+    /// Bytes written to the destination
+    pub bytes: u64,
+
+    /// Number of buffers handed to the writer
+    pub writes: u64,
+
+    /// Time from the first read to closing the writer in milliseconds
+    pub duration_ms: u64,
+
+    /// Average throughput in bytes per second
+    pub bytes_per_second: u64,
+}
+
+impl StreamingConfig {
+    /// Streams a reader (ffmpeg's stdout) to a path of an operator.
+    pub(crate) async fn stream(
+        &self,
+        mut reader: impl AsyncRead + Unpin,
+        operator: &Operator,
+        path: &str,
+    ) -> std::io::Result<StreamStats> {
+        let buffer_size = self.buffer_size.max(1);
+
+        let mut writer = operator
+            .writer_with(path)
+            .chunk(self.chunk_size.max(buffer_size))
+            .concurrent(self.concurrent.max(1))
+            .await?;
+
+        let started = Instant::now();
+        let mut bytes = 0;
+        let mut writes = 0;
+
+        loop {
+            // Fill the buffer (unless stdout ends) instead of writing every pipe read
+            let mut buffer = Vec::with_capacity(buffer_size);
+            (&mut reader)
+                .take(buffer_size as u64)
+                .read_to_end(&mut buffer)
+                .await?;
+
+            if buffer.is_empty() {
+                break;
+            }
+
+            bytes += buffer.len() as u64;
+            writes += 1;
+
+            writer.write(buffer).await?;
+        }
+
+        writer.close().await?;
+
+        let elapsed = started.elapsed();
+
+        Ok(StreamStats {
+            bytes,
+            writes,
+            duration_ms: elapsed.as_millis() as u64,
+            bytes_per_second: (bytes as f64 / elapsed.as_secs_f64().max(0.001)) as u64,
+        })
+    }
+}
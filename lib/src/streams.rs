@@ -0,0 +1,806 @@
+use std::collections::BTreeMap;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::encode::default_container;
+use crate::inputs::input_arg;
+use crate::service::{
+    FfmpegRequest, FfprobeResponse, Output, ServiceImpl, Stream, input_extension, input_stem,
+};
+
+/// Type of a media stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamType {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+    Attachment,
+}
+
+impl StreamType {
+    /// Stream specifier letter used by ffmpeg.
+    fn specifier(&self) -> &'static str {
+        match self {
+            StreamType::Video => "v",
+            StreamType::Audio => "a",
+            StreamType::Subtitle => "s",
+            StreamType::Data => "d",
+            StreamType::Attachment => "t",
+        }
+    }
+
+    fn from_codec_type(codec_type: &str) -> Option<Self> {
+        match codec_type {
+            "video" => Some(StreamType::Video),
+            "audio" => Some(StreamType::Audio),
+            "subtitle" => Some(StreamType::Subtitle),
+            "data" => Some(StreamType::Data),
+            "attachment" => Some(StreamType::Attachment),
+            _ => None,
+        }
+    }
+
+    /// Codec type reported by ffprobe.
+    fn codec_type(&self) -> &'static str {
+        match self {
+            StreamType::Video => "video",
+            StreamType::Audio => "audio",
+            StreamType::Subtitle => "subtitle",
+            StreamType::Data => "data",
+            StreamType::Attachment => "attachment",
+        }
+    }
+}
+
+/// Selects a single stream by its type and its index among the streams of that type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamSelector {
+    #[serde(rename = "type")]
+    pub kind: StreamType,
+
+    pub index: u32,
+}
+
+impl StreamSelector {
+    /// Returns the ffmpeg stream specifier (e.g. "a:1").
+    pub fn specifier(&self) -> String {
+        format!("{}:{}", self.kind.specifier(), self.index)
+    }
+
+    /// Fails with a terminal error when the input has no such stream.
+    pub(crate) fn validate(&self, probe: &FfprobeResponse) -> Result<(), TerminalError> {
+        let count = probe
+            .streams
+            .iter()
+            .flatten()
+            .filter(|stream| stream.codec_type == self.kind.codec_type())
+            .count();
+
+        if self.index as usize >= count {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("input has no {} stream", self.specifier()),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the streams of a probed input along with their selectors.
+pub(crate) fn typed_streams(probe: &FfprobeResponse) -> Vec<(StreamSelector, &Stream)> {
+    let mut counts: BTreeMap<&str, u32> = BTreeMap::new();
+
+    probe
+        .streams
+        .iter()
+        .flatten()
+        .filter_map(|stream| {
+            let kind = StreamType::from_codec_type(&stream.codec_type)?;
+            let index = counts.entry(kind.specifier()).or_default();
+            let selector = StreamSelector {
+                kind,
+                index: *index,
+            };
+            *index += 1;
+
+            Some((selector, stream))
+        })
+        .collect()
+}
+
+/// Container of a stream copy output, defaulting to the container of the input.
+fn copy_container(input: &Url, container: &Option<String>) -> String {
+    container
+        .clone()
+        .or_else(|| input_extension(input))
+        .unwrap_or_else(default_container)
+}
+
+/// Bitstream filters supported by the typed handlers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BitstreamFilterName {
+    /// Convert H.264 from length-prefixed (MP4) to Annex B (MPEG-TS) framing
+    H264Mp4toannexb,
+    /// Convert HEVC from length-prefixed (MP4) to Annex B (MPEG-TS) framing
+    HevcMp4toannexb,
+    /// Move codec parameters from the bitstream into the extradata
+    ExtractExtradata,
+    /// Copy codec parameters into every keyframe
+    DumpExtra,
+    /// Remove codec parameters from packets
+    RemoveExtra,
+    /// Convert AAC from ADTS (MPEG-TS) to MPEG-4 Audio Specific Config framing
+    AacAdtstoasc,
+    /// Rewrite H.264 headers (e.g. color description, level)
+    H264Metadata,
+    /// Rewrite HEVC headers (e.g. color description, level)
+    HevcMetadata,
+    /// Rewrite AV1 headers (e.g. color description)
+    Av1Metadata,
+    /// Rewrite packet timestamps
+    Setts,
+}
+
+impl BitstreamFilterName {
+    fn name(&self) -> &'static str {
+        match self {
+            BitstreamFilterName::H264Mp4toannexb => "h264_mp4toannexb",
+            BitstreamFilterName::HevcMp4toannexb => "hevc_mp4toannexb",
+            BitstreamFilterName::ExtractExtradata => "extract_extradata",
+            BitstreamFilterName::DumpExtra => "dump_extra",
+            BitstreamFilterName::RemoveExtra => "remove_extra",
+            BitstreamFilterName::AacAdtstoasc => "aac_adtstoasc",
+            BitstreamFilterName::H264Metadata => "h264_metadata",
+            BitstreamFilterName::HevcMetadata => "hevc_metadata",
+            BitstreamFilterName::Av1Metadata => "av1_metadata",
+            BitstreamFilterName::Setts => "setts",
+        }
+    }
+}
+
+/// A bitstream filter with its options.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BitstreamFilter {
+    pub name: BitstreamFilterName,
+
+    /// Filter options (e.g. {"colour_primaries": "9"} for hevc_metadata)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub options: BTreeMap<String, String>,
+}
+
+impl BitstreamFilter {
+    fn render(&self) -> Result<String, TerminalError> {
+        let mut filter = self.name.name().to_string();
+
+        for (i, (key, value)) in self.options.iter().enumerate() {
+            // Option values are not escaped, reject anything that would break the filter chain
+            if [key, value]
+                .iter()
+                .any(|part| part.is_empty() || part.contains([',', ':', '=', '\\', '\'']))
+            {
+                return Err(TerminalError::new_with_code(
+                    400,
+                    format!("invalid {} option: {key}={value}", self.name.name()),
+                ));
+            }
+
+            filter.push(if i == 0 { '=' } else { ':' });
+            filter.push_str(&format!("{key}={value}"));
+        }
+
+        Ok(filter)
+    }
+}
+
+/// Bitstream filters applied to the output streams of a type.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamBitstreamFilters {
+    #[serde(rename = "type")]
+    pub kind: StreamType,
+
+    /// Index of the output stream among the streams of the type (every stream of the type when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+
+    /// Filters applied in order
+    pub filters: Vec<BitstreamFilter>,
+}
+
+/// Returns the ffmpeg output arguments applying bitstream filters.
+pub(crate) fn bitstream_filter_args(
+    filters: &[StreamBitstreamFilters],
+) -> Result<Vec<String>, TerminalError> {
+    let mut args = Vec::new();
+
+    for stream in filters.iter().filter(|stream| !stream.filters.is_empty()) {
+        let specifier = match stream.index {
+            Some(index) => format!("{}:{index}", stream.kind.specifier()),
+            None => stream.kind.specifier().to_string(),
+        };
+
+        let chain = stream
+            .filters
+            .iter()
+            .map(BitstreamFilter::render)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        args.extend([format!("-bsf:{specifier}"), chain.join(",")]);
+    }
+
+    Ok(args)
+}
+
+/// Metadata changes applied to a single stream.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamTags {
+    #[serde(flatten)]
+    pub stream: StreamSelector,
+
+    /// ISO 639-2 language code (e.g. "eng")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    /// Human readable stream title
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    /// Tags removed from the stream (e.g. "handler_name")
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub clear: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_tag_streams_request())]
+pub struct TagStreamsRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    pub streams: Vec<StreamTags>,
+
+    /// Bitstream filters applied to the copied streams
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bitstream_filters: Vec<StreamBitstreamFilters>,
+
+    /// Output container extension (defaults to the extension of the input)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+}
+
+fn example_tag_streams_request() -> TagStreamsRequest {
+    TagStreamsRequest {
+        input: Url::parse("https://example.com/movie.mkv").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/tagged/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        streams: vec![
+            StreamTags {
+                stream: StreamSelector {
+                    kind: StreamType::Audio,
+                    index: 0,
+                },
+                language: Some("eng".to_string()),
+                title: Some("English 5.1".to_string()),
+                clear: vec!["handler_name".to_string()],
+            },
+            StreamTags {
+                stream: StreamSelector {
+                    kind: StreamType::Subtitle,
+                    index: 0,
+                },
+                language: Some("hun".to_string()),
+                title: None,
+                clear: Vec::new(),
+            },
+        ],
+        bitstream_filters: Vec::new(),
+        container: None,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagStreamsResponse {
+    /// Location of the tagged file
+    pub output: Url,
+}
+
+fn is_language_code(language: &str) -> bool {
+    language.len() == 3 && language.bytes().all(|b| b.is_ascii_lowercase())
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _tag_streams(
+        &self,
+        request: TagStreamsRequest,
+    ) -> HandlerResult<TagStreamsResponse> {
+        if let Some(tags) = request.streams.iter().find(|tags| {
+            tags.language
+                .as_deref()
+                .is_some_and(|language| !is_language_code(language))
+        }) {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!(
+                    "stream {}: language must be a lowercase ISO 639-2 code",
+                    tags.stream.specifier()
+                ),
+            )
+            .into());
+        }
+
+        let bitstream_filters = bitstream_filter_args(&request.bitstream_filters)?;
+
+        let probe = self.probe(&request.input).await?;
+
+        for tags in &request.streams {
+            tags.stream.validate(&probe)?;
+        }
+
+        let filename = format!(
+            "{}.{}",
+            input_stem(&request.input),
+            copy_container(&request.input, &request.container)
+        );
+
+        let mut inputs = Vec::new();
+
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-map".to_string(),
+            "0".to_string(),
+            "-c".to_string(),
+            "copy".to_string(),
+        ];
+
+        for tags in &request.streams {
+            // All streams are mapped in order, so input and output specifiers match
+            let option = format!("-metadata:s:{}", tags.stream.specifier());
+
+            for key in &tags.clear {
+                args.extend([option.clone(), format!("{key}=")]);
+            }
+
+            if let Some(language) = &tags.language {
+                args.extend([option.clone(), format!("language={language}")]);
+            }
+
+            if let Some(title) = &tags.title {
+                args.extend([option.clone(), format!("title={title}")]);
+            }
+        }
+
+        args.extend(bitstream_filters);
+        args.push(filename.clone());
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(TagStreamsResponse {
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+/// Disposition flags changed on a single stream (flags left empty are kept).
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DispositionChange {
+    #[serde(flatten)]
+    pub stream: StreamSelector,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forced: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hearing_impaired: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_set_disposition_request())]
+pub struct SetDispositionRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    pub streams: Vec<DispositionChange>,
+
+    /// Clear the default flag of other streams of the same type when a stream is made default
+    #[serde(default = "default_exclusive_default")]
+    pub exclusive_default: bool,
+
+    /// Bitstream filters applied to the copied streams
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bitstream_filters: Vec<StreamBitstreamFilters>,
+
+    /// Output container extension (defaults to the extension of the input)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+}
+
+fn default_exclusive_default() -> bool {
+    true
+}
+
+fn example_set_disposition_request() -> SetDispositionRequest {
+    SetDispositionRequest {
+        input: Url::parse("https://example.com/movie.mkv").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/fixed/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        streams: vec![
+            DispositionChange {
+                stream: StreamSelector {
+                    kind: StreamType::Audio,
+                    index: 1,
+                },
+                default: Some(true),
+                forced: None,
+                hearing_impaired: None,
+            },
+            DispositionChange {
+                stream: StreamSelector {
+                    kind: StreamType::Subtitle,
+                    index: 0,
+                },
+                default: None,
+                forced: Some(true),
+                hearing_impaired: None,
+            },
+        ],
+        exclusive_default: default_exclusive_default(),
+        bitstream_filters: Vec::new(),
+        container: None,
+    }
+}
+
+/// Resulting disposition of a stream.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamDisposition {
+    #[serde(flatten)]
+    pub stream: StreamSelector,
+
+    pub default: bool,
+    pub forced: bool,
+    pub hearing_impaired: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SetDispositionResponse {
+    /// Disposition of every stream in the output
+    pub streams: Vec<StreamDisposition>,
+
+    /// Location of the updated file
+    pub output: Url,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _set_disposition(
+        &self,
+        request: SetDispositionRequest,
+    ) -> HandlerResult<SetDispositionResponse> {
+        let bitstream_filters = bitstream_filter_args(&request.bitstream_filters)?;
+
+        let probe = self.probe(&request.input).await?;
+
+        for change in &request.streams {
+            change.stream.validate(&probe)?;
+        }
+
+        let before: Vec<StreamDisposition> = typed_streams(&probe)
+            .into_iter()
+            .map(|(selector, stream)| {
+                let disposition = stream.disposition.as_ref();
+
+                StreamDisposition {
+                    stream: selector,
+                    default: disposition.is_some_and(|d| d.default == 1),
+                    forced: disposition.is_some_and(|d| d.forced == 1),
+                    hearing_impaired: disposition.is_some_and(|d| d.hearing_impaired == 1),
+                }
+            })
+            .collect();
+
+        let mut after = before.clone();
+
+        if request.exclusive_default {
+            for change in request.streams.iter().filter(|c| c.default == Some(true)) {
+                for state in after
+                    .iter_mut()
+                    .filter(|state| state.stream.kind == change.stream.kind)
+                {
+                    state.default = false;
+                }
+            }
+        }
+
+        for change in &request.streams {
+            let Some(state) = after.iter_mut().find(|state| state.stream == change.stream) else {
+                continue;
+            };
+
+            state.default = change.default.unwrap_or(state.default);
+            state.forced = change.forced.unwrap_or(state.forced);
+            state.hearing_impaired = change.hearing_impaired.unwrap_or(state.hearing_impaired);
+        }
+
+        let filename = format!(
+            "{}.{}",
+            input_stem(&request.input),
+            copy_container(&request.input, &request.container)
+        );
+
+        let mut inputs = Vec::new();
+
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-map".to_string(),
+            "0".to_string(),
+            "-c".to_string(),
+            "copy".to_string(),
+        ];
+
+        // Flags are toggled relative to the input so that other flags (e.g. original, comment) survive
+        for (old, new) in before.iter().zip(&after) {
+            let flags: String = [
+                ("default", old.default, new.default),
+                ("forced", old.forced, new.forced),
+                (
+                    "hearing_impaired",
+                    old.hearing_impaired,
+                    new.hearing_impaired,
+                ),
+            ]
+            .into_iter()
+            .filter(|(_, old, new)| old != new)
+            .map(|(flag, _, new)| format!("{}{flag}", if new { '+' } else { '-' }))
+            .collect();
+
+            if !flags.is_empty() {
+                args.extend([format!("-disposition:{}", new.stream.specifier()), flags]);
+            }
+        }
+
+        args.extend(bitstream_filters);
+        args.push(filename.clone());
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(SetDispositionResponse {
+            streams: after,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+/// Groups of streams removed by `strip_streams`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamGroup {
+    /// Data streams (GPS, telemetry, timecode)
+    Data,
+    /// Timecode tracks only
+    Timecode,
+    /// Embedded thumbnails and cover art
+    Thumbnails,
+    /// Every audio stream except the first one
+    ExtraAudio,
+    Subtitles,
+    /// Attachments (e.g. fonts)
+    Attachments,
+}
+
+impl StreamGroup {
+    fn matches(&self, selector: &StreamSelector, stream: &Stream) -> bool {
+        match self {
+            StreamGroup::Data => selector.kind == StreamType::Data,
+            StreamGroup::Timecode => {
+                stream.codec_tag_string.as_deref() == Some("tmcd")
+                    || stream.codec_name.as_deref() == Some("timecode")
+            }
+            StreamGroup::Thumbnails => {
+                selector.kind == StreamType::Video
+                    && stream
+                        .disposition
+                        .as_ref()
+                        .is_some_and(|d| d.attached_pic == 1)
+            }
+            StreamGroup::ExtraAudio => selector.kind == StreamType::Audio && selector.index > 0,
+            StreamGroup::Subtitles => selector.kind == StreamType::Subtitle,
+            StreamGroup::Attachments => selector.kind == StreamType::Attachment,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_strip_streams_request())]
+pub struct StripStreamsRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Groups of streams to remove
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<StreamGroup>,
+
+    /// Individual streams to remove
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub streams: Vec<StreamSelector>,
+
+    /// Bitstream filters applied to the copied streams (indexes refer to the remaining streams)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bitstream_filters: Vec<StreamBitstreamFilters>,
+
+    /// Output container extension (defaults to the extension of the input)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+}
+
+fn example_strip_streams_request() -> StripStreamsRequest {
+    StripStreamsRequest {
+        input: Url::parse("https://example.com/camera.mov").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/distribution/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        remove: vec![
+            StreamGroup::Data,
+            StreamGroup::Timecode,
+            StreamGroup::Thumbnails,
+        ],
+        streams: Vec::new(),
+        bitstream_filters: Vec::new(),
+        container: None,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StripStreamsResponse {
+    /// Streams removed from the input
+    pub removed: Vec<StreamSelector>,
+
+    /// Location of the stripped file
+    pub output: Url,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _strip_streams(
+        &self,
+        request: StripStreamsRequest,
+    ) -> HandlerResult<StripStreamsResponse> {
+        let bitstream_filters = bitstream_filter_args(&request.bitstream_filters)?;
+
+        let probe = self.probe(&request.input).await?;
+
+        for selector in &request.streams {
+            selector.validate(&probe)?;
+        }
+
+        let streams = typed_streams(&probe);
+
+        let removed: Vec<StreamSelector> = streams
+            .iter()
+            .filter(|(selector, stream)| {
+                request.streams.contains(selector)
+                    || request
+                        .remove
+                        .iter()
+                        .any(|group| group.matches(selector, stream))
+            })
+            .map(|(selector, _)| *selector)
+            .collect();
+
+        if removed.len() == streams.len() {
+            return Err(TerminalError::new_with_code(400, "every stream would be removed").into());
+        }
+
+        let container = copy_container(&request.input, &request.container);
+        let filename = format!("{}.{}", input_stem(&request.input), container);
+
+        let mut inputs = Vec::new();
+
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-map".to_string(),
+            "0".to_string(),
+        ];
+
+        for selector in &removed {
+            args.extend(["-map".to_string(), format!("-0:{}", selector.specifier())]);
+        }
+
+        args.extend(["-c".to_string(), "copy".to_string()]);
+
+        if request.remove.contains(&StreamGroup::Timecode) {
+            // Drop the timecode tag as well, otherwise the MOV muxer recreates the track from it
+            args.extend(["-metadata".to_string(), "timecode=".to_string()]);
+
+            if matches!(container.as_str(), "mov" | "mp4" | "m4v") {
+                args.extend(["-write_tmcd".to_string(), "0".to_string()]);
+            }
+        }
+
+        args.extend(bitstream_filters);
+        args.push(filename.clone());
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        Ok(StripStreamsResponse {
+            removed,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
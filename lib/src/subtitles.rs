@@ -0,0 +1,704 @@
+use std::collections::HashMap;
+
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::captions::SubtitleFormat;
+use crate::service::{Output, ServiceImpl, Stream, input_stem, run_ffmpeg_in, run_ffprobe};
+use crate::staging::InputChecksum;
+
+/// Kind of broadcast subtitle service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleServiceKind {
+    /// DVB bitmap subtitles (EN 300 743)
+    Dvb,
+    /// Teletext subtitles (EN 300 706)
+    Teletext,
+}
+
+/// A subtitle service found in the input.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtitleService {
+    /// Index of the stream among the subtitle streams of the input
+    pub index: usize,
+
+    pub kind: SubtitleServiceKind,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    /// Location of the extracted subtitles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Url>,
+}
+
+/// How DVB bitmap subtitles are extracted (no OCR is performed).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BitmapSubtitleFormat {
+    /// Subtitle-only Matroska file carrying the original bitmaps and timing
+    #[default]
+    Mks,
+    /// A directory of PNG images named after their start time in milliseconds
+    Images,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_extract_broadcast_subtitles_request())]
+pub struct ExtractBroadcastSubtitlesRequest {
+    /// Path or URL to the transport stream
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Subtitle streams to extract (all of them when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub streams: Option<Vec<usize>>,
+
+    #[serde(default)]
+    pub bitmap_format: BitmapSubtitleFormat,
+
+    /// Format teletext subtitles are converted to
+    #[serde(default = "default_text_format")]
+    pub text_format: SubtitleFormat,
+
+    /// Teletext pages to decode (e.g. "888"), all subtitle pages by default
+    #[serde(default = "default_teletext_page")]
+    pub teletext_page: String,
+}
+
+fn default_text_format() -> SubtitleFormat {
+    SubtitleFormat::Srt
+}
+
+fn default_teletext_page() -> String {
+    "subtitle".to_string()
+}
+
+fn example_extract_broadcast_subtitles_request() -> ExtractBroadcastSubtitlesRequest {
+    ExtractBroadcastSubtitlesRequest {
+        input: Url::parse("https://example.com/broadcast.ts").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/subtitles/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        streams: None,
+        bitmap_format: BitmapSubtitleFormat::Mks,
+        text_format: default_text_format(),
+        teletext_page: default_teletext_page(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractBroadcastSubtitlesResponse {
+    /// Broadcast subtitle services available in the input
+    pub services: Vec<SubtitleService>,
+}
+
+fn service_kind(stream: &Stream) -> Option<SubtitleServiceKind> {
+    match stream.codec_name.as_deref() {
+        Some("dvb_subtitle") => Some(SubtitleServiceKind::Dvb),
+        Some("dvb_teletext") => Some(SubtitleServiceKind::Teletext),
+        _ => None,
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _extract_broadcast_subtitles(
+        &self,
+        request: ExtractBroadcastSubtitlesRequest,
+    ) -> HandlerResult<ExtractBroadcastSubtitlesResponse> {
+        let probe = self.probe(&request.input).await?;
+
+        let mut services: Vec<SubtitleService> = probe
+            .streams
+            .iter()
+            .flatten()
+            .filter(|stream| stream.codec_type == "subtitle")
+            .enumerate()
+            .filter_map(|(index, stream)| {
+                Some(SubtitleService {
+                    index,
+                    kind: service_kind(stream)?,
+                    language: stream.tags.get("language").cloned(),
+                    output: None,
+                })
+            })
+            .collect();
+
+        let stem = input_stem(&request.input);
+        let staging_dir = TempDir::new()?;
+        let work_dir = TempDir::new()?;
+
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        // Teletext decoder options apply to the input, so they go before -i
+        let mut args = vec![
+            "-txt_format".to_string(),
+            "text".to_string(),
+            "-txt_page".to_string(),
+            request.teletext_page.clone(),
+            "-i".to_string(),
+            input,
+        ];
+
+        let mut extracted = false;
+
+        for service in services.iter_mut() {
+            if request
+                .streams
+                .as_ref()
+                .is_some_and(|streams| !streams.contains(&service.index))
+            {
+                continue;
+            }
+
+            let map = format!("0:s:{}", service.index);
+            let language = service.language.as_deref().unwrap_or("und");
+            let name = format!("{stem}.{}.{language}", service.index);
+
+            let path = match (service.kind, request.bitmap_format) {
+                (SubtitleServiceKind::Teletext, _) => {
+                    let filename = format!("{name}.{}", request.text_format.extension());
+
+                    args.extend([
+                        "-map".to_string(),
+                        map,
+                        "-c:s".to_string(),
+                        request.text_format.encoder().to_string(),
+                        filename.clone(),
+                    ]);
+
+                    filename
+                }
+                (SubtitleServiceKind::Dvb, BitmapSubtitleFormat::Mks) => {
+                    let filename = format!("{name}.mks");
+
+                    args.extend([
+                        "-map".to_string(),
+                        map,
+                        "-c:s".to_string(),
+                        "copy".to_string(),
+                        filename.clone(),
+                    ]);
+
+                    filename
+                }
+                (SubtitleServiceKind::Dvb, BitmapSubtitleFormat::Images) => {
+                    tokio::fs::create_dir(work_dir.path().join(&name)).await?;
+
+                    // Bitmap subtitles used as filter input are rendered to video (sub2video);
+                    // duplicates are dropped so that each image marks a change on screen
+                    args.extend([
+                        "-filter_complex".to_string(),
+                        format!("[{map}]settb=1/1000,mpdecimate[sub{}]", service.index),
+                        "-map".to_string(),
+                        format!("[sub{}]", service.index),
+                        "-fps_mode".to_string(),
+                        "vfr".to_string(),
+                        "-frame_pts".to_string(),
+                        "1".to_string(),
+                        format!("{name}/%d.png"),
+                    ]);
+
+                    format!("{name}/")
+                }
+            };
+
+            service.output = Some(request.output.file_url(&path));
+            extracted = true;
+        }
+
+        if extracted {
+            run_ffmpeg_in(work_dir.path(), &args).await?;
+
+            self.upload(work_dir.path(), &request.output).await?;
+        }
+
+        Ok(ExtractBroadcastSubtitlesResponse { services })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_convert_subtitles_request())]
+pub struct ConvertSubtitlesRequest {
+    /// Path or URL to the subtitle file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Target format
+    pub format: SubtitleFormat,
+
+    /// Character encoding of the input (detected when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+
+    /// Expected checksum of the input, verified after downloading it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<InputChecksum>,
+}
+
+fn example_convert_subtitles_request() -> ConvertSubtitlesRequest {
+    ConvertSubtitlesRequest {
+        input: Url::parse("s3://bucket/subtitles/movie.srt").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/subtitles/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        format: SubtitleFormat::Webvtt,
+        charset: None,
+        checksum: None,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertSubtitlesResponse {
+    /// Character encoding the input was read with
+    pub charset: String,
+
+    /// Location of the converted subtitle file
+    pub output: Url,
+}
+
+/// Guesses the character encoding of a subtitle file.
+///
+/// Subtitle files in the wild are mostly UTF-8, UTF-16 with a BOM or a legacy
+/// Windows code page; anything that isn't valid Unicode is assumed to be the latter.
+pub(crate) fn detect_charset(data: &[u8]) -> &'static str {
+    match data {
+        [0xef, 0xbb, 0xbf, ..] => "UTF-8",
+        [0xff, 0xfe, ..] => "UTF-16LE",
+        [0xfe, 0xff, ..] => "UTF-16BE",
+        _ if std::str::from_utf8(data).is_ok() => "UTF-8",
+        _ => "CP1252",
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Downloads a subtitle file into a separate directory and returns the arguments reading it.
+    pub(crate) async fn stage_subtitles(
+        &self,
+        input: &Url,
+        charset: Option<&str>,
+        checksum: Option<&InputChecksum>,
+        staging_dir: &std::path::Path,
+    ) -> HandlerResult<(Vec<String>, String)> {
+        // Keep the original extension, the demuxer is picked based on it
+        let filename = match input.path().rsplit_once('.') {
+            Some((_, extension)) if !extension.contains('/') => format!("input.{extension}"),
+            _ => "input".to_string(),
+        };
+
+        let path = staging_dir.join(filename);
+
+        self.download(input, &path, checksum).await?;
+
+        let charset = match charset {
+            Some(charset) => charset.to_string(),
+            None => detect_charset(&tokio::fs::read(&path).await?).to_string(),
+        };
+
+        let args = vec![
+            "-sub_charenc".to_string(),
+            charset.clone(),
+            "-i".to_string(),
+            path.to_string_lossy().to_string(),
+        ];
+
+        Ok((args, charset))
+    }
+
+    pub(crate) async fn _convert_subtitles(
+        &self,
+        request: ConvertSubtitlesRequest,
+    ) -> HandlerResult<ConvertSubtitlesResponse> {
+        let staging_dir = TempDir::new()?;
+        let work_dir = TempDir::new()?;
+
+        let (mut args, charset) = self
+            .stage_subtitles(
+                &request.input,
+                request.charset.as_deref(),
+                request.checksum.as_ref(),
+                staging_dir.path(),
+            )
+            .await?;
+
+        let filename = format!(
+            "{}.{}",
+            input_stem(&request.input),
+            request.format.extension()
+        );
+
+        args.extend([
+            "-map".to_string(),
+            "0:s:0".to_string(),
+            "-c:s".to_string(),
+            request.format.encoder().to_string(),
+            filename.clone(),
+        ]);
+
+        run_ffmpeg_in(work_dir.path(), &args).await?;
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        Ok(ConvertSubtitlesResponse {
+            charset,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_retime_subtitles_request())]
+pub struct RetimeSubtitlesRequest {
+    /// Path or URL to the subtitle file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Seconds added to every timestamp (after scaling)
+    #[serde(default)]
+    pub offset: f64,
+
+    /// Factor every timestamp is multiplied by
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+
+    /// Frame rate change the subtitles should follow (e.g. 23.976 to 25 for a PAL speed-up)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_change: Option<SpeedChange>,
+
+    /// Target format (same as the input when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<SubtitleFormat>,
+
+    /// Character encoding of the input (detected when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+
+    /// Expected checksum of the input, verified after downloading it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<InputChecksum>,
+}
+
+/// Frame rate conversion done by speeding up or slowing down the video.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SpeedChange {
+    pub from_fps: f64,
+    pub to_fps: f64,
+}
+
+fn example_retime_subtitles_request() -> RetimeSubtitlesRequest {
+    RetimeSubtitlesRequest {
+        input: Url::parse("s3://bucket/subtitles/movie.srt").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/subtitles/pal/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        offset: 0.0,
+        scale: None,
+        speed_change: Some(SpeedChange {
+            from_fps: 23.976,
+            to_fps: 25.0,
+        }),
+        format: None,
+        charset: None,
+        checksum: None,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetimeSubtitlesResponse {
+    /// Scale factor applied to the timestamps
+    pub scale: f64,
+
+    /// Location of the retimed subtitle file
+    pub output: Url,
+}
+
+impl RetimeSubtitlesRequest {
+    fn scale(&self) -> HandlerResult<f64> {
+        let scale = match (self.scale, self.speed_change) {
+            (Some(_), Some(_)) => {
+                return Err(TerminalError::new_with_code(
+                    400,
+                    "scale and speedChange are mutually exclusive",
+                )
+                .into());
+            }
+            (Some(scale), None) => scale,
+            // Faster playback shortens the timeline by the ratio of the frame rates
+            (None, Some(change)) => change.from_fps / change.to_fps,
+            (None, None) => 1.0,
+        };
+
+        if !scale.is_finite() || scale <= 0.0 {
+            return Err(TerminalError::new_with_code(400, "scale must be positive").into());
+        }
+
+        Ok(scale)
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _retime_subtitles(
+        &self,
+        request: RetimeSubtitlesRequest,
+    ) -> HandlerResult<RetimeSubtitlesResponse> {
+        let scale = request.scale()?;
+
+        let format = request
+            .format
+            .or_else(|| {
+                request
+                    .input
+                    .path()
+                    .rsplit_once('.')
+                    .and_then(|(_, extension)| SubtitleFormat::from_extension(extension))
+            })
+            .unwrap_or(SubtitleFormat::Srt);
+
+        let staging_dir = TempDir::new()?;
+        let work_dir = TempDir::new()?;
+
+        let (input, _) = self
+            .stage_subtitles(
+                &request.input,
+                request.charset.as_deref(),
+                request.checksum.as_ref(),
+                staging_dir.path(),
+            )
+            .await?;
+
+        // Input timestamp options apply to the next -i
+        let mut args = vec![
+            "-itsscale".to_string(),
+            scale.to_string(),
+            "-itsoffset".to_string(),
+            request.offset.to_string(),
+        ];
+        args.extend(input);
+
+        let filename = format!("{}.{}", input_stem(&request.input), format.extension());
+
+        args.extend([
+            "-map".to_string(),
+            "0:s:0".to_string(),
+            "-c:s".to_string(),
+            format.encoder().to_string(),
+            filename.clone(),
+        ]);
+
+        run_ffmpeg_in(work_dir.path(), &args).await?;
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        Ok(RetimeSubtitlesResponse {
+            scale,
+            output: request.output.file_url(&filename),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_detect_forced_subtitles_request())]
+pub struct DetectForcedSubtitlesRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    /// A track is considered forced when it has at most this share of the cues
+    /// of the densest track in the same language
+    #[serde(default = "default_max_cue_ratio")]
+    pub max_cue_ratio: f64,
+}
+
+fn default_max_cue_ratio() -> f64 {
+    0.25
+}
+
+fn example_detect_forced_subtitles_request() -> DetectForcedSubtitlesRequest {
+    DetectForcedSubtitlesRequest {
+        input: Url::parse("https://example.com/movie.mkv").unwrap(),
+        max_cue_ratio: default_max_cue_ratio(),
+    }
+}
+
+/// Analysis of a subtitle track.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtitleTrack {
+    /// Index of the stream among the subtitle streams of the input
+    pub index: usize,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    /// Number of subtitle events
+    pub cues: usize,
+
+    /// Share of the media duration covered by subtitle events (0-1)
+    pub coverage: f64,
+
+    /// Whether the track most likely only contains forced narrative subtitles
+    pub forced: bool,
+
+    /// Signals that led to the forced classification
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DetectForcedSubtitlesResponse {
+    pub tracks: Vec<SubtitleTrack>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubtitlePacket {
+    stream_index: i32,
+    duration_time: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubtitlePackets {
+    #[serde(default)]
+    packets: Vec<SubtitlePacket>,
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _detect_forced_subtitles(
+        &self,
+        request: DetectForcedSubtitlesRequest,
+    ) -> HandlerResult<DetectForcedSubtitlesResponse> {
+        // Every subtitle packet is read, a sparse probe copy isn't enough
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let packet_args = [
+            "-select_streams".to_string(),
+            "s".to_string(),
+            "-show_entries".to_string(),
+            "packet=stream_index,duration_time".to_string(),
+            input,
+        ];
+
+        let (probe, packets) = tokio::try_join!(
+            self.probe(&request.input),
+            run_ffprobe::<SubtitlePackets>(&packet_args),
+        )?;
+
+        let duration = probe.duration().unwrap_or_default();
+
+        // Cue count and total display time per absolute stream index
+        let mut stats: HashMap<i32, (usize, f64)> = HashMap::new();
+
+        for packet in packets.packets {
+            let entry = stats.entry(packet.stream_index).or_default();
+            entry.0 += 1;
+            entry.1 += packet
+                .duration_time
+                .and_then(|d| d.parse::<f64>().ok())
+                .unwrap_or_default();
+        }
+
+        let mut tracks: Vec<SubtitleTrack> = probe
+            .streams
+            .iter()
+            .flatten()
+            .filter(|stream| stream.codec_type == "subtitle")
+            .enumerate()
+            .map(|(index, stream)| {
+                let (cues, shown) = stats.get(&stream.index).copied().unwrap_or_default();
+                let title = stream.tags.get("title").cloned();
+
+                let mut reasons = Vec::new();
+
+                if stream.disposition.as_ref().is_some_and(|d| d.forced == 1) {
+                    reasons.push("forced disposition".to_string());
+                }
+
+                if title
+                    .as_deref()
+                    .is_some_and(|title| title.to_lowercase().contains("forced"))
+                {
+                    reasons.push("title mentions forced".to_string());
+                }
+
+                SubtitleTrack {
+                    index,
+                    language: stream.tags.get("language").cloned(),
+                    title,
+                    cues,
+                    coverage: if duration > 0.0 {
+                        (shown / duration).min(1.0)
+                    } else {
+                        0.0
+                    },
+                    forced: false,
+                    reasons,
+                }
+            })
+            .collect();
+
+        // Forced tracks are sparse compared to the full track of the same language
+        let densest: HashMap<Option<String>, usize> =
+            tracks.iter().fold(HashMap::new(), |mut densest, track| {
+                let cues = densest.entry(track.language.clone()).or_default();
+                *cues = (*cues).max(track.cues);
+                densest
+            });
+
+        for track in tracks.iter_mut() {
+            let max = densest.get(&track.language).copied().unwrap_or_default();
+
+            if track.cues > 0
+                && track.cues < max
+                && (track.cues as f64) <= max as f64 * request.max_cue_ratio
+            {
+                track.reasons.push(format!(
+                    "{} cues compared to {} in the densest {} track",
+                    track.cues,
+                    max,
+                    track.language.as_deref().unwrap_or("undetermined")
+                ));
+            }
+
+            track.forced = !track.reasons.is_empty();
+        }
+
+        Ok(DetectForcedSubtitlesResponse { tracks })
+    }
+}
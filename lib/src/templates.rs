@@ -0,0 +1,134 @@
+use jiff::Timestamp;
+use restate_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::service::input_stem;
+
+tokio::task_local! {
+    static JOB: JobMetadata;
+}
+
+/// Header callers choose the ID of their job with (e.g. to diagnose it while it runs).
+pub(crate) const JOB_ID_HEADER: &str = "x-job-id";
+
+/// Longest job ID accepted in the job ID header.
+const MAX_JOB_ID_LEN: usize = 64;
+
+/// Returns the ID of the job of an invocation: the one given in the job ID header,
+/// or a random one derived from the invocation (the same on every retry).
+pub(crate) fn job_id(ctx: &mut Context<'_>) -> Result<String, TerminalError> {
+    let generated = ctx.rand_uuid().to_string();
+
+    let Some(job_id) = ctx.headers().get(JOB_ID_HEADER) else {
+        return Ok(generated);
+    };
+
+    // Job IDs are substituted into output locations
+    let valid = !job_id.is_empty()
+        && job_id.len() <= MAX_JOB_ID_LEN
+        && job_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if !valid {
+        return Err(TerminalError::new_with_code(
+            400,
+            format!(
+                "{JOB_ID_HEADER} may only contain up to {MAX_JOB_ID_LEN} letters, digits, _ and -"
+            ),
+        ));
+    }
+
+    Ok(job_id.clone())
+}
+
+/// Metadata of a job substituted into the placeholders of output locations.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct JobMetadata {
+    job_id: String,
+    date: String,
+    input_basename: String,
+    preset: String,
+
+    /// Media read by the job
+    #[serde(default)]
+    inputs: Vec<String>,
+}
+
+impl JobMetadata {
+    /// Collects the metadata of a job from its request and its (first) input.
+    ///
+    /// The date is read from the clock: call it in a journaled run.
+    pub(crate) fn new(job_id: String, inputs: &[String], request: &Value) -> Self {
+        let input_basename = inputs
+            .first()
+            .and_then(|input| Url::parse(input).ok())
+            .map(|input| input_stem(&input))
+            .unwrap_or_default();
+
+        let preset = request
+            .get("preset")
+            .and_then(Value::as_str)
+            .unwrap_or("default")
+            .to_string();
+
+        Self {
+            job_id,
+            date: Timestamp::now().strftime("%Y-%m-%d").to_string(),
+            input_basename,
+            preset,
+            inputs: inputs.to_vec(),
+        }
+    }
+
+    pub(crate) fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    pub(crate) fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    fn placeholders(&self) -> [(&'static str, &str); 4] {
+        [
+            ("jobId", &self.job_id),
+            ("date", &self.date),
+            ("inputBasename", &self.input_basename),
+            ("preset", &self.preset),
+        ]
+    }
+}
+
+/// Runs a job resolving the placeholders of output locations with its metadata.
+pub(crate) async fn with_job<T>(metadata: JobMetadata, job: impl Future<Output = T>) -> T {
+    JOB.scope(metadata, job).await
+}
+
+/// Returns the ID of the job running in the current task (if any).
+pub(crate) fn current_job_id() -> Option<String> {
+    JOB.try_with(|job| job.job_id.clone()).ok()
+}
+
+/// Replaces the placeholders (e.g. "{jobId}") of a location with the metadata of the current job.
+///
+/// Locations are returned as is outside of jobs.
+pub(crate) fn resolve(location: &Url) -> Url {
+    let Ok(resolved) = JOB.try_with(|job| {
+        let mut resolved = location.as_str().to_string();
+
+        for (name, value) in job.placeholders() {
+            // Braces are percent-encoded in the path of a URL
+            resolved = resolved
+                .replace(&format!("{{{name}}}"), value)
+                .replace(&format!("%7B{name}%7D"), value);
+        }
+
+        resolved
+    }) else {
+        return location.clone();
+    };
+
+    Url::parse(&resolved).unwrap_or_else(|_| location.clone())
+}
@@ -0,0 +1,81 @@
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::process::Child;
+
+/// Time a process is given to exit after SIGTERM before it is killed.
+const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// ffmpeg process terminated when dropped while still running.
+///
+/// Restate drops the handler future when an invocation is cancelled:
+/// without this, ffmpeg keeps encoding (and writing to the work dir) until it finishes.
+pub(crate) struct Terminating {
+    child: Option<Child>,
+    work_dir: PathBuf,
+}
+
+impl Terminating {
+    /// Takes over a process writing to the given work dir (removed again once it is terminated).
+    pub(crate) fn new(child: Child, work_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            child: Some(child),
+            work_dir: work_dir.into(),
+        }
+    }
+}
+
+impl Deref for Terminating {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        self.child.as_ref().expect("process is taken on drop only")
+    }
+}
+
+impl DerefMut for Terminating {
+    fn deref_mut(&mut self) -> &mut Child {
+        self.child.as_mut().expect("process is taken on drop only")
+    }
+}
+
+impl Drop for Terminating {
+    fn drop(&mut self) {
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+
+        // Already exited
+        if !matches!(child.try_wait(), Ok(None)) {
+            return;
+        }
+
+        // Lets ffmpeg stop gracefully first
+        if let Some(pid) = child.id() {
+            // SAFETY: the process is not reaped yet (the child is owned here), so the PID is still its own
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGTERM);
+            }
+        }
+
+        let work_dir = std::mem::take(&mut self.work_dir);
+
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            child.start_kill().ok();
+            return;
+        };
+
+        runtime.spawn(async move {
+            if tokio::time::timeout(GRACE_PERIOD, child.wait())
+                .await
+                .is_err()
+            {
+                child.kill().await.ok();
+            }
+
+            // The work dir may have been removed while ffmpeg was still writing to it
+            tokio::fs::remove_dir_all(&work_dir).await.ok();
+        });
+    }
+}
@@ -0,0 +1,221 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::editorial::StillFormat;
+use crate::service::{Output, ServiceImpl, input_stem, run_ffmpeg_in};
+
+/// Largest number of thumbnails extracted at once.
+const MAX_THUMBNAILS: usize = 100;
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_thumbnail_request())]
+pub struct ThumbnailRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Positions of the thumbnails in seconds from the start
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timestamps: Vec<f64>,
+
+    /// Number of evenly spaced thumbnails (when no timestamps are given)
+    #[serde(default = "default_count")]
+    pub count: u32,
+
+    /// Width of the thumbnails in pixels (follows the aspect ratio of the input when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+
+    /// Height of the thumbnails in pixels (follows the aspect ratio of the input when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+
+    #[serde(default)]
+    pub format: StillFormat,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn example_thumbnail_request() -> ThumbnailRequest {
+    ThumbnailRequest {
+        input: Url::parse("s3://bucket/uploads/video.mp4").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/thumbnails/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        timestamps: Vec::new(),
+        count: 3,
+        width: Some(640),
+        height: None,
+        format: StillFormat::Jpeg,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ThumbnailResponse {
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+/// Frame extracted as a thumbnail.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Thumbnail {
+    /// Position of the thumbnail in seconds
+    pub timestamp: f64,
+
+    /// Location of the image
+    pub location: Url,
+}
+
+impl ThumbnailRequest {
+    async fn validate(&self) -> HandlerResult<()> {
+        let invalid = |message: String| -> HandlerResult<()> {
+            Err(TerminalError::new_with_code(400, message).into())
+        };
+
+        let count = if self.timestamps.is_empty() {
+            self.count as usize
+        } else {
+            self.timestamps.len()
+        };
+
+        if !(1..=MAX_THUMBNAILS).contains(&count) {
+            return invalid(format!(
+                "a request takes between 1 and {MAX_THUMBNAILS} thumbnails"
+            ));
+        }
+
+        if let Some(timestamp) = self.timestamps.iter().find(|timestamp| **timestamp < 0.0) {
+            return invalid(format!("timestamp {timestamp} is negative"));
+        }
+
+        if self.width == Some(0) || self.height == Some(0) {
+            return invalid("thumbnail width and height must be positive".to_string());
+        }
+
+        self.format.validate().await
+    }
+
+    /// Scale filter of the requested size (-2 keeps the aspect ratio with an even size).
+    fn scale(&self) -> Option<String> {
+        let size = |size: Option<u32>| size.map_or("-2".to_string(), |size| size.to_string());
+
+        if self.width.is_none() && self.height.is_none() {
+            return None;
+        }
+
+        Some(format!("scale={}:{}", size(self.width), size(self.height)))
+    }
+}
+
+/// Positions of evenly spaced thumbnails, in the middle of equal parts of the input.
+///
+/// The very start and end are left out: they are often black.
+fn evenly_spaced(duration: f64, count: u32) -> Vec<f64> {
+    let part = duration / count as f64;
+
+    (0..count).map(|i| part * (i as f64 + 0.5)).collect()
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Extracts a frame per position, seeking before the input so that only the frames around it are decoded.
+    pub(crate) async fn _thumbnail(
+        &self,
+        request: ThumbnailRequest,
+    ) -> HandlerResult<ThumbnailResponse> {
+        request.validate().await?;
+
+        let probe = self.probe(&request.input).await?;
+
+        if probe.stream("video").is_none() {
+            return Err(TerminalError::new_with_code(400, "input has no video stream").into());
+        }
+
+        let duration = probe.duration();
+
+        let timestamps = if request.timestamps.is_empty() {
+            let duration = duration
+                .ok_or_else(|| TerminalError::new_with_code(400, "unknown input duration"))?;
+
+            evenly_spaced(duration, request.count)
+        } else {
+            request.timestamps.clone()
+        };
+
+        if let Some((timestamp, duration)) = duration.and_then(|duration| {
+            timestamps
+                .iter()
+                .find(|timestamp| **timestamp >= duration)
+                .map(|timestamp| (timestamp, duration))
+        }) {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!("timestamp {timestamp} is past the end of the input ({duration:.3}s)"),
+            )
+            .into());
+        }
+
+        // Every thumbnail reads the input: storage inputs are downloaded once
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let work_dir = TempDir::new()?;
+        let stem = input_stem(&request.input);
+        let mut thumbnails = Vec::new();
+
+        for (i, timestamp) in timestamps.into_iter().enumerate() {
+            let filename = format!("{stem}_thumbnail_{i:04}.{}", request.format.extension());
+
+            let mut args = vec![
+                "-ss".to_string(),
+                format!("{timestamp:.6}"),
+                "-i".to_string(),
+                input.clone(),
+                "-map".to_string(),
+                "0:v:0".to_string(),
+            ];
+
+            if let Some(scale) = request.scale() {
+                args.extend(["-vf".to_string(), scale]);
+            }
+
+            args.extend([
+                "-frames:v".to_string(),
+                "1".to_string(),
+                "-update".to_string(),
+                "1".to_string(),
+                filename.clone(),
+            ]);
+
+            run_ffmpeg_in(work_dir.path(), &args).await?;
+
+            if !work_dir.path().join(&filename).exists() {
+                return Err(
+                    TerminalError::new_with_code(400, format!("no frame at {timestamp}")).into(),
+                );
+            }
+
+            thumbnails.push(Thumbnail {
+                timestamp,
+                location: request.output.file_url(&filename),
+            });
+        }
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        Ok(ThumbnailResponse { thumbnails })
+    }
+}
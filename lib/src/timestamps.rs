@@ -0,0 +1,166 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::repair::probe_file;
+use crate::service::{
+    FfprobeResponse, Output, ServiceImpl, input_extension, input_stem, run_ffmpeg_in,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_normalize_timestamps_request())]
+pub struct NormalizeTimestampsRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Container of the output (the one of the input when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+fn example_normalize_timestamps_request() -> NormalizeTimestampsRequest {
+    NormalizeTimestampsRequest {
+        input: Url::parse("s3://bucket/recorder/cam-03.ts").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/normalized/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        format: Some("mp4".to_string()),
+    }
+}
+
+/// Start of a stream before and after normalizing.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamTimestamps {
+    pub index: i32,
+
+    pub codec_type: String,
+
+    /// Start time in the input in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<f64>,
+
+    /// Start time in the output in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized_start: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NormalizeTimestampsResponse {
+    /// Location of the normalized file
+    pub output: Url,
+
+    pub streams: Vec<StreamTimestamps>,
+
+    /// Timestamps going backwards (or repeating) that were corrected
+    pub non_monotonic: u32,
+
+    /// Duration of the output in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+}
+
+fn start_time(probe: &FfprobeResponse, index: i32) -> Option<f64> {
+    probe
+        .streams
+        .iter()
+        .flatten()
+        .find(|stream| stream.index == index)?
+        .start_time
+        .as_deref()?
+        .parse()
+        .ok()
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Remuxes the input with zero-based, monotonic timestamps.
+    ///
+    /// Missing presentation timestamps are generated, broken decoding timestamps are ignored
+    /// (and recomputed by the muxer), and every stream is shifted by the same offset so that
+    /// the earliest one starts at zero: the sync between the streams is kept.
+    pub(crate) async fn _normalize_timestamps(
+        &self,
+        request: NormalizeTimestampsRequest,
+    ) -> HandlerResult<NormalizeTimestampsResponse> {
+        let format = match &request.format {
+            Some(format) => format.to_lowercase(),
+            None => input_extension(&request.input).unwrap_or_else(|| "mkv".to_string()),
+        };
+
+        if format.is_empty() || !format.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(TerminalError::new_with_code(400, "invalid format").into());
+        }
+
+        let source = self.probe(&request.input).await?;
+
+        let staging_dir = TempDir::new()?;
+        let input = self.local_input(&request.input, staging_dir.path()).await?;
+
+        let work_dir = TempDir::new()?;
+        let filename = format!("{}_normalized.{format}", input_stem(&request.input));
+
+        let log = run_ffmpeg_in(
+            work_dir.path(),
+            &[
+                "-fflags".to_string(),
+                "+genpts+igndts".to_string(),
+                "-i".to_string(),
+                input,
+                "-map".to_string(),
+                "0".to_string(),
+                "-c".to_string(),
+                "copy".to_string(),
+                "-ignore_unknown".to_string(),
+                "-avoid_negative_ts".to_string(),
+                "make_zero".to_string(),
+                // MPEG-TS delays the first packet by 0.7s by default
+                "-muxdelay".to_string(),
+                "0".to_string(),
+                "-muxpreload".to_string(),
+                "0".to_string(),
+                filename.clone(),
+            ],
+        )
+        .await?;
+
+        let normalized = probe_file(&work_dir.path().join(&filename)).await?;
+
+        self.upload(work_dir.path(), &request.output).await?;
+
+        let non_monotonic = log
+            .lines()
+            .filter(|line| line.contains("non monotonically increasing dts"))
+            .count() as u32;
+
+        let streams = source
+            .streams
+            .iter()
+            .flatten()
+            .map(|stream| StreamTimestamps {
+                index: stream.index,
+                codec_type: stream.codec_type.clone(),
+                start: start_time(&source, stream.index),
+                normalized_start: start_time(&normalized, stream.index),
+            })
+            .collect();
+
+        Ok(NormalizeTimestampsResponse {
+            output: request.output.file_url(&filename),
+            streams,
+            non_monotonic,
+            duration: normalized.duration(),
+        })
+    }
+}
@@ -0,0 +1,415 @@
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::encode::{VideoEncoding, default_container};
+use crate::inline::with_inlined;
+use crate::inputs::input_arg;
+use crate::metering::{Metered, metered};
+use crate::service::{FfmpegRequest, Output, ServiceImpl, input_stem, parse_uri};
+use crate::templates::{JobMetadata, job_id, with_job};
+
+/// Name of the statistics file shared by the passes (ffmpeg appends the stream index).
+const PASSLOG: &str = "passlog";
+
+/// Named settings for common delivery targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum TranscodePreset {
+    #[serde(rename = "web-480p")]
+    Web480p,
+    #[serde(rename = "web-720p")]
+    Web720p,
+    #[serde(rename = "web-1080p")]
+    Web1080p,
+    #[serde(rename = "web-2160p")]
+    Web2160p,
+}
+
+impl TranscodePreset {
+    /// Returns the height, constant rate factor and audio bitrate of the preset.
+    fn settings(&self) -> (u32, u8, &'static str) {
+        match self {
+            TranscodePreset::Web480p => (480, 24, "96k"),
+            TranscodePreset::Web720p => (720, 23, "128k"),
+            TranscodePreset::Web1080p => (1080, 22, "160k"),
+            TranscodePreset::Web2160p => (2160, 20, "192k"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_transcode_request())]
+pub struct TranscodeRequest {
+    /// Path or URL to the media file
+    pub input: Url,
+
+    pub output: Output,
+
+    /// Named settings filling in the resolution, quality and audio bitrate left empty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<TranscodePreset>,
+
+    #[serde(default)]
+    pub video: VideoEncoding,
+
+    /// Output width in pixels (keeps the aspect ratio when only the height is set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+
+    /// Output height in pixels (keeps the aspect ratio when only the width is set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+
+    /// Audio codec of the output
+    #[serde(default = "default_audio_codec")]
+    pub audio_codec: String,
+
+    /// Audio bitrate of the output (e.g. "128k")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_bitrate: Option<String>,
+
+    /// Output container extension
+    #[serde(default = "default_container")]
+    pub container: String,
+
+    /// Encode in two passes (requires a bitrate), analyzing the input in the first one
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub two_pass: bool,
+}
+
+fn default_audio_codec() -> String {
+    "aac".to_string()
+}
+
+fn example_transcode_request() -> TranscodeRequest {
+    TranscodeRequest {
+        input: Url::parse("s3://bucket/masters/feature.mov").unwrap(),
+        output: Output {
+            location: Url::parse("s3://bucket/encoded/").unwrap(),
+            inline: false,
+            overwrite: Default::default(),
+        },
+        preset: Some(TranscodePreset::Web1080p),
+        video: VideoEncoding {
+            bitrate: Some("5M".to_string()),
+            ..Default::default()
+        },
+        width: None,
+        height: None,
+        audio_codec: default_audio_codec(),
+        audio_bitrate: None,
+        container: default_container(),
+        two_pass: true,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscodeResponse {
+    /// Location of the encoded file
+    pub output: Url,
+
+    /// Number of passes the video was encoded in
+    pub passes: u8,
+}
+
+impl TranscodeRequest {
+    /// Fills the settings left empty from the preset.
+    fn resolve(mut self) -> Self {
+        let Some(preset) = self.preset else {
+            return self;
+        };
+
+        let (height, crf, audio_bitrate) = preset.settings();
+
+        if self.width.is_none() && self.height.is_none() {
+            self.height = Some(height);
+        }
+
+        if self.video.crf.is_none() && self.video.bitrate.is_none() {
+            self.video.crf = Some(crf);
+        }
+
+        self.audio_bitrate
+            .get_or_insert_with(|| audio_bitrate.to_string());
+
+        self
+    }
+
+    /// Arguments scaling the video and, for presets, keeping it playable in browsers.
+    fn picture_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        // -2 keeps the aspect ratio with an even dimension, as yuv420p requires
+        let scale = match (self.width, self.height) {
+            (Some(width), Some(height)) => Some(format!("scale={width}:{height}")),
+            (Some(width), None) => Some(format!("scale={width}:-2")),
+            (None, Some(height)) => Some(format!("scale=-2:{height}")),
+            (None, None) => None,
+        };
+
+        if let Some(scale) = scale {
+            args.extend(["-vf".to_string(), scale]);
+        }
+
+        if self.preset.is_some() {
+            args.extend(["-pix_fmt".to_string(), "yuv420p".to_string()]);
+        }
+
+        args
+    }
+
+    /// Storage location the statistics of the first pass are kept at until the second one.
+    fn passlog_output(&self) -> Output {
+        Output {
+            location: self
+                .output
+                .file_url(&format!(".passlog/{}/", input_stem(&self.input))),
+            inline: false,
+            overwrite: Default::default(),
+        }
+    }
+
+    fn filename(&self) -> String {
+        format!("{}.{}", input_stem(&self.input), self.container)
+    }
+
+    async fn validate(&self) -> HandlerResult<()> {
+        self.video.validate().await?;
+
+        if self.width.is_some_and(|width| width < 2 || width % 2 != 0)
+            || self
+                .height
+                .is_some_and(|height| height < 2 || height % 2 != 0)
+        {
+            return Err(TerminalError::new_with_code(400, "width and height must be even").into());
+        }
+
+        if !self.two_pass {
+            return Ok(());
+        }
+
+        if self.video.bitrate.is_none() {
+            return Err(
+                TerminalError::new_with_code(400, "two-pass encoding requires a bitrate").into(),
+            );
+        }
+
+        if self.video.codec.pass_args(1, PASSLOG).is_none() {
+            return Err(TerminalError::new_with_code(
+                400,
+                format!(
+                    "two-pass encoding is not supported by {}",
+                    self.video.codec.encoder()
+                ),
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    /// Transcodes the input, running each pass of a two-pass encode in its own durable step.
+    ///
+    /// The statistics of the first pass are kept in storage next to the output:
+    /// a failure in the second pass does not repeat the first one.
+    pub(crate) async fn _transcode(
+        &self,
+        ctx: &mut Context<'_>,
+        request: TranscodeRequest,
+    ) -> HandlerResult<TranscodeResponse> {
+        let request = request.resolve();
+        let inputs = vec![request.input.to_string()];
+        let job_id = job_id(ctx)?;
+
+        // The metadata is journaled along with the first pass, so that both passes
+        // resolve the output location the same way
+        let Metered {
+            response: (passlogs, metadata),
+            mut usage,
+            ..
+        } = ctx
+            .run(|| {
+                metered(async {
+                    request.validate().await?;
+                    self.check_guardrails(&inputs).await?;
+
+                    let metadata =
+                        JobMetadata::new(job_id.clone(), &inputs, &serde_json::to_value(&request)?);
+
+                    let passlogs = if request.two_pass {
+                        with_job(metadata.clone(), self.first_pass(&request)).await?
+                    } else {
+                        Vec::new()
+                    };
+
+                    Ok((passlogs, metadata))
+                })
+            })
+            .name(if request.two_pass {
+                "pass 1"
+            } else {
+                "validate"
+            })
+            .await?
+            .into_inner();
+
+        let Metered {
+            response,
+            usage: encode_usage,
+            finished_at,
+        } = ctx
+            .run(|| {
+                metered(with_inlined(with_job(
+                    metadata.clone(),
+                    self.final_pass(&request, &passlogs),
+                )))
+            })
+            .name(if request.two_pass { "pass 2" } else { "encode" })
+            .await?
+            .into_inner();
+
+        usage.add(&encode_usage);
+        usage.jobs = 1;
+
+        self.record_job(ctx, "transcode", &metadata, &response, usage, finished_at)?;
+
+        Ok(response)
+    }
+
+    /// Analyzes the input and uploads the statistics, returning their location.
+    async fn first_pass(&self, request: &TranscodeRequest) -> HandlerResult<Vec<Url>> {
+        let mut inputs = Vec::new();
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-map".to_string(),
+            "0:v:0".to_string(),
+            "-an".to_string(),
+            "-sn".to_string(),
+        ];
+
+        // The statistics only apply to a second pass encoding the same picture
+        args.extend(request.picture_args());
+        args.extend(request.video.args());
+        args.extend(
+            request
+                .video
+                .codec
+                .pass_args(1, PASSLOG)
+                .unwrap_or_default(),
+        );
+
+        // Only the statistics written to the work dir are kept
+        args.extend([
+            "-f".to_string(),
+            "null".to_string(),
+            "/dev/null".to_string(),
+        ]);
+
+        let response = self
+            ._ffmpeg(FfmpegRequest {
+                args,
+                output: request.passlog_output(),
+                dry_run: false,
+                incremental_upload: false,
+                env: Default::default(),
+                inputs,
+                outputs: Vec::new(),
+                sanitize_dimensions: false,
+                logging: Default::default(),
+            })
+            .await?;
+
+        Ok(response.outputs)
+    }
+
+    /// Encodes the output, using the statistics of the first pass (if any).
+    async fn final_pass(
+        &self,
+        request: &TranscodeRequest,
+        passlogs: &[Url],
+    ) -> HandlerResult<TranscodeResponse> {
+        let dir = TempDir::new()?;
+
+        for passlog in passlogs {
+            let name = passlog
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .unwrap_or_default();
+
+            self.download(passlog, &dir.path().join(name), None).await?;
+        }
+
+        let filename = request.filename();
+        let mut inputs = Vec::new();
+
+        let mut args = vec![
+            "-i".to_string(),
+            input_arg(&request.input, &mut inputs),
+            "-map".to_string(),
+            "0:v:0".to_string(),
+            "-map".to_string(),
+            "0:a?".to_string(),
+        ];
+
+        args.extend(request.picture_args());
+        args.extend(request.video.args());
+
+        if request.two_pass {
+            let logfile = dir.path().join(PASSLOG).display().to_string();
+            args.extend(
+                request
+                    .video
+                    .codec
+                    .pass_args(2, &logfile)
+                    .unwrap_or_default(),
+            );
+        }
+
+        args.extend(["-c:a".to_string(), request.audio_codec.clone()]);
+
+        if let Some(audio_bitrate) = &request.audio_bitrate {
+            args.extend(["-b:a".to_string(), audio_bitrate.clone()]);
+        }
+
+        // Presets target progressive playback, which needs the index up front
+        if request.preset.is_some() && matches!(request.container.as_str(), "mp4" | "mov") {
+            args.extend(["-movflags".to_string(), "+faststart".to_string()]);
+        }
+
+        args.push(filename.clone());
+
+        self._ffmpeg(FfmpegRequest {
+            args,
+            output: request.output.clone(),
+            dry_run: false,
+            incremental_upload: false,
+            env: Default::default(),
+            inputs,
+            outputs: Vec::new(),
+            sanitize_dimensions: false,
+            logging: Default::default(),
+        })
+        .await?;
+
+        if request.two_pass {
+            let (uri, path) = parse_uri(request.passlog_output().location());
+            self.factory.load(uri.as_str())?.remove_all(&path).await?;
+        }
+
+        Ok(TranscodeResponse {
+            output: request.output.file_url(&filename),
+            passes: if request.two_pass { 2 } else { 1 },
+        })
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::{BTreeMap, HashMap};
+
+use futures::StreamExt;
+use opendal_util::OperatorFactory;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::metering;
+use crate::service::{ServiceImpl, parse_uri};
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+const NULL_PID: u16 = 0x1fff;
+
+/// PCR clock frequency.
+const PCR_HZ: f64 = 27_000_000.0;
+
+/// Maximum PCR interval allowed by ETSI TR 101 290.
+const MAX_PCR_INTERVAL_MS: f64 = 40.0;
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = example_analyze_ts_request())]
+pub struct AnalyzeTsRequest {
+    /// Path or URL to the transport stream
+    pub input: Url,
+
+    /// Stop after analyzing this many bytes (the whole input is analyzed when empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+}
+
+fn example_analyze_ts_request() -> AnalyzeTsRequest {
+    AnalyzeTsRequest {
+        input: Url::parse("s3://bucket/contribution/feed.ts").unwrap(),
+        max_bytes: None,
+    }
+}
+
+/// Statistics of a single PID.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PidStats {
+    pub pid: u16,
+
+    /// What the PID carries according to the PAT and PMTs (e.g. "pmt", "h264", "unknown")
+    pub kind: String,
+
+    /// Stream type from the PMT
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_type: Option<u8>,
+
+    /// Program the PID belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<u16>,
+
+    pub packets: u64,
+
+    /// Packets with an unexpected continuity counter
+    pub continuity_errors: u64,
+
+    /// Packets with the transport error indicator set
+    pub transport_errors: u64,
+
+    /// Packets with the scrambling control set
+    pub scrambled_packets: u64,
+
+    /// Packets with the discontinuity indicator set
+    pub discontinuities: u64,
+}
+
+/// Timing statistics of a PCR PID.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PcrStats {
+    pub pid: u16,
+
+    /// Number of PCR values
+    pub count: u64,
+
+    /// Longest time between two PCR values in milliseconds
+    pub max_interval_ms: f64,
+
+    /// Intervals longer than 40 ms (the TR 101 290 limit)
+    pub interval_errors: u64,
+
+    /// Largest deviation of PCR values from the constant mux bitrate in milliseconds
+    pub max_jitter_ms: f64,
+
+    /// PCR jumps (backwards or more than 100 ms) not signaled by a discontinuity indicator
+    pub discontinuities: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzeTsResponse {
+    /// Number of packets analyzed
+    pub packets: u64,
+
+    /// Number of times the sync byte was lost
+    pub sync_losses: u64,
+
+    /// Mux bitrate calculated from the PCR values
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u64>,
+
+    /// Programs and their PMT PID
+    pub programs: BTreeMap<u16, u16>,
+
+    pub pids: Vec<PidStats>,
+
+    pub pcr: Vec<PcrStats>,
+}
+
+fn stream_kind(stream_type: u8) -> &'static str {
+    match stream_type {
+        0x01 => "mpeg1video",
+        0x02 => "mpeg2video",
+        0x03 | 0x04 => "mp2",
+        0x06 => "private",
+        0x0f => "aac",
+        0x11 => "aac_latm",
+        0x15 => "metadata",
+        0x1b => "h264",
+        0x24 => "hevc",
+        0x81 => "ac3",
+        0x86 => "scte35",
+        0x87 => "eac3",
+        _ => "unknown",
+    }
+}
+
+#[derive(Debug)]
+struct Pcr {
+    value: u64,
+    position: u64,
+}
+
+#[derive(Debug, Default)]
+struct PidState {
+    stats: PidStats,
+    continuity: Option<u8>,
+    duplicate: bool,
+}
+
+#[derive(Debug, Default)]
+struct PcrState {
+    stats: PcrStats,
+    last: Option<Pcr>,
+    discontinuity: bool,
+    values: Vec<Pcr>,
+}
+
+#[derive(Debug, Default)]
+struct Analyzer {
+    packets: u64,
+    sync_losses: u64,
+    programs: BTreeMap<u16, u16>,
+    pids: HashMap<u16, PidState>,
+    pcrs: BTreeMap<u16, PcrState>,
+}
+
+/// Returns the payload of a PSI section starting in the packet.
+///
+/// Sections spanning multiple packets are not reassembled, PAT and PMT fit in a single one in practice.
+fn section(payload: &[u8]) -> Option<(u8, &[u8])> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+
+    let table_id = *section.first()?;
+    let length = (u16::from_be_bytes([*section.get(1)?, *section.get(2)?]) & 0x0fff) as usize;
+
+    // Skip the extended header (5 bytes) and drop the CRC
+    let body = section.get(8..3 + length)?.get(..length.checked_sub(9)?)?;
+
+    Some((table_id, body))
+}
+
+impl Analyzer {
+    fn packet(&mut self, packet: &[u8], position: u64) {
+        self.packets += 1;
+
+        let pid = u16::from_be_bytes([packet[1], packet[2]]) & 0x1fff;
+        let transport_error = packet[1] & 0x80 != 0;
+        let unit_start = packet[1] & 0x40 != 0;
+        let scrambled = packet[3] & 0xc0 != 0;
+        let has_adaptation = packet[3] & 0x20 != 0;
+        let has_payload = packet[3] & 0x10 != 0;
+        let continuity = packet[3] & 0x0f;
+
+        if pid == NULL_PID {
+            return;
+        }
+
+        let mut discontinuity = false;
+        let mut pcr = None;
+        let mut payload_start = 4;
+
+        if has_adaptation {
+            let length = packet[4] as usize;
+            payload_start = 5 + length;
+
+            if length > 0 {
+                let flags = packet[5];
+                discontinuity = flags & 0x80 != 0;
+
+                if flags & 0x10 != 0 && length >= 7 {
+                    let b = &packet[6..12];
+                    let base = ((b[0] as u64) << 25)
+                        | ((b[1] as u64) << 17)
+                        | ((b[2] as u64) << 9)
+                        | ((b[3] as u64) << 1)
+                        | ((b[4] as u64) >> 7);
+                    let extension = (((b[4] & 0x01) as u64) << 8) | b[5] as u64;
+
+                    pcr = Some(base * 300 + extension);
+                }
+            }
+        }
+
+        let state = self.pids.entry(pid).or_default();
+        state.stats.packets += 1;
+
+        if transport_error {
+            state.stats.transport_errors += 1;
+        }
+        if scrambled {
+            state.stats.scrambled_packets += 1;
+        }
+        if discontinuity {
+            state.stats.discontinuities += 1;
+        }
+
+        // The counter only increments with payload, and a packet may be sent twice
+        match state.continuity {
+            Some(last) if !discontinuity && has_payload => {
+                if continuity == last && !state.duplicate {
+                    state.duplicate = true;
+                } else if continuity != (last + 1) & 0x0f {
+                    state.stats.continuity_errors += 1;
+                    state.duplicate = false;
+                } else {
+                    state.duplicate = false;
+                }
+            }
+            Some(last) if !discontinuity && continuity != last => {
+                state.stats.continuity_errors += 1;
+            }
+            _ => {}
+        }
+        state.continuity = Some(continuity);
+
+        if let Some(value) = pcr {
+            self.pcr(pid, value, position, discontinuity);
+        }
+
+        if has_payload && unit_start && payload_start < PACKET_SIZE {
+            self.psi(pid, &packet[payload_start..]);
+        }
+    }
+
+    fn pcr(&mut self, pid: u16, value: u64, position: u64, discontinuity: bool) {
+        let state = self.pcrs.entry(pid).or_insert_with(|| PcrState {
+            stats: PcrStats {
+                pid,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        state.stats.count += 1;
+
+        if let Some(last) = &state.last
+            && !discontinuity
+        {
+            let interval = (value as f64 - last.value as f64) / PCR_HZ * 1000.0;
+
+            if !(0.0..=100.0).contains(&interval) {
+                state.stats.discontinuities += 1;
+                state.discontinuity = true;
+            } else {
+                state.stats.max_interval_ms = state.stats.max_interval_ms.max(interval);
+                if interval > MAX_PCR_INTERVAL_MS {
+                    state.stats.interval_errors += 1;
+                }
+            }
+        }
+
+        if discontinuity {
+            state.discontinuity = true;
+        }
+
+        // Jitter is only measured on continuous timelines
+        if !state.discontinuity {
+            state.values.push(Pcr { value, position });
+        }
+
+        state.last = Some(Pcr { value, position });
+    }
+
+    fn psi(&mut self, pid: u16, payload: &[u8]) {
+        let Some((table_id, body)) = section(payload) else {
+            return;
+        };
+
+        match table_id {
+            // PAT
+            0x00 if pid == 0 => {
+                for entry in body.chunks_exact(4) {
+                    let program = u16::from_be_bytes([entry[0], entry[1]]);
+                    let pmt = u16::from_be_bytes([entry[2], entry[3]]) & 0x1fff;
+
+                    if program != 0 {
+                        self.programs.insert(program, pmt);
+                    }
+                }
+            }
+            // PMT
+            0x02 => {
+                let Some(program) = self
+                    .programs
+                    .iter()
+                    .find(|(_, pmt)| **pmt == pid)
+                    .map(|(program, _)| *program)
+                else {
+                    return;
+                };
+
+                if body.len() < 4 {
+                    return;
+                }
+
+                let info_length = (u16::from_be_bytes([body[2], body[3]]) & 0x0fff) as usize;
+                let mut entries = body.get(4 + info_length..).unwrap_or_default();
+
+                while entries.len() >= 5 {
+                    let stream_type = entries[0];
+                    let es_pid = u16::from_be_bytes([entries[1], entries[2]]) & 0x1fff;
+                    let es_info_length =
+                        (u16::from_be_bytes([entries[3], entries[4]]) & 0x0fff) as usize;
+
+                    let state = self.pids.entry(es_pid).or_default();
+                    state.stats.stream_type = Some(stream_type);
+                    state.stats.program = Some(program);
+
+                    entries = entries.get(5 + es_info_length..).unwrap_or_default();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(self) -> AnalyzeTsResponse {
+        // The mux bitrate is derived from the PCR PID with the longest continuous timeline
+        let bitrate = self
+            .pcrs
+            .values()
+            .filter_map(|state| {
+                let (first, last) = (state.values.first()?, state.values.last()?);
+                let ticks = last.value.checked_sub(first.value).filter(|t| *t > 0)?;
+
+                Some((
+                    ticks,
+                    (last.position - first.position) as f64 * 8.0 * PCR_HZ / ticks as f64,
+                ))
+            })
+            .max_by_key(|(ticks, _)| *ticks)
+            .map(|(_, bitrate)| bitrate);
+
+        let pcr = self
+            .pcrs
+            .into_values()
+            .map(|mut state| {
+                if let (Some(bitrate), Some(first)) = (bitrate, state.values.first()) {
+                    state.stats.max_jitter_ms = state
+                        .values
+                        .iter()
+                        .map(|pcr| {
+                            let expected = (pcr.position - first.position) as f64 * 8.0 / bitrate;
+                            let actual = (pcr.value - first.value) as f64 / PCR_HZ;
+
+                            (actual - expected).abs() * 1000.0
+                        })
+                        .fold(0.0, f64::max);
+                }
+
+                state.stats
+            })
+            .collect();
+
+        let pmts: HashMap<u16, u16> = self
+            .programs
+            .iter()
+            .map(|(program, pmt)| (*pmt, *program))
+            .collect();
+
+        let mut pids: Vec<PidStats> = self
+            .pids
+            .into_iter()
+            .map(|(pid, state)| {
+                let mut stats = state.stats;
+                stats.pid = pid;
+
+                stats.kind = match (pid, pmts.get(&pid), stats.stream_type) {
+                    (0, _, _) => "pat".to_string(),
+                    (_, Some(program), _) => {
+                        stats.program = Some(*program);
+                        "pmt".to_string()
+                    }
+                    (_, _, Some(stream_type)) => stream_kind(stream_type).to_string(),
+                    (0x10..=0x1f, _, _) => "si".to_string(),
+                    _ => "unknown".to_string(),
+                };
+
+                stats
+            })
+            .collect();
+
+        pids.sort_by_key(|stats| stats.pid);
+
+        AnalyzeTsResponse {
+            packets: self.packets,
+            sync_losses: self.sync_losses,
+            bitrate: bitrate.map(|bitrate| bitrate.round() as u64),
+            programs: self.programs,
+            pids,
+            pcr,
+        }
+    }
+}
+
+impl<F> ServiceImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub(crate) async fn _analyze_ts(
+        &self,
+        request: AnalyzeTsRequest,
+    ) -> HandlerResult<AnalyzeTsResponse> {
+        let (uri, path) = parse_uri(request.input.clone());
+
+        let operator = self.factory.load(uri.as_str())?;
+        let size = operator.stat(&path).await?.content_length();
+        let end = request.max_bytes.map_or(size, |max| max.min(size));
+
+        // The packets are parsed here: ffmpeg hides continuity errors and PCR values
+        let mut stream = operator
+            .reader(&path)
+            .await?
+            .into_bytes_stream(0..end)
+            .await?;
+
+        let mut analyzer = Analyzer::default();
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut position = 0u64;
+        let mut in_sync = true;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            metering::record(|usage| usage.bytes_in += chunk.len() as u64);
+
+            buffer.extend_from_slice(&chunk);
+
+            let mut offset = 0;
+
+            while buffer.len() - offset >= PACKET_SIZE {
+                if buffer[offset] != SYNC_BYTE {
+                    if in_sync {
+                        analyzer.sync_losses += 1;
+                        in_sync = false;
+                    }
+                    offset += 1;
+                    continue;
+                }
+
+                in_sync = true;
+                analyzer.packet(
+                    &buffer[offset..offset + PACKET_SIZE],
+                    position + offset as u64,
+                );
+                offset += PACKET_SIZE;
+            }
+
+            buffer.drain(..offset);
+            position += offset as u64;
+        }
+
+        if analyzer.packets == 0 {
+            return Err(
+                TerminalError::new_with_code(400, "input is not an MPEG transport stream").into(),
+            );
+        }
+
+        Ok(analyzer.finish())
+    }
+}
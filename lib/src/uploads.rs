@@ -0,0 +1,120 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+use restate_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::metering;
+use crate::service::Output;
+
+tokio::task_local! {
+    static DEFERRED: RefCell<Deferred>;
+}
+
+#[derive(Debug, Default)]
+struct Deferred {
+    dir: PathBuf,
+    uploads: Vec<PendingUpload>,
+}
+
+/// Files of a job waiting to be uploaded.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct PendingUpload {
+    /// Directory of the files relative to the job dir
+    pub dir: String,
+
+    pub location: Url,
+}
+
+/// Response of a job along with the uploads left to the next step.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct Staged<T> {
+    pub response: T,
+    pub uploads: Vec<PendingUpload>,
+}
+
+/// Moves the entries of a directory, copying them when it's on another file system.
+pub(crate) fn move_entries(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+
+        if std::fs::rename(entry.path(), &target).is_err() {
+            if entry.metadata()?.is_dir() {
+                move_entries(&entry.path(), &target)?;
+            } else {
+                std::fs::copy(entry.path(), &target)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps the files of a work dir for the upload step of the current job.
+///
+/// Returns false when the job doesn't defer uploads (the caller has to upload right away).
+pub(crate) fn defer(work_dir: &Path, output: &Output) -> HandlerResult<bool> {
+    let Ok(result) = DEFERRED.try_with(|deferred| {
+        let mut deferred = deferred.borrow_mut();
+
+        let dir = deferred.uploads.len().to_string();
+        move_entries(work_dir, &deferred.dir.join(&dir))?;
+
+        deferred.uploads.push(PendingUpload {
+            dir,
+            location: output.location(),
+        });
+
+        Ok::<_, std::io::Error>(())
+    }) else {
+        return Ok(false);
+    };
+
+    result?;
+
+    Ok(true)
+}
+
+/// Runs a job in its own step, deferring the uploads of its outputs to the job dir.
+///
+/// The job dir is derived from the invocation, so the upload step finds the files
+/// when it's retried without running the job again.
+pub(crate) async fn deferred<T>(
+    job_dir: PathBuf,
+    job: impl Future<Output = HandlerResult<T>>,
+) -> HandlerResult<Staged<T>> {
+    // Leftovers of a previous attempt failing half-way
+    let _ = tokio::fs::remove_dir_all(&job_dir).await;
+
+    let deferred = RefCell::new(Deferred {
+        dir: job_dir.clone(),
+        uploads: Vec::new(),
+    });
+
+    let (response, uploads) = DEFERRED
+        .scope(deferred, async {
+            let response = job.await;
+            (response, DEFERRED.with(|deferred| deferred.take().uploads))
+        })
+        .await;
+
+    match response {
+        Ok(response) => {
+            // Counted here, the upload step runs outside of the metered job
+            for upload in &uploads {
+                metering::record_upload(&job_dir.join(&upload.dir));
+            }
+
+            Ok(Staged { response, uploads })
+        }
+        Err(err) => {
+            let _ = tokio::fs::remove_dir_all(&job_dir).await;
+
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use opendal_util::OperatorFactory;
+use restate_sdk::context::RequestTarget;
+use restate_sdk::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::service::parse_uri;
+
+/// A hot folder: new files under the location are processed by an FFmpeg handler.
+///
+/// Polling starts when the `start` handler of the folder (keyed by its name) is called.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WatchFolderConfig {
+    /// Prefix watched for new files
+    pub location: Url,
+
+    /// FFmpeg handler processing the files (e.g. "convert_color")
+    pub handler: String,
+
+    /// Request sent to the handler, "{input}" and "{stem}" in strings are replaced
+    /// with the URL and the name (without extension) of the file
+    pub request: Value,
+
+    /// Time between listings
+    #[serde(default = "default_interval", with = "humantime_serde")]
+    pub interval: Duration,
+
+    /// Prefix processed files are moved to (defaults to "done/" under the location)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub done: Option<Url>,
+
+    /// Prefix files failing to process are moved to (defaults to "failed/" under the location)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed: Option<Url>,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl WatchFolderConfig {
+    fn prefix(&self, name: &str) -> Url {
+        let mut location = self.location.clone();
+
+        if !location.path().ends_with('/') {
+            location.set_path(&format!("{}/", location.path()));
+        }
+
+        location.join(name).unwrap_or(location)
+    }
+
+    fn done(&self) -> Url {
+        self.done.clone().unwrap_or_else(|| self.prefix("done/"))
+    }
+
+    fn failed(&self) -> Url {
+        self.failed
+            .clone()
+            .unwrap_or_else(|| self.prefix("failed/"))
+    }
+}
+
+/// A file picked up from a watch folder.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchedFile {
+    /// Path of the file in the storage of the watch folder
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchFolderStatus {
+    pub running: bool,
+
+    /// Files being processed
+    pub processing: Vec<String>,
+
+    /// Number of files processed successfully
+    pub done: u64,
+
+    /// Number of files that failed to process
+    pub failed: u64,
+}
+
+const GENERATION: &str = "generation";
+const RUNNING: &str = "running";
+const SEEN: &str = "seen";
+const PROCESSING: &str = "processing";
+const DONE: &str = "done";
+const FAILED: &str = "failed";
+
+/// Hot folder transcoder (keyed by the name of the configured folder).
+#[restate_sdk::object]
+#[name = "FFmpegWatchFolder"]
+pub trait WatchFolder {
+    /// Start polling the folder.
+    async fn start() -> HandlerResult<()>;
+
+    /// Stop polling the folder (files being processed are finished).
+    async fn stop() -> HandlerResult<()>;
+
+    /// List the folder and submit new files.
+    async fn poll(generation: u64) -> HandlerResult<()>;
+
+    /// Process a single file and move it to the done or failed prefix.
+    #[shared]
+    async fn process(file: Json<WatchedFile>) -> HandlerResult<()>;
+
+    /// Record the outcome of processing a file.
+    async fn complete(outcome: Json<(String, bool)>) -> HandlerResult<()>;
+
+    /// Report the state of the folder.
+    #[shared]
+    async fn status() -> HandlerResult<Json<WatchFolderStatus>>;
+}
+
+pub struct WatchFolderImpl<F>
+where
+    F: OperatorFactory,
+{
+    factory: F,
+    folders: HashMap<String, WatchFolderConfig>,
+}
+
+impl<F> WatchFolderImpl<F>
+where
+    F: OperatorFactory,
+{
+    pub fn new(factory: F, folders: HashMap<String, WatchFolderConfig>) -> Self {
+        Self { factory, folders }
+    }
+
+    fn folder(&self, name: &str) -> Result<&WatchFolderConfig, TerminalError> {
+        self.folders.get(name).ok_or_else(|| {
+            TerminalError::new_with_code(404, format!("unknown watch folder: {name}"))
+        })
+    }
+
+    /// Lists the files under the folder with their size, skipping the done and failed prefixes.
+    async fn list(&self, folder: &WatchFolderConfig) -> HandlerResult<BTreeMap<String, u64>> {
+        let (uri, path) = parse_uri(folder.prefix(""));
+        let (_, done) = parse_uri(folder.done());
+        let (_, failed) = parse_uri(folder.failed());
+
+        let operator = self.factory.load(uri.as_str())?;
+
+        let entries: Vec<_> = operator
+            .lister_with(&path)
+            .recursive(true)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(entries
+            .into_iter()
+            .filter(|entry| entry.metadata().is_file())
+            .filter(|entry| {
+                let path = format!("/{}", entry.path().trim_start_matches('/'));
+                !path.starts_with(&done) && !path.starts_with(&failed)
+            })
+            .map(|entry| (entry.path().to_string(), entry.metadata().content_length()))
+            .collect())
+    }
+
+    /// Moves a processed file under another prefix, keeping its path relative to the folder.
+    async fn archive(
+        &self,
+        folder: &WatchFolderConfig,
+        file: &str,
+        target: &Url,
+    ) -> HandlerResult<()> {
+        let (uri, root) = parse_uri(folder.prefix(""));
+        let (target_uri, target_path) = parse_uri(target.clone());
+
+        let source = format!("/{}", file.trim_start_matches('/'));
+        let relative = source
+            .strip_prefix(&root)
+            .unwrap_or(&source)
+            .trim_start_matches('/');
+        let destination = format!("{}/{relative}", target_path.trim_end_matches('/'));
+
+        let operator = self.factory.load(uri.as_str())?;
+
+        if uri == target_uri {
+            operator.copy(file, &destination).await?;
+        } else {
+            let data = operator.read(file).await?;
+            self.factory
+                .load(target_uri.as_str())?
+                .write(&destination, data)
+                .await?;
+        }
+
+        operator.delete(file).await?;
+
+        Ok(())
+    }
+}
+
+/// Fills a request template for a file.
+pub(crate) fn render(template: &Value, input: &str, stem: &str) -> Value {
+    match template {
+        Value::String(s) => Value::String(s.replace("{input}", input).replace("{stem}", stem)),
+        Value::Array(values) => {
+            Value::Array(values.iter().map(|v| render(v, input, stem)).collect())
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), render(value, input, stem)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+impl<F> WatchFolder for WatchFolderImpl<F>
+where
+    F: OperatorFactory,
+{
+    async fn start(&self, ctx: ObjectContext<'_>) -> HandlerResult<()> {
+        self.folder(ctx.key())?;
+
+        if ctx.get::<bool>(RUNNING).await?.unwrap_or_default() {
+            return Ok(());
+        }
+
+        // A new generation invalidates polls scheduled before a previous stop
+        let generation = ctx.get::<u64>(GENERATION).await?.unwrap_or_default() + 1;
+
+        ctx.set(GENERATION, generation);
+        ctx.set(RUNNING, true);
+
+        ctx.object_client::<WatchFolderClient>(ctx.key())
+            .poll(generation)
+            .send();
+
+        Ok(())
+    }
+
+    async fn stop(&self, ctx: ObjectContext<'_>) -> HandlerResult<()> {
+        ctx.set(RUNNING, false);
+
+        Ok(())
+    }
+
+    async fn poll(&self, ctx: ObjectContext<'_>, generation: u64) -> HandlerResult<()> {
+        let folder = self.folder(ctx.key())?;
+
+        if !ctx.get::<bool>(RUNNING).await?.unwrap_or_default()
+            || ctx.get::<u64>(GENERATION).await?.unwrap_or_default() != generation
+        {
+            return Ok(());
+        }
+
+        let files = ctx
+            .run(|| async { Ok(Json(self.list(folder).await?)) })
+            .await?
+            .into_inner();
+
+        let seen = ctx
+            .get::<Json<BTreeMap<String, u64>>>(SEEN)
+            .await?
+            .map(Json::into_inner)
+            .unwrap_or_default();
+        let mut processing = ctx
+            .get::<Json<BTreeSet<String>>>(PROCESSING)
+            .await?
+            .map(Json::into_inner)
+            .unwrap_or_default();
+
+        // Files are submitted once their size stopped changing between two listings,
+        // so that uploads still in progress are not picked up
+        for (path, size) in &files {
+            if processing.contains(path) || seen.get(path) != Some(size) {
+                continue;
+            }
+
+            processing.insert(path.clone());
+
+            ctx.object_client::<WatchFolderClient>(ctx.key())
+                .process(Json(WatchedFile { path: path.clone() }))
+                .send();
+        }
+
+        let seen: BTreeMap<String, u64> = files
+            .into_iter()
+            .filter(|(path, _)| !processing.contains(path))
+            .collect();
+
+        ctx.set(SEEN, Json(seen));
+        ctx.set(PROCESSING, Json(processing));
+
+        ctx.object_client::<WatchFolderClient>(ctx.key())
+            .poll(generation)
+            .send_after(folder.interval);
+
+        Ok(())
+    }
+
+    async fn process(
+        &self,
+        ctx: SharedObjectContext<'_>,
+        file: Json<WatchedFile>,
+    ) -> HandlerResult<()> {
+        let folder = self.folder(ctx.key())?;
+        let file = file.into_inner();
+
+        let input = folder
+            .prefix("")
+            .join(
+                file.path
+                    .trim_start_matches(parse_uri(folder.prefix("")).1.trim_start_matches('/'))
+                    .trim_start_matches('/'),
+            )
+            .map_err(|err| TerminalError::new(err.to_string()))?;
+
+        let stem = input
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(|name| name.rsplit_once('.').map_or(name, |(stem, _)| stem))
+            .unwrap_or_default()
+            .to_string();
+
+        let request = render(&folder.request, input.as_str(), &stem);
+
+        let result = ctx
+            .request::<_, Json<Value>>(
+                RequestTarget::service("FFmpeg", &folder.handler),
+                Json(request),
+            )
+            .call()
+            .await;
+
+        let succeeded = result.is_ok();
+        let target = if succeeded {
+            folder.done()
+        } else {
+            folder.failed()
+        };
+
+        ctx.run(|| async { self.archive(folder, &file.path, &target).await })
+            .await?;
+
+        ctx.object_client::<WatchFolderClient>(ctx.key())
+            .complete(Json((file.path, succeeded)))
+            .send();
+
+        Ok(())
+    }
+
+    async fn complete(
+        &self,
+        ctx: ObjectContext<'_>,
+        outcome: Json<(String, bool)>,
+    ) -> HandlerResult<()> {
+        let (path, succeeded) = outcome.into_inner();
+
+        let mut processing = ctx
+            .get::<Json<BTreeSet<String>>>(PROCESSING)
+            .await?
+            .map(Json::into_inner)
+            .unwrap_or_default();
+
+        processing.remove(&path);
+        ctx.set(PROCESSING, Json(processing));
+
+        let counter = if succeeded { DONE } else { FAILED };
+        let count = ctx.get::<u64>(counter).await?.unwrap_or_default();
+        ctx.set(counter, count + 1);
+
+        Ok(())
+    }
+
+    async fn status(&self, ctx: SharedObjectContext<'_>) -> HandlerResult<Json<WatchFolderStatus>> {
+        Ok(Json(WatchFolderStatus {
+            running: ctx.get::<bool>(RUNNING).await?.unwrap_or_default(),
+            processing: ctx
+                .get::<Json<BTreeSet<String>>>(PROCESSING)
+                .await?
+                .map(Json::into_inner)
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            done: ctx.get::<u64>(DONE).await?.unwrap_or_default(),
+            failed: ctx.get::<u64>(FAILED).await?.unwrap_or_default(),
+        }))
+    }
+}
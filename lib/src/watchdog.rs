@@ -0,0 +1,187 @@
+use std::fmt;
+use std::io::SeekFrom;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::process::Child;
+
+use crate::diagnose::Registered;
+
+/// Longest time between two checks of the progress of a job.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Size of the end of the progress file parsed by the checks (it holds several progress blocks).
+const TAIL_SIZE: u64 = 4096;
+
+/// Kills ffmpeg processes whose progress stopped.
+///
+/// Hung demuxers (e.g. on bad network inputs) block forever otherwise.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct WatchdogConfig {
+    /// Time after which a job whose output time has not advanced is killed (disabled when empty)
+    #[serde(default, with = "humantime_serde")]
+    pub stall_timeout: Option<Duration>,
+}
+
+/// Error returned when the watchdog kills a stalled job.
+///
+/// It is retryable: the input may be readable again on the next attempt.
+#[derive(Debug)]
+pub struct StalledError {
+    /// Output time (in microseconds) the job stalled at
+    pub out_time_us: Option<u64>,
+    pub stalled_for: Duration,
+}
+
+impl fmt::Display for StalledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.out_time_us {
+            Some(out_time) => write!(
+                f,
+                "stalled: output time has not advanced past {:.3}s for {}s",
+                out_time as f64 / 1e6,
+                self.stalled_for.as_secs()
+            ),
+            None => write!(
+                f,
+                "stalled: no progress for {}s",
+                self.stalled_for.as_secs()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StalledError {}
+
+/// Progress source of a watched ffmpeg process.
+pub(crate) struct Watch {
+    /// Progress file (when ffmpeg does not report its progress to stdout)
+    progress: Option<NamedTempFile>,
+    stall_timeout: Duration,
+}
+
+impl WatchdogConfig {
+    /// Prepares watching a command, unless the watchdog is disabled
+    /// or the request reports its progress itself.
+    ///
+    /// Reported progress is watched when available, a progress file is used otherwise.
+    pub(crate) fn watch(&self, args: &[String], reported: bool) -> std::io::Result<Option<Watch>> {
+        let Some(stall_timeout) = self.stall_timeout else {
+            return Ok(None);
+        };
+
+        if args.iter().any(|arg| arg == "-progress") {
+            return Ok(None);
+        }
+
+        // Kept out of the work dir: it is not an output
+        let progress = if reported {
+            None
+        } else {
+            Some(NamedTempFile::new()?)
+        };
+
+        Ok(Some(Watch {
+            progress,
+            stall_timeout,
+        }))
+    }
+}
+
+impl Watch {
+    /// Arguments making ffmpeg report its progress to the watched file (if any).
+    pub(crate) fn args(&self) -> Vec<String> {
+        self.progress
+            .iter()
+            .flat_map(|progress| {
+                [
+                    "-progress".to_string(),
+                    progress.path().display().to_string(),
+                ]
+            })
+            .collect()
+    }
+
+    /// Resolves when the output time stops advancing for the stall timeout.
+    async fn stalled(&self, process: &Registered<'_>) -> StalledError {
+        let interval = (self.stall_timeout / 4).clamp(Duration::from_millis(100), CHECK_INTERVAL);
+
+        let mut out_time_us = None;
+        let mut advanced = Instant::now();
+
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let (current, ended) = match &self.progress {
+                Some(progress) => read(progress).await.unwrap_or_default(),
+                None => process
+                    .progress()
+                    .map(|progress| (progress.out_time_us, progress.ended))
+                    .unwrap_or_default(),
+            };
+
+            // Finishing the outputs (e.g. moving the index of an MP4 to the front) takes time
+            if ended {
+                return std::future::pending().await;
+            }
+
+            if current > out_time_us {
+                out_time_us = current;
+                advanced = Instant::now();
+            } else if advanced.elapsed() >= self.stall_timeout {
+                return StalledError {
+                    out_time_us,
+                    stalled_for: advanced.elapsed(),
+                };
+            }
+        }
+    }
+}
+
+/// Parses the last output time in the progress file and whether ffmpeg reported the end.
+async fn read(progress: &NamedTempFile) -> std::io::Result<(Option<u64>, bool)> {
+    let mut file = tokio::fs::File::open(progress.path()).await?;
+    let len = file.metadata().await?.len();
+
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_SIZE)))
+        .await?;
+
+    let mut tail = String::new();
+    file.read_to_string(&mut tail).await.ok();
+
+    let mut out_time_us = None;
+    let mut ended = false;
+
+    for (key, value) in tail.lines().filter_map(|line| line.split_once('=')) {
+        match key {
+            "out_time_us" => out_time_us = value.parse().ok().or(out_time_us),
+            "progress" => ended = value == "end",
+            _ => {}
+        }
+    }
+
+    Ok((out_time_us, ended))
+}
+
+/// Waits for a process, killing it when the watchdog finds it stalled.
+pub(crate) async fn wait(
+    cmd: &mut Child,
+    watch: Option<&Watch>,
+    process: &Registered<'_>,
+) -> std::io::Result<ExitStatus> {
+    let Some(watch) = watch else {
+        return cmd.wait().await;
+    };
+
+    let stalled = tokio::select! {
+        status = cmd.wait() => return status,
+        stalled = watch.stalled(process) => stalled,
+    };
+
+    cmd.kill().await?;
+
+    Err(std::io::Error::other(stalled))
+}